@@ -33,8 +33,10 @@ systemctl stop geth.service 2>/dev/null || true
 log "Syncing filesystems..."
 sync
 
-# Final system shutdown
-log "Executing system shutdown..."
-shutdown -h now
+# Final system shutdown. W3P_UPS_SHUTDOWN_ACTION comes from [shutdown].action
+# (poweroff | reboot | halt | suspend | hibernate).
+ACTION="${W3P_UPS_SHUTDOWN_ACTION:-poweroff}"
+log "Executing system $ACTION..."
+systemctl "$ACTION"
 
 log "Shutdown command sent"
@@ -0,0 +1,208 @@
+//! Windowed downsampling of `power.status` samples: one record per window
+//! with min / max / average of every numeric field, so long-running exports
+//! stay small without losing the extremes that matter for outage analysis.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::proto::payloads::PowerStatusV1;
+use crate::soc::SocCurve;
+
+/// Min / max / mean of one field over a window.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FieldStats {
+    pub min: i32,
+    pub max: i32,
+    pub avg: f64,
+}
+
+/// One downsampled window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Aggregate {
+    /// Raw samples folded into this record.
+    pub samples: u32,
+    /// Time from the first to the last sample in the window.
+    pub span_ms: u64,
+    pub vbus_in_mv: FieldStats,
+    pub vbus_out_mv: FieldStats,
+    pub ibus_out_ma: FieldStats,
+    pub vbat_mv: FieldStats,
+    pub ibat_ma: FieldStats,
+    pub soc_pct: FieldStats,
+    pub temp_dc: FieldStats,
+    /// Union of every fault bit seen in the window.
+    pub faults: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Acc {
+    min: i32,
+    max: i32,
+    sum: i64,
+}
+
+impl Acc {
+    fn new(v: i32) -> Self {
+        Self {
+            min: v,
+            max: v,
+            sum: v as i64,
+        }
+    }
+
+    fn push(&mut self, v: i32) {
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as i64;
+    }
+
+    fn stats(&self, n: u32) -> FieldStats {
+        FieldStats {
+            min: self.min,
+            max: self.max,
+            avg: self.sum as f64 / n as f64,
+        }
+    }
+}
+
+const FIELDS: usize = 7;
+
+fn fields(p: &PowerStatusV1, curve: SocCurve) -> [i32; FIELDS] {
+    [
+        p.vbus_in_mv as i32,
+        p.vbus_out_mv as i32,
+        p.ibus_out_ma as i32,
+        p.vbat_mv as i32,
+        p.ibat_ma as i32,
+        curve.soc_pct(p.vbat_mv) as i32,
+        p.temp_dc as i32,
+    ]
+}
+
+struct Window {
+    first_at: Instant,
+    last_at: Instant,
+    n: u32,
+    acc: [Acc; FIELDS],
+    faults: u16,
+}
+
+impl Window {
+    fn start(p: &PowerStatusV1, at: Instant, curve: SocCurve) -> Self {
+        Self {
+            first_at: at,
+            last_at: at,
+            n: 1,
+            acc: fields(p, curve).map(Acc::new),
+            faults: p.faults,
+        }
+    }
+
+    fn push(&mut self, p: &PowerStatusV1, at: Instant, curve: SocCurve) {
+        for (acc, v) in self.acc.iter_mut().zip(fields(p, curve)) {
+            acc.push(v);
+        }
+        self.n += 1;
+        self.last_at = at;
+        self.faults |= p.faults;
+    }
+
+    fn finish(&self) -> Aggregate {
+        let s = self.acc.map(|a| a.stats(self.n));
+        Aggregate {
+            samples: self.n,
+            span_ms: self
+                .last_at
+                .saturating_duration_since(self.first_at)
+                .as_millis() as u64,
+            vbus_in_mv: s[0],
+            vbus_out_mv: s[1],
+            ibus_out_ma: s[2],
+            vbat_mv: s[3],
+            ibat_ma: s[4],
+            soc_pct: s[5],
+            temp_dc: s[6],
+            faults: self.faults,
+        }
+    }
+}
+
+/// Folds samples into fixed-length windows. A window closes on the first
+/// sample that arrives `window` or later after it opened; that sample opens
+/// the next one.
+pub struct Aggregator {
+    window: Duration,
+    /// For the `soc_pct` column.
+    curve: SocCurve,
+    current: Option<Window>,
+}
+
+impl Aggregator {
+    pub fn new(window: Duration, curve: SocCurve) -> Self {
+        Self {
+            window,
+            curve,
+            current: None,
+        }
+    }
+
+    /// Add a sample taken at `at`. Returns the just-closed window, if any.
+    pub fn push(&mut self, p: &PowerStatusV1, at: Instant) -> Option<Aggregate> {
+        match &mut self.current {
+            Some(w) if at.saturating_duration_since(w.first_at) < self.window => {
+                w.push(p, at, self.curve);
+                None
+            }
+            slot => slot
+                .replace(Window::start(p, at, self.curve))
+                .map(|w| w.finish()),
+        }
+    }
+
+    /// Close the open window early (e.g. on exit).
+    pub fn flush(&mut self) -> Option<Aggregate> {
+        self.current.take().map(|w| w.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(vbat_mv: u16, ibat_ma: i16, faults: u16) -> PowerStatusV1 {
+        PowerStatusV1 {
+            vbat_mv,
+            ibat_ma,
+            faults,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn emits_min_max_avg_per_window() {
+        let t0 = Instant::now();
+        let s = Duration::from_secs;
+        let mut agg = Aggregator::new(s(10), SocCurve::STOCK);
+        assert_eq!(agg.push(&sample(7400, -1000, 0), t0), None);
+        assert_eq!(agg.push(&sample(7300, -3000, 0x1), t0 + s(4)), None);
+        assert_eq!(agg.push(&sample(7500, 500, 0x4), t0 + s(9)), None);
+
+        let a = agg
+            .push(&sample(7000, 0, 0), t0 + s(10))
+            .expect("window closed");
+        assert_eq!(a.samples, 3);
+        assert_eq!(a.span_ms, 9_000);
+        assert_eq!((a.vbat_mv.min, a.vbat_mv.max), (7300, 7500));
+        assert!((a.vbat_mv.avg - 7400.0).abs() < 1e-9);
+        assert_eq!((a.ibat_ma.min, a.ibat_ma.max), (-3000, 500));
+        assert!((a.ibat_ma.avg + 3500.0 / 3.0).abs() < 1e-9);
+        assert_eq!(a.faults, 0x5);
+
+        // The closing sample opened the next window.
+        let b = agg.flush().expect("open window");
+        assert_eq!(b.samples, 1);
+        assert_eq!(b.vbat_mv.min, 7000);
+        assert_eq!(agg.flush(), None);
+    }
+}
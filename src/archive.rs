@@ -0,0 +1,239 @@
+//! `[archive]`: a local long-term store of every real power sample, for
+//! history without a database. Each sample is appended to `path` as one
+//! JSON object per line in the `probe --follow --json` format (wall-clock
+//! `unix_ts_ms`, or `ts` with `rfc3339`), so `stats` and `replay` read the
+//! files as they read a recording.
+//!
+//! The file is rotated like a log: once it reaches `max_file_mb`, or is
+//! `rotate_hours` old, it is renamed to `path.1` (`path.1` to `path.2`, …)
+//! and a fresh one started; only `keep_files` rotated files are kept.
+//! Lines go to the page cache without an fsync, so the SD card sees a few
+//! large writes rather than one per sample. A write that fails is logged
+//! once, and the archive is reopened with the next sample. Injected samples
+//! are not archived.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tracing::{debug, info, warn};
+
+use crate::config::{ArchiveConfig, BatteryConfig};
+use crate::probe::ProbeSample;
+use crate::state::State;
+
+/// Writes [`State::sample_feed`] to disk; idle when no path is configured.
+pub async fn archive_loop(state: Arc<State>, cfg: ArchiveConfig, battery: BatteryConfig) {
+    if cfg.path.is_empty() {
+        return std::future::pending().await;
+    }
+    info!(
+        path = %cfg.path,
+        max_file_mb = cfg.max_file_mb,
+        rotate_hours = cfg.rotate_hours,
+        keep_files = cfg.keep_files,
+        "sample archive running"
+    );
+    let mut archive = Archive::new(&cfg);
+    let mut failing = false;
+    let mut feed = state.sample_feed("archive");
+    while let Some(p) = feed.next_real_sample().await {
+        let written = serde_json::to_string(&ProbeSample::new(&battery, &p, cfg.rfc3339))
+            .map_err(anyhow::Error::from)
+            .and_then(|line| archive.append(&line, unix_now_ms()));
+        match (written, failing) {
+            (Ok(()), true) => {
+                info!("sample archive {} writable again", cfg.path);
+                failing = false;
+            }
+            (Err(e), false) => {
+                warn!("sample archive not written: {e:#}");
+                failing = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The current file and when to rotate it.
+#[derive(Debug)]
+pub(crate) struct Archive {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age_ms: Option<u64>,
+    keep: usize,
+    open: Option<Current>,
+}
+
+#[derive(Debug)]
+struct Current {
+    file: File,
+    bytes: u64,
+    started_unix_ms: u64,
+}
+
+impl Archive {
+    pub(crate) fn new(cfg: &ArchiveConfig) -> Self {
+        Self {
+            path: PathBuf::from(&cfg.path),
+            max_bytes: cfg.max_file_mb.saturating_mul(1024 * 1024),
+            max_age_ms: (cfg.rotate_hours > 0)
+                .then(|| Duration::from_secs(cfg.rotate_hours * 3600).as_millis() as u64),
+            keep: cfg.keep_files,
+            open: None,
+        }
+    }
+
+    /// Append one line (without its newline), rotating first when the
+    /// current file is full or old enough.
+    pub(crate) fn append(&mut self, line: &str, unix_ms: u64) -> Result<()> {
+        if let Some(cur) = &self.open {
+            let full = self.max_bytes > 0 && cur.bytes >= self.max_bytes;
+            let old = self
+                .max_age_ms
+                .is_some_and(|age| unix_ms.saturating_sub(cur.started_unix_ms) >= age);
+            if full || old {
+                self.open = None;
+                self.rotate()?;
+            }
+        }
+        let cur = match &mut self.open {
+            Some(cur) => cur,
+            None => self.open.insert(open(&self.path, unix_ms)?),
+        };
+        let mut bytes = line.as_bytes().to_vec();
+        bytes.push(b'\n');
+        if let Err(e) = cur.file.write_all(&bytes) {
+            self.open = None;
+            return Err(e).with_context(|| format!("write {}", self.path.display()));
+        }
+        cur.bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// `path` → `path.1` → … → `path.<keep>`; what was at `path.<keep>` is
+    /// overwritten. With `keep == 0` the file is simply removed.
+    fn rotate(&self) -> Result<()> {
+        let numbered = |n: usize| {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{n}"));
+            PathBuf::from(p)
+        };
+        if self.keep == 0 {
+            return remove(&self.path);
+        }
+        for n in (1..self.keep).rev() {
+            let from = numbered(n);
+            if from.exists() {
+                std::fs::rename(&from, numbered(n + 1))
+                    .with_context(|| format!("rotate {}", from.display()))?;
+            }
+        }
+        std::fs::rename(&self.path, numbered(1))
+            .with_context(|| format!("rotate {}", self.path.display()))?;
+        debug!("sample archive {} rotated", self.path.display());
+        Ok(())
+    }
+}
+
+/// Open `path` for appending. An existing file carries on, its age counted
+/// from its creation time (or from now where the filesystem doesn't keep
+/// one).
+fn open(path: &Path, unix_ms: u64) -> Result<Current> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))?;
+    let meta = file.metadata()?;
+    let started_unix_ms = meta
+        .created()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(unix_ms, |d| d.as_millis() as u64);
+    Ok(Current {
+        file,
+        bytes: meta.len(),
+        started_unix_ms,
+    })
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_the_newest_files() {
+        let dir = Scratch::new("archive");
+        let path = dir.join("samples.jsonl");
+        let mut archive = Archive::new(&ArchiveConfig {
+            path: path.to_string_lossy().into_owned(),
+            rotate_hours: 0,
+            keep_files: 2,
+            ..Default::default()
+        });
+        // 1 MB per file would take a while; rotate every two lines.
+        archive.max_bytes = 2 * "sample 0\n".len() as u64;
+        for n in 0..7 {
+            archive.append(&format!("sample {n}"), 0).unwrap();
+        }
+        assert_eq!(read(&path), "sample 6\n");
+        assert_eq!(read(&dir.join("samples.jsonl.1")), "sample 4\nsample 5\n");
+        assert_eq!(read(&dir.join("samples.jsonl.2")), "sample 2\nsample 3\n");
+        assert!(!dir.join("samples.jsonl.3").exists());
+
+        // Reopened after a restart, the file carries on where it was.
+        let mut again = Archive::new(&ArchiveConfig {
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+        again.append("sample 7", 0).unwrap();
+        assert_eq!(read(&path), "sample 6\nsample 7\n");
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let dir = Scratch::new("archive-age");
+        let path = dir.join("samples.jsonl");
+        let mut archive = Archive::new(&ArchiveConfig {
+            path: path.to_string_lossy().into_owned(),
+            rotate_hours: 1,
+            keep_files: 1,
+            ..Default::default()
+        });
+        let hour = 3_600_000;
+        archive.append("first", 0).unwrap();
+        // Age counts from the file's creation time where there is one.
+        let base = archive.open.as_ref().unwrap().started_unix_ms;
+        archive.append("second", base + hour - 1).unwrap();
+        archive.append("third", base + hour).unwrap();
+        assert_eq!(read(&path), "third\n");
+        assert_eq!(read(&dir.join("samples.jsonl.1")), "first\nsecond\n");
+    }
+}
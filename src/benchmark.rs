@@ -0,0 +1,195 @@
+//! `w3p-ups benchmark`: read the UPS directly over serial (as `probe` does)
+//! for a while and report how the serial pipeline keeps up. It shows
+//! power samples per second, bytes per second against what the baud rate
+//! can carry, how many frames decoded versus failed, and the time spent
+//! decoding them.
+//!
+//! Together these point at the usual causes of missed samples. Many
+//! failures at a byte rate near the link's capacity suggest the wrong
+//! `baud_rate`. A clean decode at fewer samples than the firmware should
+//! send points at the firmware. Decode time near the sample interval means
+//! the host's CPU is the limit.
+//!
+//! Like `probe`, this needs the serial port to itself: stop the daemon first.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config::Config;
+use crate::monitor::UpsMonitor;
+use crate::state::PowerUpdate;
+use crate::transport::kv::KvRejectCounts;
+use crate::transport::ReadCounts;
+
+/// Below this share of frames decoding, the report suggests checking the
+/// serial settings.
+const LOW_SUCCESS_PCT: f64 = 95.0;
+
+/// What one run measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Measurement {
+    pub(crate) elapsed: Duration,
+    pub(crate) baud: u32,
+    /// `power.status` updates, including any the feed skipped.
+    pub(crate) samples: u64,
+    pub(crate) read: ReadCounts,
+    pub(crate) kv_rejects: KvRejectCounts,
+}
+
+impl Measurement {
+    pub(crate) fn report(&self) -> Vec<String> {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let r = &self.read;
+        let failed = r.frame_errors + self.kv_rejects.missing + self.kv_rejects.malformed;
+        let attempts = r.frames + failed;
+        let success_pct = match attempts {
+            0 => None,
+            n => Some(r.frames as f64 / n as f64 * 100.0),
+        };
+        // 8N1: ten bits on the wire per byte.
+        let capacity = self.baud as f64 / 10.0;
+        let bytes_per_s = r.bytes as f64 / secs;
+        let mut out = vec![
+            format!("serial benchmark over {:.1} s", secs),
+            format!(
+                "{:<10} {} power.status ({:.2}/s), {} frames in all",
+                "samples",
+                self.samples,
+                self.samples as f64 / secs,
+                r.frames
+            ),
+            format!(
+                "{:<10} {} ({bytes_per_s:.0} B/s, {:.1}% of {} baud)",
+                "bytes",
+                r.bytes,
+                bytes_per_s / capacity.max(1.0) * 100.0,
+                self.baud
+            ),
+            match success_pct {
+                Some(pct) => format!(
+                    "{:<10} {} ok, {failed} failed ({pct:.1}% success)",
+                    "parsed", r.frames
+                ),
+                None => format!("{:<10} nothing recognisable received", "parsed"),
+            },
+        ];
+        if attempts > 0 {
+            let per_frame_us = r.decode_ns as f64 / attempts as f64 / 1000.0;
+            out.push(format!(
+                "{:<10} {per_frame_us:.1} µs per frame on average ({:.3}% of one core)",
+                "decode",
+                r.decode_ns as f64 / 1e9 / secs * 100.0
+            ));
+        }
+        if r.bytes > 0 && success_pct.is_none_or(|pct| pct < LOW_SUCCESS_PCT) {
+            out.push(
+                "hint: bytes arrive but don't decode; check [serial].baud_rate and format".into(),
+            );
+        } else if r.bytes == 0 {
+            out.push("hint: nothing was received; is the UPS connected and sending?".into());
+        }
+        out
+    }
+}
+
+/// Read for `duration` (or until Ctrl-C) and print the report.
+pub async fn run_benchmark(cfg: &Config, duration: Duration) -> Result<()> {
+    let monitor = UpsMonitor::spawn(&cfg.serial)
+        .await
+        .context("open UPS serial port (is the daemon holding it?)")?;
+    eprintln!(
+        "benchmarking {} for {} s (Ctrl-C to stop early)…",
+        monitor.port(),
+        duration.as_secs()
+    );
+    let Some(mut rx) = monitor.subscribe() else {
+        bail!("serial link closed before the first sample");
+    };
+    let state = monitor.state().clone();
+    let before = (state.read_stats().counts(), state.kv_reject_counts());
+    let start = tokio::time::Instant::now();
+    let deadline = tokio::time::sleep(duration);
+    tokio::pin!(deadline);
+    let mut samples = 0;
+    loop {
+        tokio::select! {
+            update = rx.recv() => match update {
+                Ok(PowerUpdate::Status(_)) => samples += 1,
+                Ok(PowerUpdate::Event(_)) => {}
+                Err(RecvError::Lagged(n)) => samples += n,
+                Err(RecvError::Closed) => bail!("serial link closed"),
+            },
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    let (read, kv) = (state.read_stats().counts(), state.kv_reject_counts());
+    let m = Measurement {
+        elapsed: start.elapsed(),
+        baud: cfg.serial.baud_rate,
+        samples,
+        read: ReadCounts {
+            bytes: read.bytes - before.0.bytes,
+            frames: read.frames - before.0.frames,
+            frame_errors: read.frame_errors - before.0.frame_errors,
+            decode_ns: read.decode_ns - before.0.decode_ns,
+        },
+        kv_rejects: KvRejectCounts {
+            missing: kv.missing - before.1.missing,
+            malformed: kv.malformed - before.1.malformed,
+        },
+    };
+    for line in m.report() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_rates_and_flags_a_likely_baud_mismatch() {
+        let clean = Measurement {
+            elapsed: Duration::from_secs(10),
+            baud: 115_200,
+            samples: 10,
+            read: ReadCounts {
+                bytes: 1_000,
+                frames: 20,
+                frame_errors: 0,
+                decode_ns: 40_000,
+            },
+            kv_rejects: KvRejectCounts::default(),
+        };
+        assert_eq!(
+            clean.report(),
+            [
+                "serial benchmark over 10.0 s",
+                "samples    10 power.status (1.00/s), 20 frames in all",
+                "bytes      1000 (100 B/s, 0.9% of 115200 baud)",
+                "parsed     20 ok, 0 failed (100.0% success)",
+                "decode     2.0 µs per frame on average (0.000% of one core)",
+            ]
+        );
+
+        let garbled = Measurement {
+            read: ReadCounts {
+                frames: 2,
+                frame_errors: 5,
+                ..clean.read
+            },
+            kv_rejects: KvRejectCounts {
+                missing: 0,
+                malformed: 3,
+            },
+            ..clean
+        };
+        let report = garbled.report();
+        assert_eq!(report[3], "parsed     2 ok, 8 failed (20.0% success)");
+        assert!(report.last().unwrap().contains("baud_rate"));
+    }
+}
@@ -0,0 +1,229 @@
+//! Power budget for capacity planning (`w3p-ups budget`): min / avg / max
+//! input, battery-discharge and load power over a sampling window, and the
+//! runtime the pack would give at the average load.
+//!
+//! Input power needs v2 firmware (`vbus_in × iin`). The load is what the
+//! battery delivers while discharging; on grid it is the input less what
+//! goes into charging. Runtime divides the pack capacity by the average load
+//! expressed as pack current at the window's average pack voltage, so it
+//! reads a little long as the voltage sags.
+
+use std::time::Duration;
+
+/// One power sample, in the units the snapshot carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct BudgetSample {
+    /// `None` without v2 status.
+    pub(crate) input_mw: Option<u32>,
+    pub(crate) vbat_mv: u16,
+    /// Signed: negative while discharging.
+    pub(crate) ibat_ma: i16,
+    pub(crate) soc_pct: u8,
+}
+
+impl BudgetSample {
+    fn discharge_mw(&self) -> u32 {
+        (self.vbat_mv as u32 * (-(self.ibat_ma as i32)).max(0) as u32) / 1000
+    }
+
+    fn load_mw(&self) -> Option<u32> {
+        if self.ibat_ma < 0 {
+            return Some(self.discharge_mw());
+        }
+        let charge_mw = self.vbat_mv as u32 * self.ibat_ma as u32 / 1000;
+        self.input_mw.map(|i| i.saturating_sub(charge_mw))
+    }
+}
+
+/// Min / max / sum of one field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Stat {
+    pub(crate) min: u32,
+    pub(crate) max: u32,
+    sum: u64,
+    n: u32,
+}
+
+impl Stat {
+    fn new(v: u32) -> Self {
+        Self {
+            min: v,
+            max: v,
+            sum: v as u64,
+            n: 1,
+        }
+    }
+
+    fn push(&mut self, v: u32) {
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as u64;
+        self.n += 1;
+    }
+
+    pub(crate) fn avg(&self) -> f64 {
+        self.sum as f64 / self.n as f64
+    }
+}
+
+fn push(stat: &mut Option<Stat>, v: Option<u32>) {
+    match (stat.as_mut(), v) {
+        (Some(s), Some(v)) => s.push(v),
+        (None, Some(v)) => *stat = Some(Stat::new(v)),
+        (_, None) => {}
+    }
+}
+
+/// Running totals over the window.
+#[derive(Debug, Default)]
+pub(crate) struct PowerBudget {
+    pub(crate) samples: u32,
+    pub(crate) input_mw: Option<Stat>,
+    pub(crate) discharge_mw: Option<Stat>,
+    pub(crate) load_mw: Option<Stat>,
+    vbat_mv: Option<Stat>,
+    last_soc_pct: u8,
+}
+
+impl PowerBudget {
+    pub(crate) fn push(&mut self, s: BudgetSample) {
+        self.samples += 1;
+        push(&mut self.input_mw, s.input_mw);
+        push(&mut self.discharge_mw, Some(s.discharge_mw()));
+        push(&mut self.load_mw, s.load_mw());
+        push(&mut self.vbat_mv, Some(s.vbat_mv as u32));
+        self.last_soc_pct = s.soc_pct;
+    }
+
+    /// Runtime at the average load (full pack, then from the last SOC), or
+    /// `None` without a load figure.
+    pub(crate) fn runtime(&self, capacity_mah: f64) -> Option<(Duration, Duration)> {
+        let load_mw = self.load_mw?.avg();
+        let vbat_mv = self.vbat_mv?.avg();
+        if load_mw < 1.0 || vbat_mv < 1.0 {
+            return None;
+        }
+        let pack_ma = load_mw * 1000.0 / vbat_mv;
+        let full = Duration::from_secs_f64(capacity_mah / pack_ma * 3600.0);
+        Some((full, full.mul_f64(self.last_soc_pct as f64 / 100.0)))
+    }
+
+    /// The report, one line per row; `capacity` is the pack size and where
+    /// it came from, e.g. `(2180.0, "measured")`.
+    pub(crate) fn report(&self, window: Duration, capacity: (f64, &str)) -> Vec<String> {
+        let watts = |label: &str, s: Option<Stat>, note: &str| match s {
+            Some(s) => format!(
+                "{label:<10} min {:>6.2} W   avg {:>6.2} W   max {:>6.2} W{note}",
+                s.min as f64 / 1000.0,
+                s.avg() / 1000.0,
+                s.max as f64 / 1000.0
+            ),
+            None => format!("{label:<10} n/a{note}"),
+        };
+        let mut out = vec![
+            format!(
+                "power budget over {} s ({} samples)",
+                window.as_secs(),
+                self.samples
+            ),
+            watts(
+                "input",
+                self.input_mw,
+                if self.input_mw.is_none() {
+                    "  (needs v2 firmware)"
+                } else {
+                    ""
+                },
+            ),
+            watts("battery", self.discharge_mw, "  (discharge)"),
+            watts("load", self.load_mw, ""),
+        ];
+        let (mah, source) = capacity;
+        out.push(match self.runtime(mah) {
+            Some((full, now)) => format!(
+                "{:<10} {} from a full pack, {} from {}% now, at the average load ({mah:.0} mAh {source})",
+                "runtime",
+                fmt_hm(full),
+                fmt_hm(now),
+                self.last_soc_pct
+            ),
+            None => format!("{:<10} n/a (no load figure in this window)", "runtime"),
+        });
+        out
+    }
+}
+
+/// "3h 05m"; under an hour, "42m".
+pub(crate) fn fmt_hm(d: Duration) -> String {
+    let m = d.as_secs() / 60;
+    match m / 60 {
+        0 => format!("{m}m"),
+        h => format!("{h}h {:02}m", m % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_comes_from_discharge_or_input_less_charging() {
+        let on_batt = BudgetSample {
+            input_mw: None,
+            vbat_mv: 7_500,
+            ibat_ma: -800,
+            soc_pct: 80,
+        };
+        assert_eq!(on_batt.discharge_mw(), 6_000);
+        assert_eq!(on_batt.load_mw(), Some(6_000));
+        let charging = BudgetSample {
+            input_mw: Some(10_000),
+            ibat_ma: 400,
+            ..on_batt
+        };
+        assert_eq!(charging.discharge_mw(), 0);
+        assert_eq!(charging.load_mw(), Some(7_000));
+        // v1 firmware on grid: no input power, so no load figure.
+        assert_eq!(
+            BudgetSample {
+                input_mw: None,
+                ..charging
+            }
+            .load_mw(),
+            None
+        );
+    }
+
+    #[test]
+    fn runtime_scales_capacity_by_average_load() {
+        let mut b = PowerBudget::default();
+        for ibat_ma in [-400, -600] {
+            b.push(BudgetSample {
+                input_mw: None,
+                vbat_mv: 7_500,
+                ibat_ma,
+                soc_pct: 50,
+            });
+        }
+        let load = b.load_mw.unwrap();
+        assert_eq!((load.min, load.max, load.avg()), (3_000, 4_500, 3_750.0));
+        // 3.75 W at 7.5 V is 500 mA: 2000 mAh lasts 4 h, half of it 2 h.
+        let (full, now) = b.runtime(2_000.0).unwrap();
+        assert_eq!(full, Duration::from_secs(4 * 3600));
+        assert_eq!(now, Duration::from_secs(2 * 3600));
+
+        let report = b.report(Duration::from_secs(60), (2_000.0, "measured"));
+        assert_eq!(report[0], "power budget over 60 s (2 samples)");
+        assert_eq!(report[1], "input      n/a  (needs v2 firmware)");
+        assert_eq!(
+            report[3],
+            "load       min   3.00 W   avg   3.75 W   max   4.50 W"
+        );
+        assert_eq!(
+            report[4],
+            "runtime    4h 00m from a full pack, 2h 00m from 50% now, at the average load \
+             (2000 mAh measured)"
+        );
+        assert_eq!(PowerBudget::default().runtime(2_000.0), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! How regularly power samples arrive: the inter-sample gaps, and how many
+//! samples went missing against `[serial].expected_interval_ms`. A widening
+//! gap is a degrading serial link (loose cable, noisy USB hub, overloaded
+//! firmware) showing before the link drops for good.
+//!
+//! A gap over twice the expected interval counts as missed samples, one per
+//! interval that fit into it beyond the first. Only real frames take part:
+//! the clock restarts on every (re)connect, so an outage isn't mistaken for
+//! lost samples.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Intervals kept for the "recent" figures: a couple of minutes at 1 Hz.
+const RECENT_INTERVALS: usize = 120;
+
+/// Fewer intervals than this say nothing about the miss rate yet.
+const MIN_INTERVALS_FOR_RATE: usize = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct SampleCadence {
+    /// `None`: gaps are tracked, nothing counts as missed.
+    expected: Option<Duration>,
+    /// Recent miss rate (%) at which the link counts as lossy. 0 disables.
+    warn_pct: u8,
+    last_at: Option<Instant>,
+    recent: VecDeque<Duration>,
+    /// Missed samples since the daemon started.
+    pub missed: u64,
+}
+
+impl SampleCadence {
+    pub fn configure(&mut self, expected: Option<Duration>, warn_pct: u8) {
+        self.expected = expected;
+        self.warn_pct = warn_pct;
+    }
+
+    pub fn warn_pct(&self) -> u8 {
+        self.warn_pct
+    }
+
+    /// Forget the last sample time; the next one starts a fresh interval.
+    pub fn restart(&mut self) {
+        self.last_at = None;
+    }
+
+    pub(crate) fn record(&mut self, now: Instant) {
+        if let Some(last) = self.last_at.replace(now) {
+            let gap = now.saturating_duration_since(last);
+            self.missed += self.missed_in(gap);
+            if self.recent.len() == RECENT_INTERVALS {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(gap);
+        }
+    }
+
+    fn missed_in(&self, gap: Duration) -> u64 {
+        match self.expected {
+            Some(e) if !e.is_zero() && gap > e * 2 => {
+                (gap.as_secs_f64() / e.as_secs_f64()).round() as u64 - 1
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn max_recent_gap(&self) -> Option<Duration> {
+        self.recent.iter().max().copied()
+    }
+
+    /// Missed samples as a share of those expected over the recent
+    /// intervals; `None` without an expected interval or enough history.
+    pub fn recent_miss_pct(&self) -> Option<f32> {
+        self.expected?;
+        if self.recent.len() < MIN_INTERVALS_FOR_RATE {
+            return None;
+        }
+        let missed: u64 = self.recent.iter().map(|&g| self.missed_in(g)).sum();
+        Some(missed as f32 * 100.0 / (missed + self.recent.len() as u64) as f32)
+    }
+
+    /// The recent miss rate is at or over `[serial].missed_warn_pct`.
+    pub fn lossy(&self) -> bool {
+        self.warn_pct > 0
+            && self
+                .recent_miss_pct()
+                .is_some_and(|p| p >= self.warn_pct as f32)
+    }
+
+    pub fn counts(&self) -> CadenceCounts {
+        CadenceCounts {
+            expected_ms: self.expected.map(|e| e.as_millis() as u64),
+            missed: self.missed,
+            max_recent_gap_ms: self.max_recent_gap().map(|g| g.as_millis() as u64),
+            recent_miss_pct: self.recent_miss_pct(),
+        }
+    }
+}
+
+/// What `info` reports about the sample cadence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CadenceCounts {
+    #[serde(default)]
+    pub expected_ms: Option<u64>,
+    #[serde(default)]
+    pub missed: u64,
+    #[serde(default)]
+    pub max_recent_gap_ms: Option<u64>,
+    #[serde(default)]
+    pub recent_miss_pct: Option<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fed(gaps_ms: &[u64]) -> SampleCadence {
+        let mut c = SampleCadence::default();
+        c.configure(Some(Duration::from_secs(1)), 5);
+        let mut t = Instant::now();
+        c.record(t);
+        for &g in gaps_ms {
+            t += Duration::from_millis(g);
+            c.record(t);
+        }
+        c
+    }
+
+    #[test]
+    fn gaps_over_twice_the_interval_count_as_missed() {
+        // 1.9 s is jitter; 3 s lost two samples, 5.4 s lost four.
+        let c = fed(&[1_000, 1_900, 3_000, 1_000, 5_400]);
+        assert_eq!(c.missed, 6);
+        assert_eq!(c.max_recent_gap(), Some(Duration::from_millis(5_400)));
+        // Too little history for a rate yet.
+        assert_eq!(c.recent_miss_pct(), None);
+        assert!(!c.lossy());
+    }
+
+    #[test]
+    fn a_lossy_link_crosses_the_warn_rate() {
+        let mut gaps = vec![1_000; 18];
+        gaps.extend([2_000, 2_000]);
+        // Doubled intervals are jitter, not loss.
+        assert_eq!(fed(&gaps).recent_miss_pct(), Some(0.0));
+        gaps.extend([3_000]);
+        // 2 missed / (2 + 21 intervals).
+        let c = fed(&gaps);
+        assert!((c.recent_miss_pct().unwrap() - 8.7).abs() < 0.1);
+        assert!(c.lossy());
+
+        let mut unset = c.clone();
+        unset.configure(None, 5);
+        assert_eq!(unset.recent_miss_pct(), None);
+        assert!(!unset.lossy());
+    }
+
+    #[test]
+    fn restart_skips_the_outage() {
+        let mut c = fed(&[1_000]);
+        c.restart();
+        c.record(Instant::now() + Duration::from_secs(60));
+        assert_eq!(c.missed, 0);
+        assert_eq!(c.recent.len(), 1);
+    }
+}
@@ -0,0 +1,549 @@
+//! Empirical capacity tracking: integrate battery current between "full"
+//! (`charge_state == CHARGED`) and the next low-battery point, and back, so
+//! the usable capacity of the pack — and how it fades with age — can be read
+//! off real outages rather than the datasheet.
+//!
+//! Finished spans are logged, kept in [`State`] for the IPC `info` op, and
+//! persisted to `[capacity].state_file` (via [`StateStore`]) so they survive
+//! restarts.
+//!
+//! The same file keeps running energy totals ([`EnergyTotals`]): what the
+//! charger supplied on grid and what the pack delivered on battery, for
+//! cost and usage figures over months rather than the instantaneous power
+//! `status` shows.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::config::{BatteryConfig, CapacityConfig, PersistConfig};
+use crate::proto::payloads::{charge_state, PowerStatusV1, PowerStatusV2};
+use crate::shutdown_sm::classify_input;
+use crate::state::State;
+use crate::store::StateStore;
+
+/// Samples further apart than this (serial outage, daemon stalled) are not
+/// integrated across; the span carries on from the next sample.
+pub(crate) const MAX_SAMPLE_GAP: Duration = Duration::from_secs(10);
+
+/// Completed spans kept in the state file, newest last.
+const HISTORY_LEN: usize = 50;
+
+/// The energy totals grow with every sample; they are only worth a write
+/// once one has moved this far (Wh).
+const ENERGY_WRITE_STEP_WH: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpanKind {
+    /// Full → low battery: charge delivered to the load.
+    Discharge,
+    /// Low battery → full: charge put back in.
+    Charge,
+}
+
+/// One measured full→empty or empty→full span.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapacitySpan {
+    pub kind: SpanKind,
+    pub started_unix_ms: u64,
+    pub ended_unix_ms: u64,
+    /// Net charge moved in the span's direction (mAh).
+    pub mah: u32,
+}
+
+impl CapacitySpan {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.ended_unix_ms.saturating_sub(self.started_unix_ms))
+    }
+}
+
+/// Energy counted since `since_unix_ms`; only ever grows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnergyTotals {
+    /// Drawn from the charger while on grid. Needs v2 firmware, which
+    /// reports the input current.
+    pub grid_wh: f64,
+    /// Delivered by the pack while on battery.
+    pub battery_wh: f64,
+    /// First sample counted; 0 = nothing yet.
+    pub since_unix_ms: u64,
+}
+
+/// What the state file holds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CapacityLog {
+    pub spans: Vec<CapacitySpan>,
+    /// Absent from files written before energy was counted: starts at 0.
+    #[serde(default)]
+    pub energy: EnergyTotals,
+}
+
+impl CapacityLog {
+    pub fn last(&self, kind: SpanKind) -> Option<&CapacitySpan> {
+        self.spans.iter().rev().find(|s| s.kind == kind)
+    }
+
+    fn push(&mut self, span: CapacitySpan) {
+        self.spans.push(span);
+        let excess = self.spans.len().saturating_sub(HISTORY_LEN);
+        self.spans.drain(..excess);
+    }
+}
+
+impl EnergyTotals {
+    /// Whether these totals differ enough from `older` to be written.
+    fn moved_from(&self, older: &EnergyTotals) -> bool {
+        self.since_unix_ms != older.since_unix_ms
+            || self.grid_wh - older.grid_wh >= ENERGY_WRITE_STEP_WH
+            || self.battery_wh - older.battery_wh >= ENERGY_WRITE_STEP_WH
+    }
+}
+
+/// The capacity state file: the log goes to the store only when a span
+/// completed or the energy moved by [`ENERGY_WRITE_STEP_WH`], so small
+/// counting steps don't rewrite the file every interval.
+struct LogStore {
+    store: StateStore<CapacityLog>,
+    /// The last log handed to `store`.
+    handed: CapacityLog,
+}
+
+impl LogStore {
+    fn new(store: StateStore<CapacityLog>, loaded: &CapacityLog) -> Self {
+        Self {
+            store,
+            handed: loaded.clone(),
+        }
+    }
+
+    fn update(&mut self, log: &CapacityLog, now: Instant) -> anyhow::Result<bool> {
+        if log.spans == self.handed.spans && !log.energy.moved_from(&self.handed.energy) {
+            return Ok(false);
+        }
+        self.handed = log.clone();
+        self.store.update(log, now)
+    }
+
+    fn tick(&mut self, now: Instant) -> anyhow::Result<bool> {
+        self.store.tick(now)
+    }
+
+    /// On stop: the exact totals, however little they moved.
+    fn flush(&mut self, log: &CapacityLog, now: Instant) -> anyhow::Result<bool> {
+        let written = self.store.update(log, now)?;
+        Ok(self.store.flush()? || written)
+    }
+}
+
+#[derive(Debug)]
+struct Open {
+    kind: SpanKind,
+    started_unix_ms: u64,
+    /// Signed, in the span's direction.
+    mah: f64,
+}
+
+/// Trapezoidal integral of a sampled quantity, in its unit × hours (mA →
+/// mAh, mW → mWh). Steps longer than [`MAX_SAMPLE_GAP`] add nothing.
+#[derive(Debug, Default)]
+pub(crate) struct Integrator {
+    last: Option<(Instant, f64)>,
+}
+
+impl Integrator {
+    /// The amount since the previous sample. `None`: the quantity doesn't
+    /// apply right now; counting starts over from the next sample.
+    pub(crate) fn push(&mut self, value: Option<f64>, at: Instant) -> f64 {
+        let Some(value) = value else {
+            self.last = None;
+            return 0.0;
+        };
+        match self.last.replace((at, value)) {
+            Some((prev_at, prev)) => {
+                let dt = at.saturating_duration_since(prev_at);
+                if dt > MAX_SAMPLE_GAP {
+                    return 0.0;
+                }
+                (prev + value) / 2.0 * dt.as_secs_f64() / 3600.0
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Current integrator. Feed it every sample; it returns a span when one
+/// completes.
+#[derive(Debug, Default)]
+pub struct CapacityTracker {
+    open: Option<Open>,
+    current: Integrator,
+}
+
+impl CapacityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `low`: on battery below the shutdown threshold. `at` is monotonic
+    /// (integration), `unix_ms` wall-clock (reporting).
+    pub fn push(
+        &mut self,
+        p: &PowerStatusV1,
+        low: bool,
+        at: Instant,
+        unix_ms: u64,
+    ) -> Option<CapacitySpan> {
+        let mah = self.current.push(Some(p.ibat_ma as f64), at);
+        if let Some(open) = self.open.as_mut() {
+            open.mah += match open.kind {
+                SpanKind::Discharge => -mah,
+                SpanKind::Charge => mah,
+            };
+        }
+
+        let full = p.charge_state == charge_state::CHARGED;
+        let restart = |kind| Open {
+            kind,
+            started_unix_ms: unix_ms,
+            mah: 0.0,
+        };
+        match self.open.as_ref().map(|o| o.kind) {
+            // Still full (or just got there): the discharge span starts at
+            // the last full sample.
+            None | Some(SpanKind::Discharge) if full => {
+                self.open = Some(restart(SpanKind::Discharge));
+                None
+            }
+            Some(SpanKind::Discharge) if low => self.finish(restart(SpanKind::Charge), unix_ms),
+            Some(SpanKind::Charge) if full => self.finish(restart(SpanKind::Discharge), unix_ms),
+            // Start measuring a charge from the first low point we see.
+            None if low => {
+                self.open = Some(restart(SpanKind::Charge));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn finish(&mut self, next: Open, unix_ms: u64) -> Option<CapacitySpan> {
+        let done = self.open.replace(next)?;
+        Some(CapacitySpan {
+            kind: done.kind,
+            started_unix_ms: done.started_unix_ms,
+            ended_unix_ms: unix_ms,
+            mah: done.mah.max(0.0).round() as u32,
+        })
+    }
+}
+
+/// Adds each sample's energy to [`EnergyTotals`]: input power (`vbus_in ×
+/// iin`) on grid, pack discharge power on battery. Grid and battery are
+/// integrated separately, so a transition starts each one afresh.
+#[derive(Debug, Default)]
+pub(crate) struct EnergyMeter {
+    grid: Integrator,
+    battery: Integrator,
+}
+
+impl EnergyMeter {
+    pub(crate) fn push(
+        &mut self,
+        p: &PowerStatusV1,
+        v2: Option<&PowerStatusV2>,
+        on_battery: bool,
+        at: Instant,
+        unix_ms: u64,
+        totals: &mut EnergyTotals,
+    ) {
+        let grid_mw = v2
+            .filter(|_| !on_battery)
+            .map(|v2| v2.vbus_in_mv as f64 * v2.iin_ma as f64 / 1000.0);
+        let battery_mw =
+            on_battery.then(|| p.vbat_mv as f64 * (-(p.ibat_ma as f64)).max(0.0) / 1000.0);
+        totals.grid_wh += self.grid.push(grid_mw, at) / 1000.0;
+        totals.battery_wh += self.battery.push(battery_mw, at) / 1000.0;
+        if totals.since_unix_ms == 0 && (grid_mw.is_some() || battery_mw.is_some()) {
+            totals.since_unix_ms = unix_ms;
+        }
+    }
+}
+
+/// Follows [`State::sample_feed`] until `stop` fires; a write still held
+/// back by the interval is flushed then.
+pub async fn capacity_loop(
+    state: Arc<State>,
+    battery: BatteryConfig,
+    cfg: CapacityConfig,
+    persist: PersistConfig,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let interval = Duration::from_secs(persist.min_write_interval_seconds);
+    let (store, mut log) = match StateStore::<CapacityLog>::open(&cfg.state_file, interval) {
+        Ok((store, log)) => (store, log.unwrap_or_default()),
+        Err(e) => {
+            warn!("capacity log unreadable, starting fresh: {e:#}");
+            let (store, _) = StateStore::open("", interval).expect("memory-only store");
+            (store, CapacityLog::default())
+        }
+    };
+    let mut store = LogStore::new(store, &log);
+    state.set_capacity(log.clone()).await;
+    info!(state_file = %cfg.state_file, spans = log.spans.len(), "capacity tracking running");
+
+    let mut tracker = CapacityTracker::new();
+    let mut meter = EnergyMeter::default();
+    let mut feed = state.sample_feed("capacity tracker");
+    loop {
+        let update = tokio::select! {
+            p = feed.next_real_sample() => p,
+            _ = &mut stop => {
+                if let Err(e) = store.flush(&log, state.now()) {
+                    warn!("could not persist capacity log: {e:#}");
+                }
+                return;
+            }
+        };
+        let Some(p) = update else { return };
+        if let Err(e) = store.tick(state.now()) {
+            warn!("could not persist capacity log: {e:#}");
+        }
+        let snap = state.snapshot().await;
+        let on_battery = classify_input(
+            &p,
+            snap.last_power_v2.as_ref(),
+            battery.input_min_valid_mv,
+            battery.input_max_valid_mv,
+            battery.input_zero_cross_check,
+        )
+        .on_battery();
+        let low = on_battery && battery.soc_pct(p.vbat_mv) < battery.shutdown_threshold_pct;
+        let (now, unix_ms) = (state.now(), unix_now_ms());
+        meter.push(
+            &p,
+            snap.last_power_v2.as_ref(),
+            on_battery,
+            now,
+            unix_ms,
+            &mut log.energy,
+        );
+        if let Some(span) = tracker.push(&p, low, now, unix_ms) {
+            let d = span.duration().as_secs();
+            info!(
+                mah = span.mah,
+                duration_s = d,
+                "capacity: {} delivered ~{} mAh over {}h{:02}m",
+                match span.kind {
+                    SpanKind::Discharge => "full→empty discharge",
+                    SpanKind::Charge => "empty→full charge",
+                },
+                span.mah,
+                d / 3600,
+                d % 3600 / 60
+            );
+            log.push(span);
+        }
+        if let Err(e) = store.update(&log, now) {
+            warn!("could not persist capacity log: {e:#}");
+        }
+        state.set_capacity(log.clone()).await;
+    }
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    fn sample(charge_state: u8, ibat_ma: i16) -> PowerStatusV1 {
+        PowerStatusV1 {
+            charge_state,
+            ibat_ma,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn integrates_full_to_empty_and_back() {
+        let t0 = Instant::now();
+        let s = Duration::from_secs;
+        let mut t = CapacityTracker::new();
+        let at = |secs: u64| (t0 + s(secs), secs * 1000);
+
+        // Full, then two hours at 2 A on battery, sampled every 5 s.
+        let (i, ms) = at(0);
+        assert_eq!(
+            t.push(&sample(charge_state::CHARGED, 0), false, i, ms),
+            None
+        );
+        for secs in (5..7200).step_by(5) {
+            let (i, ms) = at(secs);
+            assert_eq!(
+                t.push(&sample(charge_state::IDLE, -2000), false, i, ms),
+                None
+            );
+        }
+        let (i, ms) = at(7200);
+        let span = t
+            .push(&sample(charge_state::IDLE, -2000), true, i, ms)
+            .expect("discharge span");
+        assert_eq!(span.kind, SpanKind::Discharge);
+        // First 5 s ramps from 0 A: 4000 mAh minus half a 5 s step.
+        assert_eq!(span.mah, 3999);
+        assert_eq!(span.duration(), s(7200));
+
+        // Back on grid: one hour at 1 A until full.
+        for secs in (7205..10800).step_by(5) {
+            let (i, ms) = at(secs);
+            assert_eq!(
+                t.push(&sample(charge_state::CHARGING, 1000), false, i, ms),
+                None
+            );
+        }
+        let (i, ms) = at(10800);
+        let span = t
+            .push(&sample(charge_state::CHARGED, 1000), false, i, ms)
+            .expect("charge span");
+        assert_eq!(span.kind, SpanKind::Charge);
+        // The first 5 s step still averages in the -2 A discharge sample.
+        assert_eq!(span.mah, 998);
+    }
+
+    #[test]
+    fn gaps_are_not_integrated() {
+        let t0 = Instant::now();
+        let mut t = CapacityTracker::new();
+        t.push(&sample(charge_state::CHARGED, 0), false, t0, 0);
+        t.push(&sample(charge_state::IDLE, -3600), false, t0, 0);
+        // A 10-minute hole in the data (serial outage) adds nothing.
+        let later = t0 + Duration::from_secs(600);
+        let span = t
+            .push(&sample(charge_state::IDLE, -3600), true, later, 600_000)
+            .unwrap();
+        assert_eq!(span.mah, 0);
+    }
+
+    #[test]
+    fn energy_is_counted_per_source() {
+        let t0 = Instant::now();
+        let mut meter = EnergyMeter::default();
+        let mut totals = EnergyTotals::default();
+        let v1 = PowerStatusV1 {
+            vbat_mv: 7_400,
+            ibat_ma: 500,
+            ..Default::default()
+        };
+        let v2 = PowerStatusV2 {
+            vbus_in_mv: 20_000,
+            iin_ma: 500,
+            ..Default::default()
+        };
+        // Half an hour on grid at 10 W, then an hour at 7.4 W on battery.
+        for secs in 0..=1800 {
+            let at = t0 + Duration::from_secs(secs);
+            meter.push(&v1, Some(&v2), false, at, 1_000, &mut totals);
+        }
+        let on_battery = PowerStatusV1 {
+            ibat_ma: -1_000,
+            ..v1
+        };
+        for secs in 1801..=5401 {
+            let at = t0 + Duration::from_secs(secs);
+            meter.push(&on_battery, Some(&v2), true, at, 2_000, &mut totals);
+        }
+        assert!((totals.grid_wh - 5.0).abs() < 1e-9, "{}", totals.grid_wh);
+        assert!(
+            (totals.battery_wh - 7.4).abs() < 1e-9,
+            "{}",
+            totals.battery_wh
+        );
+        assert_eq!(totals.since_unix_ms, 1_000);
+
+        // v1 firmware reports no input current: grid time adds nothing.
+        let before = totals;
+        meter.push(
+            &v1,
+            None,
+            false,
+            t0 + Duration::from_secs(5402),
+            3_000,
+            &mut totals,
+        );
+        meter.push(
+            &v1,
+            None,
+            false,
+            t0 + Duration::from_secs(5403),
+            3_000,
+            &mut totals,
+        );
+        assert_eq!(totals, before);
+    }
+
+    #[test]
+    fn log_keeps_recent_history() {
+        let mut log = CapacityLog::default();
+        for i in 0..(HISTORY_LEN as u64 + 5) {
+            log.push(CapacitySpan {
+                kind: if i % 2 == 0 {
+                    SpanKind::Discharge
+                } else {
+                    SpanKind::Charge
+                },
+                started_unix_ms: i,
+                ended_unix_ms: i + 1,
+                mah: i as u32,
+            });
+        }
+        assert_eq!(log.spans.len(), HISTORY_LEN);
+        assert_eq!(
+            log.last(SpanKind::Charge).unwrap().mah,
+            HISTORY_LEN as u32 + 3
+        );
+    }
+
+    #[test]
+    fn small_energy_steps_are_not_written() {
+        let dir = Scratch::new("capacity");
+        let path = dir.join("capacity.json");
+        let (store, _) = StateStore::open(&path.to_string_lossy(), Duration::ZERO).unwrap();
+        let mut log = CapacityLog::default();
+        let mut store = LogStore::new(store, &log);
+        let now = Instant::now();
+        let mut energy = |log: &mut CapacityLog, wh: f64| {
+            log.energy.battery_wh += wh;
+            log.energy.since_unix_ms = 1;
+            store.update(log, now).unwrap()
+        };
+
+        // Counting starts: written. Then not until a total is 1 Wh on.
+        assert!(energy(&mut log, 0.1));
+        assert!(!energy(&mut log, 0.4));
+        assert!(!energy(&mut log, 0.4));
+        assert!(energy(&mut log, 0.3));
+        assert!(!energy(&mut log, 0.2));
+        // A finished span goes out at once, and stop writes the rest.
+        log.push(CapacitySpan {
+            kind: SpanKind::Charge,
+            started_unix_ms: 0,
+            ended_unix_ms: 1,
+            mah: 1,
+        });
+        assert!(store.update(&log, now).unwrap());
+        log.energy.battery_wh += 0.1;
+        assert!(!store.update(&log, now).unwrap());
+        assert!(store.flush(&log, now).unwrap());
+        let on_disk: CapacityLog = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(on_disk, log);
+    }
+}
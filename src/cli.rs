@@ -1,28 +1,94 @@
 //! `w3p-ups status` and `w3p-ups watch` — connect to the daemon's IPC socket
-//! and print human-readable snapshots.
+//! and print human-readable snapshots. `w3p-ups info` shows the version and
+//! measured battery capacity. `w3p-ups ctl stop|reload` ask it to
+//! exit or re-read its config.
+//!
+//! On a terminal, `watch` also reads single key presses: `d` saves the
+//! daemon's full current snapshot as pretty JSON (see [`dump_snapshot`]).
+
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::UnixStream;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::mpsc;
 
-use crate::config::IpcConfig;
+use crate::budget::{BudgetSample, PowerBudget};
+use crate::cadence::CadenceCounts;
+use crate::capacity::{CapacitySpan, EnergyTotals};
+use crate::config::{IpcConfig, IpcEncoding, MonitorConfig};
+use crate::health::HealthReport;
+use crate::histogram::InputHistogram;
+use crate::ipc::{FRAME_LAYOUT, FRAME_MARK};
+use crate::packed::take;
+use crate::proto::payloads::{power2_flag, power_fault};
+use crate::replay::InjectMsg;
+use crate::transport::kv::KvRejectCounts;
 
 #[derive(Serialize)]
 #[serde(tag = "op", rename_all = "snake_case")]
 enum Request {
     Snapshot,
-    Subscribe,
+    Subscribe { encoding: IpcEncoding },
+    Info,
+    Histogram,
+    Auth { token: String },
+    Inject(InjectMsg),
+    Nut,
+    Stop,
+    Reload,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "snake_case")]
 enum Reply {
-    Snapshot(SnapshotMsg),
+    Snapshot(Box<SnapshotMsg>),
     Version { version: String },
+    Info(Box<InfoMsg>),
+    Histogram { histogram: InputHistogram },
+    Nut { vars: BTreeMap<String, String> },
+    Authenticated,
+    Injected {},
+    Stopping,
+    Reloaded { warnings: Vec<String> },
     Error { message: String },
 }
 
+#[derive(Deserialize, Debug)]
+struct InfoMsg {
+    version: String,
+    last_discharge: Option<CapacitySpan>,
+    last_charge: Option<CapacitySpan>,
+    #[serde(default)]
+    kv_rejects: KvRejectCounts,
+    #[serde(default)]
+    cadence: CadenceCounts,
+    /// `None` from a daemon that doesn't count energy.
+    #[serde(default)]
+    energy: Option<EnergyTotals>,
+    #[serde(default)]
+    health: Option<HealthReport>,
+    #[serde(default)]
+    port: Option<String>,
+    /// These are `None` from a daemon too old to send them.
+    #[serde(default)]
+    uptime_s: Option<u64>,
+    #[serde(default)]
+    clients: Option<usize>,
+    #[serde(default)]
+    subscribers: Option<usize>,
+    #[serde(default)]
+    sample_age_ms: Option<u64>,
+    #[serde(default)]
+    outages: Option<u64>,
+    #[serde(default)]
+    in_outage: Option<bool>,
+}
+
 #[derive(Deserialize, Debug)]
 struct SnapshotMsg {
     unix_ts_ms: u64,
@@ -31,6 +97,24 @@ struct SnapshotMsg {
     host: Option<HostSnap>,
     last_power_event: Option<u8>,
     shutdown_pending_for_s: Option<u64>,
+    #[serde(default)]
+    charging_fault: bool,
+    #[serde(default)]
+    pd_overload: bool,
+    #[serde(default)]
+    fault_summary: Option<String>,
+    #[serde(default)]
+    degraded: bool,
+    #[serde(default)]
+    stale: bool,
+    #[serde(default)]
+    missed_samples: u64,
+    #[serde(default)]
+    max_recent_gap_ms: Option<u64>,
+    #[serde(default)]
+    synthetic: bool,
+    #[serde(default)]
+    dry_run: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -46,9 +130,57 @@ struct PowerSnap {
     on_battery: bool,
     temp_dc: i16,
     faults: u16,
-    // pd_contract_mv / pd_contract_ma are present in the IPC JSON for
-    // diagnostics but not surfaced in this CLI — values reported by CH32X
+    #[serde(default)]
+    pd_in_contract: Option<String>,
+    #[serde(default)]
+    pd_in_mv: Option<u16>,
+    #[serde(default)]
+    pd_load_pct: Option<u32>,
+    #[serde(default)]
+    input_mw: Option<u32>,
+    #[serde(default)]
+    nominal_input_mv: Option<u16>,
+    #[serde(default)]
+    input_deviation_pct: Option<f32>,
+    #[serde(default)]
+    ups_uptime_s: Option<u32>,
+    #[serde(default)]
+    power_flags: Option<u8>,
+    #[serde(default)]
+    maintaining: bool,
+    #[serde(default)]
+    health_score: Option<u8>,
+    #[serde(default)]
+    rail_undervoltage: bool,
+    // Shown only with `[monitor].show_source`: values reported by CH32X
     // are currently misleading (track CH32X firmware fix).
+    #[serde(default)]
+    pd_contract_mv: u16,
+    #[serde(default)]
+    pd_contract_ma: u16,
+}
+
+impl PowerSnap {
+    /// What the source side of the supply reports (`VS`), as opposed to
+    /// `vbus_in_mv` measured at the UPS input; `None` if not reported.
+    fn source_voltage_v(&self) -> Option<f32> {
+        (self.pd_contract_mv > 0).then(|| self.pd_contract_mv as f32 / 1000.0)
+    }
+
+    /// The source-side current (`IS`); `None` if not reported.
+    fn source_current_a(&self) -> Option<f32> {
+        (self.pd_contract_ma > 0).then(|| self.pd_contract_ma as f32 / 1000.0)
+    }
+
+    /// The rail the UPS feeds the Pi (`VOUT`); what brownout is judged on.
+    fn rail_voltage_v(&self) -> f32 {
+        self.vbus_out_mv as f32 / 1000.0
+    }
+
+    /// The current drawn from that rail (`IOUT`), signed as reported.
+    fn rail_current_a(&self) -> f32 {
+        self.ibus_out_ma as f32 / 1000.0
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,49 +210,694 @@ struct HostSnap {
     eth_client_state: u8,
 }
 
-pub async fn run_status(ipc: &IpcConfig) -> Result<()> {
-    let mut stream = connect(ipc).await?;
+pub async fn run_status(ep: &Endpoint, monitor: &MonitorConfig) -> Result<()> {
+    let mut stream = connect(ep).await?;
     write_request(&mut stream, &Request::Snapshot).await?;
-    let (rd, _wr) = stream.split();
+    let (rd, _wr) = tokio::io::split(stream);
     let mut lines = BufReader::new(rd).lines();
     if let Some(line) = lines.next_line().await? {
-        print_reply(&line, /* refresh = */ false)?;
+        let view = View {
+            colors: None,
+            show_source: monitor.show_source,
+        };
+        print_reply(&line, None, view)?;
     }
     Ok(())
 }
 
-pub async fn run_watch(ipc: &IpcConfig) -> Result<()> {
-    let mut stream = connect(ipc).await?;
-    write_request(&mut stream, &Request::Subscribe).await?;
-    let (rd, _wr) = stream.split();
-    let mut lines = BufReader::new(rd).lines();
+/// `watch`: redraw the live snapshot until Ctrl-C or the daemon goes away.
+/// `monitor` is `[monitor]`: colors, refresh period, the source row, and with
+/// `print_on_exit` an [`exit_line`] left behind.
+pub async fn run_watch(
+    ep: &Endpoint,
+    monitor: &MonitorConfig,
+    encoding: IpcEncoding,
+) -> Result<()> {
+    // Scale the SOC estimate by the last measured discharge if there is one.
+    let capacity_mah = control(ep, &Request::Info)
+        .await
+        .ok()
+        .and_then(|reply| measured_capacity_mah(&reply))
+        .unwrap_or(NOMINAL_CAPACITY_MAH);
+    let mut est = SocEstimate::new(capacity_mah);
+    let view = View {
+        colors: (monitor.color
+            && std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none())
+        .then_some(monitor),
+        show_source: monitor.show_source,
+    };
+    let mut sub = Subscription::open(ep, encoding).await?;
+    clear_screen();
+    println!("Web3 Pi UPS");
+    println!();
+    println!("Waiting for data from daemon…");
+    let _ = std::io::stdout().flush();
+    let (key_tx, mut keys) = mpsc::unbounded_channel();
+    // Restores the terminal when `watch` ends, however it ends.
+    let raw = RawTerminal::enable();
+    if raw.is_some() {
+        spawn_key_reader(key_tx);
+    }
+    let mut notice: Option<(String, Instant)> = None;
+    let mut last = None;
+    // Snapshots arriving faster than `refresh_rate_hz` wait here; the
+    // newest one is drawn once the period is up.
+    let mut pending = None;
+    let mut next_draw = tokio::time::Instant::now();
+    // The daemon sends a snapshot a second; none for `STALL_AFTER` means
+    // it has stopped answering, though the connection is still open.
+    let mut stall_at = tokio::time::Instant::now() + STALL_AFTER;
+    let mut stalled_since: Option<Instant> = None;
     loop {
         tokio::select! {
-            res = lines.next_line() => match res? {
-                Some(line) => print_reply(&line, /* refresh = */ true)?,
-                None => break,
+            res = sub.next() => match res? {
+                Some(Reply::Snapshot(s)) => {
+                    last = exit_line(&s).or(last);
+                    pending = Some(s);
+                    stall_at = tokio::time::Instant::now() + STALL_AFTER;
+                    stalled_since = None;
+                }
+                Some(other) => show_reply(other, None, View::default()),
+                None => {
+                    println!();
+                    println!("Connection to the daemon lost.");
+                    break;
+                }
             },
+            _ = tokio::time::sleep_until(stall_at) => {
+                let first = stalled_since.is_none();
+                let since = *stalled_since.get_or_insert_with(|| Instant::now() - STALL_AFTER);
+                let msg = format!(
+                    "No update from the daemon for {} s; waiting for data…",
+                    since.elapsed().as_secs()
+                );
+                // On a terminal the line counts up in place; piped, it is
+                // written once per stall.
+                if std::io::stdout().is_terminal() {
+                    print!("\r\x1b[K{msg}");
+                } else if first {
+                    println!("{msg}");
+                }
+                let _ = std::io::stdout().flush();
+                stall_at = tokio::time::Instant::now() + Duration::from_secs(1);
+            }
+            _ = tokio::time::sleep_until(next_draw), if pending.is_some() => {}
+            Some(key) = keys.recv(), if raw.is_some() => {
+                if key.eq_ignore_ascii_case(&DUMP_KEY) {
+                    let msg = match dump_snapshot(ep, &std::env::temp_dir()).await {
+                        Ok(path) => format!("dumped to {}", path.display()),
+                        Err(e) => format!("dump failed: {e:#}"),
+                    };
+                    println!("{msg}");
+                    let _ = std::io::stdout().flush();
+                    notice = Some((msg, Instant::now()));
+                }
+            }
             _ = tokio::signal::ctrl_c() => {
                 println!();
                 break;
             }
         }
+        let now = tokio::time::Instant::now();
+        if now >= next_draw {
+            if let Some(s) = pending.take() {
+                show_reply(Reply::Snapshot(s), Some(&mut est), view);
+                // Keep the confirmation under the redrawn block a while.
+                if let Some((msg, _)) = notice.as_ref().filter(|(_, at)| at.elapsed() < NOTICE_FOR)
+                {
+                    println!("{msg}");
+                    let _ = std::io::stdout().flush();
+                }
+                next_draw = now + monitor.refresh_period();
+            }
+        }
+    }
+    if monitor.print_on_exit {
+        if let Some(line) = last {
+            println!("{line}");
+        }
     }
     Ok(())
 }
 
-async fn connect(ipc: &IpcConfig) -> Result<UnixStream> {
-    UnixStream::connect(&ipc.socket_path)
-        .await
-        .with_context(|| {
-            format!(
-                "connect IPC socket {} (is the daemon running?)",
-                ipc.socket_path
-            )
+/// `watch` key that saves the current snapshot.
+const DUMP_KEY: u8 = b'd';
+/// How long a key's confirmation stays under the redrawn snapshot.
+const NOTICE_FOR: Duration = Duration::from_secs(5);
+/// Silence from the daemon after which `watch` says it is waiting.
+const STALL_AFTER: Duration = Duration::from_secs(3);
+
+/// Fetch a fresh snapshot and save it in `dir`, pretty-printed with every
+/// field the daemon sends (raw readings and derived values alike), for a
+/// bug report. Returns the file written.
+async fn dump_snapshot(ep: &Endpoint, dir: &Path) -> Result<PathBuf> {
+    let line = raw_reply(ep, &Request::Snapshot).await?;
+    let snapshot: serde_json::Value = serde_json::from_str(&line).context("parse snapshot")?;
+    let unix_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let path = dir.join(format!("w3p-ups-snapshot-{unix_ms}.json"));
+    let mut text = serde_json::to_string_pretty(&snapshot)?;
+    text.push('\n');
+    std::fs::write(&path, text).with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Stdin without line buffering or echo while this lives, so `watch` sees
+/// single key presses. Ctrl-C still interrupts.
+struct RawTerminal {
+    saved: libc::termios,
+}
+
+impl RawTerminal {
+    /// `None` when stdin isn't a terminal (or won't switch).
+    fn enable() -> Option<Self> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        // SAFETY: tcgetattr/tcsetattr on stdin with a termios we own; an
+        // all-zero termios is valid and gets overwritten.
+        unsafe {
+            let mut t: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut t) != 0 {
+                return None;
+            }
+            let saved = t;
+            t.c_lflag &= !(libc::ICANON | libc::ECHO);
+            t.c_cc[libc::VMIN] = 1;
+            t.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &t) != 0 {
+                return None;
+            }
+            Some(Self { saved })
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `enable`.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Blocking stdin reads on their own thread: tokio's stdin would hold up
+/// the runtime's shutdown while a read is pending.
+fn spawn_key_reader(keys: mpsc::UnboundedSender<u8>) {
+    std::thread::spawn(move || {
+        use std::io::Read;
+        let mut stdin = std::io::stdin().lock();
+        let mut key = [0u8; 1];
+        while matches!(stdin.read(&mut key), Ok(1)) && keys.send(key[0]).is_ok() {}
+    });
+}
+
+/// The last reading as one line for the scrollback once `watch` ends, e.g.
+/// `last reading 2026-10-14 08:00:00 UTC: GRID VI=19.80V VBAT=7.40V …`.
+fn exit_line(s: &SnapshotMsg) -> Option<String> {
+    let p = s.power.as_ref()?;
+    let mut line = format!(
+        "last reading {}: {} VI={}V VBAT={}V IBAT={}mA SOC={}% chg={} T={:.1}°C",
+        format_clock_utc(s.unix_ts_ms),
+        if p.on_battery { "BATTERY" } else { "GRID" },
+        fmt_mv(p.vbus_in_mv as i32),
+        fmt_mv(p.vbat_mv as i32),
+        p.ibat_ma,
+        p.soc_pct,
+        charge_label(p.charge_state, p.maintaining),
+        p.temp_dc as f32 / 10.0,
+    );
+    if s.degraded || s.stale {
+        line.push_str(" (stale)");
+    } else if s.synthetic {
+        line.push_str(" (synthetic)");
+    }
+    Some(line)
+}
+
+/// `budget`: sample snapshots for `window` (or until Ctrl-C) and print the
+/// power budget and runtime estimate.
+pub async fn run_budget(
+    ep: &Endpoint,
+    window: std::time::Duration,
+    encoding: IpcEncoding,
+) -> Result<()> {
+    let capacity = match measured_capacity_mah(&control(ep, &Request::Info).await?) {
+        Some(mah) => (mah, "measured"),
+        None => (NOMINAL_CAPACITY_MAH, "rated"),
+    };
+    let mut sub = Subscription::open(ep, encoding).await?;
+    eprintln!(
+        "sampling for {} s (Ctrl-C to stop early)…",
+        window.as_secs()
+    );
+    let start = tokio::time::Instant::now();
+    let deadline = tokio::time::sleep(window);
+    tokio::pin!(deadline);
+    let mut budget = PowerBudget::default();
+    loop {
+        tokio::select! {
+            res = sub.next() => {
+                let Some(reply) = res? else {
+                    anyhow::bail!("daemon closed the connection");
+                };
+                match reply {
+                    // Skip the last-known reading of a dropped link.
+                    Reply::Snapshot(s) if !s.degraded => {
+                        if let Some(p) = &s.power {
+                            budget.push(BudgetSample {
+                                input_mw: p.input_mw,
+                                vbat_mv: p.vbat_mv,
+                                ibat_ma: p.ibat_ma,
+                                soc_pct: p.soc_pct,
+                            });
+                        }
+                    }
+                    Reply::Snapshot(_) => {}
+                    Reply::Stopping => anyhow::bail!("daemon stopping"),
+                    other => anyhow::bail!("unexpected reply: {other:?}"),
+                }
+            }
+            _ = &mut deadline => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    if budget.samples == 0 {
+        anyhow::bail!("no power samples in {} s", start.elapsed().as_secs());
+    }
+    for line in budget.report(start.elapsed(), capacity) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// `info`: daemon version, uptime, clients, sample age, outages and the
+/// last measured full↔empty capacity.
+/// `json`: `info --json`, one [`info_json`] object instead of the text.
+pub async fn run_info(ep: &Endpoint, json: bool) -> Result<()> {
+    let reply = control(ep, &Request::Info).await?;
+    if json {
+        println!("{}", info_json(reply)?);
+        return Ok(());
+    }
+    match reply {
+        Reply::Info(info) => {
+            let InfoMsg {
+                version,
+                last_discharge,
+                last_charge,
+                kv_rejects,
+                cadence,
+                energy,
+                health,
+                port,
+                uptime_s,
+                clients,
+                subscribers,
+                sample_age_ms,
+                outages,
+                in_outage,
+            } = *info;
+            match uptime_s {
+                Some(up) => println!("daemon:    w3p-ups v{version}, up {}", hm(up)),
+                None => println!("daemon:    w3p-ups v{version}"),
+            }
+            println!("port:      {}", port.as_deref().unwrap_or("not opened yet"));
+            if let (Some(clients), Some(subscribers)) = (clients, subscribers) {
+                println!("clients:   {clients} connected, {subscribers} subscribed");
+            }
+            if uptime_s.is_some() {
+                match sample_age_ms {
+                    Some(ms) => println!("sample:    last one {ms} ms ago"),
+                    None => println!("sample:    none received yet"),
+                }
+            }
+            if let Some(outages) = outages {
+                let now = if in_outage == Some(true) {
+                    ", on battery now"
+                } else {
+                    ""
+                };
+                println!("outages:   {outages} since start{now}");
+            }
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64);
+            println!("capacity:  {}", span_line(last_discharge.as_ref(), now_ms));
+            println!("recharge:  {}", span_line(last_charge.as_ref(), now_ms));
+            if let Some(energy) = energy {
+                println!("energy:    {}", energy_line(&energy));
+            }
+            println!(
+                "kv lines:  {} dropped missing a required key, {} malformed",
+                kv_rejects.missing, kv_rejects.malformed
+            );
+            println!("samples:   {}", cadence_line(&cadence));
+            if let Some(h) = health {
+                println!(
+                    "health:    {}/100 (input {}, charging {}, temperature {}, soc {}, faults {})",
+                    h.score, h.input, h.charging, h.temperature, h.soc, h.faults
+                );
+            }
+        }
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
+    Ok(())
+}
+
+/// The `info` reply without its `type` tag. Every key is always there, as
+/// `null` (or zeroed counts) when the daemon is too old to send it, so
+/// scripts can rely on the shape; keys are only ever added.
+fn info_json(reply: Reply) -> Result<serde_json::Value> {
+    let Reply::Info(info) = reply else {
+        anyhow::bail!("unexpected reply: {reply:?}");
+    };
+    let InfoMsg {
+        version,
+        last_discharge,
+        last_charge,
+        kv_rejects,
+        cadence,
+        energy,
+        health,
+        port,
+        uptime_s,
+        clients,
+        subscribers,
+        sample_age_ms,
+        outages,
+        in_outage,
+    } = *info;
+    Ok(serde_json::json!({
+        "version": version,
+        "last_discharge": last_discharge,
+        "last_charge": last_charge,
+        "kv_rejects": kv_rejects,
+        "cadence": cadence,
+        "energy": energy,
+        "health": health,
+        "port": port,
+        "uptime_s": uptime_s,
+        "clients": clients,
+        "subscribers": subscribers,
+        "sample_age_ms": sample_age_ms,
+        "outages": outages,
+        "in_outage": in_outage,
+    }))
+}
+
+/// The last measured discharge capacity in an `info` reply, if there is one.
+fn measured_capacity_mah(reply: &Reply) -> Option<f64> {
+    match reply {
+        Reply::Info(info) => info
+            .last_discharge
+            .as_ref()
+            .filter(|span| span.mah > 0)
+            .map(|span| span.mah as f64),
+        _ => None,
+    }
+}
+
+/// Hours and minutes, e.g. `3h07m`.
+fn hm(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+}
+
+fn cadence_line(c: &CadenceCounts) -> String {
+    let gap = c.max_recent_gap_ms.map_or_else(
+        || "no gap measured yet".into(),
+        |g| format!("largest recent gap {g} ms"),
+    );
+    match c.expected_ms {
+        Some(e) => {
+            let rate = c
+                .recent_miss_pct
+                .map(|p| format!(" ({p:.1}% recently)"))
+                .unwrap_or_default();
+            format!("{} missed at {e} ms expected{rate}, {gap}", c.missed)
+        }
+        None => format!("{gap} ([serial].expected_interval_ms unset)"),
+    }
+}
+
+fn span_line(span: Option<&CapacitySpan>, now_ms: u64) -> String {
+    let Some(span) = span else {
+        return "not measured yet".into();
+    };
+    format!(
+        "{} mAh over {}, measured {} ago",
+        span.mah,
+        hm(span.duration().as_secs()),
+        hm(now_ms.saturating_sub(span.ended_unix_ms) / 1000)
+    )
+}
+
+fn energy_line(e: &EnergyTotals) -> String {
+    if e.since_unix_ms == 0 {
+        return "nothing counted yet".into();
+    }
+    let wh = |wh: f64| match wh {
+        wh if wh >= 1000.0 => format!("{:.2} kWh", wh / 1000.0),
+        wh => format!("{wh:.1} Wh"),
+    };
+    format!(
+        "grid {}, battery {} since {}",
+        wh(e.grid_wh),
+        wh(e.battery_wh),
+        format_clock_utc(e.since_unix_ms)
+    )
+}
+
+/// `histogram`: the daemon's input-voltage histogram as a bar chart.
+pub async fn run_histogram(ep: &Endpoint) -> Result<()> {
+    match control(ep, &Request::Histogram).await? {
+        Reply::Histogram { histogram } => print!("{}", histogram_lines(&histogram)),
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
+    Ok(())
+}
+
+fn histogram_lines(h: &InputHistogram) -> String {
+    const BAR: u64 = 40;
+    let mean = match h.count {
+        0 => "n/a".into(),
+        n => format!("{} V", fmt_mv((h.sum_mv / n) as i32)),
+    };
+    let mut out = format!(
+        "input voltage since {} — {} samples, mean {mean}\n",
+        format_clock_utc(h.since_unix_ms),
+        h.count
+    );
+    let max = h.counts.iter().copied().max().unwrap_or(0).max(1);
+    for (i, &n) in h.counts.iter().enumerate() {
+        let label = match h.bounds_mv.get(i) {
+            Some(&b) => format!("<= {} V", fmt_mv(b as i32)),
+            None => format!(" > {} V", fmt_mv(*h.bounds_mv.last().unwrap_or(&0) as i32)),
+        };
+        let pct = if h.count == 0 {
+            0.0
+        } else {
+            n as f64 * 100.0 / h.count as f64
+        };
+        let bar = "#".repeat((n * BAR).div_ceil(max) as usize);
+        out.push_str(&format!("{label:>10}  {n:>8}  {pct:5.1}%  {bar}\n"));
+    }
+    out
+}
+
+/// `healthcheck`: succeed only if the daemon answers *and* UPS data is
+/// flowing, for orchestrators and monitoring probes.
+pub async fn run_healthcheck(ep: &Endpoint) -> Result<()> {
+    let Reply::Snapshot(s) = control(ep, &Request::Snapshot).await? else {
+        anyhow::bail!("unexpected reply to snapshot");
+    };
+    if let Some(problem) = health_problem(&s) {
+        anyhow::bail!("unhealthy: {problem}");
+    }
+    let age = s.power.as_ref().and_then(|p| p.age_ms).unwrap_or_default();
+    println!("healthy: last sample {age} ms ago");
+    Ok(())
+}
+
+fn health_problem(s: &SnapshotMsg) -> Option<String> {
+    let Some(p) = &s.power else {
+        return Some("no power sample from the UPS yet".into());
+    };
+    let age = p
+        .age_ms
+        .map(|m| format!("{m} ms ago"))
+        .unwrap_or_else(|| "at an unknown time".into());
+    if s.degraded {
+        Some(format!("serial link down, last sample {age}"))
+    } else if s.stale {
+        Some(format!(
+            "last sample {age}, past [ipc].max_sample_age_seconds"
+        ))
+    } else {
+        None
+    }
+}
+
+/// `nut`: print NUT variables like `upsc` does (`name: value`, sorted).
+pub async fn run_nut(ep: &Endpoint) -> Result<()> {
+    match control(ep, &Request::Nut).await? {
+        Reply::Nut { vars } => print!("{}", upsc_lines(&vars)),
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
+    Ok(())
+}
+
+fn upsc_lines(vars: &BTreeMap<String, String>) -> String {
+    vars.iter().map(|(k, v)| format!("{k}: {v}\n")).collect()
+}
+
+/// `ctl stop`: ask the daemon to exit cleanly.
+pub async fn run_stop(ep: &Endpoint) -> Result<()> {
+    match control(ep, &Request::Stop).await? {
+        Reply::Stopping => println!("daemon stopping"),
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
+    Ok(())
+}
+
+/// `ctl reload`: ask the daemon to re-read its config file.
+pub async fn run_reload(ep: &Endpoint) -> Result<()> {
+    match control(ep, &Request::Reload).await? {
+        Reply::Reloaded { warnings } => {
+            for w in &warnings {
+                eprintln!("warning: {w}");
+            }
+            println!("config reloaded");
+        }
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
+    Ok(())
+}
+
+async fn control(ep: &Endpoint, req: &Request) -> Result<Reply> {
+    match parse_reply(&raw_reply(ep, req).await?)? {
+        Reply::Error { message } => anyhow::bail!("daemon error: {message}"),
+        reply => Ok(reply),
+    }
+}
+
+/// The daemon's reply line to `req` on a connection of its own.
+async fn raw_reply(ep: &Endpoint, req: &Request) -> Result<String> {
+    let mut stream = connect(ep).await?;
+    write_request(&mut stream, req).await?;
+    let (rd, _wr) = tokio::io::split(stream);
+    BufReader::new(rd)
+        .lines()
+        .next_line()
+        .await?
+        .context("daemon closed the connection without replying")
+}
+
+/// One connection for a series of `inject` requests (`replay`).
+pub(crate) struct Injector {
+    lines: tokio::io::Lines<BufReader<tokio::io::ReadHalf<Box<dyn Conn>>>>,
+    wr: tokio::io::WriteHalf<Box<dyn Conn>>,
+}
+
+impl Injector {
+    pub(crate) async fn connect(ep: &Endpoint) -> Result<Self> {
+        let (rd, wr) = tokio::io::split(connect(ep).await?);
+        Ok(Self {
+            lines: BufReader::new(rd).lines(),
+            wr,
         })
+    }
+
+    pub(crate) async fn inject(&mut self, msg: InjectMsg) -> Result<()> {
+        write_request(&mut self.wr, &Request::Inject(msg)).await?;
+        let reply = self
+            .lines
+            .next_line()
+            .await?
+            .context("daemon closed the connection")?;
+        match parse_reply(&reply)? {
+            Reply::Injected {} => Ok(()),
+            Reply::Error { message } => anyhow::bail!("daemon error: {message}"),
+            other => anyhow::bail!("unexpected reply: {other:?}"),
+        }
+    }
+}
+
+/// Where the CLI reaches the daemon: its Unix socket, or the read-only TCP
+/// listener (`[ipc].tcp_listen`) with `--tcp host:port`, authenticating
+/// with `token` when the daemon has `[ipc].token` set.
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+    Unix(String),
+    Tcp { addr: String, token: Option<String> },
+}
+
+impl Endpoint {
+    /// `[ipc].socket_path`, or the fallback socket if only that one exists
+    /// (the daemon couldn't write the primary's directory).
+    pub fn unix(ipc: &IpcConfig) -> Self {
+        let primary = Path::new(&ipc.socket_path);
+        if !primary.exists()
+            && !ipc.fallback_socket_path.is_empty()
+            && Path::new(&ipc.fallback_socket_path).exists()
+        {
+            return Self::Unix(ipc.fallback_socket_path.clone());
+        }
+        Self::Unix(ipc.socket_path.clone())
+    }
+}
+
+trait Conn: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Conn for T {}
+
+async fn connect(ep: &Endpoint) -> Result<Box<dyn Conn>> {
+    match ep {
+        Endpoint::Unix(path) => match UnixStream::connect(path).await {
+            Ok(s) => Ok(Box::new(s)),
+            Err(e) => Err(e)
+                .with_context(|| format!("connect IPC socket {path} (is the daemon running?)")),
+        },
+        Endpoint::Tcp { addr, token } => {
+            let mut stream = TcpStream::connect(addr).await.with_context(|| {
+                format!("connect IPC tcp {addr} (is [ipc].tcp_listen set on the daemon?)")
+            })?;
+            if let Some(token) = token {
+                authenticate(&mut stream, token).await?;
+            }
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+async fn authenticate(stream: &mut TcpStream, token: &str) -> Result<()> {
+    write_request(
+        stream,
+        &Request::Auth {
+            token: token.into(),
+        },
+    )
+    .await?;
+    // Read byte-wise up to the newline so nothing after the reply is
+    // swallowed by a buffer we're about to drop.
+    let mut line = Vec::new();
+    loop {
+        let mut b = [0u8; 1];
+        if stream.read(&mut b).await? == 0 || b[0] == b'\n' {
+            break;
+        }
+        line.push(b[0]);
+    }
+    match parse_reply(&String::from_utf8_lossy(&line))? {
+        Reply::Authenticated => Ok(()),
+        Reply::Error { message } => anyhow::bail!("daemon error: {message}"),
+        other => anyhow::bail!("unexpected reply: {other:?}"),
+    }
 }
 
-async fn write_request(stream: &mut UnixStream, req: &Request) -> Result<()> {
+async fn write_request(stream: &mut (impl AsyncWrite + Unpin), req: &Request) -> Result<()> {
     let mut line = serde_json::to_string(req)?;
     line.push('\n');
     stream.write_all(line.as_bytes()).await?;
@@ -128,31 +905,303 @@ async fn write_request(stream: &mut UnixStream, req: &Request) -> Result<()> {
     Ok(())
 }
 
-fn print_reply(line: &str, refresh: bool) -> Result<()> {
-    let reply: Reply =
-        serde_json::from_str(line).with_context(|| format!("parse IPC reply: {line}"))?;
+fn parse_reply(line: &str) -> Result<Reply> {
+    serde_json::from_str(line).with_context(|| format!("parse IPC reply: {line}"))
+}
+
+/// A `subscribe` connection: snapshots come as JSON lines or, with
+/// [`IpcEncoding::Binary`], as binary frames; other replies are always JSON.
+struct Subscription {
+    rd: tokio::io::ReadHalf<Box<dyn Conn>>,
+    _wr: tokio::io::WriteHalf<Box<dyn Conn>>,
+    /// Bytes read but not yet handed out as a reply. Kept here rather than
+    /// in `next`'s locals, so a reply read in part survives a cancelled call.
+    buf: Vec<u8>,
+}
+
+impl Subscription {
+    async fn open(ep: &Endpoint, encoding: IpcEncoding) -> Result<Self> {
+        let mut stream = connect(ep).await?;
+        write_request(&mut stream, &Request::Subscribe { encoding }).await?;
+        let (rd, wr) = tokio::io::split(stream);
+        Ok(Self::new(rd, wr))
+    }
+
+    fn new(
+        rd: tokio::io::ReadHalf<Box<dyn Conn>>,
+        wr: tokio::io::WriteHalf<Box<dyn Conn>>,
+    ) -> Self {
+        Self {
+            rd,
+            _wr: wr,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The next reply; `None` once the daemon closes the connection.
+    /// Cancel-safe, so it can race timers and key presses in a `select!`.
+    async fn next(&mut self) -> Result<Option<Reply>> {
+        loop {
+            if let Some(reply) = self.take_reply()? {
+                return Ok(Some(reply));
+            }
+            let mut chunk = [0u8; 4096];
+            let n = self.rd.read(&mut chunk).await?;
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                anyhow::bail!("daemon closed the connection mid-reply");
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Splits one whole reply off the front of `buf`, if it has one.
+    fn take_reply(&mut self) -> Result<Option<Reply>> {
+        let Some(&first) = self.buf.first() else {
+            return Ok(None);
+        };
+        if first == FRAME_MARK {
+            let &[_, lo, hi, ..] = self.buf.as_slice() else {
+                return Ok(None);
+            };
+            let end = 3 + u16::from_le_bytes([lo, hi]) as usize;
+            if self.buf.len() < end {
+                return Ok(None);
+            }
+            let frame: Vec<u8> = self.buf.drain(..end).collect();
+            return unpack_snapshot(&frame[3..]).map(|s| Some(Reply::Snapshot(Box::new(s))));
+        }
+        let Some(newline) = self.buf.iter().position(|&b| b == b'\n') else {
+            return Ok(None);
+        };
+        let line: Vec<u8> = self.buf.drain(..=newline).collect();
+        let line = std::str::from_utf8(&line).context("IPC reply is not UTF-8")?;
+        parse_reply(line.trim_end()).map(Some)
+    }
+}
+
+fn unpack_power(buf: &mut &[u8]) -> Result<PowerSnap> {
+    let age_ms = take(buf)?;
+    let charge_state = take(buf)?;
+    let vbus_in_mv = take(buf)?;
+    let vbus_out_mv = take(buf)?;
+    let ibus_out_ma = take(buf)?;
+    let vbat_mv = take(buf)?;
+    let ibat_ma = take(buf)?;
+    let soc_pct = take(buf)?;
+    let on_battery = take(buf)?;
+    let temp_dc = take(buf)?;
+    let pd_contract_mv = take(buf)?;
+    let pd_contract_ma = take(buf)?;
+    let faults = take(buf)?;
+    let pd_in_contract = take(buf)?;
+    let pd_in_mv = take(buf)?;
+    let _pd_in_ma: Option<u16> = take(buf)?;
+    Ok(PowerSnap {
+        age_ms,
+        charge_state,
+        vbus_in_mv,
+        vbus_out_mv,
+        ibus_out_ma,
+        vbat_mv,
+        ibat_ma,
+        soc_pct,
+        on_battery,
+        temp_dc,
+        faults,
+        pd_in_contract,
+        pd_in_mv,
+        pd_load_pct: take(buf)?,
+        input_mw: take(buf)?,
+        nominal_input_mv: take(buf)?,
+        input_deviation_pct: take(buf)?,
+        ups_uptime_s: take(buf)?,
+        power_flags: take(buf)?,
+        maintaining: take(buf)?,
+        health_score: take(buf)?,
+        rail_undervoltage: take(buf)?,
+        pd_contract_mv,
+        pd_contract_ma,
+    })
+}
+
+/// Inverse of the daemon's `SnapshotMsg::pack`; fields this CLI doesn't
+/// show are read and dropped.
+fn unpack_snapshot(payload: &[u8]) -> Result<SnapshotMsg> {
+    let buf = &mut &payload[..];
+    let layout: u8 = take(buf)?;
+    anyhow::ensure!(
+        layout == FRAME_LAYOUT,
+        "binary snapshot layout {layout}, this CLI reads {FRAME_LAYOUT}; set [ipc].encoding = \"json\""
+    );
+    let unix_ts_ms = take(buf)?;
+    let flags: u8 = take(buf)?;
+    let flag = |bit: u8| flags & (1 << bit) != 0;
+    let last_power_event = take(buf)?;
+    let shutdown_pending_for_s = take(buf)?;
+    let fault_summary = take(buf)?;
+    let _last_update_age_ms: Option<u64> = take(buf)?;
+    let missed_samples = take(buf)?;
+    let max_recent_gap_ms = take(buf)?;
+    let power = match take(buf)? {
+        false => None,
+        true => Some(unpack_power(buf)?),
+    };
+    let net = match take(buf)? {
+        false => None,
+        true => Some(NetSnap {
+            age_ms: take(buf)?,
+            state: take(buf)?,
+            rssi_dbm: take(buf)?,
+            rsrp_dbm: take(buf)?,
+            rsrq_db: take(buf)?,
+            bytes_tx: take(buf)?,
+            bytes_rx: take(buf)?,
+        }),
+    };
+    let host = match take(buf)? {
+        false => None,
+        true => Some(HostSnap {
+            age_ms: take(buf)?,
+            cpu_temp_dc: take(buf)?,
+            cpu_usage_pct: take(buf)?,
+            load_avg_x100: take(buf)?,
+            mem_used_pct: take(buf)?,
+            disk_used_pct: take(buf)?,
+            uptime_s: take(buf)?,
+            net_bytes_rx_total: take(buf)?,
+            net_bytes_tx_total: take(buf)?,
+            net_rx_bytes_per_s: take(buf)?,
+            net_tx_bytes_per_s: take(buf)?,
+            eth_client_state: take(buf)?,
+        }),
+    };
+    Ok(SnapshotMsg {
+        unix_ts_ms,
+        power,
+        net,
+        host,
+        last_power_event,
+        shutdown_pending_for_s,
+        charging_fault: flag(0),
+        pd_overload: flag(1),
+        fault_summary,
+        degraded: flag(3),
+        stale: flag(4),
+        missed_samples,
+        max_recent_gap_ms,
+        synthetic: flag(5),
+        dry_run: flag(6),
+    })
+}
+
+/// `watch` passes its SOC estimator, which also means "redraw in place".
+fn print_reply(line: &str, watch: Option<&mut SocEstimate>, view: View) -> Result<()> {
+    show_reply(parse_reply(line)?, watch, view);
+    Ok(())
+}
+
+/// How `status` and `watch` draw a snapshot.
+#[derive(Debug, Clone, Copy, Default)]
+struct View<'a> {
+    /// `watch` on a terminal: the `[monitor]` thresholds to colour the
+    /// input voltage by.
+    colors: Option<&'a MonitorConfig>,
+    /// `[monitor].show_source`.
+    show_source: bool,
+}
+
+fn show_reply(reply: Reply, watch: Option<&mut SocEstimate>, view: View) {
     match reply {
         Reply::Snapshot(s) => {
-            if refresh {
-                // Clear screen + cursor home — for `watch` mode so each
-                // new snapshot replaces the previous block in place.
-                print!("\x1b[2J\x1b[H");
-            }
-            print_snapshot(&s);
+            let soc_est = watch.and_then(|est| {
+                clear_screen();
+                let p = s.power.as_ref()?;
+                // Stale or injected current says nothing about the pack now.
+                if s.degraded || s.synthetic {
+                    est.reset();
+                    return None;
+                }
+                Some(est.update(p.soc_pct, p.ibat_ma, Instant::now()))
+            });
+            print_snapshot(&s, soc_est, view);
         }
         Reply::Version { version } => println!("daemon version: {version}"),
+        Reply::Info(info) => println!("daemon version: {}", info.version),
+        Reply::Nut { vars } => print!("{}", upsc_lines(&vars)),
+        Reply::Histogram { histogram } => print!("{}", histogram_lines(&histogram)),
+        Reply::Stopping => println!("daemon stopping"),
+        Reply::Authenticated | Reply::Injected {} => {}
+        Reply::Reloaded { .. } => println!("config reloaded"),
         Reply::Error { message } => eprintln!("daemon error: {message}"),
     }
-    Ok(())
+    // Everything printed for this reply is out before `watch` goes back to
+    // waiting, pipe or not.
+    let _ = std::io::stdout().flush();
+}
+
+/// The 2S pack of CGR18650CH 2250 mAh cells the SOC table is built for.
+const NOMINAL_CAPACITY_MAH: f64 = 2250.0;
+
+/// Display-only SOC between whole-percent steps: coulomb-counts the battery
+/// current from the last change of the real (voltage-derived) SOC and snaps
+/// back to it whenever it moves. Never strays a whole point from the real
+/// value, and nothing but `watch` uses it — shutdown decisions always see
+/// the real SOC.
+#[derive(Debug)]
+struct SocEstimate {
+    capacity_mah: f64,
+    /// Real SOC the estimate hangs off, estimate, and when it was updated.
+    base: Option<(u8, f64, Instant)>,
+}
+
+impl SocEstimate {
+    fn new(capacity_mah: f64) -> Self {
+        Self {
+            capacity_mah,
+            base: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.base = None;
+    }
+
+    fn update(&mut self, soc_pct: u8, ibat_ma: i16, now: Instant) -> f64 {
+        let est = match self.base {
+            Some((real, est, at)) if real == soc_pct => {
+                let hours = now.saturating_duration_since(at).as_secs_f64() / 3600.0;
+                let est = est + ibat_ma as f64 * hours / self.capacity_mah * 100.0;
+                let real = real as f64;
+                est.clamp((real - 0.9).max(0.0), (real + 0.9).min(100.0))
+            }
+            _ => soc_pct as f64,
+        };
+        self.base = Some((soc_pct, est, now));
+        est
+    }
 }
 
 const LBL: usize = 11; // label column width
 
-fn print_snapshot(s: &SnapshotMsg) {
+/// `watch` on a terminal: clear screen + cursor home, so each new block
+/// replaces the previous one in place. Piped or redirected, blocks are
+/// appended a blank line apart instead of filling the log with escape codes.
+fn clear_screen() {
+    if std::io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    } else {
+        println!();
+    }
+}
+
+fn print_snapshot(s: &SnapshotMsg, soc_est: Option<f64>, view: View) {
     println!("Web3 Pi UPS — {}", format_clock_utc(s.unix_ts_ms));
     println!();
 
-    print_power_block(s);
+    print_power_block(s, soc_est, view);
     if s.net.is_some() {
         println!();
         print_net_block(s);
@@ -161,48 +1210,109 @@ fn print_snapshot(s: &SnapshotMsg) {
     print_host_block(s);
 }
 
-fn print_power_block(s: &SnapshotMsg) {
+fn print_power_block(s: &SnapshotMsg, soc_est: Option<f64>, view: View) {
     let header = match &s.power {
         Some(p) => {
             let age = p
                 .age_ms
                 .map(|m| format!("{}ms ago", m))
                 .unwrap_or_else(|| "no data".into());
-            format!("power  ({age})")
+            if s.synthetic {
+                format!("power  (SYNTHETIC — injected over IPC, not UPS data; {age})")
+            } else if s.degraded {
+                format!("power  (DATA STALE — serial disconnected, last update {age})")
+            } else if s.stale {
+                format!("power  (DATA STALE — no new sample from the UPS, last update {age})")
+            } else {
+                format!("power  ({age})")
+            }
         }
-        None => "power  (no data yet)".into(),
+        // Connected, but the daemon has had nothing from the UPS yet.
+        None if s.degraded => "power  (waiting for data — serial disconnected)".into(),
+        None => "power  (waiting for the first sample from the UPS…)".into(),
     };
     println!("{header}");
 
     let Some(p) = &s.power else { return };
     let src = if p.on_battery { "BATTERY" } else { "GRID" };
-    let charge = charge_state_name(p.charge_state);
+    let charge = charge_label(p.charge_state, p.maintaining);
     let temp_c = p.temp_dc as f32 / 10.0;
 
     row("source", &format!("{src:<8}  charge: {charge}"));
-    row(
-        "input",
-        &format!("VI   = {} V", fmt_mv(p.vbus_in_mv as i32)),
-    );
+    if let Some(flags) = p.power_flags {
+        row("", &format!("flags: {}", power2_flag::describe(flags)));
+    }
+    let nominal = match (p.nominal_input_mv, p.input_deviation_pct) {
+        (Some(n), Some(dev)) => format!("    (nominal {} V, {dev:+.0}%)", fmt_mv(n as i32)),
+        (Some(n), None) => format!("    (nominal {} V)", fmt_mv(n as i32)),
+        _ => String::new(),
+    };
+    let pd = p
+        .pd_in_mv
+        .filter(|&mv| mv > 0)
+        .map(|mv| format!(" ({} V PD)", fmt_volts(mv)))
+        .unwrap_or_default();
+    let vi = format!("{} V", fmt_mv(p.vbus_in_mv as i32));
+    let vi = match view
+        .colors
+        .and_then(|m| input_level(p, m.input_warn_pct, m.input_alarm_pct))
+    {
+        Some(level) => format!("\x1b[{}m{vi}\x1b[0m", level.ansi()),
+        None => vi,
+    };
+    row("input", &format!("VI   = {vi}{pd}{nominal}"));
+    if let Some(pd) = &p.pd_in_contract {
+        let load = p
+            .pd_load_pct
+            .map(|l| format!("    load = {l}%"))
+            .unwrap_or_default();
+        row("", &format!("PD contract: {pd}{load}"));
+    }
+    if view.show_source {
+        if let Some(line) = source_line(p) {
+            row("source", &line);
+        }
+    }
+    let vout = format!("{:.2} V", p.rail_voltage_v());
+    let vout = match (p.rail_undervoltage, view.colors) {
+        (true, Some(_)) => format!("\x1b[{}m{vout}\x1b[0m", InputLevel::Bad.ansi()),
+        _ => vout,
+    };
     row(
         "output",
-        &format!(
-            "VOUT = {} V    IOUT = {} A",
-            fmt_mv(p.vbus_out_mv as i32),
-            fmt_ma(p.ibus_out_ma as i32),
-        ),
+        &format!("VOUT = {vout}    IOUT = {:.2} A", p.rail_current_a()),
     );
+    let est = soc_est
+        .map(|e| format!("  (est. {e:.1}%)"))
+        .unwrap_or_default();
     row(
         "battery",
         &format!(
-            "VBAT = {} V    IBAT = {} mA    SOC  = {}%",
+            "VBAT = {} V    IBAT = {} mA    SOC  = {}%{est}",
             fmt_mv(p.vbat_mv as i32),
             p.ibat_ma,
             p.soc_pct,
         ),
     );
     row("thermal", &format!("T = {temp_c:.1} °C"));
-    row("faults", &format!("0x{:04x}", p.faults));
+    let fault_names = match p.faults {
+        0 => String::new(),
+        f => format!(" ({})", power_fault::describe(f)),
+    };
+    row("faults", &format!("0x{:04x}{fault_names}", p.faults));
+    if let Some(score) = p.health_score {
+        row("health", &format!("{score}/100"));
+    }
+    if s.missed_samples > 0 {
+        let gap = s
+            .max_recent_gap_ms
+            .map(|g| format!(", largest recent gap {g} ms"))
+            .unwrap_or_default();
+        row("samples", &format!("{} missed{gap}", s.missed_samples));
+    }
+    if let Some(up) = p.ups_uptime_s {
+        row("ups uptime", &fmt_uptime(up));
+    }
 
     if let Some(ev) = s.last_power_event {
         row("last event", power_event_name(ev));
@@ -210,6 +1320,33 @@ fn print_power_block(s: &SnapshotMsg) {
     if let Some(secs) = s.shutdown_pending_for_s {
         row("ALERT", &format!("shutdown pending: {secs} s elapsed"));
     }
+    if p.rail_undervoltage {
+        row(
+            "ALERT",
+            &format!(
+                "output rail at {:.2} V: below the Pi's brownout point",
+                p.rail_voltage_v()
+            ),
+        );
+    }
+    if let Some(faults) = &s.fault_summary {
+        row("UNHEALTHY", faults);
+    }
+    if s.dry_run {
+        row(
+            "DRY RUN",
+            "daemon only logs what it would do; it will not shut down",
+        );
+    }
+    if s.charging_fault {
+        row("ALERT", "charging fault: on grid but battery not charging");
+    }
+    if s.pd_overload {
+        row(
+            "ALERT",
+            "PD overload: load exceeds the charger's contract; battery draining on grid",
+        );
+    }
 }
 
 fn print_net_block(s: &SnapshotMsg) {
@@ -292,23 +1429,87 @@ fn print_host_block(s: &SnapshotMsg) {
     );
 }
 
+/// How the input voltage looks against what it should be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputLevel {
+    Good,
+    Marginal,
+    Bad,
+}
+
+impl InputLevel {
+    /// SGR colour: green, yellow, red.
+    fn ansi(self) -> u8 {
+        match self {
+            Self::Good => 32,
+            Self::Marginal => 33,
+            Self::Bad => 31,
+        }
+    }
+}
+
+/// Outside the valid range (the daemon has gone to battery) is bad;
+/// otherwise the deviation from `[battery].nominal_input_mv`, or from the
+/// PD contract's voltage without one, against `warn` / `alarm` percent.
+/// `None` when there is nothing to compare with.
+fn input_level(p: &PowerSnap, warn: f32, alarm: f32) -> Option<InputLevel> {
+    if p.on_battery {
+        return Some(InputLevel::Bad);
+    }
+    let expected = p.nominal_input_mv.or(p.pd_in_mv).filter(|&mv| mv > 0)? as f32;
+    let dev = ((p.vbus_in_mv as f32 - expected) / expected * 100.0).abs();
+    Some(if dev > alarm {
+        InputLevel::Bad
+    } else if dev > warn {
+        InputLevel::Marginal
+    } else {
+        InputLevel::Good
+    })
+}
+
+/// The `source` row, if v1 or key-value firmware reports either value. A
+/// v2 status has its own source fields (the `PD contract` row) and puts
+/// VSYS / IIN where v1 carries these.
+fn source_line(p: &PowerSnap) -> Option<String> {
+    if p.power_flags.is_some() {
+        return None;
+    }
+    let v = p.source_voltage_v().map(|v| format!("VS   = {v:.2} V"));
+    let i = p.source_current_a().map(|i| format!("IS   = {i:.2} A"));
+    match (v, i) {
+        (None, None) => None,
+        (v, i) => Some(
+            [v, i]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join("    "),
+        ),
+    }
+}
+
 fn row(label: &str, value: &str) {
     println!("  {label:<width$}  {value}", width = LBL);
 }
 
-fn fmt_mv(mv: i32) -> String {
+pub(crate) fn fmt_mv(mv: i32) -> String {
     // mV → "X.XX" volts
     let v = mv as f32 / 1000.0;
     format!("{v:.2}")
 }
 
-fn fmt_ma(ma: i32) -> String {
-    // mA → "X.XX" amps (handle sign)
-    let a = ma as f32 / 1000.0;
-    format!("{a:.2}")
+/// A PD voltage as written on the charger: "20", "9", "5.5".
+fn fmt_volts(mv: u16) -> String {
+    let v = mv as f32 / 1000.0;
+    format!("{v}")
 }
 
+/// "3d 4h 5m 6s", dropping leading zero units; 0 reads as "n/a" (the
+/// counter isn't reported).
 fn fmt_uptime(secs: u32) -> String {
+    if secs == 0 {
+        return "n/a".into();
+    }
     let d = secs / 86_400;
     let h = (secs / 3_600) % 24;
     let m = (secs / 60) % 60;
@@ -324,7 +1525,7 @@ fn fmt_uptime(secs: u32) -> String {
     }
 }
 
-fn format_clock_utc(unix_ms: u64) -> String {
+pub(crate) fn format_clock_utc(unix_ms: u64) -> String {
     // Render unix epoch ms as "YYYY-MM-DD HH:MM:SS UTC". Civil date via
     // Howard Hinnant's days_from_civil inverse — avoids pulling chrono.
     let secs = unix_ms / 1000;
@@ -336,6 +1537,18 @@ fn format_clock_utc(unix_ms: u64) -> String {
     format!("{y:04}-{mo:02}-{d:02} {h:02}:{mi:02}:{s:02} UTC")
 }
 
+/// Unix epoch ms as RFC 3339 UTC with milliseconds, e.g.
+/// "2026-10-14T08:00:00.123Z".
+pub(crate) fn format_rfc3339_utc(unix_ms: u64) -> String {
+    let secs = unix_ms / 1000;
+    let (y, mo, d) = days_to_ymd((secs / 86_400) as i64);
+    let h = (secs / 3_600) % 24;
+    let mi = (secs / 60) % 60;
+    let s = secs % 60;
+    let ms = unix_ms % 1000;
+    format!("{y:04}-{mo:02}-{d:02}T{h:02}:{mi:02}:{s:02}.{ms:03}Z")
+}
+
 // Howard Hinnant's "days from civil" inverse: convert days-from-epoch to (Y, M, D).
 // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
 fn days_to_ymd(days: i64) -> (i64, u32, u32) {
@@ -352,6 +1565,34 @@ fn days_to_ymd(days: i64) -> (i64, u32, u32) {
     (y, m as u32, d as u32)
 }
 
+/// Inverse of [`format_rfc3339_utc`]: `YYYY-MM-DDTHH:MM:SS[.fff]Z` only
+/// (what `probe --rfc3339` writes), no offsets.
+pub(crate) fn parse_rfc3339_utc(ts: &str) -> Option<u64> {
+    let (date, time) = ts.strip_suffix('Z')?.split_once('T')?;
+    let mut d = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (d.next()??, d.next()??, d.next()??);
+    let (hms, frac) = time.split_once('.').unwrap_or((time, ""));
+    let mut t = hms.splitn(3, ':').map(|p| p.parse::<u64>().ok());
+    let (h, mi, s) = (t.next()??, t.next()??, t.next()??);
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || h > 23 || mi > 59 || s > 60 {
+        return None;
+    }
+    let ms = match frac.len() {
+        0 => 0,
+        1..=3 => frac.parse::<u64>().ok()? * 10u64.pow(3 - frac.len() as u32),
+        _ => frac[..3].parse().ok()?,
+    };
+    // Howard Hinnant's days_from_civil.
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some(((days * 86_400 + h * 3_600 + mi * 60 + s) * 1000) + ms)
+}
+
 fn fmt_bytes(b: u64) -> String {
     const K: u64 = 1024;
     const M: u64 = K * 1024;
@@ -369,7 +1610,7 @@ fn fmt_bytes(b: u64) -> String {
 
 // eth client state is now decoded via host_metrics::eth::{unpack, state_name}.
 
-fn charge_state_name(s: u8) -> &'static str {
+pub(crate) fn charge_state_name(s: u8) -> &'static str {
     match s {
         0 => "idle",
         1 => "charging",
@@ -379,6 +1620,16 @@ fn charge_state_name(s: u8) -> &'static str {
     }
 }
 
+/// [`charge_state_name`], or "maintaining" while the daemon counts a full
+/// pack's top-off cycling as such (`[battery].maintenance_soc_pct`).
+pub(crate) fn charge_label(s: u8, maintaining: bool) -> &'static str {
+    if maintaining {
+        "maintaining"
+    } else {
+        charge_state_name(s)
+    }
+}
+
 fn net_state_name(s: u8) -> &'static str {
     match s {
         0 => "off",
@@ -401,3 +1652,791 @@ fn power_event_name(e: u8) -> &'static str {
         _ => "?",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    //! Round-trips through the real server path (`ipc::handle_client`) and
+    //! the real client parser, so the two hand-kept schemas can't drift.
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::host_metrics::{HostMetricsSample, NetTotals};
+    use crate::ipc::{handle_client, ClientCtx};
+    use crate::proto::payloads::{HostStatusV1, NetStatusV1, PowerStatusV1};
+    use crate::state::State;
+    use crate::test_util::Scratch;
+
+    /// Ask a fresh server for one snapshot of `state`.
+    async fn round_trip(state: Arc<State>, req: &Request) -> Reply {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_client(
+            server,
+            state,
+            Arc::new(ClientCtx::new(&Config::default(), None)),
+        ));
+        write_request(&mut client, req).await.unwrap();
+        let (rd, _wr) = client.split();
+        let line = BufReader::new(rd).lines().next_line().await.unwrap();
+        parse_reply(&line.expect("server closed without replying")).unwrap()
+    }
+
+    async fn snapshot_of(state: Arc<State>) -> SnapshotMsg {
+        match round_trip(state, &Request::Snapshot).await {
+            Reply::Snapshot(s) => *s,
+            other => panic!("expected snapshot, got {other:?}"),
+        }
+    }
+
+    fn assert_power(got: &PowerSnap, want: &PowerStatusV1) {
+        assert_eq!(got.charge_state, want.charge_state);
+        assert_eq!(got.vbus_in_mv, want.vbus_in_mv);
+        assert_eq!(got.vbus_out_mv, want.vbus_out_mv);
+        assert_eq!(got.ibus_out_ma, want.ibus_out_ma);
+        assert_eq!(got.vbat_mv, want.vbat_mv);
+        assert_eq!(got.ibat_ma, want.ibat_ma);
+        assert_eq!(got.temp_dc, want.temp_dc);
+        assert_eq!(got.faults, want.faults);
+    }
+
+    #[tokio::test]
+    async fn empty_state_round_trips() {
+        let s = snapshot_of(State::new()).await;
+        assert!(s.power.is_none() && s.net.is_none() && s.host.is_none());
+        assert_eq!(s.last_power_event, None);
+        assert_eq!(s.shutdown_pending_for_s, None);
+        assert!(!s.charging_fault);
+        assert!(!s.degraded);
+    }
+
+    #[tokio::test]
+    async fn power_round_trips_including_extremes() {
+        let cases = [
+            // Discharging: negative battery current.
+            PowerStatusV1 {
+                charge_state: 0,
+                vbus_out_mv: 5100,
+                ibus_out_ma: 2300,
+                vbat_mv: 7400,
+                ibat_ma: -1500,
+                temp_dc: -55,
+                ..Default::default()
+            },
+            PowerStatusV1::default(),
+            PowerStatusV1 {
+                charge_state: u8::MAX,
+                vbus_in_mv: u16::MAX,
+                vbus_out_mv: u16::MAX,
+                ibus_out_ma: i16::MIN,
+                vbat_mv: u16::MAX,
+                ibat_ma: i16::MAX,
+                temp_dc: i16::MIN,
+                pd_contract_mv: u16::MAX,
+                pd_contract_ma: u16::MAX,
+                faults: u16::MAX,
+            },
+        ];
+        for want in cases {
+            let state = State::new();
+            state.update_power(want).await;
+            let s = snapshot_of(state).await;
+            let got = s.power.expect("power block");
+            assert_power(&got, &want);
+            assert_eq!(
+                got.on_battery,
+                crate::shutdown_sm::classify_input(&want, None, 8000, 26000, true).on_battery()
+            );
+            assert_eq!(got.soc_pct, crate::soc::pack_mv_to_soc_pct(want.vbat_mv));
+            assert!(got.age_ms.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn net_host_and_flags_round_trip() {
+        let state = State::new();
+        let net = NetStatusV1 {
+            state: 4,
+            rssi_dbm: i8::MIN,
+            rsrp_dbm: -110,
+            rsrq_db: 0,
+            bytes_tx: u32::MAX,
+            bytes_rx: 0,
+            ..Default::default()
+        };
+        state.update_net(net).await;
+        let host = HostStatusV1 {
+            eth_client_state: 0b01_10_01,
+            cpu_temp_dc: -100,
+            mem_used_pct: 100,
+            disk_used_pct: 0,
+            load_avg_x100: u16::MAX,
+            uptime_s: u32::MAX,
+        };
+        state
+            .update_host_sample(HostMetricsSample {
+                status: host,
+                cpu_usage_pct: None,
+                net: Some(NetTotals {
+                    bytes_rx: u64::MAX,
+                    bytes_tx: 0,
+                }),
+                net_rx_bytes_per_s: Some(0),
+                net_tx_bytes_per_s: None,
+            })
+            .await;
+        state.update_power_event(5).await;
+        state.set_charging_fault(true).await;
+
+        let s = snapshot_of(state).await;
+        let n = s.net.expect("net block");
+        assert_eq!(
+            (n.state, n.rssi_dbm, n.rsrp_dbm, n.rsrq_db),
+            (4, i8::MIN, -110, 0)
+        );
+        assert_eq!((n.bytes_tx, n.bytes_rx), (u32::MAX, 0));
+        let h = s.host.expect("host block");
+        assert_eq!(h.eth_client_state, host.eth_client_state);
+        assert_eq!(h.cpu_temp_dc, -100);
+        assert_eq!((h.mem_used_pct, h.disk_used_pct), (100, 0));
+        assert_eq!((h.load_avg_x100, h.uptime_s), (u16::MAX, u32::MAX));
+        assert_eq!(h.cpu_usage_pct, None);
+        assert_eq!(h.net_bytes_rx_total, Some(u64::MAX));
+        assert_eq!(h.net_bytes_tx_total, Some(0));
+        assert_eq!(h.net_rx_bytes_per_s, Some(0));
+        assert_eq!(h.net_tx_bytes_per_s, None);
+        assert_eq!(s.last_power_event, Some(5));
+        assert!(s.charging_fault);
+    }
+
+    #[tokio::test]
+    async fn exit_line_sums_up_the_last_reading() {
+        let state = State::new();
+        assert_eq!(exit_line(&snapshot_of(state.clone()).await), None);
+        state
+            .update_power(PowerStatusV1 {
+                charge_state: 1,
+                vbus_in_mv: 19_800,
+                vbat_mv: 7_400,
+                ibat_ma: 850,
+                temp_dc: 315,
+                ..Default::default()
+            })
+            .await;
+        state.set_serial_connected(true).await;
+        let line = exit_line(&snapshot_of(state.clone()).await).unwrap();
+        assert!(line.starts_with("last reading "), "{line}");
+        assert!(
+            line.ends_with(
+                " UTC: GRID VI=19.80V VBAT=7.40V IBAT=850mA SOC=60% chg=charging T=31.5°C"
+            ),
+            "{line}"
+        );
+        state.set_serial_connected(false).await;
+        let line = exit_line(&snapshot_of(state).await).unwrap();
+        assert!(line.ends_with("T=31.5°C (stale)"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn degraded_when_serial_down_with_stale_power() {
+        let state = State::new();
+        state.update_power(PowerStatusV1::default()).await;
+        assert!(snapshot_of(state.clone()).await.degraded);
+        state.set_serial_connected(true).await;
+        assert!(!snapshot_of(state).await.degraded);
+    }
+
+    #[tokio::test]
+    async fn healthcheck_fails_once_samples_stop() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        state.set_serial_connected(true).await;
+        state
+            .set_max_sample_age(Some(std::time::Duration::from_secs(10)))
+            .await;
+        let problem = health_problem(&snapshot_of(state.clone()).await);
+        assert_eq!(problem.as_deref(), Some("no power sample from the UPS yet"));
+
+        state.update_power(PowerStatusV1::default()).await;
+        clock.advance(std::time::Duration::from_secs(10));
+        let s = snapshot_of(state.clone()).await;
+        assert!(!s.stale);
+        assert_eq!(health_problem(&s), None);
+
+        clock.advance(std::time::Duration::from_secs(1));
+        let s = snapshot_of(state.clone()).await;
+        assert!(s.stale);
+        assert_eq!(
+            health_problem(&s).as_deref(),
+            Some("last sample 11000 ms ago, past [ipc].max_sample_age_seconds")
+        );
+
+        // 0 disables the age check.
+        state.set_max_sample_age(None).await;
+        assert_eq!(health_problem(&snapshot_of(state).await), None);
+    }
+
+    /// The IPC server is its own task, independent of serial cadence: with
+    /// no serial link at all, a client gets its first reply promptly.
+    #[tokio::test]
+    async fn client_served_promptly_without_serial() {
+        let dir = Scratch::new("test");
+        let socket_path = dir.join("agent.sock").to_string_lossy().into_owned();
+        let server =
+            crate::ipc::spawn_ipc(socket_path.clone(), State::new(), &Config::default(), None)
+                .await
+                .unwrap();
+        let ep = Endpoint::Unix(socket_path);
+
+        let reply = tokio::time::timeout(std::time::Duration::from_millis(100), async {
+            let mut stream = connect(&ep).await.unwrap();
+            write_request(&mut stream, &Request::Snapshot)
+                .await
+                .unwrap();
+            let (rd, _wr) = tokio::io::split(stream);
+            BufReader::new(rd).lines().next_line().await.unwrap()
+        })
+        .await
+        .expect("IPC reply took longer than 100 ms");
+        assert!(matches!(
+            parse_reply(&reply.unwrap()).unwrap(),
+            Reply::Snapshot(_)
+        ));
+
+        server.abort();
+    }
+
+    /// Send raw request lines to a server allowed (or not) to inject and
+    /// collect one reply line per request.
+    async fn raw_exchange(state: Arc<State>, allow_inject: bool, reqs: &[&str]) -> Vec<String> {
+        let mut cfg = Config::default();
+        cfg.debug.allow_inject = allow_inject;
+        let (mut client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_client(
+            server,
+            state,
+            Arc::new(ClientCtx::new(&cfg, None)),
+        ));
+        let (rd, mut wr) = client.split();
+        let mut lines = BufReader::new(rd).lines();
+        let mut out = Vec::new();
+        for req in reqs {
+            wr.write_all(format!("{req}\n").as_bytes()).await.unwrap();
+            out.push(lines.next_line().await.unwrap().unwrap());
+        }
+        out
+    }
+
+    const INJECT_LOW: &str =
+        r#"{"op":"inject","data":{"soc_pct":5,"ibat_ma":-900},"exercise_shutdown":true}"#;
+
+    #[tokio::test]
+    async fn inject_refused_unless_enabled() {
+        let state = State::new();
+        let reply = raw_exchange(state.clone(), false, &[INJECT_LOW]).await;
+        assert!(matches!(
+            parse_reply(&reply[0]).unwrap(),
+            Reply::Error { message } if message.contains("allow_inject")
+        ));
+        assert!(state.snapshot().await.last_power.is_none());
+    }
+
+    #[tokio::test]
+    async fn injected_sample_is_served_and_flagged() {
+        let state = State::new();
+        let mut feed = state.subscribe_power();
+        let replies = raw_exchange(state, true, &[INJECT_LOW, r#"{"op":"snapshot"}"#]).await;
+        let ack: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(ack["type"], "injected");
+        assert_eq!(ack["hold_s"], 60);
+        assert_eq!(ack["exercise_shutdown"], true);
+
+        let Reply::Snapshot(s) = parse_reply(&replies[1]).unwrap() else {
+            panic!("expected snapshot");
+        };
+        assert!(s.synthetic);
+        let p = s.power.expect("power");
+        assert_eq!(p.soc_pct, 5);
+        assert!(p.on_battery);
+        // An overlay for clients, not a frame for the daemon's own consumers.
+        assert!(feed.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn inject_hold_is_capped() {
+        let state = State::new();
+        let req = r#"{"op":"inject","data":{"soc_pct":5},"hold_s":18446744073709551615}"#;
+        let reply = raw_exchange(state.clone(), true, &[req]).await;
+        assert!(matches!(
+            parse_reply(&reply[0]).unwrap(),
+            Reply::Error { message } if message.contains("at most 3600")
+        ));
+        assert!(state.snapshot().await.injected.is_none());
+    }
+
+    #[tokio::test]
+    async fn nut_vars_round_trip_in_upsc_format() {
+        let state = State::new();
+        assert!(matches!(
+            round_trip(state.clone(), &Request::Nut).await,
+            Reply::Error { message } if message.starts_with("DATA-STALE")
+        ));
+        state.set_serial_connected(true).await;
+        state
+            .update_power(PowerStatusV1 {
+                vbus_in_mv: 20_000,
+                vbat_mv: 8_000,
+                ..Default::default()
+            })
+            .await;
+        let Reply::Nut { vars } = round_trip(state, &Request::Nut).await else {
+            panic!("expected nut reply");
+        };
+        let text = upsc_lines(&vars);
+        assert!(text.contains("battery.charge: 100\n"), "{text}");
+        assert!(text.contains("ups.status: OL\n"), "{text}");
+    }
+
+    #[test]
+    fn uptime_is_readable_and_zero_is_unknown() {
+        assert_eq!(fmt_uptime(0), "n/a");
+        assert_eq!(fmt_uptime(59), "59s");
+        assert_eq!(fmt_uptime(3_600), "1h 0m 0s");
+        assert_eq!(fmt_uptime(12_345_678), "142d 21h 21m 18s");
+        assert_eq!(fmt_uptime(u32::MAX), "49710d 6h 28m 15s");
+    }
+
+    #[test]
+    fn rfc3339_stamps_parse_back() {
+        for ms in [0, 1_791_964_800_123, 951_782_400_000] {
+            assert_eq!(parse_rfc3339_utc(&format_rfc3339_utc(ms)), Some(ms));
+        }
+        assert_eq!(
+            parse_rfc3339_utc("2026-10-14T08:00:01Z"),
+            Some(1_791_964_801_000)
+        );
+        for bad in [
+            "",
+            "2026-10-14 08:00:01Z",
+            "2026-10-14T08:00:01+02:00",
+            "2026-13-01T00:00:00Z",
+            "2026-10-14T24:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339_utc(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn soc_estimate_counts_current_and_snaps_to_real_soc() {
+        let t0 = Instant::now();
+        let min = |m: u64| t0 + std::time::Duration::from_secs(m * 60);
+        let mut est = SocEstimate::new(2000.0);
+        assert_eq!(est.update(55, -120, t0), 55.0);
+        // 120 mA for 6 min out of 2000 mAh: 0.6 points.
+        let e = est.update(55, -120, min(6));
+        assert!((e - 54.4).abs() < 1e-9, "{e}");
+        // Bounded to within a point of the real value however long it lags.
+        assert!((est.update(55, -120, min(60)) - 54.1).abs() < 1e-9);
+        // A fresh SOC reading wins.
+        assert_eq!(est.update(54, -1200, min(61)), 54.0);
+        est.reset();
+        assert_eq!(est.update(53, -1200, min(90)), 53.0);
+    }
+
+    #[test]
+    fn input_is_graded_against_the_nominal_or_pd_voltage() {
+        let snap = |vi: u16, nominal: Option<u16>, pd: Option<u16>, on_battery: bool| {
+            serde_json::from_value::<PowerSnap>(serde_json::json!({
+                "age_ms": 0, "charge_state": 1, "vbus_in_mv": vi, "vbus_out_mv": 5100,
+                "ibus_out_ma": 900, "vbat_mv": 7400, "ibat_ma": 500, "soc_pct": 60,
+                "on_battery": on_battery, "temp_dc": 300, "faults": 0,
+                "nominal_input_mv": nominal, "pd_in_mv": pd,
+            }))
+            .unwrap()
+        };
+        let level = |p: &PowerSnap| input_level(p, 5.0, 15.0);
+        assert_eq!(
+            level(&snap(19_800, Some(20_000), None, false)),
+            Some(InputLevel::Good)
+        );
+        assert_eq!(
+            level(&snap(18_500, Some(20_000), None, false)),
+            Some(InputLevel::Marginal)
+        );
+        assert_eq!(
+            level(&snap(16_500, Some(20_000), None, false)),
+            Some(InputLevel::Bad)
+        );
+        // Without a nominal the PD contract is what the input should be.
+        assert_eq!(
+            level(&snap(14_900, None, Some(15_000), false)),
+            Some(InputLevel::Good)
+        );
+        assert_eq!(level(&snap(19_800, None, None, false)), None);
+        assert_eq!(level(&snap(4_000, None, None, true)), Some(InputLevel::Bad));
+        assert_eq!(fmt_volts(20_000), "20");
+        assert_eq!(fmt_volts(5_500), "5.5");
+    }
+
+    #[test]
+    fn source_row_shows_what_v1_firmware_reports() {
+        let snap = |vs: u16, is: u16, flags: Option<u8>| {
+            serde_json::from_value::<PowerSnap>(serde_json::json!({
+                "age_ms": 0, "charge_state": 1, "vbus_in_mv": 19_800, "vbus_out_mv": 5100,
+                "ibus_out_ma": 900, "vbat_mv": 7400, "ibat_ma": 500, "soc_pct": 60,
+                "on_battery": false, "temp_dc": 300, "faults": 0,
+                "pd_contract_mv": vs, "pd_contract_ma": is, "power_flags": flags,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            source_line(&snap(20_000, 1_500, None)).as_deref(),
+            Some("VS   = 20.00 V    IS   = 1.50 A")
+        );
+        assert_eq!(
+            source_line(&snap(0, 1_500, None)).as_deref(),
+            Some("IS   = 1.50 A")
+        );
+        assert_eq!(source_line(&snap(0, 0, None)), None);
+        // v2: those fields hold VSYS / IIN, not the source.
+        assert_eq!(source_line(&snap(20_000, 1_500, Some(0x08))), None);
+    }
+
+    #[tokio::test]
+    async fn info_json_has_every_key_whatever_the_daemon_sent() {
+        let keys = [
+            "cadence",
+            "clients",
+            "energy",
+            "health",
+            "in_outage",
+            "kv_rejects",
+            "last_charge",
+            "last_discharge",
+            "outages",
+            "port",
+            "sample_age_ms",
+            "subscribers",
+            "uptime_s",
+            "version",
+        ];
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        state.set_serial_port("/dev/ttyACM1".into()).await;
+        state.update_power(PowerStatusV1::default()).await;
+        state.note_outage(true).await;
+        clock.advance(std::time::Duration::from_millis(1_500));
+        let current = raw_exchange(state, false, &[r#"{"op":"info"}"#]).await;
+        let old = r#"{"type":"info","version":"2.0.0","last_discharge":null,"last_charge":null}"#;
+        for line in [current[0].as_str(), old] {
+            let json = info_json(parse_reply(line).unwrap()).unwrap();
+            let got: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+            assert_eq!(got, keys, "{line}");
+        }
+        let json = info_json(parse_reply(&current[0]).unwrap()).unwrap();
+        assert_eq!(json["port"], "/dev/ttyACM1");
+        assert_eq!(
+            (json["uptime_s"].as_u64(), json["sample_age_ms"].as_u64()),
+            (Some(1), Some(1_500))
+        );
+        assert_eq!(
+            (json["clients"].as_u64(), json["subscribers"].as_u64()),
+            (Some(1), Some(0))
+        );
+        assert_eq!(
+            (json["outages"].as_u64(), json["in_outage"].as_bool()),
+            (Some(1), Some(true))
+        );
+        let json = info_json(parse_reply(old).unwrap()).unwrap();
+        assert!(json["port"].is_null());
+        assert!(json["uptime_s"].is_null() && json["clients"].is_null());
+        assert_eq!(json["kv_rejects"]["missing"], 0);
+        assert!(json["energy"].is_null());
+        assert!(info_json(Reply::Stopping).is_err());
+    }
+
+    #[tokio::test]
+    async fn dump_saves_the_full_snapshot() {
+        let dir = Scratch::new("dump");
+        let sock = dir.join("agent.sock");
+        let listener = tokio::net::UnixListener::bind(&sock).unwrap();
+        let state = State::new();
+        state
+            .update_power(PowerStatusV1 {
+                vbus_in_mv: 19_800,
+                vbat_mv: 7_400,
+                ..Default::default()
+            })
+            .await;
+        tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            handle_client(
+                conn,
+                state,
+                Arc::new(ClientCtx::new(&Config::default(), None)),
+            )
+            .await;
+        });
+
+        let ep = Endpoint::Unix(sock.to_string_lossy().into_owned());
+        let path = dump_snapshot(&ep, &dir).await.unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(
+            name.starts_with("w3p-ups-snapshot-") && name.ends_with(".json"),
+            "{name}"
+        );
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("\n  \"power\": {"), "pretty-printed: {text}");
+        let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(v["power"]["vbus_in_mv"], 19_800);
+        assert_eq!(v["power"]["vbat_mv"], 7_400);
+    }
+
+    #[tokio::test]
+    async fn info_reports_last_capacity_span() {
+        use crate::capacity::{CapacityLog, SpanKind};
+
+        let state = State::new();
+        let Reply::Info(info) = round_trip(state.clone(), &Request::Info).await else {
+            panic!("expected info reply");
+        };
+        assert!(info.last_discharge.is_none());
+        assert_eq!(span_line(None, 0), "not measured yet");
+
+        let span = CapacitySpan {
+            kind: SpanKind::Discharge,
+            started_unix_ms: 0,
+            ended_unix_ms: 7_200_000,
+            mah: 4100,
+        };
+        let energy = EnergyTotals {
+            grid_wh: 12_345.6,
+            battery_wh: 512.04,
+            since_unix_ms: 0,
+        };
+        assert_eq!(energy_line(&energy), "nothing counted yet");
+        state
+            .set_capacity(CapacityLog {
+                spans: vec![span.clone()],
+                energy: EnergyTotals {
+                    since_unix_ms: 1_760_428_800_000,
+                    ..energy
+                },
+            })
+            .await;
+        let Reply::Info(info) = round_trip(state, &Request::Info).await else {
+            panic!("expected info reply");
+        };
+        let InfoMsg {
+            last_discharge,
+            last_charge,
+            energy,
+            ..
+        } = *info;
+        assert_eq!(last_discharge.as_ref(), Some(&span));
+        assert!(last_charge.is_none());
+        assert_eq!(
+            span_line(last_discharge.as_ref(), 7_200_000 + 90_000),
+            "4100 mAh over 2h00m, measured 0h01m ago"
+        );
+        assert_eq!(
+            energy_line(&energy.unwrap()),
+            "grid 12.35 kWh, battery 512.0 Wh since 2025-10-14 08:00:00 UTC"
+        );
+    }
+
+    #[tokio::test]
+    async fn info_counts_missed_samples() {
+        use std::time::Duration;
+
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        state.set_serial_connected(true).await;
+        state
+            .set_sample_cadence(Some(Duration::from_secs(1)), 5)
+            .await;
+        for gap_s in [0, 1, 1, 4, 1] {
+            clock.advance(Duration::from_secs(gap_s));
+            state.update_power(PowerStatusV1::default()).await;
+        }
+        let Reply::Info(info) = round_trip(state.clone(), &Request::Info).await else {
+            panic!("expected info reply");
+        };
+        let cadence = info.cadence;
+        assert_eq!(cadence.missed, 3);
+        assert_eq!(
+            cadence_line(&cadence),
+            "3 missed at 1000 ms expected, largest recent gap 4000 ms"
+        );
+        let s = snapshot_of(state).await;
+        assert_eq!((s.missed_samples, s.max_recent_gap_ms), (3, Some(4_000)));
+
+        assert_eq!(
+            cadence_line(&CadenceCounts::default()),
+            "no gap measured yet ([serial].expected_interval_ms unset)"
+        );
+    }
+
+    #[tokio::test]
+    async fn histogram_reports_buckets_or_disabled() {
+        let state = State::new();
+        let reply = round_trip(state.clone(), &Request::Histogram).await;
+        assert!(matches!(reply, Reply::Error { .. }), "{reply:?}");
+
+        let mut h = InputHistogram::new(vec![19_500, 20_500], 0);
+        for mv in [20_000, 20_000, 20_100, 19_000] {
+            h.record(mv);
+        }
+        state.set_input_histogram(Some(h.clone())).await;
+        let Reply::Histogram { histogram } = round_trip(state, &Request::Histogram).await else {
+            panic!("expected histogram reply");
+        };
+        assert_eq!(histogram, h);
+        assert_eq!(
+            histogram_lines(&histogram),
+            "input voltage since 1970-01-01 00:00:00 UTC — 4 samples, mean 19.77 V\n\
+             <= 19.50 V         1   25.0%  ##############\n\
+             <= 20.50 V         3   75.0%  ########################################\n\
+             \x20> 20.50 V         0    0.0%  \n"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_replies_with_snapshot_first() {
+        let reply = round_trip(
+            State::new(),
+            &Request::Subscribe {
+                encoding: IpcEncoding::Json,
+            },
+        )
+        .await;
+        assert!(matches!(reply, Reply::Snapshot(_)));
+    }
+
+    #[tokio::test]
+    async fn binary_subscription_decodes_like_json() {
+        use crate::proto::payloads::PowerStatusV2;
+
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        state.set_serial_connected(true).await;
+        state
+            .update_power_v2(PowerStatusV2 {
+                flags: power2_flag::POWER_GOOD,
+                vbus_in_mv: 15_100,
+                pd_in_mv: 15_000,
+                pd_in_ma: 3_000,
+                vout_read_mv: 4_500,
+                vbat_mv: 7_300,
+                ichg_ma: 400,
+                iin_ma: 1_200,
+                temp_lm_dc: -55,
+                temp_mp_dc: i16::MIN,
+                faults: crate::proto::payloads::power_fault::OTP,
+                uptime_s: 3_600,
+                ..Default::default()
+            })
+            .await;
+        state
+            .update_net(NetStatusV1 {
+                state: 4,
+                rssi_dbm: -70,
+                ..Default::default()
+            })
+            .await;
+        state
+            .update_host_sample(HostMetricsSample {
+                status: HostStatusV1 {
+                    cpu_temp_dc: 512,
+                    ..Default::default()
+                },
+                cpu_usage_pct: Some(37),
+                net: None,
+                net_rx_bytes_per_s: Some(1_024),
+                net_tx_bytes_per_s: None,
+            })
+            .await;
+        state.update_power_event(1).await;
+        state.set_pd_overload(true).await;
+        clock.advance(std::time::Duration::from_millis(250));
+
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_client(
+            server,
+            state.clone(),
+            Arc::new(ClientCtx::new(&Config::default(), None)),
+        ));
+        let (rd, mut wr) = tokio::io::split(Box::new(client) as Box<dyn Conn>);
+        write_request(
+            &mut wr,
+            &Request::Subscribe {
+                encoding: IpcEncoding::Binary,
+            },
+        )
+        .await
+        .unwrap();
+        let mut sub = Subscription::new(rd, wr);
+        let Some(Reply::Snapshot(mut binary)) = sub.next().await.unwrap() else {
+            panic!("expected a binary snapshot");
+        };
+        let mut json = snapshot_of(state).await;
+        // Taken a moment apart on the wall clock.
+        (binary.unix_ts_ms, json.unix_ts_ms) = (0, 0);
+        assert_eq!(format!("{binary:?}"), format!("{json:?}"));
+        let p = binary.power.as_ref().unwrap();
+        assert_eq!(p.pd_in_contract.as_deref(), Some("15V @ 3A"));
+        assert_eq!((p.temp_dc, p.input_mw), (-55, Some(18_120)));
+        assert!(binary.fault_summary.is_some() && binary.pd_overload);
+        assert!(p.rail_undervoltage);
+        assert_eq!((p.rail_voltage_v(), p.rail_current_a()), (4.5, 0.0));
+
+        // A newer layout, and a truncated frame.
+        assert!(unpack_snapshot(&[FRAME_LAYOUT + 1]).is_err());
+        assert!(unpack_snapshot(&[FRAME_LAYOUT, 0, 0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_reply_split_around_a_timer_is_not_lost() {
+        // A real frame, straight off the daemon's socket.
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_client(
+            server,
+            State::new(),
+            Arc::new(ClientCtx::new(&Config::default(), None)),
+        ));
+        let (mut rd, mut wr) = tokio::io::split(client);
+        write_request(
+            &mut wr,
+            &Request::Subscribe {
+                encoding: IpcEncoding::Binary,
+            },
+        )
+        .await
+        .unwrap();
+        let mut frame = vec![0u8; 3];
+        rd.read_exact(&mut frame).await.unwrap();
+        assert_eq!(frame[0], FRAME_MARK);
+        frame.resize(3 + u16::from_le_bytes([frame[1], frame[2]]) as usize, 0);
+        rd.read_exact(&mut frame[3..]).await.unwrap();
+
+        let (client, mut daemon) = UnixStream::pair().unwrap();
+        let (rd, wr) = tokio::io::split(Box::new(client) as Box<dyn Conn>);
+        let mut sub = Subscription::new(rd, wr);
+        let line = b"{\"type\":\"version\",\"version\":\"9.9.9\"}\n";
+        for (first, rest) in [frame.split_at(2), line.split_at(line.len() - 1)] {
+            daemon.write_all(first).await.unwrap();
+            tokio::select! {
+                r = sub.next() => panic!("a partial reply came through: {:?}", r.map(|_| ())),
+                _ = tokio::time::sleep(Duration::from_millis(20)) => {}
+            }
+            daemon.write_all(rest).await.unwrap();
+            match sub.next().await.unwrap() {
+                Some(Reply::Snapshot(_)) if first[0] == FRAME_MARK => {}
+                Some(Reply::Version { version }) => assert_eq!(version, "9.9.9"),
+                other => panic!("unexpected reply: {other:?}"),
+            }
+        }
+        drop(daemon);
+        assert!(sub.next().await.unwrap().is_none());
+    }
+}
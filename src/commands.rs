@@ -3,16 +3,15 @@
 //! `[commands]` config.
 
 use std::collections::HashSet;
-use std::path::Path;
 use std::sync::Arc;
 
-use tokio::process::Command;
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
 use crate::config::{CommandsConfig, ShutdownConfig};
 use crate::proto::payloads::HostServiceRestartV1;
 use crate::proto::{addr, flag, Frame};
+use crate::shutdown_sm;
 use crate::state::State;
 use crate::transport::OutboundFrame;
 
@@ -25,7 +24,6 @@ const RESP_DENIED: u8 = 2; // kill switch off, or unit not in whitelist
 const RESP_SYSTEMCTL_FAILED: u8 = 3; // systemctl exited non-zero / failed to run
 
 pub struct CommandsHandler {
-    #[allow(dead_code)] // kept for future use (e.g., recording last command in state)
     state: Arc<State>,
     commands_cfg: CommandsConfig,
     shutdown_cfg: ShutdownConfig,
@@ -52,15 +50,31 @@ impl CommandsHandler {
         }
     }
 
+    async fn dry_run(&self) -> bool {
+        self.state.snapshot().await.dry_run
+    }
+
     pub async fn handle_host_shutdown(&self, req: &Frame, out_tx: &mpsc::Sender<OutboundFrame>) {
         info!(src = req.src, seq = req.seq, "host.shutdown REQ");
-        spawn_shutdown_script(&self.shutdown_cfg.script_path).await;
+        if self.dry_run().await {
+            warn!(
+                "[dry-run] would shut down: run {} (action: {})",
+                self.shutdown_cfg.script_path,
+                self.shutdown_cfg.action.systemctl_verb()
+            );
+        } else {
+            let _ = shutdown_sm::trigger_shutdown(&self.shutdown_cfg).await;
+        }
         send_resp(req, out_tx).await;
     }
 
     pub async fn handle_host_reset(&self, req: &Frame, out_tx: &mpsc::Sender<OutboundFrame>) {
         info!(src = req.src, seq = req.seq, "host.reset REQ");
-        if let Err(e) = Command::new("shutdown").args(["-r", "now"]).spawn() {
+        if self.dry_run().await {
+            warn!("[dry-run] would run `shutdown -r now`");
+        } else if let Err(e) =
+            shutdown_sm::privileged(&self.shutdown_cfg, "shutdown", &["-r", "now"]).spawn()
+        {
             error!("spawn `shutdown -r now`: {e}");
         }
         send_resp(req, out_tx).await;
@@ -107,15 +121,24 @@ impl CommandsHandler {
         // We append `.service` for systemd; whitelist entries are stored
         // without the suffix so they match what operators type in the cloud UI.
         let unit_with_suffix = format!("{unit}.service");
+        if self.dry_run().await {
+            warn!("[dry-run] would run `systemctl {action} {unit_with_suffix}`");
+            send_resp_code(req, out_tx, RESP_OK).await;
+            return;
+        }
         info!(unit = %unit, action, "host.service executing systemctl");
         // Await the exit status so the RESP reports the REAL outcome — a
         // fire-and-forget spawn() reports success even when systemctl failed
-        // (wrong/unknown unit, etc.). The agent runs as root, so a non-zero
-        // status is a genuine failure, surfaced to the panel as code_3.
-        let code = match Command::new("systemctl")
-            .args([action, &unit_with_suffix])
-            .status()
-            .await
+        // (wrong/unknown unit, etc.). The agent runs as root (or through
+        // `[shutdown].helper_command`), so a non-zero status is a genuine
+        // failure, surfaced to the panel as code_3.
+        let code = match shutdown_sm::privileged(
+            &self.shutdown_cfg,
+            "systemctl",
+            &[action, &unit_with_suffix],
+        )
+        .status()
+        .await
         {
             Ok(s) if s.success() => RESP_OK,
             Ok(s) => {
@@ -131,18 +154,6 @@ impl CommandsHandler {
     }
 }
 
-async fn spawn_shutdown_script(path: &str) {
-    if Path::new(path).exists() {
-        if let Err(e) = Command::new("sh").arg(path).spawn() {
-            error!("spawn shutdown script {path}: {e}; falling back to shutdown -h now");
-            let _ = Command::new("shutdown").args(["-h", "now"]).spawn();
-        }
-    } else {
-        warn!("shutdown script {path} not found; running shutdown -h now");
-        let _ = Command::new("shutdown").args(["-h", "now"]).spawn();
-    }
-}
-
 async fn send_resp(req: &Frame, out_tx: &mpsc::Sender<OutboundFrame>) {
     let resp = Frame {
         dst: req.src,
@@ -192,6 +203,7 @@ mod tests {
         let shutdown = ShutdownConfig {
             script_path: "/nonexistent".into(),
             delay_seconds: 0,
+            ..crate::config::Config::default().shutdown
         };
         CommandsHandler::new(state, commands, shutdown)
     }
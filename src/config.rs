@@ -1,12 +1,26 @@
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::soc::SocCurve;
 
 pub const DEFAULT_CONFIG_PATH: &str = "/etc/w3p-ups/config.toml";
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+/// `--config -`: read the config from stdin.
+pub const STDIN_CONFIG: &str = "-";
+
+/// How long fetching an `http://` config may take in all, from connecting
+/// to the last byte.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest `http://` response taken for a config; real ones are a few KB.
+const MAX_FETCH_BYTES: usize = 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     pub serial: SerialConfig,
     pub battery: BatteryConfig,
@@ -21,48 +35,400 @@ pub struct Config {
     pub ipc: IpcConfig,
     #[serde(default)]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub capacity: CapacityConfig,
+    #[serde(default)]
+    pub persist: PersistConfig,
+    #[serde(default)]
+    pub power_quality: PowerQualityConfig,
+    #[serde(default)]
+    pub web: WebConfig,
+    #[serde(default)]
+    pub monitor: MonitorConfig,
+    #[serde(default)]
+    pub forward: ForwardConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub privileges: PrivilegesConfig,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SerialConfig {
     /// "auto" to auto-detect, or a path like "/dev/ttyACM0".
     pub port: String,
+    /// With `port = "auto"`: only the USB device with this serial number
+    /// (sysfs `serial`). Empty: detect by product name.
+    #[serde(default)]
+    pub match_serial: String,
+    /// With `port = "auto"` (and no `match_serial`): USB product names that
+    /// mark the UPS, best first. A device matches an entry its product
+    /// string contains.
+    #[serde(default = "default_match_products")]
+    pub match_products: Vec<String>,
     pub baud_rate: u32,
+    /// What the firmware sends.
+    #[serde(default)]
+    pub format: SerialFormat,
+    /// How often the firmware sends a power sample (ms); a gap over twice
+    /// this counts as missed samples. 0 = unknown: gaps are still tracked,
+    /// nothing counts as missed.
+    #[serde(default)]
+    pub expected_interval_ms: u64,
+    /// Warn when the recent missed-sample rate reaches this (%). 0 disables.
+    #[serde(default = "default_missed_warn_pct")]
+    pub missed_warn_pct: u8,
+    /// The daemon writes the port it opened here (one line), on startup and
+    /// after every reconnect, and removes it on exit. Empty disables.
+    #[serde(default = "default_port_file")]
+    pub port_file: String,
+}
+
+impl SerialConfig {
+    pub fn expected_interval(&self) -> Option<std::time::Duration> {
+        (self.expected_interval_ms > 0)
+            .then(|| std::time::Duration::from_millis(self.expected_interval_ms))
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-// Tolerate extra keys here so a v2.0.0 config (which had
-// `voltage_at_zero_pct` / `voltage_at_full_pct`) keeps parsing on v2.0.1.
-// The SOC curve is now a hardcoded LUT (see [`crate::soc`]) — those config
-// knobs would be misleading if kept.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialFormat {
+    /// WUPS binary frames (current firmware).
+    #[default]
+    Wups,
+    /// Legacy `KEY=VALUE` text lines, one per sample (read-only: the
+    /// firmware gets no host.status or command replies it could parse).
+    Kv,
+    /// Whichever decodes first.
+    Auto,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct BatteryConfig {
     /// SOC% below which shutdown is initiated (when on battery).
     pub shutdown_threshold_pct: u8,
+    /// SOC% below which the host shuts down at once: no countdown, and no
+    /// cancel by power returning once one was pending. 0 disables.
+    #[serde(default)]
+    pub critical_threshold_pct: u8,
     /// Margin above threshold required to cancel a pending shutdown.
     #[serde(default = "default_cancel_margin")]
     pub shutdown_cancel_margin_pct: u8,
+    /// What has to recover for a pending shutdown to be cancelled while
+    /// still on battery.
+    #[serde(default)]
+    pub shutdown_cancel_basis: CancelBasis,
+    /// Pack voltage that cancels under the `voltage` / `either` basis.
+    /// 0 = the voltage matching threshold + margin.
+    #[serde(default)]
+    pub shutdown_cancel_vbat_mv: u16,
     /// Input (PD) voltage range considered "on grid". Outside this → on battery.
     pub input_min_valid_mv: u16,
     pub input_max_valid_mv: u16,
+    /// Warn when on grid yet not charging (and not full) for this long (s).
+    /// 0 disables.
+    #[serde(default = "default_not_charging_warn")]
+    pub not_charging_warn_seconds: u64,
+    /// Once the charger reports the pack full, keep counting it as full
+    /// ("maintaining") while on grid at this SOC or above, whatever top-off
+    /// pulses the charger reports in between. 0 disables.
+    #[serde(default = "default_maintenance_soc")]
+    pub maintenance_soc_pct: u8,
+    /// Treat a 0 mV input reading as a sensor glitch (not an outage) when
+    /// the firmware still reports power-good, or the battery isn't
+    /// discharging.
+    #[serde(default = "default_true")]
+    pub input_zero_cross_check: bool,
+    /// After an outage, the input must stay valid this long (s) before power
+    /// counts as restored: until then a countdown keeps running and no
+    /// `on_grid` fires. 0 takes the first good sample.
+    #[serde(default = "default_grid_restore_seconds")]
+    pub grid_restore_seconds: u64,
+    /// This many grid↔battery transitions within `unstable_window_seconds`
+    /// make one "unstable power" episode for the event handlers, instead of
+    /// an `on_battery` / `on_grid` pair each. Shutdown decisions still
+    /// follow every transition. 0 disables.
+    #[serde(default)]
+    pub unstable_transitions: u32,
+    /// The window for `unstable_transitions` (s); the episode ends once the
+    /// input holds this long without a transition.
+    #[serde(default = "default_unstable_window_seconds")]
+    pub unstable_window_seconds: u64,
+    /// Compare the first this many power-good samples after connecting with
+    /// `input_min_valid_mv`, and warn if the supply sits below it (say a
+    /// 5 V USB-C charger against the 8 V default). v2 status only.
+    /// 0 disables.
+    #[serde(default = "default_input_range_check_samples")]
+    pub input_range_check_samples: u32,
+    /// Expected input (PD contract) voltage, e.g. 20000 for a 20 V charger.
+    /// 0 = unset: no deviation reporting.
+    #[serde(default)]
+    pub nominal_input_mv: u16,
+    /// Warn when on grid and the input deviates from nominal by more than
+    /// this (percent). 0 disables the warning; deviation is still reported.
+    #[serde(default)]
+    pub input_deviation_warn_pct: u8,
+    /// Warn when measured input power stays above this share of the
+    /// negotiated PD contract (percent): the charger is maxed out and the
+    /// battery will drain even on grid. v2 status only. 0 disables.
+    #[serde(default = "default_pd_load_warn")]
+    pub pd_load_warn_pct: u8,
+    /// The output rail to the Pi (`vbus_out_mv`) below this is reported at
+    /// once as `rail-undervoltage`: the Pi browns out near 4.63 V. A reading
+    /// of 0 (output off, or not measured) doesn't count. 0 disables.
+    #[serde(default = "default_rail_min_mv")]
+    pub rail_min_mv: u16,
+    /// Once the SOC has been below `shutdown_threshold_pct`, warn if it isn't
+    /// back at this (percent) within `recovery_timeout_minutes` on grid.
+    #[serde(default = "default_recovery_target_soc")]
+    pub recovery_target_soc: u8,
+    /// 0 disables the recovery warning.
+    #[serde(default = "default_recovery_timeout_minutes")]
+    pub recovery_timeout_minutes: u64,
+    /// A single-sample SOC drop larger than this (percentage points) is held
+    /// back from the shutdown decision as a likely firmware glitch. 0
+    /// disables the filter.
+    #[serde(default = "default_soc_glitch_drop")]
+    pub soc_glitch_drop_pct: u8,
+    /// Samples a suspect drop must persist before it is believed.
+    #[serde(default = "default_soc_glitch_samples")]
+    pub soc_glitch_samples: u8,
+    /// Consecutive plausible samples needed after (re)connecting before the
+    /// shutdown logic acts on them. 0 = act on the first one.
+    #[serde(default = "default_min_valid_samples")]
+    pub min_valid_samples: u32,
+    /// Frames in a row the v2 power-good flag must hold a new value before
+    /// it is believed. 1 takes every change at once.
+    #[serde(default = "default_power_good_debounce_samples")]
+    pub power_good_debounce_samples: u32,
+    /// Voltage→SOC curve. `stock` is the Web3 Pi UPS cell, as on the OLED.
+    #[serde(default)]
+    pub chemistry: Chemistry,
+    /// Cells in series; the pack voltage is divided by this before the
+    /// per-cell lookup.
+    #[serde(default = "default_cell_count")]
+    pub cell_count: u8,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Chemistry {
+    /// Panasonic CGR18650CH, the firmware's table.
+    #[default]
+    Stock,
+    /// Generic Li-ion, 4.20 V full.
+    Liion,
+    /// LiFePO4, 3.40 V resting full; flat between ~20 % and ~90 %.
+    Lifepo4,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
+/// Largest `cell_count` whose pack voltage still fits the wire format (u16 mV).
+pub const MAX_CELLS: u8 = 12;
+
+/// Reading a pending shutdown's cancellation hysteresis is applied to.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelBasis {
+    /// SOC back at threshold + margin.
+    #[default]
+    Soc,
+    /// Pack voltage back at `shutdown_cancel_vbat_mv`. Reacts as soon as
+    /// charge current resumes, before the SOC estimate catches up.
+    Voltage,
+    /// Whichever of the two recovers first.
+    Either,
+}
+
+impl BatteryConfig {
+    /// The configured pack's voltage→SOC mapping.
+    pub fn soc_curve(&self) -> SocCurve {
+        SocCurve::new(self.chemistry, self.cell_count)
+    }
+
+    /// SOC% of a pack voltage on [`Self::soc_curve`].
+    pub fn soc_pct(&self, vbat_mv: u16) -> u8 {
+        self.soc_curve().soc_pct(vbat_mv)
+    }
+
+    /// Pack voltage at which `voltage` / `either` cancel a pending shutdown.
+    pub fn cancel_vbat_mv(&self) -> u16 {
+        if self.shutdown_cancel_vbat_mv > 0 {
+            return self.shutdown_cancel_vbat_mv;
+        }
+        let pct = self
+            .shutdown_threshold_pct
+            .saturating_add(self.shutdown_cancel_margin_pct);
+        self.soc_curve().pack_mv(pct)
+    }
+
+    /// Whether the battery has recovered enough to cancel a pending shutdown
+    /// (the on-battery case; power coming back always cancels).
+    pub fn battery_recovered(&self, soc_pct: u8, vbat_mv: u16) -> bool {
+        let by_soc = soc_pct
+            >= self
+                .shutdown_threshold_pct
+                .saturating_add(self.shutdown_cancel_margin_pct);
+        let by_voltage = || vbat_mv >= self.cancel_vbat_mv();
+        match self.shutdown_cancel_basis {
+            CancelBasis::Soc => by_soc,
+            CancelBasis::Voltage => by_voltage(),
+            CancelBasis::Either => by_soc || by_voltage(),
+        }
+    }
+
+    /// Signed deviation of `vbus_in_mv` from `nominal_input_mv`, in percent.
+    /// `None` if no nominal is configured.
+    pub fn input_deviation_pct(&self, vbus_in_mv: u16) -> Option<f32> {
+        (self.nominal_input_mv > 0).then(|| {
+            let nominal = self.nominal_input_mv as f32;
+            (vbus_in_mv as f32 - nominal) / nominal * 100.0
+        })
+    }
+
+    /// `vbus_out_mv` is below `rail_min_mv` (and actually measured).
+    pub fn rail_undervoltage(&self, vbus_out_mv: u16) -> bool {
+        vbus_out_mv > 0 && vbus_out_mv < self.rail_min_mv
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ShutdownConfig {
     pub script_path: String,
     pub delay_seconds: u64,
+    /// What "shutdown" means for this host. Passed to the script as
+    /// `W3P_UPS_SHUTDOWN_ACTION`; run directly if the script is missing.
+    #[serde(default)]
+    pub action: ShutdownAction,
+    /// Power back mid-countdown with SOC still under `recovery_soc`: keep the
+    /// shutdown armed instead of cancelling, until the pack has charged
+    /// that far. Off: grid return always cancels.
+    #[serde(default)]
+    pub require_recovery_soc: bool,
+    #[serde(default = "default_recovery_soc")]
+    pub recovery_soc: u8,
+    /// After a cancel, don't re-arm for this long (s) even if the battery
+    /// is low again: flapping power then doesn't churn arm/cancel. The
+    /// critical floor still applies. 0 re-arms at once.
+    #[serde(default)]
+    pub rearm_cooldown_seconds: u64,
+    /// The daemon is stopped (package upgrade, `systemctl stop`) while a
+    /// countdown runs: exit without shutting down, or shut down first.
+    #[serde(default)]
+    pub on_sigterm_during_countdown: SigtermPolicy,
+    /// Fail-safe, off by default and aggressive: no UPS data for
+    /// `serial_loss_timeout_seconds` while the last sample was on battery
+    /// under `serial_loss_soc_pct` shuts the host down rather than letting
+    /// it run blind until the pack dies. A USB cable knocked loose
+    /// mid-outage then powers the host off too.
+    #[serde(default)]
+    pub on_serial_loss_when_low: bool,
+    #[serde(default = "default_serial_loss_timeout")]
+    pub serial_loss_timeout_seconds: u64,
+    #[serde(default = "default_serial_loss_soc")]
+    pub serial_loss_soc_pct: u8,
+    /// The output rail to the Pi (`vbus_out_mv`) below this for 3 samples in
+    /// a row shuts the host down at once, whatever the SOC and without the
+    /// countdown: a Pi crashing from undervoltage can corrupt its SD card.
+    /// Keep it under `[battery].rail_min_mv`, which warns first. A reading of
+    /// 0 doesn't count. 0 (the default) disables.
+    #[serde(default)]
+    pub rail_critical_mv: u16,
+    /// For this long after a config reload, a low battery doesn't arm the
+    /// shutdown; it is logged instead. A tightened threshold then can't
+    /// shut the host down before the operator has seen it at work. A
+    /// running countdown carries on, and the critical floor and
+    /// `rail_critical_mv` still apply. 0 (the default) disables.
+    #[serde(default)]
+    pub reload_grace_seconds: u64,
+    /// Refuse to start when the startup check finds the shutdown path
+    /// unusable (no interpreter, no `systemctl`, no privilege), instead of
+    /// only warning. `daemon --allow-no-shutdown` overrides it.
+    #[serde(default)]
+    pub require_prerequisites: bool,
+    /// Run the shutdown script and `systemctl` through this, e.g.
+    /// `["sudo", "-n"]` or a setuid helper: the argv of the command is
+    /// appended to it. For a daemon without root (`[privileges].user`).
+    /// Empty runs them directly.
+    #[serde(default)]
+    pub helper_command: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SigtermPolicy {
+    /// Abandon the countdown; the host stays up, unprotected until the
+    /// daemon runs again.
+    #[default]
+    Abort,
+    /// Skip the rest of the delay and shut down now, then exit.
+    Proceed,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownAction {
+    #[default]
+    Poweroff,
+    Reboot,
+    Halt,
+    /// Suspend to RAM. The UPS keeps the Pi powered while suspended.
+    Suspend,
+    /// Suspend to disk; resumes where it left off once power returns.
+    Hibernate,
+}
+
+impl ShutdownAction {
+    /// The `systemctl` verb for this action.
+    pub fn systemctl_verb(self) -> &'static str {
+        match self {
+            Self::Poweroff => "poweroff",
+            Self::Reboot => "reboot",
+            Self::Halt => "halt",
+            Self::Suspend => "suspend",
+            Self::Hibernate => "hibernate",
+        }
+    }
+
+    /// Suspend or hibernate: the host comes back, and so must the
+    /// shutdown logic.
+    pub fn is_sleep(self) -> bool {
+        matches!(self, Self::Suspend | Self::Hibernate)
+    }
+
+    /// Sleep states need kernel support; check `/sys/power/state` lists the
+    /// one we'd use. Power-state actions are always available.
+    pub fn check_supported(self) -> Result<()> {
+        let needed = match self {
+            Self::Suspend => "mem",
+            Self::Hibernate => "disk",
+            _ => return Ok(()),
+        };
+        let states = fs::read_to_string("/sys/power/state").context("read /sys/power/state")?;
+        if states.split_whitespace().any(|s| s == needed) {
+            Ok(())
+        } else {
+            anyhow::bail!(
+                "kernel does not support `{needed}` sleep (/sys/power/state: {})",
+                states.trim()
+            )
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct HostMetricsConfig {
     /// Period between host.status emissions to RP2040 (s). 0 disables.
     pub interval_seconds: u64,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, default)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct CommandsConfig {
     /// Master kill switch for all `host.service.{start,stop,restart}` REQs.
     pub allow_service_restart: bool,
@@ -73,8 +439,8 @@ pub struct CommandsConfig {
 /// systemd unit names for the three Ethereum-client roles the agent monitors.
 /// We report the unit's *service* state (running/stopped/failed) only — never
 /// chain sync status. An empty string disables monitoring for that role.
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, default)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct EthClientsConfig {
     /// Execution-layer client unit (e.g. `geth`, `reth`, `besu`).
     pub execution: String,
@@ -84,25 +450,380 @@ pub struct EthClientsConfig {
     pub validator: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, default)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct IpcConfig {
     pub socket_path: String,
+    /// Also serve IPC (read-only) on this TCP `ip:port`, e.g.
+    /// `127.0.0.1:9186`. Empty disables it.
+    pub tcp_listen: String,
+    /// Shared secret TCP clients must present (`auth` op) before anything
+    /// else. Empty leaves the TCP listener open. The Unix socket never asks.
+    pub token: String,
+    /// A power sample older than this (s) marks the snapshot `stale` and
+    /// fails `healthcheck`, even while the socket answers. 0 disables.
+    pub max_sample_age_seconds: u64,
+    /// Bind here instead when `socket_path`'s directory is read-only or not
+    /// writable (hardened images). Empty makes that a startup error.
+    pub fallback_socket_path: String,
+    /// How `watch` / `budget` ask for their once-a-second snapshots. The
+    /// daemon serves each subscriber the encoding it asks for.
+    pub encoding: IpcEncoding,
+}
+
+/// Wire encoding of subscribed snapshots (`[ipc].encoding`).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpcEncoding {
+    /// One JSON line per snapshot: readable, and what every client speaks.
+    #[default]
+    Json,
+    /// Packed little-endian frames; cheaper to build on a Pi Zero.
+    Binary,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields, default)]
+impl IpcConfig {
+    pub fn max_sample_age(&self) -> Option<std::time::Duration> {
+        (self.max_sample_age_seconds > 0)
+            .then(|| std::time::Duration::from_secs(self.max_sample_age_seconds))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct LoggingConfig {
     /// trace | debug | info | warn | error
     pub level: String,
     /// When true, emit logs through journald (in addition to stderr).
     pub journald: bool,
+    /// Period of the one-line power status log (s). 0 disables it.
+    pub status_interval_seconds: u64,
+    /// Which fields the status line shows, in order.
+    pub status_fields: Vec<StatusField>,
+    /// JSON record of why and how the daemon last exited, rewritten on every
+    /// exit. Empty disables it.
+    pub exit_report_file: String,
+}
+
+/// A field of the periodic status log line (`[logging].status_fields`).
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusField {
+    /// GRID / BATTERY.
+    Source,
+    Vin,
+    Vout,
+    Iout,
+    Vbat,
+    Ibat,
+    Soc,
+    Charge,
+    Temp,
+    Faults,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CapacityConfig {
+    /// Where measured full↔empty spans are kept across restarts. Empty
+    /// keeps them in memory only.
+    pub state_file: String,
+}
+
+/// How often state files (see [`crate::store`]) may be rewritten.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct PersistConfig {
+    /// Minimum time between two writes of the same state file (s); changes
+    /// in between are coalesced. Spares the SD card.
+    pub min_write_interval_seconds: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerQualityConfig {
+    /// Upper bounds (mV) of the input-voltage histogram buckets, plus an
+    /// implicit one above the last. Empty disables the histogram.
+    pub input_buckets_mv: Vec<u16>,
+    /// Record an incident when the input voltage falls this much (mV) from
+    /// one sample to the next. 0 disables the recorder.
+    pub incident_drop_mv: u16,
+    /// Samples kept from before the drop.
+    pub incident_samples_before: usize,
+    /// Samples recorded after the drop.
+    pub incident_samples_after: usize,
+    /// Directory for `incident-<unix_ms>.jsonl` files; empty logs the
+    /// window instead.
+    pub incident_dir: String,
+}
+
+impl Default for PowerQualityConfig {
+    fn default() -> Self {
+        Self {
+            input_buckets_mv: Vec::new(),
+            incident_drop_mv: 0,
+            incident_samples_before: 30,
+            incident_samples_after: 30,
+            incident_dir: "/var/lib/w3p-ups/incidents".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebConfig {
+    /// Serve the read-only browser dashboard.
+    pub enabled: bool,
+    /// `ip:port`. There is no authentication; keep it on localhost unless the
+    /// network is trusted.
+    pub listen_addr: String,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_addr: "127.0.0.1:9187".into(),
+        }
+    }
+}
+
+/// `w3p-ups watch` (alias `monitor`) display options.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MonitorConfig {
+    /// When `watch` ends, print the last reading as one line so it stays in
+    /// the scrollback.
+    pub print_on_exit: bool,
+    /// Most redraws per second. Snapshots in between are folded into the
+    /// next redraw.
+    pub refresh_rate_hz: f64,
+    /// Colour the input voltage on a terminal (unless `NO_COLOR` is set).
+    pub color: bool,
+    /// Input further than this (percent) from its expected voltage (the
+    /// nominal, else the PD contract) shows yellow.
+    pub input_warn_pct: f32,
+    /// Further than this shows red, as does an input outside the valid
+    /// range.
+    pub input_alarm_pct: f32,
+    /// Show the source-side voltage / current (`VS` / `IS`) in `status` and
+    /// `watch` when the firmware reports them. Off by default: current
+    /// CH32X firmware fills these fields with misleading values.
+    pub show_source: bool,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            print_on_exit: false,
+            refresh_rate_hz: 4.0,
+            color: true,
+            input_warn_pct: 5.0,
+            input_alarm_pct: 15.0,
+            show_source: false,
+        }
+    }
+}
+
+impl MonitorConfig {
+    /// Shortest time between two redraws.
+    pub fn refresh_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(1.0 / self.refresh_rate_hz)
+    }
+}
+
+/// Telemetry forwarding to integrations that aren't built in.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ForwardConfig {
+    pub exec: ExecForwardConfig,
+}
+
+/// `[forward.exec]`: a long-lived child process fed one JSON line per power
+/// sample on stdin (see [`crate::forward`]).
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ExecForwardConfig {
+    /// Program and arguments, run without a shell. Empty disables it.
+    pub command: Vec<String>,
+}
+
+/// `[archive]`: every power sample appended to a rotated set of JSON-lines
+/// files (see [`crate::archive`]). Read at startup.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// The current file; rotated ones get `.1`, `.2`, …. Empty disables
+    /// the archive.
+    pub path: String,
+    /// Rotate once the file reaches this size (MiB). 0: no size limit.
+    pub max_file_mb: u64,
+    /// Rotate once the file is this old (h). 0: no age limit.
+    pub rotate_hours: u64,
+    /// Rotated files kept besides the current one.
+    pub keep_files: usize,
+    /// Stamp lines with an RFC 3339 `ts` instead of `unix_ts_ms`.
+    pub rfc3339: bool,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            max_file_mb: 10,
+            rotate_hours: 24,
+            keep_files: 7,
+            rfc3339: false,
+        }
+    }
+}
+
+/// Upper bound on each `[health].*_weight`, so the weighted sum can't
+/// overflow.
+pub const MAX_HEALTH_WEIGHT: u32 = 1000;
+
+/// `[health]`: the 0–100 power health score and how much each part of it
+/// counts (see [`crate::health`]). Read at startup.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    /// Samples the input spread and SOC trend are judged over.
+    pub window_samples: usize,
+    pub input_weight: u32,
+    pub charging_weight: u32,
+    pub temperature_weight: u32,
+    pub soc_weight: u32,
+    pub faults_weight: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_samples: 60,
+            input_weight: 25,
+            charging_weight: 20,
+            temperature_weight: 15,
+            soc_weight: 20,
+            faults_weight: 20,
+        }
+    }
+}
+
+/// Testing hooks. Everything here is off by default and should stay off in
+/// production.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DebugConfig {
+    /// Accept the IPC `inject` op, which replaces the live power reading with
+    /// an operator-supplied (synthetic) one.
+    pub allow_inject: bool,
+    /// Log shutdowns, reboots, service commands and event hooks instead of
+    /// performing them (`--dry-run`). Read at startup.
+    pub dry_run: bool,
+}
+
+/// Drop root once the daemon is set up. Read at startup.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PrivilegesConfig {
+    /// Switch to this user after binding the IPC socket. Empty: keep
+    /// running as started.
+    pub user: String,
+    /// And this group; empty: the user's primary group.
+    pub group: String,
 }
 
 fn default_cancel_margin() -> u8 {
     5
 }
 
+fn default_missed_warn_pct() -> u8 {
+    5
+}
+
+fn default_port_file() -> String {
+    "/run/w3p-ups/port".into()
+}
+
+fn default_not_charging_warn() -> u64 {
+    600
+}
+
+fn default_recovery_target_soc() -> u8 {
+    80
+}
+
+fn default_recovery_timeout_minutes() -> u64 {
+    360
+}
+
+fn default_pd_load_warn() -> u8 {
+    90
+}
+
+fn default_rail_min_mv() -> u16 {
+    4630
+}
+
+fn default_soc_glitch_drop() -> u8 {
+    30
+}
+
+fn default_soc_glitch_samples() -> u8 {
+    3
+}
+
+fn default_cell_count() -> u8 {
+    2
+}
+
+fn default_min_valid_samples() -> u32 {
+    3
+}
+
+fn default_maintenance_soc() -> u8 {
+    95
+}
+
+fn default_serial_loss_timeout() -> u64 {
+    60
+}
+
+fn default_serial_loss_soc() -> u8 {
+    20
+}
+
+fn default_grid_restore_seconds() -> u64 {
+    5
+}
+
+fn default_unstable_window_seconds() -> u64 {
+    600
+}
+
+/// Production firmware, then legacy bring-up firmware on a bare Pico.
+pub fn default_match_products() -> Vec<String> {
+    vec!["Web3_Pi_UPS".into(), "Pico".into()]
+}
+
+fn default_input_range_check_samples() -> u32 {
+    5
+}
+
+fn default_power_good_debounce_samples() -> u32 {
+    3
+}
+
+fn default_recovery_soc() -> u8 {
+    30
+}
+
+fn default_true() -> bool {
+    true
+}
+
 impl Default for HostMetricsConfig {
     fn default() -> Self {
         // 30 s, sized against the M.2 modem's ~500 MB/mo LTE data plan
@@ -148,6 +869,27 @@ impl Default for IpcConfig {
     fn default() -> Self {
         Self {
             socket_path: "/run/w3p-ups/agent.sock".into(),
+            tcp_listen: String::new(),
+            token: String::new(),
+            max_sample_age_seconds: 10,
+            fallback_socket_path: String::new(),
+            encoding: IpcEncoding::default(),
+        }
+    }
+}
+
+impl Default for CapacityConfig {
+    fn default() -> Self {
+        Self {
+            state_file: "/var/lib/w3p-ups/capacity.json".into(),
+        }
+    }
+}
+
+impl Default for PersistConfig {
+    fn default() -> Self {
+        Self {
+            min_write_interval_seconds: 300,
         }
     }
 }
@@ -157,6 +899,17 @@ impl Default for LoggingConfig {
         Self {
             level: "info".into(),
             journald: false,
+            status_interval_seconds: 60,
+            status_fields: vec![
+                StatusField::Source,
+                StatusField::Vin,
+                StatusField::Vbat,
+                StatusField::Ibat,
+                StatusField::Soc,
+                StatusField::Charge,
+                StatusField::Temp,
+            ],
+            exit_report_file: "/var/lib/w3p-ups/exit.json".into(),
         }
     }
 }
@@ -167,32 +920,1021 @@ impl Default for Config {
             serial: SerialConfig {
                 port: "auto".into(),
                 baud_rate: 115200,
+                format: SerialFormat::default(),
+                match_serial: String::new(),
+                match_products: default_match_products(),
+                expected_interval_ms: 0,
+                missed_warn_pct: default_missed_warn_pct(),
+                port_file: default_port_file(),
             },
             battery: BatteryConfig {
                 shutdown_threshold_pct: 10,
+                critical_threshold_pct: 0,
                 shutdown_cancel_margin_pct: 5,
+                shutdown_cancel_basis: CancelBasis::default(),
+                shutdown_cancel_vbat_mv: 0,
                 input_min_valid_mv: 8000,
                 input_max_valid_mv: 26000,
+                not_charging_warn_seconds: default_not_charging_warn(),
+                maintenance_soc_pct: default_maintenance_soc(),
+                input_zero_cross_check: true,
+                grid_restore_seconds: default_grid_restore_seconds(),
+                unstable_transitions: 0,
+                unstable_window_seconds: default_unstable_window_seconds(),
+                input_range_check_samples: default_input_range_check_samples(),
+                nominal_input_mv: 0,
+                input_deviation_warn_pct: 0,
+                pd_load_warn_pct: default_pd_load_warn(),
+                rail_min_mv: default_rail_min_mv(),
+                recovery_target_soc: default_recovery_target_soc(),
+                recovery_timeout_minutes: default_recovery_timeout_minutes(),
+                soc_glitch_drop_pct: default_soc_glitch_drop(),
+                soc_glitch_samples: default_soc_glitch_samples(),
+                min_valid_samples: default_min_valid_samples(),
+                power_good_debounce_samples: default_power_good_debounce_samples(),
+                chemistry: Chemistry::default(),
+                cell_count: default_cell_count(),
             },
             shutdown: ShutdownConfig {
                 script_path: "/etc/w3p-ups/shutdown.sh".into(),
                 delay_seconds: 30,
+                action: ShutdownAction::default(),
+                require_recovery_soc: false,
+                recovery_soc: default_recovery_soc(),
+                rearm_cooldown_seconds: 0,
+                on_sigterm_during_countdown: SigtermPolicy::default(),
+                on_serial_loss_when_low: false,
+                serial_loss_timeout_seconds: default_serial_loss_timeout(),
+                serial_loss_soc_pct: default_serial_loss_soc(),
+                rail_critical_mv: 0,
+                reload_grace_seconds: 0,
+                require_prerequisites: false,
+                helper_command: Vec::new(),
             },
             host_metrics: HostMetricsConfig::default(),
             commands: CommandsConfig::default(),
             eth_clients: EthClientsConfig::default(),
             ipc: IpcConfig::default(),
             logging: LoggingConfig::default(),
+            capacity: CapacityConfig::default(),
+            persist: PersistConfig::default(),
+            power_quality: PowerQualityConfig::default(),
+            web: WebConfig::default(),
+            monitor: MonitorConfig::default(),
+            forward: ForwardConfig::default(),
+            archive: ArchiveConfig::default(),
+            health: HealthConfig::default(),
+            debug: DebugConfig::default(),
+            privileges: PrivilegesConfig::default(),
         }
     }
 }
 
-pub fn load(path: &str) -> Result<Config> {
-    if Path::new(path).exists() {
-        let content = fs::read_to_string(path).with_context(|| format!("read config: {path}"))?;
-        toml::from_str(&content).with_context(|| format!("parse config: {path}"))
+/// Load `path`, or defaults if it doesn't exist, with any drop-ins (see
+/// [`dropin_files`]) merged over it. `path` may also be `-` (stdin) or an
+/// `http://` URL; see [`read_source`]. The second value lists
+/// config keys this build doesn't know; they are ignored rather than fatal so
+/// a config written for a newer release still starts an older binary. The
+/// caller decides how to surface them (logging may not be up yet).
+pub fn load(path: &str, config_dir: Option<&Path>) -> Result<(Config, Vec<String>)> {
+    load_text(path, read_source(path)?, config_dir)
+}
+
+/// [`load`] with the text `path` names already read; `None`: no such file.
+pub fn load_text(
+    path: &str,
+    content: Option<String>,
+    config_dir: Option<&Path>,
+) -> Result<(Config, Vec<String>)> {
+    let dropins = dropin_files(path, config_dir)?;
+    if dropins.is_empty() {
+        // Parse straight from the text so errors point at its lines.
+        return match content {
+            Some(content) => parse(&content).with_context(|| format!("parse config: {path}")),
+            // Return defaults; caller logs the situation.
+            None => Ok((Config::default(), Vec::new())),
+        };
+    }
+    let mut merged = match content {
+        Some(content) => {
+            toml::from_str(&content).with_context(|| format!("parse config: {path}"))?
+        }
+        None => toml::Table::new(),
+    };
+    for file in &dropins {
+        let content = fs::read_to_string(file)
+            .with_context(|| format!("read config drop-in: {}", file.display()))?;
+        let overlay: toml::Table = toml::from_str(&content)
+            .with_context(|| format!("parse config drop-in: {}", file.display()))?;
+        merge_tables(&mut merged, overlay);
+    }
+    let cfg = toml::Value::Table(merged.clone()).try_into();
+    check(merged, cfg).with_context(|| {
+        format!(
+            "parse config: {path} with drop-ins from {}",
+            dropins[0].parent().unwrap_or(Path::new("")).display()
+        )
+    })
+}
+
+/// The config text `path` names: a file (`None` if there is none), all of
+/// stdin for `-`, or the body of an `http://` URL, fetched with a deadline
+/// and a size cap. Blocks; async callers run it on a blocking thread.
+/// https needs TLS, which this build doesn't carry.
+pub fn read_source(path: &str) -> Result<Option<String>> {
+    if path == STDIN_CONFIG {
+        return std::io::read_to_string(std::io::stdin())
+            .context("read config from stdin")
+            .map(Some);
+    }
+    if is_url(path) {
+        return fetch(path, FETCH_TIMEOUT, MAX_FETCH_BYTES)
+            .with_context(|| format!("fetch config: {path}"))
+            .map(Some);
+    }
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(path)
+        .with_context(|| format!("read config: {path}"))
+        .map(Some)
+}
+
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// `path` names a file, not stdin or a URL.
+pub fn is_file_path(path: &str) -> bool {
+    path != STDIN_CONFIG && !is_url(path)
+}
+
+/// A plain HTTP/1.0 GET; anything but 200 is an error, as is a response
+/// that takes longer than `timeout` or is bigger than `max_bytes`.
+fn fetch(url: &str, timeout: Duration, max_bytes: usize) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    let Some(rest) = url.strip_prefix("http://") else {
+        anyhow::bail!(
+            "https is not supported by this build; fetch it yourself and pipe it in, \
+             e.g. `curl -fsS {url} | w3p-ups --config - daemon`"
+        );
+    };
+    let (authority, path) = match rest.split_once('/') {
+        Some((authority, path)) => (authority, format!("/{path}")),
+        None => (rest, "/".to_string()),
+    };
+    let addr = if authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'))
+    {
+        authority.to_string()
     } else {
-        // Return defaults; caller logs the situation.
-        Ok(Config::default())
+        format!("{authority}:80")
+    };
+    let sock = addr
+        .to_socket_addrs()
+        .with_context(|| format!("resolve {authority}"))?
+        .next()
+        .with_context(|| format!("resolve {authority}: no address"))?;
+    // Each step may only use what is left of the deadline, so a server
+    // trickling bytes can't keep a reload waiting.
+    let left = || {
+        deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+            .with_context(|| format!("no complete answer within {} s", timeout.as_secs_f32()))
+    };
+    let mut stream =
+        TcpStream::connect_timeout(&sock, left()?).with_context(|| format!("connect {addr}"))?;
+    stream.set_write_timeout(Some(left()?))?;
+    // One write: `write!` on the bare stream would send each piece as its
+    // own segment.
+    let request = format!(
+        "GET {path} HTTP/1.0\r\nHost: {authority}\r\nUser-Agent: w3p-ups/{}\r\n\r\n",
+        crate::VERSION
+    );
+    stream
+        .write_all(request.as_bytes())
+        .context("send request")?;
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        stream.set_read_timeout(Some(left()?))?;
+        let n = match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            // The read timeout is what was left of the deadline.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e).context("read response"),
+        };
+        if response.len() + n > max_bytes {
+            anyhow::bail!("response is over {} KB", max_bytes / 1024);
+        }
+        response.extend_from_slice(&chunk[..n]);
+    }
+    let response = String::from_utf8(response).context("response is not UTF-8")?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .context("malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        anyhow::bail!("server answered {status:?}");
+    }
+    Ok(body.to_string())
+}
+
+/// The `*.toml` files, in lexical order, of `config_dir` (which must exist)
+/// or else of `config.d/` next to `path` (if there is one; stdin and URLs
+/// have none). They are merged over the main file in that order, so a
+/// later file wins.
+pub fn dropin_files(path: &str, config_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let dir = match config_dir {
+        Some(dir) => {
+            if !dir.is_dir() {
+                anyhow::bail!("config dir not found: {}", dir.display());
+            }
+            dir.to_path_buf()
+        }
+        None if !is_file_path(path) => return Ok(Vec::new()),
+        None => {
+            let dir = Path::new(path).with_file_name("config.d");
+            if !dir.is_dir() {
+                return Ok(Vec::new());
+            }
+            dir
+        }
+    };
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(&dir).with_context(|| format!("read config dir: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "toml") && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Tables merge key by key, recursively; anything else in `overlay`
+/// (values, arrays) replaces what `base` had.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge_tables(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn parse(content: &str) -> Result<(Config, Vec<String>)> {
+    let raw: toml::Table = toml::from_str(content)?;
+    check(raw, toml::from_str(content))
+}
+
+/// Validate the deserialized `cfg` and list the keys of `raw` it ignored.
+fn check(raw: toml::Table, cfg: Result<Config, toml::de::Error>) -> Result<(Config, Vec<String>)> {
+    let cfg = match cfg {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            // A misspelt *required* key surfaces as "missing field"; point at
+            // the likely culprit.
+            let known = toml::Table::try_from(Config::default()).context("serialize defaults")?;
+            let mut unknown = Vec::new();
+            collect_unknown(&raw, &known, "", &mut unknown);
+            let hints: Vec<String> = unknown.iter().map(UnknownKey::describe).collect();
+            if hints.is_empty() {
+                return Err(e.into());
+            }
+            return Err(anyhow::Error::new(e).context(hints.join("; ")));
+        }
+    };
+    let mut warnings = cfg.validate()?;
+    // Every key serde consumed round-trips through Serialize; whatever is in
+    // the file but not in the round-trip was ignored.
+    let known = toml::Table::try_from(&cfg).context("re-serialize config")?;
+    let mut unknown = Vec::new();
+    collect_unknown(&raw, &known, "", &mut unknown);
+    warnings.extend(unknown.iter().map(|k| format!("{} ignored", k.describe())));
+    Ok((cfg, warnings))
+}
+
+impl Config {
+    /// Checks across fields and sections that each parse fine on their own
+    /// but contradict each other. Contradictions are errors; settings that
+    /// merely go unused are returned as warnings.
+    fn validate(&self) -> Result<Vec<String>> {
+        let b = &self.battery;
+        let mut warnings = Vec::new();
+        if !(1..=MAX_CELLS).contains(&b.cell_count) {
+            anyhow::bail!(
+                "[battery].cell_count must be 1–{MAX_CELLS}, got {}",
+                b.cell_count
+            );
+        }
+        if b.shutdown_threshold_pct > 100 {
+            anyhow::bail!(
+                "[battery].shutdown_threshold_pct must be 0–100, got {}",
+                b.shutdown_threshold_pct
+            );
+        }
+        if b.critical_threshold_pct > 0 && b.critical_threshold_pct >= b.shutdown_threshold_pct {
+            anyhow::bail!(
+                "[battery].critical_threshold_pct ({}) must be below shutdown_threshold_pct ({}), \
+                 or 0 to disable it; otherwise the shutdown countdown never runs",
+                b.critical_threshold_pct,
+                b.shutdown_threshold_pct
+            );
+        }
+        let cancel_pct = b.shutdown_threshold_pct as u16 + b.shutdown_cancel_margin_pct as u16;
+        let cancel_by_soc =
+            b.shutdown_cancel_basis != CancelBasis::Voltage || b.shutdown_cancel_vbat_mv == 0;
+        if cancel_pct > 100 && cancel_by_soc {
+            anyhow::bail!(
+                "[battery].shutdown_threshold_pct + shutdown_cancel_margin_pct is {cancel_pct}%, \
+                 above 100%: a pending shutdown could never be cancelled on battery"
+            );
+        }
+        if b.shutdown_cancel_vbat_mv > 0 {
+            if b.shutdown_cancel_basis == CancelBasis::Soc {
+                warnings.push(
+                    "[battery].shutdown_cancel_vbat_mv is set but unused with \
+                     shutdown_cancel_basis = \"soc\""
+                        .to_string(),
+                );
+            } else {
+                let threshold_mv = b.soc_curve().pack_mv(b.shutdown_threshold_pct);
+                if b.shutdown_cancel_vbat_mv <= threshold_mv {
+                    anyhow::bail!(
+                        "[battery].shutdown_cancel_vbat_mv ({} mV) must be above the pack voltage \
+                         at shutdown_threshold_pct ({threshold_mv} mV); otherwise a pending \
+                         shutdown is cancelled as soon as it starts",
+                        b.shutdown_cancel_vbat_mv
+                    );
+                }
+            }
+        }
+        if b.input_min_valid_mv >= b.input_max_valid_mv {
+            anyhow::bail!(
+                "[battery].input_min_valid_mv ({}) must be below input_max_valid_mv ({}); \
+                 otherwise no input ever counts as on grid",
+                b.input_min_valid_mv,
+                b.input_max_valid_mv
+            );
+        }
+        if b.nominal_input_mv > 0
+            && !(b.input_min_valid_mv..=b.input_max_valid_mv).contains(&b.nominal_input_mv)
+        {
+            anyhow::bail!(
+                "[battery].nominal_input_mv ({}) is outside input_min_valid_mv..input_max_valid_mv \
+                 ({}..{}): the charger's own voltage would read as on battery",
+                b.nominal_input_mv,
+                b.input_min_valid_mv,
+                b.input_max_valid_mv
+            );
+        }
+        if b.input_deviation_warn_pct > 0 && b.nominal_input_mv == 0 {
+            warnings.push(
+                "[battery].input_deviation_warn_pct is set but unused without nominal_input_mv"
+                    .to_string(),
+            );
+        }
+        if b.recovery_timeout_minutes > 0
+            && !(b.shutdown_threshold_pct < b.recovery_target_soc && b.recovery_target_soc <= 100)
+        {
+            anyhow::bail!(
+                "[battery].recovery_target_soc ({}) must be above shutdown_threshold_pct ({}) \
+                 and at most 100, or set recovery_timeout_minutes = 0 to disable the check",
+                b.recovery_target_soc,
+                b.shutdown_threshold_pct
+            );
+        }
+        if b.unstable_transitions == 1
+            || (b.unstable_transitions > 0 && b.unstable_window_seconds == 0)
+        {
+            anyhow::bail!(
+                "[battery].unstable_transitions needs at least 2 transitions (got {}) within \
+                 unstable_window_seconds above 0 (got {})",
+                b.unstable_transitions,
+                b.unstable_window_seconds
+            );
+        }
+        if b.maintenance_soc_pct > 100 {
+            anyhow::bail!(
+                "[battery].maintenance_soc_pct must be 0–100, got {}",
+                b.maintenance_soc_pct
+            );
+        }
+        if self.shutdown.recovery_soc > 100 {
+            anyhow::bail!(
+                "[shutdown].recovery_soc must be 0–100, got {}",
+                self.shutdown.recovery_soc
+            );
+        }
+        if self.web.enabled
+            && !self.ipc.tcp_listen.is_empty()
+            && self.web.listen_addr == self.ipc.tcp_listen
+        {
+            anyhow::bail!(
+                "[web].listen_addr and [ipc].tcp_listen are both {}; they need different ports",
+                self.ipc.tcp_listen
+            );
+        }
+        if !self.ipc.fallback_socket_path.is_empty()
+            && self.ipc.fallback_socket_path == self.ipc.socket_path
+        {
+            anyhow::bail!(
+                "[ipc].fallback_socket_path is the same as socket_path ({}); \
+                 point it at a writable directory elsewhere, or leave it empty",
+                self.ipc.socket_path
+            );
+        }
+        let sd = &self.shutdown;
+        if sd.on_serial_loss_when_low
+            && (sd.serial_loss_timeout_seconds == 0 || sd.serial_loss_soc_pct > 100)
+        {
+            anyhow::bail!(
+                "[shutdown].on_serial_loss_when_low needs serial_loss_timeout_seconds above 0 \
+                 (got {}) and serial_loss_soc_pct at most 100 (got {})",
+                sd.serial_loss_timeout_seconds,
+                sd.serial_loss_soc_pct
+            );
+        }
+        if sd.rail_critical_mv > 0 && b.rail_min_mv > 0 && sd.rail_critical_mv >= b.rail_min_mv {
+            anyhow::bail!(
+                "[shutdown].rail_critical_mv ({}) must be below [battery].rail_min_mv ({}), \
+                 so the undervoltage warning comes before the shutdown",
+                sd.rail_critical_mv,
+                b.rail_min_mv
+            );
+        }
+        let ar = &self.archive;
+        if !ar.path.is_empty() && ar.max_file_mb == 0 && ar.rotate_hours == 0 {
+            warnings.push(format!(
+                "[archive] with max_file_mb = 0 and rotate_hours = 0 never rotates {}; it grows \
+                 until the disk is full",
+                ar.path
+            ));
+        }
+        let h = &self.health;
+        if h.enabled {
+            let weights = [
+                ("input_weight", h.input_weight),
+                ("charging_weight", h.charging_weight),
+                ("temperature_weight", h.temperature_weight),
+                ("soc_weight", h.soc_weight),
+                ("faults_weight", h.faults_weight),
+            ];
+            for (key, w) in weights {
+                if w > MAX_HEALTH_WEIGHT {
+                    anyhow::bail!("[health].{key} must be 0–{MAX_HEALTH_WEIGHT}, got {w}");
+                }
+            }
+            if weights.iter().all(|&(_, w)| w == 0) {
+                anyhow::bail!("[health] weights are all 0; set enabled = false instead");
+            }
+            if h.window_samples == 0 {
+                anyhow::bail!("[health].window_samples must be at least 1");
+            }
+        }
+        if self.privileges.user.is_empty() && !self.privileges.group.is_empty() {
+            anyhow::bail!("[privileges].group needs a user to switch to");
+        }
+        if !self.privileges.user.is_empty() && sd.helper_command.is_empty() {
+            warnings.push(format!(
+                "[privileges].user = {:?} without [shutdown].helper_command: the shutdown is \
+                 likely to be refused once the daemon no longer runs as root",
+                self.privileges.user
+            ));
+        }
+        let hz = self.monitor.refresh_rate_hz;
+        if !(hz.is_finite() && hz > 0.0) {
+            anyhow::bail!("[monitor].refresh_rate_hz must be above 0, got {hz}");
+        }
+        if self
+            .serial
+            .match_products
+            .iter()
+            .any(|p| p.trim().is_empty())
+        {
+            anyhow::bail!(
+                "[serial].match_products has an empty entry, which would match any device"
+            );
+        }
+        let (warn, alarm) = (self.monitor.input_warn_pct, self.monitor.input_alarm_pct);
+        if !(warn.is_finite() && alarm.is_finite() && 0.0 <= warn && warn <= alarm) {
+            anyhow::bail!(
+                "[monitor].input_warn_pct ({warn}) must be between 0 and input_alarm_pct ({alarm})"
+            );
+        }
+        Ok(warnings)
+    }
+}
+
+struct UnknownKey {
+    path: String,
+    /// Closest known key at the same level, if close enough to be a typo.
+    suggestion: Option<String>,
+}
+
+impl UnknownKey {
+    fn describe(&self) -> String {
+        match &self.suggestion {
+            Some(s) => format!("unknown config key `{}` (did you mean `{s}`?)", self.path),
+            None => format!("unknown config key `{}`", self.path),
+        }
+    }
+}
+
+fn collect_unknown(
+    raw: &toml::Table,
+    known: &toml::Table,
+    prefix: &str,
+    out: &mut Vec<UnknownKey>,
+) {
+    for (key, value) in raw {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match (value, known.get(key)) {
+            (_, None) => out.push(UnknownKey {
+                path,
+                suggestion: closest(key, known.keys()),
+            }),
+            (toml::Value::Table(r), Some(toml::Value::Table(k))) => {
+                collect_unknown(r, k, &path, out)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Nearest candidate by edit distance, allowing roughly one typo per four
+/// characters (at least 2).
+fn closest<'a>(key: &str, candidates: impl Iterator<Item = &'a String>) -> Option<String> {
+    let limit = (key.chars().count() / 4).max(2);
+    candidates
+        .map(|c| (levenshtein(key, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c.clone())
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = subst.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    const MINIMAL: &str = r#"
+[serial]
+port = "auto"
+baud_rate = 115200
+
+[battery]
+shutdown_threshold_pct = 10
+input_min_valid_mv = 8000
+input_max_valid_mv = 26000
+
+[shutdown]
+script_path = "/etc/w3p-ups/shutdown.sh"
+delay_seconds = 30
+"#;
+
+    #[test]
+    fn example_config_has_no_unknown_keys() {
+        let (_, warnings) = parse(include_str!("../config.toml.example")).unwrap();
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn typo_key_is_ignored_with_warning() {
+        let content = MINIMAL.replace("[shutdown]", "shutdown_cancel_margn_pct = 20\n\n[shutdown]");
+        let (cfg, warnings) = parse(&content).unwrap();
+        // The misspelt key didn't take effect: default margin applies.
+        assert_eq!(cfg.battery.shutdown_cancel_margin_pct, 5);
+        assert_eq!(
+            warnings,
+            [
+                "unknown config key `battery.shutdown_cancel_margn_pct` (did you mean \
+              `shutdown_cancel_margin_pct`?) ignored"
+            ]
+        );
+    }
+
+    #[test]
+    fn misspelt_required_key_names_the_typo() {
+        let content = MINIMAL.replace("shutdown_threshold_pct", "shutdown_treshold_pct");
+        let err = format!("{:#}", parse(&content).unwrap_err());
+        assert!(
+            err.contains(
+                "unknown config key `battery.shutdown_treshold_pct` \
+                 (did you mean `shutdown_threshold_pct`?)"
+            ),
+            "{err}"
+        );
+        assert!(err.contains("missing field"), "{err}");
+    }
+
+    #[test]
+    fn dropins_merge_over_the_main_file_in_order() {
+        let dir = Scratch::new("config");
+        let dropins = dir.join("config.d");
+        fs::create_dir_all(&dropins).unwrap();
+        let main = dir.join("config.toml");
+        fs::write(&main, MINIMAL).unwrap();
+        let main = main.to_str().unwrap();
+        fs::write(
+            dropins.join("20-site.toml"),
+            "[battery]\nshutdown_threshold_pct = 25\n[logging]\nlevel = \"debug\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dropins.join("10-package.toml"),
+            "[battery]\nshutdown_threshold_pct = 15\nnot_charging_warn_seconds = 60\n",
+        )
+        .unwrap();
+        fs::write(dropins.join("README"), "not toml").unwrap();
+
+        let (cfg, warnings) = load(main, None).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(cfg.battery.shutdown_threshold_pct, 25);
+        assert_eq!(cfg.battery.not_charging_warn_seconds, 60);
+        // Sibling keys in a merged table survive.
+        assert_eq!(cfg.battery.input_min_valid_mv, 8000);
+        assert_eq!(cfg.shutdown.delay_seconds, 30);
+        assert_eq!(cfg.logging.level, "debug");
+
+        // An explicit --config-dir replaces config.d; it must exist.
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        let (cfg, _) = load(main, Some(&other)).unwrap();
+        assert_eq!(cfg.battery.shutdown_threshold_pct, 10);
+        assert!(load(main, Some(&dir.join("missing"))).is_err());
+
+        fs::write(dropins.join("30-bad.toml"), "[battery\n").unwrap();
+        let err = format!("{:#}", load(main, None).unwrap_err());
+        assert!(err.contains("30-bad.toml"), "{err}");
+    }
+
+    #[test]
+    fn config_is_fetched_over_http_and_validated() {
+        use std::net::TcpListener;
+        let serve = |response: String| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/cfg/ups.toml", listener.local_addr().unwrap());
+            let server = std::thread::spawn(move || {
+                let (mut conn, _) = listener.accept().unwrap();
+                // The whole request: closing on unread bytes resets the
+                // connection instead of delivering the response.
+                let mut request = Vec::new();
+                let mut byte = [0u8; 1];
+                while !request.ends_with(b"\r\n\r\n") && conn.read(&mut byte).unwrap() == 1 {
+                    request.push(byte[0]);
+                }
+                conn.write_all(response.as_bytes()).unwrap();
+                String::from_utf8_lossy(&request).into_owned()
+            });
+            (url, server)
+        };
+
+        let (url, server) = serve(format!("HTTP/1.0 200 OK\r\n\r\n{MINIMAL}"));
+        let (cfg, _) = load(&url, None).unwrap();
+        assert_eq!(cfg.shutdown.delay_seconds, 30);
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /cfg/ups.toml HTTP/1.0\r\n"));
+
+        // The fetched text goes through the same checks as a file.
+        let bad = MINIMAL.replace(
+            "shutdown_threshold_pct = 10",
+            "shutdown_threshold_pct = 101",
+        );
+        let (url, _) = serve(format!("HTTP/1.0 200 OK\r\n\r\n{bad}"));
+        let err = format!("{:#}", load(&url, None).unwrap_err());
+        assert!(
+            err.contains("shutdown_threshold_pct must be 0–100"),
+            "{err}"
+        );
+
+        let (url, _) = serve("HTTP/1.0 404 Not Found\r\n\r\n".into());
+        let err = format!("{:#}", load(&url, None).unwrap_err());
+        assert!(
+            err.ends_with("server answered \"HTTP/1.0 404 Not Found\""),
+            "{err}"
+        );
+        let err = format!(
+            "{:#}",
+            load("https://example.org/ups.toml", None).unwrap_err()
+        );
+        assert!(err.contains("https is not supported"), "{err}");
+
+        // A body past the cap, or one that trickles in past the deadline,
+        // is an error rather than a hung or bloated reload.
+        let (url, _) = serve(format!("HTTP/1.0 200 OK\r\n\r\n{}", "#".repeat(4096)));
+        let err = format!("{:#}", fetch(&url, FETCH_TIMEOUT, 1024).unwrap_err());
+        assert!(err.contains("response is over 1 KB"), "{err}");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let _ = conn.read(&mut [0u8; 1024]);
+            while conn.write_all(b"#").is_ok() {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        });
+        let started = Instant::now();
+        let err = format!(
+            "{:#}",
+            fetch(&url, Duration::from_millis(200), MAX_FETCH_BYTES).unwrap_err()
+        );
+        assert!(err.contains("no complete answer within 0.2 s"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // Neither stdin nor a URL has a config.d next to it.
+        assert!(dropin_files(STDIN_CONFIG, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn levenshtein_distances() {
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("delay_seconds", "delay_seconds"), 0);
+        assert_eq!(levenshtein("shutdown_treshold", "shutdown_threshold"), 1);
+        assert_eq!(levenshtein("jornald", "journald"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn unrelated_keys_get_no_suggestion() {
+        let known = ["level".to_string(), "journald".to_string()];
+        assert_eq!(closest("format", known.iter()), None);
+        assert_eq!(
+            closest("jurnald", known.iter()).as_deref(),
+            Some("journald")
+        );
+    }
+
+    #[test]
+    fn future_keys_and_sections_are_tolerated() {
+        let content = format!(
+            "{MINIMAL}\n[logging]\nlevel = \"debug\"\nformat = \"json\"\n\n[telemetry]\nendpoint = \"x\"\n"
+        );
+        let (cfg, warnings) = parse(&content).unwrap();
+        assert_eq!(cfg.logging.level, "debug");
+        assert_eq!(
+            warnings,
+            [
+                "unknown config key `logging.format` ignored",
+                "unknown config key `telemetry` ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn shutdown_action_parses_and_defaults() {
+        let s: ShutdownConfig =
+            toml::from_str("script_path = \"/x\"\ndelay_seconds = 1\naction = \"hibernate\"")
+                .unwrap();
+        assert_eq!(s.action, ShutdownAction::Hibernate);
+        let s: ShutdownConfig = toml::from_str("script_path = \"/x\"\ndelay_seconds = 1").unwrap();
+        assert_eq!(s.action, ShutdownAction::Poweroff);
+        assert!(toml::from_str::<ShutdownConfig>(
+            "script_path = \"/x\"\ndelay_seconds = 1\naction = \"nap\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn input_deviation_against_nominal() {
+        let mut b = Config::default().battery;
+        assert_eq!(b.input_deviation_pct(19_800), None);
+        b.nominal_input_mv = 20_000;
+        assert_eq!(b.input_deviation_pct(20_000), Some(0.0));
+        assert!((b.input_deviation_pct(19_800).unwrap() + 1.0).abs() < 1e-3);
+        assert!((b.input_deviation_pct(21_000).unwrap() - 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn status_fields_are_validated() {
+        let (cfg, _) = parse(&format!(
+            "{MINIMAL}\n[logging]\nstatus_fields = [\"temp\", \"soc\"]\n"
+        ))
+        .unwrap();
+        assert_eq!(
+            cfg.logging.status_fields,
+            [StatusField::Temp, StatusField::Soc]
+        );
+        let err = parse(&format!(
+            "{MINIMAL}\n[logging]\nstatus_fields = [\"soc\", \"ba\"]\n"
+        ))
+        .unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("unknown variant `ba`"), "{msg}");
+        assert!(msg.contains("`faults`"), "{msg}");
+    }
+
+    #[test]
+    fn chemistry_and_cell_count() {
+        let b = parse(MINIMAL).unwrap().0.battery;
+        assert_eq!((b.chemistry, b.cell_count), (Chemistry::Stock, 2));
+        assert_eq!(b.soc_curve(), SocCurve::STOCK);
+
+        let content = MINIMAL.replace(
+            "[shutdown]",
+            "chemistry = \"lifepo4\"\ncell_count = 4\n\n[shutdown]",
+        );
+        let b = parse(&content).unwrap().0.battery;
+        assert_eq!(b.soc_pct(4 * 3250), 50);
+
+        let content = MINIMAL.replace("[shutdown]", "cell_count = 0\n\n[shutdown]");
+        let msg = format!("{:#}", parse(&content).unwrap_err());
+        assert!(msg.contains("cell_count must be 1–12"), "{msg}");
+    }
+
+    #[test]
+    fn contradicting_settings_are_rejected() {
+        let battery =
+            |extra: &str| MINIMAL.replace("[shutdown]", &format!("{extra}\n\n[shutdown]"));
+        let cases = [
+            (
+                MINIMAL.replace(
+                    "shutdown_threshold_pct = 10",
+                    "shutdown_threshold_pct = 101",
+                ),
+                "must be 0–100, got 101",
+            ),
+            (
+                battery("critical_threshold_pct = 10"),
+                "critical_threshold_pct (10) must be below shutdown_threshold_pct (10)",
+            ),
+            (
+                battery("shutdown_cancel_margin_pct = 95"),
+                "shutdown_cancel_margin_pct is 105%, above 100%",
+            ),
+            (
+                battery("shutdown_cancel_basis = \"voltage\"\nshutdown_cancel_vbat_mv = 5000"),
+                "shutdown_cancel_vbat_mv (5000 mV) must be above the pack voltage",
+            ),
+            (
+                MINIMAL.replace("input_min_valid_mv = 8000", "input_min_valid_mv = 26000"),
+                "input_min_valid_mv (26000) must be below input_max_valid_mv (26000)",
+            ),
+            (
+                battery("nominal_input_mv = 28000"),
+                "nominal_input_mv (28000) is outside",
+            ),
+            (
+                format!(
+                    "{MINIMAL}\n[ipc]\ntcp_listen = \"127.0.0.1:8080\"\n\n\
+                     [web]\nenabled = true\nlisten_addr = \"127.0.0.1:8080\"\n"
+                ),
+                "[web].listen_addr and [ipc].tcp_listen are both 127.0.0.1:8080",
+            ),
+            (
+                format!(
+                    "{MINIMAL}\n[ipc]\nsocket_path = \"/run/a.sock\"\n\
+                     fallback_socket_path = \"/run/a.sock\"\n"
+                ),
+                "fallback_socket_path is the same as socket_path",
+            ),
+            (
+                battery("recovery_target_soc = 10"),
+                "recovery_target_soc (10) must be above shutdown_threshold_pct (10)",
+            ),
+            (
+                format!("{MINIMAL}\n[monitor]\nrefresh_rate_hz = 0.0\n"),
+                "[monitor].refresh_rate_hz must be above 0, got 0",
+            ),
+            (
+                MINIMAL.replace(
+                    "[battery]",
+                    "match_products = [\"My_UPS\", \" \"]\n\n[battery]",
+                ),
+                "[serial].match_products has an empty entry, which would match any device",
+            ),
+            (
+                format!("{MINIMAL}\n[monitor]\ninput_warn_pct = 20.0\n"),
+                "[monitor].input_warn_pct (20) must be between 0 and input_alarm_pct (15)",
+            ),
+            (
+                MINIMAL.replace(
+                    "[shutdown]",
+                    "[shutdown]\non_serial_loss_when_low = true\nserial_loss_timeout_seconds = 0",
+                ),
+                "needs serial_loss_timeout_seconds above 0 (got 0)",
+            ),
+            (
+                MINIMAL.replace("[shutdown]", "[shutdown]\nrail_critical_mv = 4800"),
+                "[shutdown].rail_critical_mv (4800) must be below [battery].rail_min_mv (4630)",
+            ),
+            (
+                battery("maintenance_soc_pct = 120"),
+                "[battery].maintenance_soc_pct must be 0–100, got 120",
+            ),
+            (
+                MINIMAL.replace("[shutdown]", "[shutdown]\nrecovery_soc = 101"),
+                "[shutdown].recovery_soc must be 0–100, got 101",
+            ),
+            (
+                battery("unstable_transitions = 1"),
+                "[battery].unstable_transitions needs at least 2 transitions (got 1)",
+            ),
+            (
+                format!("{MINIMAL}\n[privileges]\ngroup = \"dialout\"\n"),
+                "[privileges].group needs a user to switch to",
+            ),
+            (
+                format!(
+                    "{MINIMAL}\n[health]\ninput_weight = 0\ncharging_weight = 0\n\
+                     temperature_weight = 0\nsoc_weight = 0\nfaults_weight = 0\n"
+                ),
+                "[health] weights are all 0; set enabled = false instead",
+            ),
+            (
+                format!("{MINIMAL}\n[health]\nsoc_weight = 4294967295\n"),
+                "[health].soc_weight must be 0–1000, got 4294967295",
+            ),
+        ];
+        for (content, want) in cases {
+            let msg = format!("{:#}", parse(&content).unwrap_err());
+            assert!(msg.contains(want), "{msg}");
+        }
+
+        // The same margin is fine once an explicit voltage does the cancelling.
+        let content = battery(
+            "shutdown_cancel_margin_pct = 95\nshutdown_cancel_basis = \"voltage\"\n\
+             shutdown_cancel_vbat_mv = 7600",
+        );
+        assert!(parse(&content).unwrap().1.is_empty());
+        // A critical threshold below the shutdown one is the intended setup.
+        assert!(parse(&battery("critical_threshold_pct = 5")).is_ok());
+    }
+
+    #[test]
+    fn unused_settings_are_warned_about() {
+        let content = MINIMAL.replace(
+            "[shutdown]",
+            "shutdown_cancel_vbat_mv = 7600\ninput_deviation_warn_pct = 10\n\n[shutdown]",
+        );
+        let (_, warnings) = parse(&content).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "[battery].shutdown_cancel_vbat_mv is set but unused with shutdown_cancel_basis = \"soc\"",
+                "[battery].input_deviation_warn_pct is set but unused without nominal_input_mv",
+            ]
+        );
+    }
+
+    #[test]
+    fn cancel_hysteresis_basis() {
+        let mut b = Config::default().battery; // cancel at 10 + 5 = 15 %
+        assert_eq!(b.cancel_vbat_mv(), crate::soc::soc_pct_to_pack_mv(15));
+        b.shutdown_cancel_vbat_mv = 6_800;
+
+        // Voltage back up (charge current resumed), SOC estimate still low.
+        assert!(!b.battery_recovered(12, 6_850));
+        b.shutdown_cancel_basis = CancelBasis::Voltage;
+        assert!(b.battery_recovered(12, 6_850));
+        assert!(!b.battery_recovered(20, 6_700));
+        b.shutdown_cancel_basis = CancelBasis::Either;
+        assert!(b.battery_recovered(12, 6_850));
+        assert!(b.battery_recovered(20, 6_700));
+        assert!(!b.battery_recovered(12, 6_700));
+
+        let content = MINIMAL.replace(
+            "[shutdown]",
+            "shutdown_cancel_basis = \"either\"\nshutdown_cancel_vbat_mv = 6900\n\n[shutdown]",
+        );
+        let cfg = parse(&content).unwrap().0;
+        assert_eq!(cfg.battery.shutdown_cancel_basis, CancelBasis::Either);
+        assert_eq!(cfg.battery.cancel_vbat_mv(), 6_900);
     }
 }
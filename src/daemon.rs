@@ -0,0 +1,740 @@
+//! Daemon supervisor: owns the IPC server and (re)starts the per-connection
+//! serial, dispatcher, shutdown-SM, power-watch, status-log and host-metrics
+//! tasks until SIGTERM/SIGINT or an IPC `stop`. SIGHUP or an IPC `reload`
+//! re-reads the config and restarts those tasks with it. However it ends,
+//! the reason is logged and written to `[logging].exit_report_file`.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
+
+use crate::events::{EventHandler, EventHandlers};
+use crate::ipc::Control;
+use crate::{
+    archive, capacity, commands, config, dispatcher, exit_report, forward, health, histogram,
+    host_metrics, incident, ipc, power_watch, shutdown_sm, state, status_log, store, transport,
+    web,
+};
+
+/// Re-reads the config for SIGHUP / IPC `reload`; returns it with its
+/// warnings, like [`config::load`]. It may block (an `http://` config is
+/// fetched), so it is run on a blocking thread.
+pub type ConfigLoader = Box<dyn Fn() -> Result<(config::Config, Vec<String>)> + Send + Sync>;
+
+/// A [`ConfigLoader`] that can be handed to `spawn_blocking`.
+type SharedLoader = Arc<dyn Fn() -> Result<(config::Config, Vec<String>)> + Send + Sync>;
+
+/// Run the agent until SIGTERM/SIGINT or an IPC `stop`. Serial errors are
+/// retried with a 5 s backoff; the IPC server stays up across reconnects.
+///
+/// `handlers` are notified of power transitions after the built-in ones
+/// (see [`crate::events`]). Without a `reload` loader, reload requests are
+/// answered with an error.
+pub async fn run_daemon(
+    cfg: config::Config,
+    handlers: Vec<Box<dyn EventHandler>>,
+    reload: Option<ConfigLoader>,
+) -> Result<()> {
+    let state = state::State::new();
+    // Read at startup only, like the other files the daemon owns.
+    let report_file = cfg.logging.exit_report_file.clone();
+    let battery = cfg.battery.clone();
+    let started = state.now();
+    let outcome = supervise(cfg, handlers, reload.map(SharedLoader::from), &state).await;
+    exit_report::write(&report_file, &state, &battery, started, &outcome).await;
+    outcome.map(drop)
+}
+
+/// The daemon proper; returns why it stopped.
+async fn supervise(
+    mut cfg: config::Config,
+    handlers: Vec<Box<dyn EventHandler>>,
+    reload: Option<SharedLoader>,
+    state: &Arc<state::State>,
+) -> Result<&'static str> {
+    check_chemistry(&cfg.battery);
+    let state = state.clone();
+    let dry_run = cfg.debug.dry_run;
+    refuse_without_shutdown(&cfg)?;
+    if dry_run {
+        warn!("DRY RUN MODE: shutdowns, reboots, service commands and event hooks are logged, not performed");
+        state.set_dry_run(true).await;
+        if !handlers.is_empty() {
+            warn!(
+                "[dry-run] {} event handler(s) will not be called",
+                handlers.len()
+            );
+        }
+    }
+    let handlers = Arc::new(EventHandlers::with_builtin(if dry_run {
+        Vec::new()
+    } else {
+        handlers
+    }));
+    let (control_tx, control_rx) = mpsc::channel(4);
+
+    // Start the IPC server up front; clients can connect even before the
+    // serial transport comes up (snapshot will be empty until then).
+    let mut ipc_handle = start_ipc(&cfg, &state, &control_tx).await;
+    // Everything after this (state files, the serial port, the shutdown)
+    // happens as the configured user.
+    if !cfg.privileges.user.is_empty() {
+        let (user, group) = (&cfg.privileges.user, &cfg.privileges.group);
+        crate::privileges::hand_over(&runtime_dirs(&cfg), user, group).context("[privileges]")?;
+        crate::privileges::drop_to(user, group).context("[privileges]")?;
+        refuse_without_shutdown(&cfg)?;
+    }
+
+    // Capacity spans run across reconnects (an outage may outlast the
+    // link), so this one isn't per-connection.
+    let (capacity_stop, stop_rx) = oneshot::channel();
+    let capacity = tokio::spawn(capacity::capacity_loop(
+        state.clone(),
+        cfg.battery.clone(),
+        cfg.capacity.clone(),
+        cfg.persist.clone(),
+        stop_rx,
+    ));
+    let histogram = tokio::spawn(histogram::histogram_loop(
+        state.clone(),
+        cfg.power_quality.clone(),
+    ));
+    let incident = tokio::spawn(incident::incident_loop(
+        state.clone(),
+        cfg.power_quality.clone(),
+        cfg.battery.clone(),
+    ));
+    let archive = tokio::spawn(archive::archive_loop(
+        state.clone(),
+        cfg.archive.clone(),
+        cfg.battery.clone(),
+    ));
+    let health = tokio::spawn(health::health_loop(
+        state.clone(),
+        cfg.health.clone(),
+        cfg.battery.clone(),
+    ));
+    let web = tokio::spawn(web::web_loop(
+        state.clone(),
+        cfg.web.clone(),
+        cfg.battery.clone(),
+    ));
+    let forward = tokio::spawn(forward::forward_loop(
+        state.clone(),
+        cfg.forward.exec.clone(),
+        cfg.battery.clone(),
+    ));
+    let serial_loss = tokio::spawn(shutdown_sm::serial_loss_loop(
+        state.clone(),
+        cfg.battery.clone(),
+        cfg.shutdown.clone(),
+    ));
+    let detached_countdown = tokio::spawn(shutdown_sm::detached_countdown_loop(
+        state.clone(),
+        cfg.shutdown.clone(),
+    ));
+
+    let mut wake = Wakeups {
+        sigterm: signal(SignalKind::terminate()).context("install SIGTERM handler")?,
+        sigint: signal(SignalKind::interrupt()).context("install SIGINT handler")?,
+        sighup: signal(SignalKind::hangup()).context("install SIGHUP handler")?,
+        control: control_rx,
+    };
+
+    // "auto" is re-detected on every attempt (a replugged device may come
+    // back as another ttyACMx); a fixed path is simply reopened.
+    let mut last_port: Option<String> = None;
+    let why = 'reconnect: loop {
+        let port_path = match transport::resolve_port(
+            &cfg.serial.port,
+            &cfg.serial.match_serial,
+            &cfg.serial.match_products,
+        ) {
+            Ok(p) => {
+                match last_port.replace(p.clone()) {
+                    Some(old) if old != p => {
+                        warn!("UPS re-enumerated: now at {p} (was {old})")
+                    }
+                    _ => {}
+                }
+                p
+            }
+            Err(e) => {
+                error!("port detection failed: {e}; retrying in 5 s");
+                match wake.backoff(RETRY, reload.as_ref()).await {
+                    Backoff::Elapsed => continue 'reconnect,
+                    Backoff::Stop(why) => break 'reconnect why,
+                    Backoff::Reloaded(new) => {
+                        apply_reload(&mut cfg, *new, &state, &control_tx, &mut ipc_handle).await;
+                        continue 'reconnect;
+                    }
+                }
+            }
+        };
+
+        let handles = match transport::spawn_serial_tasks(
+            port_path.clone(),
+            cfg.serial.baud_rate,
+            cfg.serial.format,
+            cfg.battery.soc_curve(),
+            state.kv_rejects(),
+            state.read_stats(),
+        )
+        .await
+        {
+            Ok(h) => h,
+            Err(e) => {
+                error!("open serial: {e}; retrying in 5 s");
+                match wake.backoff(RETRY, reload.as_ref()).await {
+                    Backoff::Elapsed => continue 'reconnect,
+                    Backoff::Stop(why) => break 'reconnect why,
+                    Backoff::Reloaded(new) => {
+                        apply_reload(&mut cfg, *new, &state, &control_tx, &mut ipc_handle).await;
+                        continue 'reconnect;
+                    }
+                }
+            }
+        };
+
+        state.set_serial_connected(true).await;
+        write_port_file(&cfg.serial.port_file, &port_path);
+        state.set_serial_port(port_path).await;
+        let commands_handler = Arc::new(commands::CommandsHandler::new(
+            state.clone(),
+            cfg.commands.clone(),
+            cfg.shutdown.clone(),
+        ));
+        let mut reader = handles.reader;
+        let mut writer = handles.writer;
+        let mut dispatcher = tokio::spawn(dispatcher::dispatch_loop(
+            state.clone(),
+            handles.inbound,
+            handles.outbound.clone(),
+            Some(commands_handler),
+        ));
+        let mut sm = tokio::spawn(shutdown_sm::shutdown_sm_loop(
+            state.clone(),
+            cfg.battery.clone(),
+            cfg.shutdown.clone(),
+            handles.outbound.clone(),
+            handlers.clone(),
+        ));
+        let mut watch = tokio::spawn(power_watch::power_watch_loop(
+            state.clone(),
+            cfg.battery.clone(),
+            handlers.clone(),
+        ));
+        let mut status = tokio::spawn(status_log::status_log_loop(
+            state.clone(),
+            cfg.battery.clone(),
+            cfg.logging.clone(),
+        ));
+        let mut metrics = tokio::spawn(host_metrics::host_metrics_loop(
+            state.clone(),
+            cfg.host_metrics.clone(),
+            cfg.eth_clients.clone(),
+            handles.outbound.clone(),
+        ));
+
+        info!("transport tasks running; entering supervisor loop");
+
+        let cause = loop {
+            break tokio::select! {
+                w = wake.next() => match w {
+                    Wake::Stop(why) => Cause::Stop(why),
+                    Wake::Reload(done) => match try_reload(reload.as_ref(), done).await {
+                        Some(new) => Cause::Reload(new),
+                        // Bad config: keep running on the old one.
+                        None => continue,
+                    },
+                },
+                r = &mut reader    => Cause::Reader(format_join(r)),
+                w = &mut writer    => Cause::Writer(format_join(w)),
+                d = &mut dispatcher => Cause::Dispatcher(format_join(d)),
+                s = &mut sm         => Cause::Sm(format_join(s)),
+                p = &mut watch      => Cause::Watch(format_join(p)),
+                l = &mut status     => Cause::Status(format_join(l)),
+                m = &mut metrics    => Cause::Metrics(format_join(m)),
+            };
+        };
+
+        reader.abort();
+        writer.abort();
+        dispatcher.abort();
+        sm.abort();
+        watch.abort();
+        status.abort();
+        metrics.abort();
+        let _ = reader.await;
+        let _ = writer.await;
+        let _ = dispatcher.await;
+        let _ = sm.await;
+        let _ = watch.await;
+        let _ = status.await;
+        let _ = metrics.await;
+        // IPC keeps serving the last-known state, flagged `degraded`.
+        state.set_serial_connected(false).await;
+
+        match cause {
+            Cause::Stop(why) => {
+                info!("{why}; shutting down");
+                break 'reconnect why;
+            }
+            Cause::Reload(new) => {
+                // Reconnect right away with the new settings.
+                apply_reload(&mut cfg, *new, &state, &control_tx, &mut ipc_handle).await;
+                continue 'reconnect;
+            }
+            Cause::Reader(why) | Cause::Writer(why) => {
+                warn!("transport task exited ({why}); restarting in 5 s");
+            }
+            Cause::Dispatcher(why)
+            | Cause::Sm(why)
+            | Cause::Watch(why)
+            | Cause::Status(why)
+            | Cause::Metrics(why) => {
+                error!("supervisor task exited unexpectedly ({why}); restarting in 5 s");
+            }
+        }
+        match wake.backoff(RETRY, reload.as_ref()).await {
+            Backoff::Elapsed => {}
+            Backoff::Stop(why) => break 'reconnect why,
+            Backoff::Reloaded(new) => {
+                apply_reload(&mut cfg, *new, &state, &control_tx, &mut ipc_handle).await;
+            }
+        }
+    };
+
+    if why == SIGTERM {
+        shutdown_sm::on_sigterm(&state, &cfg.shutdown).await;
+    }
+    // Let connected IPC clients print "daemon stopping" rather than lose
+    // the connection mid-read.
+    if state.announce_stopping() > 0 {
+        tokio::time::sleep(STOP_NOTICE).await;
+    }
+    // Let it flush a held-back write rather than aborting it.
+    let _ = capacity_stop.send(());
+    let _ = capacity.await;
+    histogram.abort();
+    incident.abort();
+    archive.abort();
+    health.abort();
+    web.abort();
+    forward.abort();
+    let _ = forward.await;
+    serial_loss.abort();
+    detached_countdown.abort();
+    if let Some(h) = ipc_handle {
+        h.abort();
+        let _ = h.await;
+    }
+    let _ = tokio::fs::remove_file(&cfg.ipc.socket_path).await;
+    if !cfg.serial.port_file.is_empty() {
+        let _ = tokio::fs::remove_file(&cfg.serial.port_file).await;
+    }
+    if !cfg.ipc.fallback_socket_path.is_empty() {
+        let _ = tokio::fs::remove_file(&cfg.ipc.fallback_socket_path).await;
+    }
+    Ok(why)
+}
+
+const RETRY: Duration = Duration::from_secs(5);
+/// Stop reason for SIGTERM, which `[shutdown].on_sigterm_during_countdown`
+/// applies to.
+const SIGTERM: &str = "SIGTERM received";
+/// How long IPC clients get to read the stop notice before the exit.
+const STOP_NOTICE: Duration = Duration::from_millis(250);
+
+/// Directories the daemon writes into after dropping privileges: those
+/// of the IPC socket (and its fallback) and `[serial].port_file`.
+fn runtime_dirs(cfg: &config::Config) -> Vec<&Path> {
+    let mut dirs: Vec<&Path> = Vec::new();
+    for file in [
+        &cfg.ipc.socket_path,
+        &cfg.ipc.fallback_socket_path,
+        &cfg.serial.port_file,
+    ] {
+        if let Some(dir) = Path::new(file)
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+        {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// `[serial].port_file`: the port just opened, for scripts that need the
+/// same device without repeating the `"auto"` detection. A failure is only
+/// a warning; the daemon doesn't depend on the file.
+fn write_port_file(path: &str, port: &str) {
+    if path.is_empty() {
+        return;
+    }
+    if let Err(e) = store::write_atomic(Path::new(path), format!("{port}\n").as_bytes()) {
+        warn!("could not write [serial].port_file: {e:#}");
+    }
+}
+
+/// LiFePO4 holds ~3.2–3.3 V/cell from about 20 % to 90 %, so a threshold in
+/// that band fires on a few mV of sag or noise rather than on charge.
+fn check_chemistry(battery: &config::BatteryConfig) {
+    let t = battery.shutdown_threshold_pct;
+    if battery.chemistry == config::Chemistry::Lifepo4 && (21..90).contains(&t) {
+        warn!(
+            "shutdown_threshold_pct = {t} is on the flat part of the LiFePO4 curve; \
+             the voltage-based SOC can't place it reliably (20 or below is on the knee)"
+        );
+    }
+}
+
+async fn start_ipc(
+    cfg: &config::Config,
+    state: &Arc<state::State>,
+    control: &mpsc::Sender<Control>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    apply_ipc_settings(cfg, state).await;
+    match ipc::spawn_ipc(
+        cfg.ipc.socket_path.clone(),
+        state.clone(),
+        cfg,
+        Some(control.clone()),
+    )
+    .await
+    {
+        Ok(h) => Some(h),
+        Err(e) => {
+            error!("IPC server failed to start: {e}; continuing without it");
+            None
+        }
+    }
+}
+
+/// What snapshots are built with, kept in `state` rather than the server.
+async fn apply_ipc_settings(cfg: &config::Config, state: &state::State) {
+    state.set_max_sample_age(cfg.ipc.max_sample_age()).await;
+    state
+        .set_sample_cadence(cfg.serial.expected_interval(), cfg.serial.missed_warn_pct)
+        .await;
+    state
+        .set_power_good_debounce(cfg.battery.power_good_debounce_samples)
+        .await;
+}
+
+/// Check the shutdown path ([`shutdown_sm::check_script`]) and, with
+/// `[shutdown].require_prerequisites`, refuse to run without one.
+fn refuse_without_shutdown(cfg: &config::Config) -> Result<()> {
+    let problems = shutdown_sm::check_script(&cfg.shutdown);
+    if !problems.is_empty() && cfg.shutdown.require_prerequisites && !cfg.debug.dry_run {
+        anyhow::bail!(
+            "refusing to start: the shutdown could not run ({}); fix it, or start with \
+             --allow-no-shutdown",
+            problems.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Load the new config, answering the requester either way.
+async fn try_reload(
+    reload: Option<&SharedLoader>,
+    done: Option<oneshot::Sender<Result<Vec<String>, String>>>,
+) -> Option<Box<config::Config>> {
+    let result = match reload {
+        Some(load) => {
+            let load = load.clone();
+            match tokio::task::spawn_blocking(move || load()).await {
+                Ok(loaded) => loaded.map_err(|e| format!("{e:#}")),
+                Err(e) => Err(format!("config loader failed: {e}")),
+            }
+        }
+        None => Err("reload is not supported by this daemon".into()),
+    };
+    let (reply, new) = match result {
+        Ok((new, warnings)) => {
+            for w in &warnings {
+                warn!("reload: {w}");
+            }
+            (Ok(warnings), Some(Box::new(new)))
+        }
+        Err(e) => {
+            error!("reload failed, keeping the current config: {e}");
+            (Err(e), None)
+        }
+    };
+    if let Some(done) = done {
+        let _ = done.send(reply);
+    }
+    new
+}
+
+/// Swap in a freshly loaded config. Per-connection tasks pick it up when
+/// the caller restarts them; the IPC server is restarted here (clients
+/// already connected keep their session).
+async fn apply_reload(
+    cfg: &mut config::Config,
+    new: config::Config,
+    state: &Arc<state::State>,
+    control: &mpsc::Sender<Control>,
+    ipc_handle: &mut Option<tokio::task::JoinHandle<()>>,
+) {
+    if new.logging.level != cfg.logging.level || new.logging.journald != cfg.logging.journald {
+        warn!("reload: [logging] changes take effect on restart");
+    }
+    if new.capacity.state_file != cfg.capacity.state_file
+        || new.persist.min_write_interval_seconds != cfg.persist.min_write_interval_seconds
+    {
+        warn!("reload: [capacity] / [persist] changes take effect on restart");
+    }
+    if new.debug.dry_run != cfg.debug.dry_run {
+        warn!("reload: [debug].dry_run changes take effect on restart");
+    }
+    if new.privileges != cfg.privileges {
+        warn!("reload: [privileges] changes take effect on restart");
+    }
+    if new.archive != cfg.archive {
+        warn!("reload: [archive] changes take effect on restart");
+    }
+    if new.health != cfg.health {
+        warn!("reload: [health] changes take effect on restart");
+    }
+    if new.power_quality != cfg.power_quality {
+        warn!("reload: [power_quality] changes take effect on restart");
+    }
+    if new.web.enabled != cfg.web.enabled || new.web.listen_addr != cfg.web.listen_addr {
+        warn!("reload: [web] changes take effect on restart");
+    }
+    if new.logging.exit_report_file != cfg.logging.exit_report_file {
+        warn!("reload: [logging].exit_report_file changes take effect on restart");
+    }
+    if new.forward.exec.command != cfg.forward.exec.command {
+        warn!("reload: [forward.exec] changes take effect on restart");
+    }
+    let (old_sd, new_sd) = (&cfg.shutdown, &new.shutdown);
+    if (
+        new_sd.on_serial_loss_when_low,
+        new_sd.serial_loss_timeout_seconds,
+        new_sd.serial_loss_soc_pct,
+    ) != (
+        old_sd.on_serial_loss_when_low,
+        old_sd.serial_loss_timeout_seconds,
+        old_sd.serial_loss_soc_pct,
+    ) {
+        warn!("reload: [shutdown].on_serial_loss_when_low / serial_loss_* changes take effect on restart");
+    }
+    if new_sd.delay_seconds != old_sd.delay_seconds {
+        warn!(
+            "reload: [shutdown].delay_seconds takes effect on restart for a countdown \
+             running while the UPS is disconnected"
+        );
+    }
+    // Rebind only for what the server was started with: `[ipc]`, and the
+    // `[battery]` / `allow_inject` its clients are answered with.
+    let restart_ipc = new.ipc != cfg.ipc
+        || new.battery != cfg.battery
+        || new.debug.allow_inject != cfg.debug.allow_inject;
+    if new.ipc.socket_path != cfg.ipc.socket_path {
+        let _ = tokio::fs::remove_file(&cfg.ipc.socket_path).await;
+    }
+    // The reconnect after the reload writes the new one.
+    if new.serial.port_file != cfg.serial.port_file && !cfg.serial.port_file.is_empty() {
+        let _ = tokio::fs::remove_file(&cfg.serial.port_file).await;
+    }
+    *cfg = new;
+    check_chemistry(&cfg.battery);
+    let _ = shutdown_sm::check_script(&cfg.shutdown);
+    if restart_ipc || ipc_handle.is_none() {
+        if let Some(h) = ipc_handle.take() {
+            h.abort();
+            let _ = h.await;
+        }
+        *ipc_handle = start_ipc(cfg, state, control).await;
+    } else {
+        apply_ipc_settings(cfg, state).await;
+    }
+    state.note_reload().await;
+    info!("config reloaded");
+}
+
+enum Cause {
+    Stop(&'static str),
+    Reload(Box<config::Config>),
+    Reader(String),
+    Writer(String),
+    Dispatcher(String),
+    Sm(String),
+    Watch(String),
+    Status(String),
+    Metrics(String),
+}
+
+/// Everything besides task exits that makes the supervisor act.
+struct Wakeups {
+    sigterm: Signal,
+    sigint: Signal,
+    sighup: Signal,
+    control: mpsc::Receiver<Control>,
+}
+
+enum Wake {
+    Stop(&'static str),
+    /// SIGHUP (no reply) or IPC `reload`.
+    Reload(Option<oneshot::Sender<Result<Vec<String>, String>>>),
+}
+
+enum Backoff {
+    Elapsed,
+    Stop(&'static str),
+    Reloaded(Box<config::Config>),
+}
+
+impl Wakeups {
+    async fn next(&mut self) -> Wake {
+        loop {
+            return tokio::select! {
+                _ = self.sigterm.recv() => Wake::Stop(SIGTERM),
+                _ = self.sigint.recv()  => Wake::Stop("SIGINT received"),
+                _ = self.sighup.recv()  => {
+                    info!("SIGHUP received; reloading config");
+                    Wake::Reload(None)
+                }
+                c = self.control.recv() => match c {
+                    Some(Control::Stop) => Wake::Stop("stop requested over IPC"),
+                    Some(Control::Reload(done)) => Wake::Reload(Some(done)),
+                    // The supervisor holds a sender, so this never closes.
+                    None => continue,
+                },
+            };
+        }
+    }
+
+    /// Sleep for `dur` unless asked to stop or reload first. A failed reload
+    /// keeps sleeping; a successful one ends the backoff early.
+    async fn backoff(&mut self, dur: Duration, reload: Option<&SharedLoader>) -> Backoff {
+        let sleep = tokio::time::sleep(dur);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return Backoff::Elapsed,
+                w = self.next() => match w {
+                    Wake::Stop(why) => {
+                        info!("{why} during backoff; shutting down");
+                        return Backoff::Stop(why);
+                    }
+                    Wake::Reload(done) => {
+                        if let Some(new) = try_reload(reload, done).await {
+                            return Backoff::Reloaded(new);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn format_join<T: std::fmt::Debug>(r: Result<T, tokio::task::JoinError>) -> String {
+    match r {
+        Ok(v) => format!("clean: {v:?}"),
+        Err(e) if e.is_cancelled() => "cancelled".into(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    /// Run `f` on a thread of its own that has dropped to `nobody`, the way
+    /// `[privileges]` leaves the daemon. Raw syscalls change only the
+    /// calling thread, so the rest of the test binary stays root.
+    fn as_nobody(f: impl std::future::Future<Output = ()> + Send + 'static) {
+        std::thread::spawn(move || {
+            // SAFETY: plain syscalls; each result is checked.
+            unsafe {
+                assert_eq!(
+                    libc::syscall(libc::SYS_setgroups, 0, std::ptr::null::<libc::gid_t>()),
+                    0
+                );
+                assert_eq!(libc::syscall(libc::SYS_setresgid, 65534, 65534, 65534), 0);
+                assert_eq!(libc::syscall(libc::SYS_setresuid, 65534, 65534, 65534), 0);
+            }
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(f);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_slow_reload_leaves_the_runtime_free() {
+        // The loader only returns once another task has run: on the
+        // runtime's own (single) thread it would time out instead.
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let rx = std::sync::Mutex::new(rx);
+        let load: SharedLoader = Arc::new(move || {
+            rx.lock()
+                .unwrap()
+                .recv_timeout(Duration::from_secs(5))
+                .context("the runtime was blocked")?;
+            Ok((config::Config::default(), Vec::new()))
+        });
+        tokio::spawn(async move { tx.send(()).unwrap() });
+        assert!(try_reload(Some(&load), None).await.is_some());
+    }
+
+    #[test]
+    fn reloads_keep_working_after_the_privilege_drop() {
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        if unsafe { libc::geteuid() } != 0 {
+            return; // Needs root to drop from.
+        }
+        let base = Scratch::new("drop");
+        let dir = base.join(ipc::RUNTIME_DIR_NAME);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cfg = config::Config::default();
+        cfg.ipc.socket_path = dir.join("agent.sock").to_string_lossy().into_owned();
+        cfg.ipc.fallback_socket_path = String::new();
+        cfg.serial.port_file = dir.join("port").to_string_lossy().into_owned();
+        crate::privileges::hand_over(&runtime_dirs(&cfg), "nobody", "").unwrap();
+
+        as_nobody(async move {
+            let state = state::State::new();
+            let (control, _control_rx) = mpsc::channel(4);
+            let sock = cfg.ipc.socket_path.clone();
+            let mut ipc = start_ipc(&cfg, &state, &control).await;
+            write_port_file(&cfg.serial.port_file, "/dev/ttyACM0");
+            assert!(Path::new(&cfg.serial.port_file).exists());
+
+            // Nothing the server uses changed: it keeps running.
+            let id = ipc.as_ref().unwrap().id();
+            let mut new = cfg.clone();
+            new.shutdown.delay_seconds += 1;
+            apply_reload(&mut cfg, new, &state, &control, &mut ipc).await;
+            assert_eq!(ipc.as_ref().unwrap().id(), id);
+
+            // An [ipc] change rebinds the socket, now as nobody.
+            let mut new = cfg.clone();
+            new.ipc.max_sample_age_seconds += 1;
+            apply_reload(&mut cfg, new, &state, &control, &mut ipc).await;
+            assert_ne!(ipc.as_ref().unwrap().id(), id);
+            tokio::net::UnixStream::connect(&sock).await.unwrap();
+
+            // And the exit cleanup can remove both.
+            ipc.take().unwrap().abort();
+            std::fs::remove_file(&sock).unwrap();
+            std::fs::remove_file(&cfg.serial.port_file).unwrap();
+        });
+    }
+}
@@ -12,14 +12,17 @@ use crate::state::State;
 use crate::transport::OutboundFrame;
 
 /// Loop forever (until inbound channel closes), dispatching incoming frames.
+///
+/// With `commands = None` (library / read-only use) host REQs are ignored
+/// and left for the panel to time out.
 pub async fn dispatch_loop(
     state: Arc<State>,
     mut inbound: mpsc::Receiver<Frame>,
     outbound: mpsc::Sender<OutboundFrame>,
-    commands: Arc<CommandsHandler>,
+    commands: Option<Arc<CommandsHandler>>,
 ) {
     while let Some(frame) = inbound.recv().await {
-        handle(&state, frame, &outbound, &commands).await;
+        handle(&state, frame, &outbound, commands.as_deref()).await;
     }
     info!("dispatcher: inbound closed; exiting");
 }
@@ -28,7 +31,7 @@ async fn handle(
     state: &State,
     frame: Frame,
     outbound: &mpsc::Sender<OutboundFrame>,
-    commands: &CommandsHandler,
+    commands: Option<&CommandsHandler>,
 ) {
     let cls = frame.class;
     let opc = frame.op;
@@ -72,13 +75,14 @@ async fn handle(
         // ---- POWER ----
         (class::POWER, op::power::STATUS) => {
             // Dispatch on the version byte: v2 is decoded then down-converted
-            // to v1 for storage (host stays v1-native for now); v1 as before.
+            // to v1 for storage (host stays v1-native for now, the raw v2 is
+            // kept alongside); v1 as before.
             let decoded = match frame.payload.first().copied() {
-                Some(2) => PowerStatusV2::decode(&frame.payload).map(|p2| p2.to_v1()),
-                _ => PowerStatusV1::decode(&frame.payload),
+                Some(2) => PowerStatusV2::decode(&frame.payload).map(|p2| (p2.to_v1(), Some(p2))),
+                _ => PowerStatusV1::decode(&frame.payload).map(|p| (p, None)),
             };
             match decoded {
-                Ok(p) => {
+                Ok((p, v2)) => {
                     debug!(
                         vbus_in_mv = p.vbus_in_mv,
                         vbat_mv = p.vbat_mv,
@@ -87,7 +91,10 @@ async fn handle(
                         faults = format!("{:#06x}", p.faults),
                         "power.status"
                     );
-                    state.update_power(p).await;
+                    match v2 {
+                        Some(p2) => state.update_power_v2(p2).await,
+                        None => state.update_power(p).await,
+                    }
                 }
                 Err(e) => warn!("power.status decode: {e}"),
             }
@@ -123,27 +130,14 @@ async fn handle(
         }
 
         // ---- HOST (commands directed to this RPi agent) ----
-        (class::HOST, op::host::SHUTDOWN) if flags & flag::REQ != 0 => {
-            commands.handle_host_shutdown(&frame, outbound).await;
-        }
-        (class::HOST, op::host::RESET) if flags & flag::REQ != 0 => {
-            commands.handle_host_reset(&frame, outbound).await;
-        }
-        (class::HOST, op::host::SERVICE_RESTART) if flags & flag::REQ != 0 => {
-            commands
-                .handle_host_service_action(&frame, outbound, "restart")
-                .await;
-        }
-        (class::HOST, op::host::SERVICE_START) if flags & flag::REQ != 0 => {
-            commands
-                .handle_host_service_action(&frame, outbound, "start")
-                .await;
-        }
-        (class::HOST, op::host::SERVICE_STOP) if flags & flag::REQ != 0 => {
-            commands
-                .handle_host_service_action(&frame, outbound, "stop")
-                .await;
-        }
+        (class::HOST, _) if flags & flag::REQ != 0 => match commands {
+            Some(c) => handle_host_req(c, &frame, outbound).await,
+            None => debug!(
+                op = opc,
+                src = frame.src,
+                "host REQ ignored (no command handler)"
+            ),
+        },
         (class::HOST, op::host::EVENT) => match HostEventV1::decode(&frame.payload) {
             Ok(e) => debug!(event = e.event, src = frame.src, "host.event echoed back"),
             Err(err) => warn!("host.event decode: {err}"),
@@ -165,3 +159,30 @@ async fn handle(
     // Drop NULL/unknown DST silently — the RP2040 hub is the authoritative router.
     let _ = (addr::NULL,);
 }
+
+async fn handle_host_req(
+    commands: &CommandsHandler,
+    frame: &Frame,
+    outbound: &mpsc::Sender<OutboundFrame>,
+) {
+    match frame.op {
+        op::host::SHUTDOWN => commands.handle_host_shutdown(frame, outbound).await,
+        op::host::RESET => commands.handle_host_reset(frame, outbound).await,
+        op::host::SERVICE_RESTART => {
+            commands
+                .handle_host_service_action(frame, outbound, "restart")
+                .await
+        }
+        op::host::SERVICE_START => {
+            commands
+                .handle_host_service_action(frame, outbound, "start")
+                .await
+        }
+        op::host::SERVICE_STOP => {
+            commands
+                .handle_host_service_action(frame, outbound, "stop")
+                .await
+        }
+        other => trace!(src = frame.src, op = other, "unhandled host REQ"),
+    }
+}
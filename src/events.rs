@@ -0,0 +1,405 @@
+//! Power-state event hooks.
+//!
+//! The shutdown SM reports the power state it starts in, then transitions
+//! (grid ↔ battery, low battery, shutdown armed / initiated), to a list of
+//! [`EventHandler`]s, and the power watch adds a battery that isn't
+//! recovering on grid and each fault's onset and resolution. Rapid grid ↔
+//! battery flapping is folded into one unstable-power episode
+//! (`[battery].unstable_transitions`). The daemon's own log lines are the
+//! built-in [`LogHandler`]; embedders add theirs via
+//! [`crate::daemon::run_daemon`], optionally wrapped in [`Filtered`] so a
+//! channel only hears about the events it subscribes to.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::proto::payloads::PowerStatusV1;
+
+/// What the SM saw when it raised an event.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerContext {
+    pub power: PowerStatusV1,
+    pub soc_pct: u8,
+}
+
+/// Callbacks run synchronously on the SM tick — return promptly and hand
+/// anything slow (network, subprocesses) off to a thread or task. All
+/// methods default to no-ops.
+pub trait EventHandler: Send + Sync {
+    /// Where power stood on the first sample the SM accepted after the
+    /// daemon started (past the warm-up); once per run. A boot during an
+    /// outage raises this with `on_battery` set, then [`Self::on_battery`].
+    fn on_initial_state(&self, _ctx: &PowerContext, _on_battery: bool) {}
+    /// Input voltage left the valid range (also raised at startup if the
+    /// first sample is already on battery).
+    fn on_battery(&self, _ctx: &PowerContext) {}
+    /// Input voltage back in the valid range.
+    fn on_grid(&self, _ctx: &PowerContext) {}
+    /// SOC dropped below `shutdown_threshold_pct` while on battery.
+    fn on_low_battery(&self, _ctx: &PowerContext) {}
+    /// Shutdown countdown started; `delay` until it fires.
+    fn on_shutdown_armed(&self, _ctx: &PowerContext, _delay: Duration) {}
+    /// Countdown elapsed; the shutdown script is about to run.
+    fn on_shutdown(&self, _ctx: &PowerContext) {}
+    /// On grid, yet the SOC is still short of `recovery_target_soc`
+    /// `waited` after it was last low: a charging problem or an undersized
+    /// supply. Raised by the power watch, not the SM.
+    fn on_recovery_overdue(&self, _ctx: &PowerContext, _waited: Duration) {}
+    /// One finding of the fault summary (`battery-absent`,
+    /// `implausible-temp (104.0 °C)`, …) has held for 10 s. Raised once per
+    /// fault by the power watch until it clears.
+    fn on_fault(&self, _ctx: &PowerContext, _fault: &str) {}
+    /// A fault reported by [`Self::on_fault`] is gone; `fault` is its name
+    /// (`implausible-temp`).
+    fn on_fault_cleared(&self, _ctx: &PowerContext, _fault: &str) {}
+    /// `transitions` grid↔battery transitions within
+    /// `[battery].unstable_window_seconds` reached `unstable_transitions`.
+    /// Until [`Self::on_power_stable`], further transitions raise no
+    /// [`Self::on_battery`] / [`Self::on_grid`].
+    fn on_unstable_power(&self, _ctx: &PowerContext, _transitions: u32) {}
+    /// The input held through a whole window after [`Self::on_unstable_power`]:
+    /// `transitions` in all over `lasted`, settled on battery or grid as
+    /// `on_battery` says.
+    fn on_power_stable(
+        &self,
+        _ctx: &PowerContext,
+        _on_battery: bool,
+        _transitions: u32,
+        _lasted: Duration,
+    ) {
+    }
+}
+
+/// The [`EventHandler`] callbacks by name, for per-channel allowlists such
+/// as `events = ["shutdown_armed", "shutdown"]`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    InitialState,
+    OnBattery,
+    OnGrid,
+    LowBattery,
+    ShutdownArmed,
+    Shutdown,
+    RecoveryOverdue,
+    Fault,
+    FaultCleared,
+    UnstablePower,
+    PowerStable,
+}
+
+/// Passes only the allowlisted events on to `inner`; an empty list passes
+/// everything.
+pub struct Filtered {
+    events: Vec<EventKind>,
+    inner: Box<dyn EventHandler>,
+}
+
+impl Filtered {
+    pub fn new(events: Vec<EventKind>, inner: Box<dyn EventHandler>) -> Self {
+        Self { events, inner }
+    }
+
+    fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+impl EventHandler for Filtered {
+    fn on_initial_state(&self, ctx: &PowerContext, on_battery: bool) {
+        if self.wants(EventKind::InitialState) {
+            self.inner.on_initial_state(ctx, on_battery);
+        }
+    }
+
+    fn on_battery(&self, ctx: &PowerContext) {
+        if self.wants(EventKind::OnBattery) {
+            self.inner.on_battery(ctx);
+        }
+    }
+
+    fn on_grid(&self, ctx: &PowerContext) {
+        if self.wants(EventKind::OnGrid) {
+            self.inner.on_grid(ctx);
+        }
+    }
+
+    fn on_low_battery(&self, ctx: &PowerContext) {
+        if self.wants(EventKind::LowBattery) {
+            self.inner.on_low_battery(ctx);
+        }
+    }
+
+    fn on_shutdown_armed(&self, ctx: &PowerContext, delay: Duration) {
+        if self.wants(EventKind::ShutdownArmed) {
+            self.inner.on_shutdown_armed(ctx, delay);
+        }
+    }
+
+    fn on_shutdown(&self, ctx: &PowerContext) {
+        if self.wants(EventKind::Shutdown) {
+            self.inner.on_shutdown(ctx);
+        }
+    }
+
+    fn on_recovery_overdue(&self, ctx: &PowerContext, waited: Duration) {
+        if self.wants(EventKind::RecoveryOverdue) {
+            self.inner.on_recovery_overdue(ctx, waited);
+        }
+    }
+
+    fn on_fault(&self, ctx: &PowerContext, fault: &str) {
+        if self.wants(EventKind::Fault) {
+            self.inner.on_fault(ctx, fault);
+        }
+    }
+
+    fn on_fault_cleared(&self, ctx: &PowerContext, fault: &str) {
+        if self.wants(EventKind::FaultCleared) {
+            self.inner.on_fault_cleared(ctx, fault);
+        }
+    }
+
+    fn on_unstable_power(&self, ctx: &PowerContext, transitions: u32) {
+        if self.wants(EventKind::UnstablePower) {
+            self.inner.on_unstable_power(ctx, transitions);
+        }
+    }
+
+    fn on_power_stable(
+        &self,
+        ctx: &PowerContext,
+        on_battery: bool,
+        transitions: u32,
+        lasted: Duration,
+    ) {
+        if self.wants(EventKind::PowerStable) {
+            self.inner
+                .on_power_stable(ctx, on_battery, transitions, lasted);
+        }
+    }
+}
+
+/// Ordered fan-out over the registered handlers.
+#[derive(Default)]
+pub struct EventHandlers(Vec<Box<dyn EventHandler>>);
+
+impl EventHandlers {
+    /// Built-in handlers first, then `extra` in registration order.
+    pub fn with_builtin(extra: Vec<Box<dyn EventHandler>>) -> Self {
+        let mut all: Vec<Box<dyn EventHandler>> = vec![Box::new(LogHandler)];
+        all.extend(extra);
+        Self(all)
+    }
+
+    pub fn initial_state(&self, ctx: &PowerContext, on_battery: bool) {
+        self.0
+            .iter()
+            .for_each(|h| h.on_initial_state(ctx, on_battery));
+    }
+
+    pub fn battery(&self, ctx: &PowerContext) {
+        self.0.iter().for_each(|h| h.on_battery(ctx));
+    }
+
+    pub fn grid(&self, ctx: &PowerContext) {
+        self.0.iter().for_each(|h| h.on_grid(ctx));
+    }
+
+    pub fn low_battery(&self, ctx: &PowerContext) {
+        self.0.iter().for_each(|h| h.on_low_battery(ctx));
+    }
+
+    pub fn shutdown_armed(&self, ctx: &PowerContext, delay: Duration) {
+        self.0.iter().for_each(|h| h.on_shutdown_armed(ctx, delay));
+    }
+
+    pub fn shutdown(&self, ctx: &PowerContext) {
+        self.0.iter().for_each(|h| h.on_shutdown(ctx));
+    }
+
+    pub fn recovery_overdue(&self, ctx: &PowerContext, waited: Duration) {
+        self.0
+            .iter()
+            .for_each(|h| h.on_recovery_overdue(ctx, waited));
+    }
+
+    pub fn fault(&self, ctx: &PowerContext, fault: &str) {
+        self.0.iter().for_each(|h| h.on_fault(ctx, fault));
+    }
+
+    pub fn fault_cleared(&self, ctx: &PowerContext, fault: &str) {
+        self.0.iter().for_each(|h| h.on_fault_cleared(ctx, fault));
+    }
+
+    pub fn unstable_power(&self, ctx: &PowerContext, transitions: u32) {
+        self.0
+            .iter()
+            .for_each(|h| h.on_unstable_power(ctx, transitions));
+    }
+
+    pub fn power_stable(
+        &self,
+        ctx: &PowerContext,
+        on_battery: bool,
+        transitions: u32,
+        lasted: Duration,
+    ) {
+        self.0
+            .iter()
+            .for_each(|h| h.on_power_stable(ctx, on_battery, transitions, lasted));
+    }
+}
+
+/// Logs every transition through `tracing`.
+pub struct LogHandler;
+
+impl EventHandler for LogHandler {
+    fn on_initial_state(&self, ctx: &PowerContext, on_battery: bool) {
+        if on_battery {
+            warn!(
+                soc = ctx.soc_pct,
+                vbus_in_mv = ctx.power.vbus_in_mv,
+                "initial power state: on battery (started during an outage)"
+            );
+        } else {
+            info!(
+                soc = ctx.soc_pct,
+                vbus_in_mv = ctx.power.vbus_in_mv,
+                "initial power state: on grid"
+            );
+        }
+    }
+
+    fn on_battery(&self, ctx: &PowerContext) {
+        warn!(
+            soc = ctx.soc_pct,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "running on battery power"
+        );
+    }
+
+    fn on_grid(&self, ctx: &PowerContext) {
+        info!(
+            soc = ctx.soc_pct,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "grid power restored"
+        );
+    }
+
+    fn on_low_battery(&self, ctx: &PowerContext) {
+        warn!(
+            soc = ctx.soc_pct,
+            vbat_mv = ctx.power.vbat_mv,
+            "battery low"
+        );
+    }
+
+    fn on_shutdown_armed(&self, ctx: &PowerContext, delay: Duration) {
+        warn!(
+            soc = ctx.soc_pct,
+            vbat_mv = ctx.power.vbat_mv,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "low battery on battery power; shutdown in {} s unless restored",
+            delay.as_secs()
+        );
+    }
+
+    fn on_shutdown(&self, ctx: &PowerContext) {
+        warn!(
+            soc = ctx.soc_pct,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "delay elapsed; initiating shutdown"
+        );
+    }
+
+    fn on_recovery_overdue(&self, ctx: &PowerContext, waited: Duration) {
+        warn!(
+            soc = ctx.soc_pct,
+            ibat_ma = ctx.power.ibat_ma,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "battery not recovering: SOC {}% {} min after it was last low; \
+             charging problem or undersized supply?",
+            ctx.soc_pct,
+            waited.as_secs() / 60
+        );
+    }
+
+    fn on_fault(&self, ctx: &PowerContext, fault: &str) {
+        warn!(soc = ctx.soc_pct, "fault detected: {fault}");
+    }
+
+    fn on_fault_cleared(&self, ctx: &PowerContext, fault: &str) {
+        info!(soc = ctx.soc_pct, "fault resolved: {fault}");
+    }
+
+    fn on_unstable_power(&self, ctx: &PowerContext, transitions: u32) {
+        warn!(
+            soc = ctx.soc_pct,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "unstable power: {transitions} grid/battery transitions in a short time; \
+             further ones are only logged until it settles"
+        );
+    }
+
+    fn on_power_stable(
+        &self,
+        ctx: &PowerContext,
+        on_battery: bool,
+        transitions: u32,
+        lasted: Duration,
+    ) {
+        info!(
+            soc = ctx.soc_pct,
+            vbus_in_mv = ctx.power.vbus_in_mv,
+            "power stable again, on {}: {transitions} transitions over {} min",
+            if on_battery { "battery" } else { "grid" },
+            lasted.as_secs() / 60
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Recorder(Arc<Mutex<Vec<&'static str>>>);
+
+    impl EventHandler for Recorder {
+        fn on_battery(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("battery");
+        }
+        fn on_low_battery(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("low");
+        }
+        fn on_shutdown(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("shutdown");
+        }
+    }
+
+    #[test]
+    fn filtered_handlers_see_only_their_events() {
+        let all = Arc::new(Mutex::new(Vec::new()));
+        let some = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![
+            Box::new(Filtered::new(Vec::new(), Box::new(Recorder(all.clone())))),
+            Box::new(Filtered::new(
+                vec![EventKind::Shutdown],
+                Box::new(Recorder(some.clone())),
+            )),
+        ]);
+        let ctx = PowerContext {
+            power: PowerStatusV1::default(),
+            soc_pct: 5,
+        };
+        handlers.battery(&ctx);
+        handlers.low_battery(&ctx);
+        handlers.shutdown(&ctx);
+        assert_eq!(*all.lock().unwrap(), ["battery", "low", "shutdown"]);
+        assert_eq!(*some.lock().unwrap(), ["shutdown"]);
+    }
+}
@@ -0,0 +1,160 @@
+//! End-of-life record of the daemon, written to `[logging].exit_report_file`
+//! on every exit — a stop signal, an IPC `stop`, or an error — so an
+//! `ExecStopPost=` hook or a monitoring script can tell why the service
+//! stopped without digging through the journal:
+//!
+//! ```json
+//! {"reason":"SIGTERM received","clean":true,"version":"2.2.1",
+//!  "unix_ts_ms":1760428800000,"uptime_s":86400,"outages":2,
+//!  "shutdown_triggered":true,"shutdown_pending":false,"dry_run":false,
+//!  "last_sample":{"age_ms":420,"vbus_in_mv":0,"soc_pct":9,…}}
+//! ```
+//!
+//! `last_sample` is the snapshot's `power` object (`null` if the UPS never
+//! reported). The same summary is logged. The file is replaced atomically
+//! and holds the most recent exit only.
+
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::BatteryConfig;
+use crate::state::State;
+use crate::VERSION;
+
+#[derive(Debug, Serialize)]
+struct ExitReport {
+    /// What ended the daemon: the stop cause, or the error.
+    reason: String,
+    /// Stopped on request rather than by an error.
+    clean: bool,
+    version: &'static str,
+    unix_ts_ms: u64,
+    uptime_s: u64,
+    /// Outages while the daemon ran, including one under way at startup.
+    outages: u64,
+    /// The daemon ran the shutdown; it is exiting because the host goes down.
+    shutdown_triggered: bool,
+    /// A shutdown countdown was running at exit.
+    shutdown_pending: bool,
+    dry_run: bool,
+    last_sample: Option<serde_json::Value>,
+}
+
+async fn build(
+    state: &State,
+    battery: &BatteryConfig,
+    started: Instant,
+    outcome: &Result<&'static str>,
+) -> ExitReport {
+    let snap = state.snapshot().await;
+    let now = state.now();
+    let (reason, clean) = match outcome {
+        Ok(why) => (why.to_string(), true),
+        Err(e) => (format!("{e:#}"), false),
+    };
+    ExitReport {
+        reason,
+        clean,
+        version: VERSION,
+        unix_ts_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        uptime_s: now.saturating_duration_since(started).as_secs(),
+        outages: snap.outages,
+        shutdown_triggered: snap.shutdown_triggered,
+        shutdown_pending: snap.shutdown_pending_since.is_some(),
+        dry_run: snap.dry_run,
+        last_sample: crate::ipc::power_json(&snap, battery, now),
+    }
+}
+
+/// Log the report and write it to `path` (empty: log only). Never fails:
+/// the daemon is going away either way.
+pub(crate) async fn write(
+    path: &str,
+    state: &State,
+    battery: &BatteryConfig,
+    started: Instant,
+    outcome: &Result<&'static str>,
+) {
+    let report = build(state, battery, started, outcome).await;
+    info!(
+        reason = %report.reason,
+        clean = report.clean,
+        uptime_s = report.uptime_s,
+        outages = report.outages,
+        shutdown_triggered = report.shutdown_triggered,
+        "daemon exiting"
+    );
+    if path.is_empty() {
+        return;
+    }
+    let written = serde_json::to_vec_pretty(&report)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| crate::store::write_atomic(Path::new(path), &json));
+    if let Err(e) = written {
+        warn!("exit report not written: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::Config;
+    use crate::proto::payloads::PowerStatusV1;
+    use crate::test_util::Scratch;
+
+    #[tokio::test]
+    async fn report_says_why_and_what_happened() {
+        let clock = Arc::new(ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = Config::default().battery;
+        let started = state.now();
+        state
+            .update_power(PowerStatusV1 {
+                vbat_mv: battery.soc_curve().pack_mv(9),
+                ..Default::default()
+            })
+            .await;
+        state.note_outage(true).await;
+        state.set_shutdown_triggered().await;
+        clock.advance(Duration::from_secs(3_600));
+
+        let dir = Scratch::new("exit");
+        let path = dir.join("exit.json");
+        let path_str = path.to_string_lossy();
+        write(
+            &path_str,
+            &state,
+            &battery,
+            started,
+            &Ok("SIGTERM received"),
+        )
+        .await;
+        let v: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(v["reason"], "SIGTERM received");
+        assert_eq!(v["clean"], true);
+        assert_eq!(v["uptime_s"], 3_600);
+        assert_eq!(v["outages"], 1);
+        assert_eq!(v["shutdown_triggered"], true);
+        assert_eq!(v["last_sample"]["soc_pct"], 9);
+        assert_eq!(v["last_sample"]["age_ms"], 3_600_000);
+
+        // An error exit replaces it.
+        let failed = Err(anyhow::anyhow!("install SIGTERM handler"));
+        write(&path_str, &State::new(), &battery, started, &failed).await;
+        let v: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(v["reason"], "install SIGTERM handler");
+        assert_eq!(v["clean"], false);
+        assert!(v["last_sample"].is_null());
+    }
+}
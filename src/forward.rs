@@ -0,0 +1,197 @@
+//! `[forward.exec]`: an escape hatch for integrations the daemon doesn't
+//! have built in. One long-lived child process is started and fed every
+//! real power sample on stdin, one JSON object per line, in the
+//! `probe --follow --json` format (so a forwarder that just appends its
+//! input to a file writes a recording `replay` and `stats` can read). Push
+//! it to your own API, a database, anything — in any language.
+//!
+//! The child's stdout and stderr are logged at debug. If it exits, closes
+//! its stdin, or leaves a sample unread for [`WRITE_TIMEOUT`] (hung, or
+//! stuck on its own output), it is killed and restarted after a backoff
+//! that doubles from 1 s up to a minute and starts over once a child has
+//! stayed up for a minute. Samples arriving in between are dropped, not
+//! queued. Injected readings never reach it: they are shown to IPC clients,
+//! not fed to integrations.
+
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::config::{BatteryConfig, ExecForwardConfig};
+use crate::probe::ProbeSample;
+use crate::state::State;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A child that ran this long counts as healthy: the next restart is quick.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+/// How long one sample may wait for the child to take it off the pipe.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Feeds [`State::sample_feed`] to the command; idle when none is
+/// configured. Aborting it kills the child.
+pub async fn forward_loop(state: Arc<State>, cfg: ExecForwardConfig, battery: BatteryConfig) {
+    let Some((program, args)) = cfg.command.split_first() else {
+        return std::future::pending().await;
+    };
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let why = match run_child(&state, program, args, &battery, WRITE_TIMEOUT).await {
+            Ok(status) => format!("exited ({status})"),
+            Err(e) => format!("failed: {e:#}"),
+        };
+        if started.elapsed() >= STABLE_RUN {
+            backoff = MIN_BACKOFF;
+        }
+        warn!(
+            "forwarder `{program}` {why}; restarting in {} s",
+            backoff.as_secs()
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Start the child and feed it until it exits or stops reading.
+async fn run_child(
+    state: &State,
+    program: &str,
+    args: &[String],
+    battery: &BatteryConfig,
+    write_timeout: Duration,
+) -> Result<ExitStatus> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("spawn")?;
+    info!(pid = child.id(), "forwarder `{program}` started");
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    if let Some(out) = child.stdout.take() {
+        tokio::spawn(log_lines(out, "stdout"));
+    }
+    if let Some(err) = child.stderr.take() {
+        tokio::spawn(log_lines(err, "stderr"));
+    }
+
+    // Subscribed per child, so a restart doesn't replay what queued up
+    // during the backoff.
+    let mut feed = state.sample_feed("forwarder");
+    loop {
+        let p = tokio::select! {
+            status = child.wait() => return Ok(status?),
+            p = feed.next_real_sample() => match p {
+                Some(p) => p,
+                None => bail!("power feed closed"),
+            },
+        };
+        let mut line = serde_json::to_string(&ProbeSample::new(battery, &p, false))?;
+        line.push('\n');
+        match tokio::time::timeout(write_timeout, stdin.write_all(line.as_bytes())).await {
+            Ok(Ok(())) => {}
+            // Exiting, or closed its stdin and would never see another sample.
+            Ok(Err(e)) => {
+                let _ = child.kill().await;
+                bail!("stopped reading stdin ({e})");
+            }
+            // Alive but not reading: the pipe is full and would stay so.
+            Err(_) => {
+                let _ = child.kill().await;
+                bail!(
+                    "left a sample unread for {} s; killed",
+                    write_timeout.as_secs_f32()
+                );
+            }
+        }
+    }
+}
+
+async fn log_lines(pipe: impl AsyncRead + Unpin, stream: &'static str) {
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        debug!(stream, "forwarder: {line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::payloads::PowerStatusV1;
+    use crate::test_util::Scratch;
+
+    #[tokio::test]
+    async fn samples_reach_the_child_and_it_is_restarted() {
+        let dir = Scratch::new("forward");
+        let path = dir.join("forwarded.jsonl");
+        // Takes one line and exits, so the second line needs a restart.
+        let cfg = ExecForwardConfig {
+            command: vec![
+                "sh".into(),
+                "-c".into(),
+                format!("head -n 1 >> {}", path.display()),
+            ],
+        };
+        let state = State::new();
+        let task = tokio::spawn(forward_loop(state.clone(), cfg, Config::default().battery));
+
+        let lines = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                state
+                    .update_power(PowerStatusV1 {
+                        vbus_in_mv: 20_000,
+                        vbat_mv: 8_000,
+                        ..Default::default()
+                    })
+                    .await;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let text = std::fs::read_to_string(&path).unwrap_or_default();
+                if text.lines().count() >= 2 {
+                    break text;
+                }
+            }
+        })
+        .await
+        .expect("two forwarded lines");
+        task.abort();
+
+        for line in lines.lines() {
+            let v: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(v["vbus_in_mv"], 20_000);
+            assert_eq!(v["vbat_mv"], 8_000);
+            assert_eq!(v["on_battery"], false);
+            assert!(v["unix_ts_ms"].is_u64());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_child_that_stops_reading_is_killed() {
+        let state = State::new();
+        let battery = Config::default().battery;
+        // Alive, but never reads: once the pipe fills, writes stall.
+        let args = ["-c".to_string(), "sleep 600".to_string()];
+        let child = run_child(&state, "sh", &args, &battery, Duration::from_millis(100));
+        tokio::pin!(child);
+        let err = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                tokio::select! {
+                    r = &mut child => break r.unwrap_err(),
+                    () = state.update_power(PowerStatusV1::default()) => {}
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the stalled child is given up on");
+        assert!(err.to_string().contains("unread for 0.1 s"), "{err:#}");
+    }
+}
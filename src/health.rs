@@ -0,0 +1,308 @@
+//! `[health]`: one 0–100 number for "is the UPS doing alright", for anyone
+//! who doesn't want to read a dozen raw fields. It is recomputed with every
+//! real power sample from five parts, each scored 0–100:
+//!
+//! - `input`: 0 on battery. On grid, the worse of two terms. One is how far
+//!   the input is from what it should be (`[battery].nominal_input_mv`, else
+//!   the PD voltage negotiated on the input). The other is how much it
+//!   wanders over the last `window_samples` (max − min, against the mean).
+//!   Each scores 100 up to 2%, 0 from 10%, linear between.
+//! - `charging`: 100 on battery, where nothing is expected of the charger.
+//!   On grid: 100 while charging, charged or maintaining, 50 when idle, 0
+//!   with a charger fault or the not-charging warning raised.
+//! - `temperature`: 100 up to 45 °C, 0 from 65 °C, linear between; 0 for a
+//!   reading that can't be real.
+//! - `soc`: the SOC, halved on grid while it is below where it was at the
+//!   start of the window (draining although the grid is there).
+//! - `faults`: 100 with nothing in the fault summary, else 0.
+//!
+//! The score is their mean weighted by the `*_weight` keys; a weight of 0
+//! leaves its part out. Injected samples are not scored.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{BatteryConfig, HealthConfig};
+use crate::proto::payloads::charge_state;
+use crate::shutdown_sm::classify_input;
+use crate::state::{AgentState, State, PLAUSIBLE_TEMP_DC};
+
+/// Input deviation or spread (%) that still scores 100, and the one that
+/// scores 0.
+const INPUT_GOOD_PCT: f32 = 2.0;
+const INPUT_BAD_PCT: f32 = 10.0;
+/// Board temperature (°C) that still scores 100, and the one that scores 0.
+const TEMP_GOOD_C: f32 = 45.0;
+const TEMP_BAD_C: f32 = 65.0;
+
+/// The score and what it was made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub score: u8,
+    pub input: u8,
+    pub charging: u8,
+    pub temperature: u8,
+    pub soc: u8,
+    pub faults: u8,
+}
+
+/// Rescores on each [`State::sample_feed`] sample; idle when disabled.
+pub async fn health_loop(state: Arc<State>, cfg: HealthConfig, battery: BatteryConfig) {
+    if !cfg.enabled {
+        return std::future::pending().await;
+    }
+    info!(
+        window_samples = cfg.window_samples,
+        "power health score running"
+    );
+    let mut meter = HealthMeter::new(cfg.window_samples);
+    let mut feed = state.sample_feed("health score");
+    while feed.next_real_sample().await.is_some() {
+        let snap = state.snapshot().await;
+        if let Some(report) = meter.update(&snap, &cfg, &battery) {
+            state.set_health(report).await;
+        }
+    }
+}
+
+/// The recent samples the input spread and SOC trend are judged over.
+#[derive(Debug)]
+pub(crate) struct HealthMeter {
+    window: usize,
+    /// `(vbus_in_mv, soc_pct)`, oldest first.
+    recent: VecDeque<(u16, u8)>,
+}
+
+impl HealthMeter {
+    pub(crate) fn new(window_samples: usize) -> Self {
+        Self {
+            window: window_samples.max(1),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Score the latest sample in `snap`; `None` before there is one.
+    pub(crate) fn update(
+        &mut self,
+        snap: &AgentState,
+        cfg: &HealthConfig,
+        battery: &BatteryConfig,
+    ) -> Option<HealthReport> {
+        let p = snap.last_power?;
+        let soc_pct = battery.soc_pct(p.vbat_mv);
+        if self.recent.len() == self.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((p.vbus_in_mv, soc_pct));
+        let on_battery = classify_input(
+            &p,
+            snap.last_power_v2.as_ref(),
+            battery.input_min_valid_mv,
+            battery.input_max_valid_mv,
+            battery.input_zero_cross_check,
+        )
+        .on_battery();
+
+        let input = if on_battery {
+            0
+        } else {
+            let expected = (battery.nominal_input_mv > 0)
+                .then_some(battery.nominal_input_mv)
+                .or(snap.last_power_v2.map(|v2| v2.pd_in_mv))
+                .filter(|&mv| mv > 0);
+            let deviation = expected.map_or(100, |mv| {
+                let dev = (p.vbus_in_mv as f32 - mv as f32) / mv as f32 * 100.0;
+                ramp(dev.abs(), INPUT_GOOD_PCT, INPUT_BAD_PCT)
+            });
+            deviation.min(ramp(self.input_spread_pct(), INPUT_GOOD_PCT, INPUT_BAD_PCT))
+        };
+        let charging = if on_battery {
+            100
+        } else if snap.charging_fault || p.charge_state == charge_state::FAULT {
+            0
+        } else if snap.charge_maintaining
+            || matches!(
+                p.charge_state,
+                charge_state::CHARGING | charge_state::CHARGED
+            )
+        {
+            100
+        } else {
+            50
+        };
+        let temperature = if PLAUSIBLE_TEMP_DC.contains(&p.temp_dc) {
+            ramp(p.temp_dc as f32 / 10.0, TEMP_GOOD_C, TEMP_BAD_C)
+        } else {
+            0
+        };
+        let draining = self
+            .recent
+            .front()
+            .is_some_and(|&(_, first)| soc_pct < first);
+        let soc = if !on_battery && draining {
+            soc_pct / 2
+        } else {
+            soc_pct
+        };
+        let faults = if snap.faults(battery).is_empty() {
+            100
+        } else {
+            0
+        };
+
+        let parts = [
+            (input, cfg.input_weight),
+            (charging, cfg.charging_weight),
+            (temperature, cfg.temperature_weight),
+            (soc, cfg.soc_weight),
+            (faults, cfg.faults_weight),
+        ];
+        // u64: even unvalidated weights can't overflow.
+        let total: u64 = parts.iter().map(|&(_, w)| u64::from(w)).sum();
+        let weighted: u64 = parts
+            .iter()
+            .map(|&(v, w)| u64::from(v) * u64::from(w))
+            .sum();
+        let score = if total == 0 {
+            100
+        } else {
+            (weighted as f32 / total as f32).round() as u8
+        };
+        Some(HealthReport {
+            score,
+            input,
+            charging,
+            temperature,
+            soc,
+            faults,
+        })
+    }
+
+    /// Max − min of the input over the window, in percent of its mean.
+    fn input_spread_pct(&self) -> f32 {
+        let mvs = || self.recent.iter().map(|&(mv, _)| mv as f32);
+        let mean = mvs().sum::<f32>() / self.recent.len().max(1) as f32;
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        let (min, max) = mvs().fold((f32::MAX, f32::MIN), |(lo, hi), mv| {
+            (lo.min(mv), hi.max(mv))
+        });
+        (max - min) / mean * 100.0
+    }
+}
+
+/// 100 at or below `good`, 0 at or above `bad`, linear between.
+fn ramp(value: f32, good: f32, bad: f32) -> u8 {
+    if value <= good {
+        100
+    } else if value >= bad {
+        0
+    } else {
+        (100.0 * (bad - value) / (bad - good)).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::payloads::PowerStatusV1;
+
+    #[test]
+    fn each_part_pulls_the_score_down_by_its_weight() {
+        let cfg = HealthConfig::default();
+        let mut battery = crate::config::Config::default().battery;
+        battery.nominal_input_mv = 20_000;
+        let good = PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: 8_400,
+            charge_state: charge_state::CHARGED,
+            temp_dc: 300,
+            ..Default::default()
+        };
+        let full = battery.soc_pct(good.vbat_mv);
+        let mut snap = AgentState {
+            last_power: Some(good),
+            ..Default::default()
+        };
+        let mut meter = HealthMeter::new(4);
+        assert_eq!(meter.update(&AgentState::default(), &cfg, &battery), None);
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert_eq!(
+            (r.input, r.charging, r.temperature, r.faults),
+            (100, 100, 100, 100)
+        );
+        assert_eq!(r.soc, full);
+
+        // 6% low on the input: halfway down the input ramp. 55 °C: halfway
+        // down the temperature ramp.
+        snap.last_power = Some(PowerStatusV1 {
+            vbus_in_mv: 18_800,
+            temp_dc: 550,
+            ..good
+        });
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert!(r.input < 50, "the jump also counts as spread");
+        assert_eq!(r.temperature, 50);
+        for _ in 0..4 {
+            meter.update(&snap, &cfg, &battery);
+        }
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert_eq!((r.input, r.temperature), (50, 50));
+        let expected = (50 * 25 + 100 * 20 + 50 * 15 + full as u32 * 20 + 100 * 20) as f32 / 100.0;
+        assert_eq!(r.score, expected.round() as u8);
+
+        // A fault zeroes its part (and the charger's, here); weight 0
+        // leaves a part out.
+        snap.charging_fault = true;
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert_eq!((r.charging, r.faults), (0, 0));
+        let only_temp = HealthConfig {
+            input_weight: 0,
+            charging_weight: 0,
+            soc_weight: 0,
+            faults_weight: 0,
+            ..cfg
+        };
+        assert_eq!(meter.update(&snap, &only_temp, &battery).unwrap().score, 50);
+    }
+
+    #[test]
+    fn on_battery_the_input_scores_zero_and_a_draining_soc_counts_on_grid_only() {
+        let cfg = HealthConfig::default();
+        let battery = crate::config::Config::default().battery;
+        let grid = PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: 8_000,
+            charge_state: charge_state::IDLE,
+            temp_dc: 300,
+            ..Default::default()
+        };
+        let mut meter = HealthMeter::new(10);
+        let mut snap = AgentState {
+            last_power: Some(grid),
+            ..Default::default()
+        };
+        meter.update(&snap, &cfg, &battery);
+        snap.last_power = Some(PowerStatusV1 {
+            vbat_mv: 7_600,
+            ..grid
+        });
+        let low = battery.soc_pct(7_600);
+        assert!(low < battery.soc_pct(8_000));
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert_eq!((r.charging, r.soc), (50, low / 2));
+
+        snap.last_power = Some(PowerStatusV1 {
+            vbus_in_mv: 0,
+            vbat_mv: 7_600,
+            ibat_ma: -900,
+            ..grid
+        });
+        let r = meter.update(&snap, &cfg, &battery).unwrap();
+        assert_eq!((r.input, r.charging, r.soc), (0, 100, low));
+    }
+}
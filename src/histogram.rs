@@ -0,0 +1,92 @@
+//! Input-voltage histogram for power-quality reporting: every real
+//! `power.status` sample's `vbus_in_mv` is counted into the buckets set by
+//! `[power_quality].input_buckets_mv`, so a day's worth shows whether the
+//! supply sits at 20 V or wanders. Read it with the IPC `histogram` op.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::PowerQualityConfig;
+use crate::state::State;
+
+/// Counts per bucket, Prometheus-style: bucket `i` holds samples
+/// `<= bounds_mv[i]` (and above the previous bound); the extra last bucket
+/// is everything above the top bound.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputHistogram {
+    pub bounds_mv: Vec<u16>,
+    /// `bounds_mv.len() + 1` entries.
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum_mv: u64,
+    /// When counting started (daemon start).
+    pub since_unix_ms: u64,
+}
+
+impl InputHistogram {
+    /// `bounds_mv` are sorted and de-duplicated; order in the config doesn't
+    /// matter.
+    pub fn new(mut bounds_mv: Vec<u16>, since_unix_ms: u64) -> Self {
+        bounds_mv.sort_unstable();
+        bounds_mv.dedup();
+        let counts = vec![0; bounds_mv.len() + 1];
+        Self {
+            bounds_mv,
+            counts,
+            count: 0,
+            sum_mv: 0,
+            since_unix_ms,
+        }
+    }
+
+    pub fn record(&mut self, mv: u16) {
+        let i = self.bounds_mv.partition_point(|&b| b < mv);
+        self.counts[i] += 1;
+        self.count += 1;
+        self.sum_mv += mv as u64;
+    }
+}
+
+/// Buckets [`State::sample_feed`]; idle when no buckets are configured.
+pub async fn histogram_loop(state: Arc<State>, cfg: PowerQualityConfig) {
+    if cfg.input_buckets_mv.is_empty() {
+        return std::future::pending().await;
+    }
+    let mut hist = InputHistogram::new(cfg.input_buckets_mv, unix_now_ms());
+    info!(buckets = ?hist.bounds_mv, "input voltage histogram running");
+    state.set_input_histogram(Some(hist.clone())).await;
+
+    let mut feed = state.sample_feed("input histogram");
+    while let Some(p) = feed.next_real_sample().await {
+        hist.record(p.vbus_in_mv);
+        state.set_input_histogram(Some(hist.clone())).await;
+    }
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_land_in_le_buckets() {
+        let mut h = InputHistogram::new(vec![20_500, 19_500, 20_000, 19_500], 0);
+        assert_eq!(h.bounds_mv, [19_500, 20_000, 20_500]);
+        for mv in [0, 19_500, 19_501, 20_000, 20_300, 21_000, 22_000] {
+            h.record(mv);
+        }
+        // <=19.5 V, <=20 V, <=20.5 V, above.
+        assert_eq!(h.counts, [2, 2, 1, 2]);
+        assert_eq!(h.count, 7);
+        assert_eq!(h.sum_mv, 122_301);
+    }
+}
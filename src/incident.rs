@@ -0,0 +1,255 @@
+//! Flight recorder for supply trouble: the last few real samples are kept
+//! in memory, and when the input voltage falls by `[power_quality].
+//! incident_drop_mv` or more from one sample to the next, that window plus
+//! as many samples after the drop is written out as one incident. It gives
+//! the moments around an intermittent brown-out without logging every
+//! sample all the time.
+//!
+//! Each incident is a file `incident-<unix_ms>.jsonl` in
+//! `[power_quality].incident_dir`, one sample per line in the
+//! `probe --follow --json` format, so `stats` and `replay` read it as they
+//! read a recording. Only the newest [`KEEP_FILES`] are kept. With no
+//! directory the lines go to the log instead.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tracing::{debug, info, warn};
+
+use crate::config::{BatteryConfig, PowerQualityConfig};
+use crate::probe::ProbeSample;
+use crate::state::State;
+
+/// Incident files kept in the directory; older ones are removed.
+const KEEP_FILES: usize = 20;
+
+/// One recorded window.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Incident {
+    pub(crate) from_mv: u16,
+    pub(crate) to_mv: u16,
+    /// JSON lines, oldest first; the drop is `lines[before]`.
+    pub(crate) lines: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct FlightRecorder {
+    drop_mv: u16,
+    before: usize,
+    after: usize,
+    ring: VecDeque<String>,
+    last_mv: Option<u16>,
+    /// Being filled with the samples after a drop; `usize`: still to go.
+    open: Option<(Incident, usize)>,
+}
+
+impl FlightRecorder {
+    pub(crate) fn new(cfg: &PowerQualityConfig) -> Self {
+        Self {
+            drop_mv: cfg.incident_drop_mv,
+            before: cfg.incident_samples_before,
+            after: cfg.incident_samples_after,
+            ring: VecDeque::with_capacity(cfg.incident_samples_before + 1),
+            last_mv: None,
+            open: None,
+        }
+    }
+
+    /// Record a sample (`line` is its JSON); returns an incident once its
+    /// window is complete. A drop while one is being recorded is part of
+    /// that window rather than a new incident. The sample that completes a
+    /// window starts the history of the next one.
+    pub(crate) fn push(&mut self, vbus_in_mv: u16, line: String) -> Option<Incident> {
+        let from_mv = self.last_mv.replace(vbus_in_mv);
+        if let Some((incident, remaining)) = self.open.as_mut() {
+            incident.lines.push(line.clone());
+            *remaining -= 1;
+            if *remaining > 0 {
+                return None;
+            }
+            self.remember(line);
+            return self.open.take().map(|(incident, _)| incident);
+        }
+        let Some(from_mv) = from_mv.filter(|&from| from.saturating_sub(vbus_in_mv) >= self.drop_mv)
+        else {
+            self.remember(line);
+            return None;
+        };
+        let mut lines: Vec<String> = self.ring.drain(..).collect();
+        lines.push(line.clone());
+        let incident = Incident {
+            from_mv,
+            to_mv: vbus_in_mv,
+            lines,
+        };
+        if self.after == 0 {
+            self.remember(line);
+            return Some(incident);
+        }
+        self.open = Some((incident, self.after));
+        None
+    }
+
+    fn remember(&mut self, line: String) {
+        self.ring.push_back(line);
+        if self.ring.len() > self.before {
+            self.ring.pop_front();
+        }
+    }
+}
+
+/// Watches [`State::sample_feed`] for drops; idle unless `incident_drop_mv`
+/// is set.
+pub async fn incident_loop(state: Arc<State>, cfg: PowerQualityConfig, battery: BatteryConfig) {
+    if cfg.incident_drop_mv == 0 {
+        return std::future::pending().await;
+    }
+    info!(
+        drop_mv = cfg.incident_drop_mv,
+        before = cfg.incident_samples_before,
+        after = cfg.incident_samples_after,
+        dir = %cfg.incident_dir,
+        "input drop recorder running"
+    );
+    let mut recorder = FlightRecorder::new(&cfg);
+    let mut feed = state.sample_feed("incident recorder");
+    while let Some(p) = feed.next_real_sample().await {
+        let line = match serde_json::to_string(&ProbeSample::new(&battery, &p, false)) {
+            Ok(line) => line,
+            Err(e) => {
+                debug!("incident recorder: {e}");
+                continue;
+            }
+        };
+        let was_open = recorder.open.is_some();
+        let done = recorder.push(p.vbus_in_mv, line);
+        let started = recorder.open.as_ref().map(|(i, _)| i).or(done.as_ref());
+        if let Some(&Incident { from_mv, to_mv, .. }) = started.filter(|_| !was_open) {
+            warn!(
+                from_mv,
+                to_mv,
+                "input dropped {} mV in one sample; recording an incident",
+                from_mv - to_mv
+            );
+        }
+        if let Some(incident) = done {
+            save(&cfg.incident_dir, &incident);
+        }
+    }
+}
+
+fn save(dir: &str, incident: &Incident) {
+    if dir.is_empty() {
+        for line in &incident.lines {
+            info!("incident: {line}");
+        }
+        return;
+    }
+    match write(Path::new(dir), incident) {
+        Ok(path) => info!(
+            samples = incident.lines.len(),
+            "incident written to {}",
+            path.display()
+        ),
+        Err(e) => warn!("incident not written: {e:#}"),
+    }
+}
+
+fn write(dir: &Path, incident: &Incident) -> Result<std::path::PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    let unix_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let path = dir.join(format!("incident-{unix_ms}.jsonl"));
+    let mut text = incident.lines.join("\n");
+    text.push('\n');
+    crate::store::write_atomic(&path, text.as_bytes())?;
+    prune(dir);
+    Ok(path)
+}
+
+/// Drop all but the newest [`KEEP_FILES`] incidents. The names sort by
+/// time.
+fn prune(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("incident-") && n.ends_with(".jsonl"))
+        })
+        .collect();
+    files.sort();
+    let excess = files.len().saturating_sub(KEEP_FILES);
+    for old in &files[..excess] {
+        if let Err(e) = std::fs::remove_file(old) {
+            debug!("could not remove {}: {e}", old.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    fn recorder(drop_mv: u16, before: usize, after: usize) -> FlightRecorder {
+        FlightRecorder::new(&PowerQualityConfig {
+            incident_drop_mv: drop_mv,
+            incident_samples_before: before,
+            incident_samples_after: after,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn a_drop_records_the_window_around_it() {
+        let mut r = recorder(5_000, 2, 2);
+        let mut feed = |mv: u16| r.push(mv, mv.to_string());
+        for mv in [20_000, 20_100, 19_900, 20_000] {
+            assert_eq!(feed(mv), None);
+        }
+        // Sagging by less than the threshold is not an incident.
+        assert_eq!(feed(16_000), None);
+        assert_eq!(feed(9_000), None); // the drop
+        assert_eq!(feed(0), None); // a second drop is part of the window
+        let incident = feed(19_000).expect("window complete");
+        assert_eq!((incident.from_mv, incident.to_mv), (16_000, 9_000));
+        assert_eq!(incident.lines, ["20000", "16000", "9000", "0", "19000"]);
+
+        // The recorder starts over: the next window has fresh history.
+        assert_eq!(feed(19_500), None);
+        let incident = feed(0).map(|i| i.lines);
+        assert_eq!(incident, None);
+        assert_eq!(feed(0), None);
+        assert_eq!(feed(0).unwrap().lines, ["19000", "19500", "0", "0", "0"]);
+    }
+
+    #[test]
+    fn files_are_written_and_pruned() {
+        let dir = Scratch::new("incidents");
+        for n in 0..KEEP_FILES + 2 {
+            let old = dir.join(format!("incident-{:013}.jsonl", n));
+            std::fs::write(old, "{}\n").unwrap();
+        }
+        let incident = Incident {
+            from_mv: 20_000,
+            to_mv: 0,
+            lines: vec!["{\"vbus_in_mv\":20000}".into(), "{\"vbus_in_mv\":0}".into()],
+        };
+        let path = write(&dir, &incident).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"vbus_in_mv\":20000}\n{\"vbus_in_mv\":0}\n"
+        );
+        let left = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(left, KEEP_FILES);
+        assert!(path.exists());
+        assert!(!dir.join(format!("incident-{:013}.jsonl", 0)).exists());
+    }
+}
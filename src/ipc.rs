@@ -1,26 +1,86 @@
-//! Unix-socket IPC server. Read-only state queries for the CLI and (later) the
-//! LCD plugin.
+//! Unix-socket IPC server. State queries for the CLI and (later) the LCD
+//! plugin, plus a small control surface (`stop` / `reload`).
 //!
 //! Wire format: line-delimited JSON. One JSON object per line; client closes
 //! the socket to disconnect. Ops:
 //!   - `{"op":"snapshot"}`  → one `snapshot` reply, then connection stays open
-//!   - `{"op":"subscribe"}` → `snapshot` reply, then a `snapshot` every second until disconnect
-//!   - `{"op":"version"}`   → `{"type":"version","version":"<x.y.z>"}` then connection stays open
+//!   - `{"op":"subscribe"}` → `snapshot` reply, then a `snapshot` every second
+//!     until disconnect. With `"encoding":"binary"` each snapshot is a binary
+//!     frame instead (see below)
+//!   - `{"op":"version"}`   → `{"type":"version","version":"<x.y.z>"}`, then
+//!     connection stays open
+//!   - `{"op":"info"}`      → `{"type":"info","version":…,"last_discharge":{…},
+//!     "last_charge":{…},"kv_rejects":{…},"cadence":{…},…}`: the most recent
+//!     measured capacity spans (see [`crate::capacity`]), `null` until one
+//!     completes, key-value telemetry lines dropped so far (`missing` /
+//!     `malformed`), missed power samples and the largest recent gap (see
+//!     [`crate::cadence`]), energy totals, the health report and the serial
+//!     port; also `uptime_s`, `clients` / `subscribers`, `sample_age_ms`,
+//!     `outages` and `in_outage`
+//!   - `{"op":"histogram"}` → `{"type":"histogram","histogram":{"bounds_mv":[…],
+//!     "counts":[…],…}}`: input-voltage counts since start (see
+//!     [`crate::histogram`]), or an `error` if not configured
+//!   - `{"op":"inject","data":{…},"hold_s":60,"exercise_shutdown":false}` →
+//!     `injected` reply. Testing hook, refused unless `[debug].allow_inject`:
+//!     shows clients `data` (`power.status` fields, plus an optional
+//!     `soc_pct` that sets `vbat_mv`; unset fields are 0) in place of the
+//!     UPS's reading for `hold_s` (at most [`MAX_INJECT_HOLD_S`]). Real
+//!     frames keep driving the daemon. With `exercise_shutdown` the shutdown
+//!     SM acts on `data` instead, but never actually shuts down.
+//!   - `{"op":"nut"}`    → `{"type":"nut","vars":{"battery.charge":"55",…}}`:
+//!     NUT-named variables (see [`crate::nut`]), or an `error` if stale
+//!   - `{"op":"stop"}`   → `{"type":"stopping"}`, then the daemon exits cleanly
+//!   - `{"op":"reload"}` → `{"type":"reloaded","warnings":[…]}` once the config
+//!     has been re-read (or an `error` if it didn't parse; the old one stays)
+//!
+//! When the daemon exits, for whatever reason, every connected client gets an
+//! unsolicited `{"type":"stopping"}` and the connection is closed.
+//!
+//! A binary frame is a `0x00` byte (a JSON line starts with `{`), a `u16`
+//! LE payload length, then the payload (see [`crate::packed`] for how
+//! values are laid out): layout version `1u8`, then the snapshot fields in
+//! the order [`SnapshotMsg::pack`] writes them. Other replies, `stopping`
+//! included, stay JSON lines on the same connection.
+//!
+//! `stop` / `reload` are refused unless the peer is root or the daemon's own
+//! user (`SO_PEERCRED`).
+//!
+//! With `[ipc].tcp_listen` set, the same protocol is also served over TCP for
+//! remote dashboards. TCP clients are read-only: `stop`, `reload` and
+//! `inject` are refused there. With `[ipc].token` set, a TCP client must
+//! first send `{"op":"auth","token":"…"}` (→ `authenticated`); anything else
+//! before that gets an `error`, and a wrong token closes the connection, as
+//! does not authenticating within [`AUTH_TIMEOUT`]. At most
+//! [`MAX_TCP_CLIENTS`] TCP clients are served at once; more are turned away.
+//!
+//! A request line longer than [`MAX_REQUEST_LINE`] bytes gets an `error`
+//! and closes the connection, on either transport.
 
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::mpsc;
-use tracing::{debug, info, warn};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, warn};
 
+use crate::cadence::CadenceCounts;
+use crate::capacity::{CapacitySpan, EnergyTotals, SpanKind};
+use crate::config::{BatteryConfig, Config, IpcEncoding};
+use crate::health::HealthReport;
+use crate::histogram::InputHistogram;
+use crate::packed::Pack;
 use crate::proto::payloads::{HostStatusV1, NetStatusV1, PowerStatusV1};
-use crate::soc::pack_mv_to_soc_pct;
+use crate::soc::SocCurve;
 use crate::state::{AgentState, State};
+use crate::transport::kv::KvRejectCounts;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -28,16 +88,133 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 #[serde(tag = "op", rename_all = "snake_case")]
 enum Request {
     Snapshot,
-    Subscribe,
+    Subscribe {
+        #[serde(default)]
+        encoding: IpcEncoding,
+    },
     Version,
+    Info,
+    Histogram,
+    Auth {
+        token: String,
+    },
+    Inject(InjectRequest),
+    Nut,
+    Stop,
+    Reload,
+}
+
+/// Control requests forwarded from IPC clients to the daemon supervisor.
+#[derive(Debug)]
+pub enum Control {
+    Stop,
+    /// Re-read the config; answered with its warnings, or why it failed.
+    Reload(oneshot::Sender<Result<Vec<String>, String>>),
+}
+
+#[derive(Debug, Deserialize)]
+struct InjectRequest {
+    data: InjectedPower,
+    #[serde(default = "default_inject_hold_s")]
+    hold_s: u64,
+    #[serde(default)]
+    exercise_shutdown: bool,
+}
+
+fn default_inject_hold_s() -> u64 {
+    60
+}
+
+/// Longest `inject` hold: a test fixture, not a way to blind clients.
+pub const MAX_INJECT_HOLD_S: u64 = 3600;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct InjectedPower {
+    charge_state: u8,
+    vbus_in_mv: u16,
+    vbus_out_mv: u16,
+    ibus_out_ma: i16,
+    vbat_mv: u16,
+    ibat_ma: i16,
+    temp_dc: i16,
+    pd_contract_mv: u16,
+    pd_contract_ma: u16,
+    faults: u16,
+    /// Overrides `vbat_mv` with a pack voltage that reads as this SOC.
+    soc_pct: Option<u8>,
+}
+
+impl InjectedPower {
+    fn to_status(&self, curve: SocCurve) -> PowerStatusV1 {
+        PowerStatusV1 {
+            charge_state: self.charge_state,
+            vbus_in_mv: self.vbus_in_mv,
+            vbus_out_mv: self.vbus_out_mv,
+            ibus_out_ma: self.ibus_out_ma,
+            vbat_mv: self.soc_pct.map_or(self.vbat_mv, |pct| curve.pack_mv(pct)),
+            ibat_ma: self.ibat_ma,
+            temp_dc: self.temp_dc,
+            pd_contract_mv: self.pd_contract_mv,
+            pd_contract_ma: self.pd_contract_ma,
+            faults: self.faults,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 enum Reply {
-    Snapshot(SnapshotMsg),
-    Version { version: &'static str },
-    Error { message: String },
+    Snapshot(Box<SnapshotMsg>),
+    Version {
+        version: &'static str,
+    },
+    /// Version plus the most recent measured capacity spans.
+    Info {
+        version: &'static str,
+        last_discharge: Option<CapacitySpan>,
+        last_charge: Option<CapacitySpan>,
+        /// Key-value telemetry lines dropped for a missing or malformed field.
+        kv_rejects: KvRejectCounts,
+        /// Missed power samples and recent inter-sample gaps.
+        cadence: CadenceCounts,
+        /// Grid and battery energy since counting started.
+        energy: EnergyTotals,
+        /// The power health score and its parts.
+        health: Option<HealthReport>,
+        /// The serial port last opened (`[serial].port_file`).
+        port: Option<String>,
+        /// Seconds since the daemon started.
+        uptime_s: u64,
+        /// Connected IPC clients, this one included, and how many of them
+        /// are subscribed.
+        clients: usize,
+        subscribers: usize,
+        /// Age of the last real power sample; `None` before the first.
+        sample_age_ms: Option<u64>,
+        /// Outages seen since start, and whether one is under way.
+        outages: u64,
+        in_outage: bool,
+    },
+    /// Input-voltage histogram since daemon start.
+    Histogram {
+        histogram: InputHistogram,
+    },
+    Injected {
+        hold_s: u64,
+        exercise_shutdown: bool,
+    },
+    Nut {
+        vars: BTreeMap<&'static str, String>,
+    },
+    Authenticated,
+    Stopping,
+    Reloaded {
+        warnings: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
 }
 
 #[derive(Debug, Serialize)]
@@ -49,6 +226,27 @@ struct SnapshotMsg {
     host: Option<HostSnapshot>,
     last_power_event: Option<u8>,
     shutdown_pending_for_s: Option<u64>,
+    charging_fault: bool,
+    pd_overload: bool,
+    /// [`AgentState::fault_summary`]: everything that looks wrong, if anything.
+    fault_summary: Option<String>,
+    serial_connected: bool,
+    /// Serial link is down and `power` is the last sample seen before it
+    /// dropped.
+    degraded: bool,
+    /// Age of the newest power sample, if any.
+    last_update_age_ms: Option<u64>,
+    /// That age is past `[ipc].max_sample_age_seconds`: the daemon is up
+    /// but no data is flowing.
+    stale: bool,
+    /// Power samples missed against `[serial].expected_interval_ms`.
+    missed_samples: u64,
+    /// Longest gap between the recent power samples.
+    max_recent_gap_ms: Option<u64>,
+    /// `power` was injected over IPC, not read from the UPS.
+    synthetic: bool,
+    /// Daemon runs with `[debug].dry_run`: nothing will actually shut down.
+    dry_run: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -66,6 +264,34 @@ struct PowerSnapshot {
     pd_contract_mv: u16,
     pd_contract_ma: u16,
     faults: u16,
+    /// Negotiated input PD contract (v2 status only), e.g. "15V @ 3A".
+    pd_in_contract: Option<String>,
+    pd_in_mv: Option<u16>,
+    pd_in_ma: Option<u16>,
+    /// Measured input power as % of the negotiated contract.
+    pd_load_pct: Option<u32>,
+    /// Measured input power, `vbus_in × iin` (v2 status only).
+    input_mw: Option<u32>,
+    /// `[battery].nominal_input_mv`, if set.
+    nominal_input_mv: Option<u16>,
+    /// Signed input deviation from nominal; only while on grid.
+    input_deviation_pct: Option<f32>,
+    /// UPS firmware uptime (v2 status only); 0 if the firmware doesn't count.
+    ups_uptime_s: Option<u32>,
+    /// v2 `flags` byte (`power2_flag`): input / output enable, battery
+    /// present, power-good, USB-C attached.
+    power_flags: Option<u8>,
+    /// `power_flags` as the firmware sent them, before power-good is
+    /// debounced (`[battery].power_good_debounce_samples`). JSON only.
+    power_flags_raw: Option<u8>,
+    /// The pack is full and the charger tops it off; `charge_state` then
+    /// cycles between charging and charged.
+    maintaining: bool,
+    /// [`crate::health`] score, 0–100; `None` while disabled or before the
+    /// first sample is scored.
+    health_score: Option<u8>,
+    /// `vbus_out_mv` is below `[battery].rail_min_mv`: the Pi may brown out.
+    rail_undervoltage: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -96,50 +322,243 @@ struct HostSnapshot {
     eth_client_state: u8,
 }
 
-/// Spawn the IPC listener on `socket_path`. Returns the listener task handle.
+/// Settings every client connection needs.
+pub(crate) struct ClientCtx {
+    pub(crate) battery: BatteryConfig,
+    pub(crate) allow_inject: bool,
+    /// Where `stop` / `reload` go; `None` refuses them.
+    pub(crate) control: Option<mpsc::Sender<Control>>,
+    /// `[ipc].token`: TCP clients must `auth` with it first. Empty = open.
+    pub(crate) token: String,
+    /// How long they have to; [`AUTH_TIMEOUT`].
+    pub(crate) auth_timeout: Duration,
+}
+
+impl ClientCtx {
+    pub(crate) fn new(cfg: &Config, control: Option<mpsc::Sender<Control>>) -> Self {
+        Self {
+            battery: cfg.battery.clone(),
+            allow_inject: cfg.debug.allow_inject,
+            control,
+            token: cfg.ipc.token.clone(),
+            auth_timeout: AUTH_TIMEOUT,
+        }
+    }
+}
+
+/// Spawn the IPC listener on `socket_path`, plus the TCP one when
+/// `[ipc].tcp_listen` is set. Returns the task handle serving both.
+/// `stop` / `reload` requests are forwarded to `control` (refused if `None`).
 pub async fn spawn_ipc(
     socket_path: String,
     state: Arc<State>,
-    on_batt_min_mv: u16,
-    on_batt_max_mv: u16,
+    cfg: &Config,
+    control: Option<mpsc::Sender<Control>>,
 ) -> Result<tokio::task::JoinHandle<()>> {
-    if let Some(parent) = Path::new(&socket_path).parent() {
-        if !parent.as_os_str().is_empty() {
-            tokio::fs::create_dir_all(parent)
-                .await
-                .with_context(|| format!("create IPC dir {}", parent.display()))?;
+    let listener = match bind_socket(Path::new(&socket_path)) {
+        Ok(l) => {
+            info!("IPC listening on {socket_path}");
+            l
+        }
+        Err(e) => {
+            let Some(why) = unwritable(&e) else {
+                return Err(e);
+            };
+            let fallback = &cfg.ipc.fallback_socket_path;
+            if fallback.is_empty() {
+                return Err(e.context(format!(
+                    "the IPC socket directory is {why}: set [ipc].socket_path to a writable \
+                     location (e.g. \"/tmp/w3p-ups/agent.sock\"), or set \
+                     [ipc].fallback_socket_path to fall back to one automatically"
+                )));
+            }
+            warn!(
+                "IPC socket {socket_path}: directory is {why} ({e:#}); \
+                 falling back to {fallback} ([ipc].fallback_socket_path)"
+            );
+            let l = bind_socket(Path::new(fallback)).context("bind [ipc].fallback_socket_path")?;
+            info!("IPC listening on {fallback}");
+            l
+        }
+    };
+    // A bad TCP address shouldn't take the local socket down with it.
+    let tcp = match bind_tcp(&cfg.ipc.tcp_listen, !cfg.ipc.token.is_empty()).await {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            error!("IPC TCP listener disabled: {e:#}");
+            None
         }
+    };
+
+    if cfg.debug.allow_inject {
+        warn!("IPC inject enabled ([debug].allow_inject): clients can feed synthetic power data");
     }
-    // Best-effort cleanup of a stale socket from a previous run.
-    let _ = tokio::fs::remove_file(&socket_path).await;
-
-    let listener = UnixListener::bind(&socket_path)
-        .with_context(|| format!("bind IPC socket {socket_path}"))?;
-    info!("IPC listening on {socket_path}");
-
-    let handle = tokio::spawn(accept_loop(
-        listener,
-        state,
-        OnBattCfg {
-            min_mv: on_batt_min_mv,
-            max_mv: on_batt_max_mv,
-        },
-    ));
+    let ctx = Arc::new(ClientCtx::new(cfg, control));
+    let handle = tokio::spawn(async move {
+        let tcp_loop = async {
+            if let Some(tcp) = tcp {
+                tcp_accept_loop(tcp, state.clone(), ctx.clone()).await;
+            }
+        };
+        tokio::join!(accept_loop(listener, state.clone(), ctx.clone()), tcp_loop);
+    });
     Ok(handle)
 }
 
-#[derive(Clone, Copy)]
-struct OnBattCfg {
-    min_mv: u16,
-    max_mv: u16,
+/// Bind `[ipc].tcp_listen` (empty = off).
+async fn bind_tcp(addr: &str, with_token: bool) -> Result<Option<TcpListener>> {
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    let addr: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("[ipc].tcp_listen {addr:?} is not an ip:port"))?;
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("bind IPC TCP {addr}"))?;
+    let auth = if with_token { "token" } else { "none" };
+    if addr.ip().is_loopback() || with_token {
+        info!(auth, "IPC listening on tcp {addr} (read-only)");
+    } else {
+        warn!(
+            auth,
+            "IPC listening on tcp {addr} (read-only) without [ipc].token; anyone who can reach it can read UPS telemetry"
+        );
+    }
+    Ok(Some(listener))
+}
+
+/// Mode of the socket directory; matches `RuntimeDirectoryMode` in the unit.
+const SOCKET_DIR_MODE: u32 = 0o755;
+
+/// Bind `path`, preparing its directory first. `/run` is a fresh tmpfs each
+/// boot and we can race systemd's `RuntimeDirectory=` / tmpfiles setup, so
+/// a bind that fails because the directory went away is retried once after
+/// recreating it.
+fn bind_socket(path: &Path) -> Result<UnixListener> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        prepare_socket_dir(dir)?;
+    }
+    // Best-effort cleanup of a stale socket from a previous run.
+    if std::fs::remove_file(path).is_ok() {
+        debug!("removed stale IPC socket {}", path.display());
+    }
+    match (UnixListener::bind(path), dir) {
+        (Ok(l), _) => Ok(l),
+        (Err(e), Some(dir)) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(
+                "bind IPC socket {}: {e}; directory {} vanished, recreating and retrying once",
+                path.display(),
+                dir.display()
+            );
+            prepare_socket_dir(dir)?;
+            UnixListener::bind(path)
+                .with_context(|| format!("bind IPC socket {} (after retry)", path.display()))
+        }
+        (Err(e), _) => Err(e).with_context(|| format!("bind IPC socket {}", path.display())),
+    }
+}
+
+/// Why binding failed, if it's because the socket's directory can't be
+/// written (as opposed to, say, the path being taken by something else).
+fn unwritable(err: &anyhow::Error) -> Option<&'static str> {
+    let io = err
+        .chain()
+        .find_map(|c| c.downcast_ref::<std::io::Error>())?;
+    match io.raw_os_error()? {
+        libc::EROFS => Some("on a read-only filesystem"),
+        libc::EACCES | libc::EPERM => Some("not writable by this user"),
+        _ => None,
+    }
 }
 
-async fn accept_loop(listener: UnixListener, state: Arc<State>, cfg: OnBattCfg) {
+/// Name of the daemon's own runtime directory (`/run/w3p-ups`, or the
+/// `/tmp/w3p-ups` fallback), whose owner and mode the daemon may fix up.
+pub(crate) const RUNTIME_DIR_NAME: &str = "w3p-ups";
+
+/// Create `dir` if needed, then make sure it really is a directory (not a
+/// symlink to one). Owner and [`SOCKET_DIR_MODE`] are only fixed up on a
+/// directory created here or on the dedicated runtime directory: a socket
+/// put straight into a shared directory (`--socket /tmp/a.sock`) must not
+/// change who can use `/tmp`. There a mismatch is only warned about.
+fn prepare_socket_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let created = match std::fs::symlink_metadata(dir) {
+        Ok(_) => false,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("create IPC dir {}", dir.display()))?;
+            info!("created IPC dir {}", dir.display());
+            true
+        }
+        Err(e) => return Err(e).with_context(|| format!("stat IPC dir {}", dir.display())),
+    };
+    let meta = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("stat IPC dir {}", dir.display()))?;
+    if meta.file_type().is_symlink() {
+        anyhow::bail!(
+            "IPC dir {} is a symlink; refusing to use it (point [ipc].socket_path at a real directory)",
+            dir.display()
+        );
+    }
+    if !meta.is_dir() {
+        anyhow::bail!("IPC dir {} exists but is not a directory", dir.display());
+    }
+
+    // SAFETY: geteuid/getegid have no preconditions and cannot fail.
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let mode = meta.permissions().mode() & 0o7777;
+    let ours = created || dir.file_name().is_some_and(|n| n == RUNTIME_DIR_NAME);
+    if !ours {
+        if meta.uid() != uid || mode != SOCKET_DIR_MODE {
+            warn!(
+                "IPC dir {} is not the daemon's own (uid {}, mode {mode:o}); leaving it as is",
+                dir.display(),
+                meta.uid()
+            );
+        }
+        return Ok(());
+    }
+    if meta.uid() != uid {
+        // lchown: never follow a symlink swapped in since the check.
+        match std::os::unix::fs::lchown(dir, Some(uid), Some(gid)) {
+            Ok(()) => info!(
+                "chowned IPC dir {} from {}:{} to {uid}:{gid}",
+                dir.display(),
+                meta.uid(),
+                meta.gid()
+            ),
+            Err(e) => warn!(
+                "IPC dir {} is owned by uid {}, not us ({uid}), and chown failed: {e}",
+                dir.display(),
+                meta.uid()
+            ),
+        }
+    }
+
+    if mode != SOCKET_DIR_MODE {
+        match std::fs::set_permissions(dir, std::fs::Permissions::from_mode(SOCKET_DIR_MODE)) {
+            Ok(()) => info!(
+                "IPC dir {} mode {mode:o} -> {SOCKET_DIR_MODE:o}",
+                dir.display()
+            ),
+            Err(e) => warn!(
+                "IPC dir {} has mode {mode:o}; chmod failed: {e}",
+                dir.display()
+            ),
+        }
+    }
+    Ok(())
+}
+
+async fn accept_loop(listener: UnixListener, state: Arc<State>, ctx: Arc<ClientCtx>) {
     loop {
         match listener.accept().await {
             Ok((stream, _addr)) => {
                 let state = state.clone();
-                tokio::spawn(handle_client(stream, state, cfg));
+                tokio::spawn(handle_client(stream, state, ctx.clone()));
             }
             Err(e) => {
                 warn!("IPC accept failed: {e}");
@@ -149,26 +568,114 @@ async fn accept_loop(listener: UnixListener, state: Arc<State>, cfg: OnBattCfg)
     }
 }
 
-async fn handle_client(stream: UnixStream, state: Arc<State>, cfg: OnBattCfg) {
-    let (rd, mut wr) = stream.into_split();
-    let mut reader = BufReader::new(rd).lines();
+/// Longest request line; real requests are well under 1 KB.
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// TCP clients served at once.
+pub const MAX_TCP_CLIENTS: usize = 16;
+/// How long a TCP client has to `auth` when `[ipc].token` is set.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn tcp_accept_loop(listener: TcpListener, state: Arc<State>, ctx: Arc<ClientCtx>) {
+    let slots = Arc::new(tokio::sync::Semaphore::new(MAX_TCP_CLIENTS));
+    // Warn once per spell at the limit, not once per refused connection.
+    let mut full = false;
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let Ok(slot) = slots.clone().try_acquire_owned() else {
+                    if !std::mem::replace(&mut full, true) {
+                        warn!("IPC tcp: {MAX_TCP_CLIENTS} clients connected; refusing more");
+                    }
+                    debug!("IPC tcp client {addr} refused: too many clients");
+                    continue;
+                };
+                full = false;
+                debug!("IPC tcp client {addr} connected");
+                let (rd, wr) = stream.into_split();
+                let (state, ctx) = (state.clone(), ctx.clone());
+                tokio::spawn(async move {
+                    serve(rd, wr, Peer::Remote(addr), state, ctx).await;
+                    drop(slot);
+                });
+            }
+            Err(e) => {
+                warn!("IPC tcp accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        }
+    }
+}
+
+/// Who is on the other end, for the ops that aren't open to everyone.
+#[derive(Debug, Clone, Copy)]
+enum Peer {
+    /// Unix socket; `SO_PEERCRED` uid if the kernel gave us one.
+    Local { uid: Option<u32> },
+    /// TCP: read-only, no control or inject.
+    Remote(SocketAddr),
+}
+
+pub(crate) async fn handle_client(stream: UnixStream, state: Arc<State>, ctx: Arc<ClientCtx>) {
+    let uid = stream.peer_cred().ok().map(|c| c.uid());
+    let (rd, wr) = stream.into_split();
+    serve(rd, wr, Peer::Local { uid }, state, ctx).await;
+}
+
+async fn serve<R, W>(rd: R, mut wr: W, peer: Peer, state: Arc<State>, ctx: Arc<ClientCtx>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let battery = &ctx.battery;
+    // The Unix socket is guarded by filesystem permissions; TCP by the token.
+    let mut authed = matches!(peer, Peer::Local { .. }) || ctx.token.is_empty();
+    let auth_deadline = tokio::time::sleep(ctx.auth_timeout);
+    tokio::pin!(auth_deadline);
+    let mut reader = BufReader::new(rd);
+    let mut line = Vec::new();
     let (tick_tx, mut tick_rx) = mpsc::channel::<()>(4);
     let mut subscribed = false;
+    let mut encoding = IpcEncoding::Json;
     let mut ticker_handle: Option<tokio::task::JoinHandle<()>> = None;
+    let mut stopping = state.subscribe_stopping();
+    let _client = state.count_ipc_client();
+    let mut _subscription = None;
 
     loop {
         tokio::select! {
-            line = reader.next_line() => match line {
-                Ok(Some(line)) => {
-                    let req: Result<Request, _> = serde_json::from_str(line.trim());
+            read = read_line(&mut reader, &mut line) => match read {
+                Ok(Some(Line::TooLong)) => {
+                    warn!(?peer, "IPC request over {MAX_REQUEST_LINE} bytes; closing");
+                    send_reply(&mut wr, &Reply::Error { message: format!("request too long (max {MAX_REQUEST_LINE} bytes)") }).await;
+                    break;
+                }
+                Ok(Some(Line::Complete)) => {
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    let req: Result<Request, _> = serde_json::from_str(text.trim());
                     match req {
+                        Ok(Request::Auth { token }) => {
+                            if authed || token_matches(&token, &ctx.token) {
+                                authed = true;
+                                send_reply(&mut wr, &Reply::Authenticated).await;
+                            } else {
+                                warn!(?peer, "IPC auth failed; closing");
+                                send_reply(&mut wr, &Reply::Error { message: "auth: bad token".into() }).await;
+                                break;
+                            }
+                        }
+                        Ok(_) if !authed => {
+                            send_reply(&mut wr, &Reply::Error { message: "auth required: send {\"op\":\"auth\",\"token\":…} first".into() }).await;
+                        }
                         Ok(Request::Snapshot) => {
-                            send_snapshot(&mut wr, &state, &cfg).await;
+                            send_snapshot(&mut wr, &state, battery, IpcEncoding::Json).await;
                         }
-                        Ok(Request::Subscribe) => {
-                            send_snapshot(&mut wr, &state, &cfg).await;
+                        Ok(Request::Subscribe { encoding: e }) => {
+                            encoding = e;
+                            send_snapshot(&mut wr, &state, battery, encoding).await;
                             if !subscribed {
                                 subscribed = true;
+                                _subscription = Some(state.count_ipc_subscriber());
                                 let tx = tick_tx.clone();
                                 ticker_handle = Some(tokio::spawn(async move {
                                     let mut interval = tokio::time::interval(Duration::from_secs(1));
@@ -182,6 +689,57 @@ async fn handle_client(stream: UnixStream, state: Arc<State>, cfg: OnBattCfg) {
                         Ok(Request::Version) => {
                             send_reply(&mut wr, &Reply::Version { version: VERSION }).await;
                         }
+                        Ok(Request::Info) => {
+                            let snap = state.snapshot().await;
+                            let (clients, subscribers) = state.ipc_clients();
+                            let reply = Reply::Info {
+                                version: VERSION,
+                                last_discharge: snap.capacity.last(SpanKind::Discharge).cloned(),
+                                last_charge: snap.capacity.last(SpanKind::Charge).cloned(),
+                                kv_rejects: state.kv_reject_counts(),
+                                cadence: snap.cadence.counts(),
+                                energy: snap.capacity.energy,
+                                health: snap.health,
+                                port: snap.serial_port.clone(),
+                                uptime_s: state.uptime().as_secs(),
+                                clients,
+                                subscribers,
+                                sample_age_ms: snap.last_power_at.map(|at| {
+                                    state.now().saturating_duration_since(at).as_millis() as u64
+                                }),
+                                outages: snap.outages,
+                                in_outage: snap.in_outage,
+                            };
+                            send_reply(&mut wr, &reply).await;
+                        }
+                        Ok(Request::Histogram) => {
+                            let reply = match state.snapshot().await.input_histogram {
+                                Some(histogram) => Reply::Histogram { histogram },
+                                None => Reply::Error {
+                                    message: "histogram disabled: [power_quality].input_buckets_mv is empty".into(),
+                                },
+                            };
+                            send_reply(&mut wr, &reply).await;
+                        }
+                        Ok(Request::Inject(req)) => {
+                            let reply = inject(&state, &ctx, peer, &req).await;
+                            send_reply(&mut wr, &reply).await;
+                        }
+                        Ok(Request::Nut) => {
+                            let reply = match crate::nut::nut_vars(&state.snapshot().await, battery) {
+                                Some(vars) => Reply::Nut { vars },
+                                None => Reply::Error { message: "DATA-STALE: no live UPS reading".into() },
+                            };
+                            send_reply(&mut wr, &reply).await;
+                        }
+                        Ok(Request::Stop) => {
+                            let reply = control(&ctx, peer, false).await;
+                            send_reply(&mut wr, &reply).await;
+                        }
+                        Ok(Request::Reload) => {
+                            let reply = control(&ctx, peer, true).await;
+                            send_reply(&mut wr, &reply).await;
+                        }
                         Err(e) => {
                             send_reply(&mut wr, &Reply::Error { message: format!("bad request: {e}") }).await;
                         }
@@ -193,9 +751,18 @@ async fn handle_client(stream: UnixStream, state: Arc<State>, cfg: OnBattCfg) {
                     break;
                 }
             },
+            () = &mut auth_deadline, if !authed => {
+                warn!(?peer, "IPC client did not authenticate within {:?}; closing", ctx.auth_timeout);
+                send_reply(&mut wr, &Reply::Error { message: "auth: timed out".into() }).await;
+                break;
+            }
             tick = tick_rx.recv() => {
                 if tick.is_none() { break; }
-                send_snapshot(&mut wr, &state, &cfg).await;
+                send_snapshot(&mut wr, &state, battery, encoding).await;
+            }
+            Ok(()) = stopping.changed() => {
+                send_reply(&mut wr, &Reply::Stopping).await;
+                break;
             }
         }
     }
@@ -205,13 +772,274 @@ async fn handle_client(stream: UnixStream, state: Arc<State>, cfg: OnBattCfg) {
     debug!("IPC client disconnected");
 }
 
-async fn send_snapshot(wr: &mut tokio::net::unix::OwnedWriteHalf, state: &State, cfg: &OnBattCfg) {
+/// What [`read_line`] left in its buffer.
+enum Line {
+    /// Up to and including `\n`, or what was left at EOF.
+    Complete,
+    /// More than [`MAX_REQUEST_LINE`] bytes without a `\n`.
+    TooLong,
+}
+
+/// Read one request line into `buf`, but never more than
+/// [`MAX_REQUEST_LINE`] bytes of it. `None` at EOF with nothing read.
+/// Cancel-safe like `read_until`: a partial line stays in `buf` for the
+/// next call.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    rd: &mut R,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<Option<Line>> {
+    let room = (MAX_REQUEST_LINE + 1).saturating_sub(buf.len());
+    let n = rd.take(room as u64).read_until(b'\n', buf).await?;
+    if buf.ends_with(b"\n") {
+        return Ok(Some(Line::Complete));
+    }
+    if buf.len() > MAX_REQUEST_LINE {
+        return Ok(Some(Line::TooLong));
+    }
+    // EOF: a last line without `\n` still counts, as with `lines()`.
+    Ok((n > 0 || !buf.is_empty()).then_some(Line::Complete))
+}
+
+/// Compare without an early exit, so response timing doesn't leak how much
+/// of the token a guess got right.
+fn token_matches(given: &str, want: &str) -> bool {
+    given.len() == want.len()
+        && given
+            .bytes()
+            .zip(want.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Root and the daemon's own user may stop/reload it.
+fn may_control(peer_uid: Option<u32>, own_uid: u32) -> bool {
+    matches!(peer_uid, Some(uid) if uid == 0 || uid == own_uid)
+}
+
+async fn control(ctx: &ClientCtx, peer: Peer, reload: bool) -> Reply {
+    let op = if reload { "reload" } else { "stop" };
+    let peer_uid = match peer {
+        Peer::Local { uid } => uid,
+        Peer::Remote(addr) => {
+            warn!(%addr, "IPC {op} refused over tcp");
+            return Reply::Error {
+                message: format!("{op}: not allowed over tcp"),
+            };
+        }
+    };
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    let own_uid = unsafe { libc::geteuid() };
+    if !may_control(peer_uid, own_uid) {
+        warn!(
+            ?peer_uid,
+            "IPC {op} refused: peer is neither root nor uid {own_uid}"
+        );
+        return Reply::Error {
+            message: format!("{op}: permission denied"),
+        };
+    }
+    let Some(tx) = &ctx.control else {
+        return Reply::Error {
+            message: format!("{op} is not supported by this server"),
+        };
+    };
+    info!(?peer_uid, "IPC {op} requested");
+    let gone = || Reply::Error {
+        message: "daemon is shutting down".into(),
+    };
+    if !reload {
+        return match tx.send(Control::Stop).await {
+            Ok(()) => Reply::Stopping,
+            Err(_) => gone(),
+        };
+    }
+    let (done_tx, done_rx) = oneshot::channel();
+    if tx.send(Control::Reload(done_tx)).await.is_err() {
+        return gone();
+    }
+    match done_rx.await {
+        Ok(Ok(warnings)) => Reply::Reloaded { warnings },
+        Ok(Err(message)) => Reply::Error { message },
+        Err(_) => gone(),
+    }
+}
+
+async fn inject(state: &State, ctx: &ClientCtx, peer: Peer, req: &InjectRequest) -> Reply {
+    if !ctx.allow_inject {
+        return Reply::Error {
+            message: "inject is disabled (set [debug].allow_inject = true)".into(),
+        };
+    }
+    if let Peer::Remote(addr) = peer {
+        warn!(%addr, "IPC inject refused over tcp");
+        return Reply::Error {
+            message: "inject: not allowed over tcp".into(),
+        };
+    }
+    if req.hold_s > MAX_INJECT_HOLD_S {
+        return Reply::Error {
+            message: format!("inject: hold_s is at most {MAX_INJECT_HOLD_S}"),
+        };
+    }
+    let p = req.data.to_status(ctx.battery.soc_curve());
+    warn!(
+        vbus_in_mv = p.vbus_in_mv,
+        vbat_mv = p.vbat_mv,
+        ibat_ma = p.ibat_ma,
+        soc_pct = ctx.battery.soc_pct(p.vbat_mv),
+        hold_s = req.hold_s,
+        exercise_shutdown = req.exercise_shutdown,
+        "SYNTHETIC power.status injected over IPC; this is not UPS data"
+    );
+    if !state
+        .inject_power(p, Duration::from_secs(req.hold_s), req.exercise_shutdown)
+        .await
+    {
+        return Reply::Error {
+            message: "inject: hold_s is out of range".into(),
+        };
+    }
+    Reply::Injected {
+        hold_s: req.hold_s,
+        exercise_shutdown: req.exercise_shutdown,
+    }
+}
+
+async fn send_snapshot(
+    wr: &mut (impl AsyncWrite + Unpin),
+    state: &State,
+    battery: &BatteryConfig,
+    encoding: IpcEncoding,
+) {
     let snap = state.snapshot().await;
-    let msg = build_snapshot(&snap, cfg);
-    send_reply(wr, &Reply::Snapshot(msg)).await;
+    let msg = build_snapshot(&snap, battery, state.now());
+    match encoding {
+        IpcEncoding::Json => send_reply(wr, &Reply::Snapshot(Box::new(msg))).await,
+        IpcEncoding::Binary => send_frame(wr, &msg.pack()).await,
+    }
 }
 
-async fn send_reply(wr: &mut tokio::net::unix::OwnedWriteHalf, reply: &Reply) {
+/// First byte of a binary frame; never the first byte of a JSON reply.
+pub(crate) const FRAME_MARK: u8 = 0x00;
+
+/// Binary snapshot layout written by [`SnapshotMsg::pack`].
+pub(crate) const FRAME_LAYOUT: u8 = 1;
+
+async fn send_frame(wr: &mut (impl AsyncWrite + Unpin), payload: &[u8]) {
+    let Ok(len) = u16::try_from(payload.len()) else {
+        warn!("binary snapshot too large: {} bytes", payload.len());
+        return;
+    };
+    let mut frame = Vec::with_capacity(3 + payload.len());
+    frame.push(FRAME_MARK);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(payload);
+    if let Err(e) = wr.write_all(&frame).await {
+        debug!("IPC write failed: {e}");
+        return;
+    }
+    let _ = wr.flush().await;
+}
+
+impl SnapshotMsg {
+    /// The binary frame payload. Fields go in declaration order, except that
+    /// the seven flags share one byte after `unix_ts_ms` (bit 0
+    /// `charging_fault` up to bit 6 `dry_run`, in that order) and `power`,
+    /// `net` and `host` come last, each an `Option` of its fields in their
+    /// declaration order. Anything but appending needs a new
+    /// [`FRAME_LAYOUT`].
+    fn pack(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(160);
+        FRAME_LAYOUT.pack(&mut out);
+        self.unix_ts_ms.pack(&mut out);
+        let flags = [
+            self.charging_fault,
+            self.pd_overload,
+            self.serial_connected,
+            self.degraded,
+            self.stale,
+            self.synthetic,
+            self.dry_run,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u8, |acc, (bit, &set)| acc | (set as u8) << bit);
+        flags.pack(&mut out);
+        self.last_power_event.pack(&mut out);
+        self.shutdown_pending_for_s.pack(&mut out);
+        self.fault_summary.pack(&mut out);
+        self.last_update_age_ms.pack(&mut out);
+        self.missed_samples.pack(&mut out);
+        self.max_recent_gap_ms.pack(&mut out);
+        self.power.is_some().pack(&mut out);
+        if let Some(p) = &self.power {
+            p.age_ms.pack(&mut out);
+            p.charge_state.pack(&mut out);
+            p.vbus_in_mv.pack(&mut out);
+            p.vbus_out_mv.pack(&mut out);
+            p.ibus_out_ma.pack(&mut out);
+            p.vbat_mv.pack(&mut out);
+            p.ibat_ma.pack(&mut out);
+            p.soc_pct.pack(&mut out);
+            p.on_battery.pack(&mut out);
+            p.temp_dc.pack(&mut out);
+            p.pd_contract_mv.pack(&mut out);
+            p.pd_contract_ma.pack(&mut out);
+            p.faults.pack(&mut out);
+            p.pd_in_contract.pack(&mut out);
+            p.pd_in_mv.pack(&mut out);
+            p.pd_in_ma.pack(&mut out);
+            p.pd_load_pct.pack(&mut out);
+            p.input_mw.pack(&mut out);
+            p.nominal_input_mv.pack(&mut out);
+            p.input_deviation_pct.pack(&mut out);
+            p.ups_uptime_s.pack(&mut out);
+            p.power_flags.pack(&mut out);
+            p.maintaining.pack(&mut out);
+            p.health_score.pack(&mut out);
+            p.rail_undervoltage.pack(&mut out);
+        }
+        self.net.is_some().pack(&mut out);
+        if let Some(n) = &self.net {
+            n.age_ms.pack(&mut out);
+            n.state.pack(&mut out);
+            n.rssi_dbm.pack(&mut out);
+            n.rsrp_dbm.pack(&mut out);
+            n.rsrq_db.pack(&mut out);
+            n.bytes_tx.pack(&mut out);
+            n.bytes_rx.pack(&mut out);
+        }
+        self.host.is_some().pack(&mut out);
+        if let Some(h) = &self.host {
+            h.age_ms.pack(&mut out);
+            h.cpu_temp_dc.pack(&mut out);
+            h.cpu_usage_pct.pack(&mut out);
+            h.load_avg_x100.pack(&mut out);
+            h.mem_used_pct.pack(&mut out);
+            h.disk_used_pct.pack(&mut out);
+            h.uptime_s.pack(&mut out);
+            h.net_bytes_rx_total.pack(&mut out);
+            h.net_bytes_tx_total.pack(&mut out);
+            h.net_rx_bytes_per_s.pack(&mut out);
+            h.net_tx_bytes_per_s.pack(&mut out);
+            h.eth_client_state.pack(&mut out);
+        }
+        out
+    }
+}
+
+/// The `snapshot` reply's body as JSON (no `type` tag), for the HTTP
+/// endpoints in [`crate::web`].
+pub(crate) async fn snapshot_json(state: &State, battery: &BatteryConfig) -> String {
+    let snap = state.snapshot().await;
+    let msg = build_snapshot(&snap, battery, state.now());
+    serde_json::to_string(&msg).unwrap_or_else(|e| {
+        warn!("serialize snapshot: {e}");
+        "{}".into()
+    })
+}
+
+async fn send_reply(wr: &mut (impl AsyncWrite + Unpin), reply: &Reply) {
     let line = match serde_json::to_string(reply) {
         Ok(s) => s,
         Err(e) => {
@@ -227,18 +1055,17 @@ async fn send_reply(wr: &mut tokio::net::unix::OwnedWriteHalf, reply: &Reply) {
     let _ = wr.flush().await;
 }
 
-fn build_snapshot(snap: &AgentState, cfg: &OnBattCfg) -> SnapshotMsg {
-    let now = Instant::now();
+fn build_snapshot(snap: &AgentState, battery: &BatteryConfig, now: Instant) -> SnapshotMsg {
+    let snap = &*snap.client_view();
     let unix_ts_ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|d| d.as_millis() as u64)
         .unwrap_or(0);
 
-    let power = snap
-        .last_power
-        .map(|p| make_power(p, snap.last_power_at, now, cfg));
+    let power = snap.last_power.map(|p| make_power(p, snap, now, battery));
     let net = snap.last_net.map(|n| make_net(n, snap.last_net_at, now));
     let host = snap.last_host.map(|h| make_host(h, snap, now));
+    let last_update_age_ms = power.as_ref().and_then(|p| p.age_ms);
 
     SnapshotMsg {
         unix_ts_ms,
@@ -246,20 +1073,56 @@ fn build_snapshot(snap: &AgentState, cfg: &OnBattCfg) -> SnapshotMsg {
         net,
         host,
         last_power_event: snap.last_power_event,
-        shutdown_pending_for_s: snap.shutdown_pending_since.map(|t| t.elapsed().as_secs()),
+        shutdown_pending_for_s: snap
+            .shutdown_pending_since
+            .map(|t| now.saturating_duration_since(t).as_secs()),
+        charging_fault: snap.charging_fault,
+        pd_overload: snap.pd_overload,
+        fault_summary: snap.fault_summary(battery),
+        serial_connected: snap.serial_connected,
+        degraded: !snap.serial_connected && snap.last_power.is_some(),
+        last_update_age_ms,
+        stale: snap
+            .max_sample_age
+            .zip(last_update_age_ms)
+            .is_some_and(|(max, age)| age > max.as_millis() as u64),
+        missed_samples: snap.cadence.missed,
+        max_recent_gap_ms: snap.cadence.max_recent_gap().map(|g| g.as_millis() as u64),
+        synthetic: snap.injected.is_some(),
+        dry_run: snap.dry_run,
     }
 }
 
+/// `power` of the snapshot `snap` would give now, as JSON.
+pub(crate) fn power_json(
+    snap: &AgentState,
+    battery: &BatteryConfig,
+    now: Instant,
+) -> Option<serde_json::Value> {
+    let snap = &*snap.client_view();
+    let p = snap.last_power?;
+    serde_json::to_value(make_power(p, snap, now, battery)).ok()
+}
+
 fn make_power(
     p: PowerStatusV1,
-    at: Option<Instant>,
+    snap: &AgentState,
     now: Instant,
-    cfg: &OnBattCfg,
+    battery: &BatteryConfig,
 ) -> PowerSnapshot {
-    let soc_pct = pack_mv_to_soc_pct(p.vbat_mv);
-    let on_battery = crate::shutdown_sm::is_on_battery(p.vbus_in_mv, cfg.min_mv, cfg.max_mv);
+    let soc_pct = battery.soc_pct(p.vbat_mv);
+    let on_battery = crate::shutdown_sm::classify_input(
+        &p,
+        snap.last_power_v2.as_ref(),
+        battery.input_min_valid_mv,
+        battery.input_max_valid_mv,
+        battery.input_zero_cross_check,
+    )
+    .on_battery();
     PowerSnapshot {
-        age_ms: at.map(|t| now.saturating_duration_since(t).as_millis() as u64),
+        age_ms: snap
+            .last_power_at
+            .map(|t| now.saturating_duration_since(t).as_millis() as u64),
         charge_state: p.charge_state,
         vbus_in_mv: p.vbus_in_mv,
         vbus_out_mv: p.vbus_out_mv,
@@ -272,6 +1135,25 @@ fn make_power(
         pd_contract_mv: p.pd_contract_mv,
         pd_contract_ma: p.pd_contract_ma,
         faults: p.faults,
+        pd_in_contract: snap.last_power_v2.and_then(|v2| v2.pd_contract_str()),
+        pd_in_mv: snap.last_power_v2.map(|v2| v2.pd_in_mv),
+        pd_in_ma: snap.last_power_v2.map(|v2| v2.pd_in_ma),
+        pd_load_pct: snap.last_power_v2.and_then(|v2| v2.pd_load_pct()),
+        input_mw: snap
+            .last_power_v2
+            .map(|v2| (v2.vbus_in_mv as u64 * v2.iin_ma as u64 / 1000) as u32),
+        nominal_input_mv: (battery.nominal_input_mv > 0).then_some(battery.nominal_input_mv),
+        input_deviation_pct: if on_battery {
+            None
+        } else {
+            battery.input_deviation_pct(p.vbus_in_mv)
+        },
+        ups_uptime_s: snap.last_power_v2.map(|v2| v2.uptime_s),
+        power_flags: snap.last_power_v2.map(|v2| v2.flags),
+        power_flags_raw: snap.last_power_flags_raw,
+        maintaining: snap.charge_maintaining,
+        health_score: snap.health.map(|h| h.score),
+        rail_undervoltage: battery.rail_undervoltage(p.vbus_out_mv),
     }
 }
 
@@ -305,3 +1187,307 @@ fn make_net(n: NetStatusV1, at: Option<Instant>, now: Instant) -> NetSnapshot {
         bytes_rx: n.bytes_rx,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::test_util::Scratch;
+
+    fn mode_of(p: &Path) -> u32 {
+        std::fs::metadata(p).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[tokio::test]
+    async fn bind_creates_missing_dir_and_fixes_mode() {
+        let root = Scratch::new("sockdir");
+        let dir = root.join("run").join("w3p-ups");
+        let sock = dir.join("agent.sock");
+        drop(bind_socket(&sock).unwrap());
+        assert_eq!(mode_of(&dir), SOCKET_DIR_MODE);
+
+        // Wrong mode (e.g. a tmpfiles entry racing us) is corrected, and the
+        // stale socket from the first bind doesn't block the second.
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        drop(bind_socket(&sock).unwrap());
+        assert_eq!(mode_of(&dir), SOCKET_DIR_MODE);
+    }
+
+    #[test]
+    fn unwritable_socket_dirs_are_recognised() {
+        let err = |errno| {
+            anyhow::Error::new(std::io::Error::from_raw_os_error(errno))
+                .context("create IPC dir /run/w3p-ups")
+        };
+        assert_eq!(
+            unwritable(&err(libc::EROFS)),
+            Some("on a read-only filesystem")
+        );
+        assert_eq!(
+            unwritable(&err(libc::EACCES)),
+            Some("not writable by this user")
+        );
+        assert_eq!(unwritable(&err(libc::EADDRINUSE)), None);
+        assert_eq!(unwritable(&anyhow::anyhow!("not a directory")), None);
+    }
+
+    #[test]
+    fn control_allowed_for_root_and_own_user_only() {
+        assert!(may_control(Some(0), 1000));
+        assert!(may_control(Some(1000), 1000));
+        assert!(!may_control(Some(1001), 1000));
+        assert!(!may_control(None, 1000));
+    }
+
+    #[tokio::test]
+    async fn control_ops_reach_the_supervisor() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let ctx = ClientCtx::new(&Config::default(), Some(tx));
+        // SAFETY: see `control`.
+        let me = Peer::Local {
+            uid: Some(unsafe { libc::geteuid() }),
+        };
+
+        assert!(matches!(control(&ctx, me, false).await, Reply::Stopping));
+        assert!(matches!(rx.recv().await, Some(Control::Stop)));
+
+        let supervisor = tokio::spawn(async move {
+            let Some(Control::Reload(done)) = rx.recv().await else {
+                panic!("expected reload");
+            };
+            done.send(Ok(vec!["w".into()])).unwrap();
+        });
+        match control(&ctx, me, true).await {
+            Reply::Reloaded { warnings } => assert_eq!(warnings, ["w"]),
+            other => panic!("expected reloaded, got {other:?}"),
+        }
+        supervisor.await.unwrap();
+
+        let ctx = ClientCtx::new(&Config::default(), None);
+        assert!(matches!(
+            control(&ctx, me, false).await,
+            Reply::Error { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn shared_and_symlinked_socket_dirs_are_left_alone() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = Scratch::new("sockshared");
+        let shared = root.join("tmp");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o1777)).unwrap();
+        drop(bind_socket(&shared.join("a.sock")).unwrap());
+        assert_eq!(mode_of(&shared), 0o1777);
+
+        // A planted `w3p-ups -> elsewhere` is refused, and its target untouched.
+        let target = root.join("private");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let link = shared.join("w3p-ups");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let err = bind_socket(&link.join("agent.sock")).unwrap_err();
+        assert!(format!("{err:#}").contains("symlink"), "{err:#}");
+        assert_eq!(mode_of(&target), 0o700);
+    }
+
+    #[test]
+    fn socket_dir_that_is_a_file_is_an_error() {
+        let root = Scratch::new("sockfile");
+        let file = root.join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        let err = prepare_socket_dir(&file).unwrap_err();
+        assert!(err.to_string().contains("not a directory"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn tcp_clients_are_read_only() {
+        let mut cfg = Config::default();
+        cfg.debug.allow_inject = true;
+        let (tx, mut rx) = mpsc::channel(1);
+        let listener = bind_tcp("127.0.0.1:0", false).await.unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(tcp_accept_loop(
+            listener,
+            State::new(),
+            Arc::new(ClientCtx::new(&cfg, Some(tx))),
+        ));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (rd, mut wr) = stream.into_split();
+        let mut lines = BufReader::new(rd).lines();
+        let mut replies = Vec::new();
+        for req in [
+            r#"{"op":"snapshot"}"#,
+            r#"{"op":"stop"}"#,
+            r#"{"op":"inject","data":{"soc_pct":5}}"#,
+        ] {
+            wr.write_all(format!("{req}\n").as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            replies.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+        }
+        assert_eq!(replies[0]["type"], "snapshot");
+        assert_eq!(replies[1]["message"], "stop: not allowed over tcp");
+        assert_eq!(replies[2]["message"], "inject: not allowed over tcp");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_cut_off() {
+        let ctx = Arc::new(ClientCtx::new(&Config::default(), None));
+        let (client, server) = UnixStream::pair().unwrap();
+        let served = tokio::spawn(handle_client(server, State::new(), ctx));
+        let (rd, mut wr) = client.into_split();
+        let mut lines = BufReader::new(rd).lines();
+        // Up to the limit is fine, split across writes or not…
+        let padded = format!(
+            "{{\"op\":\"version\"{}}}\n",
+            " ".repeat(MAX_REQUEST_LINE - 17)
+        );
+        assert_eq!(padded.len(), MAX_REQUEST_LINE);
+        let (a, b) = padded.split_at(100);
+        wr.write_all(a.as_bytes()).await.unwrap();
+        wr.write_all(b.as_bytes()).await.unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains(r#""type":"version""#), "{reply}");
+        // …one byte over, and without a newline, is not.
+        wr.write_all(&vec![b'x'; MAX_REQUEST_LINE + 1])
+            .await
+            .unwrap();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("request too long"), "{reply}");
+        assert_eq!(lines.next_line().await.unwrap(), None);
+        served.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_clients_must_authenticate_in_time() {
+        let mut cfg = Config::default();
+        cfg.ipc.token = "s3cret".into();
+        let mut ctx = ClientCtx::new(&cfg, None);
+        ctx.auth_timeout = Duration::from_millis(50);
+        let listener = bind_tcp("127.0.0.1:0", true).await.unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(tcp_accept_loop(listener, State::new(), Arc::new(ctx)));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let reply = lines.next_line().await.unwrap().unwrap();
+        assert!(reply.contains("auth: timed out"), "{reply}");
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn tcp_clients_are_limited() {
+        let listener = bind_tcp("127.0.0.1:0", false).await.unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = Arc::new(ClientCtx::new(&Config::default(), None));
+        tokio::spawn(tcp_accept_loop(listener, State::new(), ctx));
+
+        async fn version(stream: &mut tokio::net::TcpStream) -> Option<String> {
+            stream.write_all(b"{\"op\":\"version\"}\n").await.ok()?;
+            let mut line = String::new();
+            let n = BufReader::new(stream).read_line(&mut line).await.ok()?;
+            (n > 0).then_some(line)
+        }
+        let mut held = Vec::new();
+        for _ in 0..MAX_TCP_CLIENTS {
+            let mut s = tokio::net::TcpStream::connect(addr).await.unwrap();
+            assert!(version(&mut s).await.is_some());
+            held.push(s);
+        }
+        let mut extra = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert_eq!(version(&mut extra).await, None);
+        // A slot frees up once a client leaves (as soon as its EOF is seen).
+        drop(held.pop());
+        let mut served = false;
+        for _ in 0..40 {
+            let mut again = tokio::net::TcpStream::connect(addr).await.unwrap();
+            if version(&mut again).await.is_some() {
+                served = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        assert!(served);
+    }
+
+    #[tokio::test]
+    async fn clients_hear_the_daemon_stop() {
+        let state = State::new();
+        let ctx = Arc::new(ClientCtx::new(&Config::default(), None));
+        let (client, server) = UnixStream::pair().unwrap();
+        let served = tokio::spawn(handle_client(server, state.clone(), ctx));
+        let (rd, mut wr) = client.into_split();
+        let mut lines = BufReader::new(rd).lines();
+        wr.write_all(b"{\"op\":\"subscribe\"}\n").await.unwrap();
+        let first = lines.next_line().await.unwrap().unwrap();
+        assert!(first.contains(r#""type":"snapshot""#), "{first}");
+
+        assert_eq!(state.announce_stopping(), 1);
+        let mut last = String::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            last = line;
+        }
+        assert_eq!(last, r#"{"type":"stopping"}"#);
+        served.await.unwrap();
+        assert_eq!(state.announce_stopping(), 0);
+    }
+
+    #[tokio::test]
+    async fn tcp_token_gates_every_op() {
+        let mut cfg = Config::default();
+        cfg.ipc.token = "s3cret".into();
+        let listener = bind_tcp("127.0.0.1:0", true).await.unwrap().unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(tcp_accept_loop(
+            listener,
+            State::new(),
+            Arc::new(ClientCtx::new(&cfg, None)),
+        ));
+
+        async fn exchange(addr: SocketAddr, reqs: &[&str]) -> Vec<serde_json::Value> {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (rd, mut wr) = stream.into_split();
+            let mut lines = BufReader::new(rd).lines();
+            let mut out = Vec::new();
+            for req in reqs {
+                wr.write_all(format!("{req}\n").as_bytes()).await.unwrap();
+                match lines.next_line().await.unwrap() {
+                    Some(line) => out.push(serde_json::from_str(&line).unwrap()),
+                    None => break,
+                }
+            }
+            out
+        }
+
+        let r = exchange(addr, &[r#"{"op":"subscribe"}"#]).await;
+        assert!(r[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("auth required"));
+
+        // A wrong token is answered, then the connection is closed.
+        let r = exchange(
+            addr,
+            &[r#"{"op":"auth","token":"guess"}"#, r#"{"op":"snapshot"}"#],
+        )
+        .await;
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0]["message"], "auth: bad token");
+
+        let r = exchange(
+            addr,
+            &[r#"{"op":"auth","token":"s3cret"}"#, r#"{"op":"snapshot"}"#],
+        )
+        .await;
+        assert_eq!(r[0]["type"], "authenticated");
+        assert_eq!(r[1]["type"], "snapshot");
+
+        assert!(token_matches("abc", "abc"));
+        assert!(!token_matches("abd", "abc"));
+        assert!(!token_matches("ab", "abc"));
+    }
+}
@@ -0,0 +1,51 @@
+//! Web3 Pi UPS agent as a library.
+//!
+//! The `w3p-ups` binary is a thin wrapper around [`daemon::run_daemon`]
+//! (full agent) and [`cli`] (IPC clients). Programs that only want UPS
+//! telemetry can use [`UpsMonitor`] instead.
+
+pub mod aggregate;
+pub mod archive;
+pub mod benchmark;
+pub mod cadence;
+pub mod capacity;
+pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod daemon;
+pub mod events;
+pub mod forward;
+pub mod health;
+pub mod histogram;
+pub mod host_metrics;
+pub mod incident;
+pub mod ipc;
+pub mod logging;
+pub mod monitor;
+pub mod nut;
+pub mod probe;
+pub mod proto;
+pub mod replay;
+pub mod soc;
+pub mod state;
+pub mod stats;
+pub mod store;
+pub mod transport;
+pub mod web;
+
+mod budget;
+mod commands;
+mod dispatcher;
+mod exit_report;
+mod packed;
+mod power_watch;
+mod privileges;
+mod shutdown_sm;
+mod status_log;
+#[cfg(test)]
+mod test_util;
+
+pub use events::{EventHandler, PowerContext};
+pub use monitor::UpsMonitor;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,218 +1,317 @@
-mod cli;
-mod commands;
-mod config;
-mod dispatcher;
-mod host_metrics;
-mod ipc;
-mod logging;
-mod proto;
-mod shutdown_sm;
-mod soc;
-mod state;
-mod transport;
-
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use tokio::signal::unix::{signal, SignalKind};
-use tracing::{error, info, warn};
-
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+use clap::{ArgAction, Parser, Subcommand};
+use tracing::{info, warn};
+use w3p_ups::{benchmark, cli, config, daemon, logging, probe, replay, stats, VERSION};
 
 #[derive(Parser, Debug)]
-#[command(name = "w3p-ups", version = VERSION, about = "Web3 Pi UPS agent")]
+#[command(
+    name = "w3p-ups",
+    version = VERSION,
+    about = "Web3 Pi UPS agent",
+    disable_version_flag = true,
+    arg_required_else_help = true
+)]
 struct Cli {
-    /// Path to TOML config file.
+    /// Path to TOML config file; `-` reads it from stdin, an `http://` URL
+    /// fetches it.
     #[arg(short, long, global = true, default_value = config::DEFAULT_CONFIG_PATH)]
     config: PathBuf,
 
+    /// Directory of `*.toml` drop-ins merged over the config file in lexical
+    /// order (default: `config.d/` next to it, if present).
+    #[arg(long, global = true, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+
+    /// IPC socket path (overrides `[ipc].socket_path`).
+    #[arg(long, global = true, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Reach the daemon's read-only TCP listener (`[ipc].tcp_listen`) instead
+    /// of the Unix socket.
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    tcp: Option<String>,
+
+    /// Token for `--tcp` when the daemon sets `[ipc].token` (default:
+    /// `$W3P_UPS_TOKEN`, which keeps it out of `ps`).
+    #[arg(long, global = true, value_name = "TOKEN", requires = "tcp")]
+    token: Option<String>,
+
+    /// Run the daemon with every side effect (shutdown, reboot, service
+    /// commands, event hooks) logged instead of performed (sets `[debug].dry_run`).
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Raise the log level to debug; twice for trace (overrides `[logging].level`).
+    #[arg(long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Print version.
+    #[arg(short = 'V', long, short_alias = 'v', action = ArgAction::Version)]
+    version: (),
+
     #[command(subcommand)]
-    command: Option<Command>,
+    command: Command,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
+    /// Run the agent (serial monitoring, shutdown logic, IPC) in the
+    /// foreground — what the systemd unit starts.
+    Daemon {
+        /// Start even if the shutdown path looks unusable, overriding
+        /// `[shutdown].require_prerequisites`.
+        #[arg(long)]
+        allow_no_shutdown: bool,
+    },
     /// Print one snapshot from the running daemon and exit.
     Status,
-    /// Stream snapshots from the running daemon (Ctrl-C to stop).
+    /// Stream snapshots from the running daemon (Ctrl-C to stop; `d` saves
+    /// the current one as JSON).
+    #[command(visible_alias = "monitor")]
     Watch,
+    /// Read the UPS directly over serial, without the daemon.
+    Probe {
+        /// Keep printing samples until Ctrl-C.
+        #[arg(short, long)]
+        follow: bool,
+        /// One JSON object per line instead of the compact text line.
+        #[arg(long)]
+        json: bool,
+        /// Downsample to one min/avg/max record per SECS (implies --follow).
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        every: Option<u64>,
+        /// Stamp records with an RFC 3339 time (`ts`) instead of unix ms.
+        #[arg(long)]
+        rfc3339: bool,
+    },
+    /// Read the UPS directly over serial for a while and report samples and
+    /// bytes per second, the parse success rate and the decode time.
+    Benchmark {
+        /// How long to read.
+        #[arg(long, default_value_t = 30, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        duration: u64,
+    },
+    /// Play a `probe --follow --json` recording into the running daemon as
+    /// injected samples (needs `[debug].allow_inject`).
+    Replay {
+        /// The recording, one JSON sample per line.
+        file: PathBuf,
+        /// Play back this many times faster than recorded (0.5 = half speed;
+        /// 0.001–10000).
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        /// Start over after the last sample until Ctrl-C.
+        #[arg(long = "loop")]
+        looping: bool,
+        /// Let the replayed samples drive the shutdown logic, as with
+        /// `inject`'s `exercise_shutdown`.
+        #[arg(long)]
+        exercise_shutdown: bool,
+    },
+    /// Summarize a recording offline: time on battery, SOC and input
+    /// extremes, temperature and the capacity its discharges point to.
+    Stats {
+        /// The recording, one JSON sample per line (`probe --follow --json`).
+        file: PathBuf,
+    },
+    /// Exit non-zero unless the daemon answers and its last UPS sample is
+    /// fresh (`[ipc].max_sample_age_seconds`) — for container/systemd health checks.
+    Healthcheck,
+    /// Sample the running daemon for a while and report min/avg/max input,
+    /// battery and load power, with the runtime at the average load.
+    Budget {
+        /// Sampling window.
+        #[arg(long, default_value_t = 60, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        seconds: u64,
+    },
+    /// Show the daemon version and the last measured battery capacity.
+    Info {
+        /// One JSON object instead of the text, for scripts.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the input-voltage histogram (`[power_quality].input_buckets_mv`).
+    Histogram,
+    /// Print NUT-style variables (`battery.charge`, `ups.status`, …) from
+    /// the running daemon, in `upsc` format.
+    Nut,
+    /// Control the running daemon over IPC (needs root or the daemon's user).
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+    },
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let cfg_path = cli.config.to_string_lossy().to_string();
-
-    let config_present = Path::new(&cfg_path).exists();
-    let cfg = config::load(&cfg_path).with_context(|| format!("loading {cfg_path}"))?;
+#[derive(Subcommand, Debug)]
+enum CtlAction {
+    /// Ask the daemon to exit cleanly.
+    Stop,
+    /// Ask the daemon to re-read its config file (same as SIGHUP).
+    Reload,
+}
 
-    match cli.command {
-        Some(Command::Status) => return cli::run_status(&cfg.ipc).await,
-        Some(Command::Watch) => return cli::run_watch(&cfg.ipc).await,
-        None => {} // fall through to daemon mode
-    }
+/// Config file plus the command-line overrides, re-applied on every reload.
+struct ConfigSource {
+    path: String,
+    /// `--config -`: stdin, read once at startup; a reload re-applies it.
+    stdin: Option<String>,
+    config_dir: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    dry_run: bool,
+    allow_no_shutdown: bool,
+    verbose: u8,
+}
 
-    logging::init(&cfg.logging)?;
-    info!("w3p-ups v{VERSION} starting");
-    if !config_present {
-        warn!("config not found at {cfg_path}; using defaults");
-    } else {
-        info!("config loaded from {cfg_path}");
+impl ConfigSource {
+    fn load(&self) -> Result<(config::Config, Vec<String>)> {
+        let loaded = match &self.stdin {
+            Some(text) => {
+                config::load_text(&self.path, Some(text.clone()), self.config_dir.as_deref())
+            }
+            None => config::load(&self.path, self.config_dir.as_deref()),
+        };
+        let (mut cfg, warnings) = loaded.with_context(|| format!("loading {}", self.path))?;
+        if let Some(socket) = &self.socket {
+            cfg.ipc.socket_path = socket.to_string_lossy().into_owned();
+        }
+        if self.dry_run {
+            cfg.debug.dry_run = true;
+        }
+        if self.allow_no_shutdown {
+            cfg.shutdown.require_prerequisites = false;
+        }
+        match self.verbose {
+            0 => {}
+            1 => cfg.logging.level = "debug".into(),
+            _ => cfg.logging.level = "trace".into(),
+        }
+        Ok((cfg, warnings))
     }
-
-    run_daemon(cfg).await
 }
 
-async fn run_daemon(cfg: config::Config) -> Result<()> {
-    let state = state::State::new();
-    let commands_handler = std::sync::Arc::new(commands::CommandsHandler::new(
-        state.clone(),
-        cfg.commands.clone(),
-        cfg.shutdown.clone(),
-    ));
-
-    // Start the IPC server up front; clients can connect even before the
-    // serial transport comes up (snapshot will be empty until then).
-    let ipc_handle = match ipc::spawn_ipc(
-        cfg.ipc.socket_path.clone(),
-        state.clone(),
-        cfg.battery.input_min_valid_mv,
-        cfg.battery.input_max_valid_mv,
-    )
-    .await
-    {
-        Ok(h) => Some(h),
-        Err(e) => {
-            error!("IPC server failed to start: {e}; continuing without it");
+#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let path = cli.config.to_string_lossy().to_string();
+    let source = ConfigSource {
+        stdin: if path == config::STDIN_CONFIG {
+            config::read_source(&path)?
+        } else {
             None
-        }
-    };
-
-    let mut sigterm = signal(SignalKind::terminate()).context("install SIGTERM handler")?;
-    let mut sigint = signal(SignalKind::interrupt()).context("install SIGINT handler")?;
-
-    'reconnect: loop {
-        let port_path = match transport::resolve_port(&cfg.serial.port) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("port detection failed: {e}; retrying in 5 s");
-                if wait_or_signal(Duration::from_secs(5), &mut sigterm, &mut sigint).await {
-                    break 'reconnect;
-                }
-                continue 'reconnect;
+        },
+        path,
+        config_dir: cli.config_dir.clone(),
+        socket: cli.socket.clone(),
+        dry_run: cli.dry_run,
+        allow_no_shutdown: matches!(
+            cli.command,
+            Command::Daemon {
+                allow_no_shutdown: true
             }
-        };
+        ),
+        verbose: cli.verbose,
+    };
+    let cfg_path = source.path.clone();
 
-        let handles = match transport::spawn_serial_tasks(port_path, cfg.serial.baud_rate).await {
-            Ok(h) => h,
-            Err(e) => {
-                error!("open serial: {e}; retrying in 5 s");
-                if wait_or_signal(Duration::from_secs(5), &mut sigterm, &mut sigint).await {
-                    break 'reconnect;
-                }
-                continue 'reconnect;
-            }
-        };
+    let config_present = !config::is_file_path(&cfg_path) || Path::new(&cfg_path).exists();
+    // A URL config is fetched with blocking I/O; keep it off the runtime.
+    let source = Arc::new(source);
+    let (cfg, cfg_warnings) = {
+        let source = source.clone();
+        tokio::task::spawn_blocking(move || source.load()).await??
+    };
 
-        let mut reader = handles.reader;
-        let mut writer = handles.writer;
-        let mut dispatcher = tokio::spawn(dispatcher::dispatch_loop(
-            state.clone(),
-            handles.inbound,
-            handles.outbound.clone(),
-            commands_handler.clone(),
-        ));
-        let mut sm = tokio::spawn(shutdown_sm::shutdown_sm_loop(
-            state.clone(),
-            cfg.battery.clone(),
-            cfg.shutdown.clone(),
-            handles.outbound.clone(),
-        ));
-        let mut metrics = tokio::spawn(host_metrics::host_metrics_loop(
-            state.clone(),
-            cfg.host_metrics.clone(),
-            cfg.eth_clients.clone(),
-            handles.outbound.clone(),
-        ));
-
-        info!("transport tasks running; entering supervisor loop");
-
-        let cause = tokio::select! {
-            _ = sigterm.recv() => Cause::Signal("SIGTERM"),
-            _ = sigint.recv()  => Cause::Signal("SIGINT"),
-            r = &mut reader    => Cause::Reader(format_join(r)),
-            w = &mut writer    => Cause::Writer(format_join(w)),
-            d = &mut dispatcher => Cause::Dispatcher(format_join(d)),
-            s = &mut sm         => Cause::Sm(format_join(s)),
-            m = &mut metrics    => Cause::Metrics(format_join(m)),
-        };
+    let daemon_mode = matches!(cli.command, Command::Daemon { .. });
+    if !daemon_mode {
+        // Client subcommands don't set up logging; keep it to stderr.
+        for w in &cfg_warnings {
+            eprintln!("warning: {cfg_path}: {w}");
+        }
+    }
 
-        reader.abort();
-        writer.abort();
-        dispatcher.abort();
-        sm.abort();
-        metrics.abort();
-        let _ = reader.await;
-        let _ = writer.await;
-        let _ = dispatcher.await;
-        let _ = sm.await;
-        let _ = metrics.await;
-
-        match cause {
-            Cause::Signal(s) => {
-                info!("{s} received; shutting down");
-                break 'reconnect;
-            }
-            Cause::Reader(why) | Cause::Writer(why) => {
-                warn!("transport task exited ({why}); restarting in 5 s");
-                if wait_or_signal(Duration::from_secs(5), &mut sigterm, &mut sigint).await {
-                    break 'reconnect;
-                }
+    let ep = match &cli.tcp {
+        Some(addr) => cli::Endpoint::Tcp {
+            addr: addr.clone(),
+            token: cli.token.clone().or_else(|| {
+                std::env::var("W3P_UPS_TOKEN")
+                    .ok()
+                    .filter(|t| !t.is_empty())
+            }),
+        },
+        None => cli::Endpoint::unix(&cfg.ipc),
+    };
+    match cli.command {
+        Command::Status => return cli::run_status(&ep, &cfg.monitor).await,
+        Command::Watch => return cli::run_watch(&ep, &cfg.monitor, cfg.ipc.encoding).await,
+        Command::Info { json } => return cli::run_info(&ep, json).await,
+        Command::Budget { seconds } => {
+            return cli::run_budget(
+                &ep,
+                std::time::Duration::from_secs(seconds),
+                cfg.ipc.encoding,
+            )
+            .await
+        }
+        Command::Healthcheck => return cli::run_healthcheck(&ep).await,
+        Command::Histogram => return cli::run_histogram(&ep).await,
+        Command::Nut => return cli::run_nut(&ep).await,
+        Command::Ctl { action } => {
+            return match action {
+                CtlAction::Stop => cli::run_stop(&ep).await,
+                CtlAction::Reload => cli::run_reload(&ep).await,
+            };
+        }
+        Command::Replay {
+            file,
+            speed,
+            looping,
+            exercise_shutdown,
+        } => return replay::run_replay(&ep, &file, speed, looping, exercise_shutdown).await,
+        Command::Stats { file } => return stats::run_stats(&cfg, &file),
+        Command::Probe {
+            follow,
+            json,
+            every,
+            rfc3339,
+        } => {
+            // Logs to stderr only when asked (-v), so stdout stays pipeable.
+            if cli.verbose > 0 {
+                logging::init(&cfg.logging)?;
             }
-            Cause::Dispatcher(why) | Cause::Sm(why) | Cause::Metrics(why) => {
-                error!("supervisor task exited unexpectedly ({why}); restarting in 5 s");
-                if wait_or_signal(Duration::from_secs(5), &mut sigterm, &mut sigint).await {
-                    break 'reconnect;
-                }
+            let every = every.map(std::time::Duration::from_secs);
+            return probe::run_probe(&cfg, follow, json, every, rfc3339).await;
+        }
+        Command::Benchmark { duration } => {
+            if cli.verbose > 0 {
+                logging::init(&cfg.logging)?;
             }
+            let duration = std::time::Duration::from_secs(duration);
+            return benchmark::run_benchmark(&cfg, duration).await;
         }
+        Command::Daemon { .. } => {}
     }
 
-    if let Some(h) = ipc_handle {
-        h.abort();
-        let _ = h.await;
+    logging::init(&cfg.logging)?;
+    info!("w3p-ups v{VERSION} starting");
+    if !config_present {
+        warn!("config not found at {cfg_path}; using defaults");
+    } else {
+        let from = if source.stdin.is_some() {
+            "stdin"
+        } else {
+            &cfg_path
+        };
+        info!("config loaded from {from}");
     }
-    let _ = tokio::fs::remove_file(&cfg.ipc.socket_path).await;
-    Ok(())
-}
-
-enum Cause {
-    Signal(&'static str),
-    Reader(String),
-    Writer(String),
-    Dispatcher(String),
-    Sm(String),
-    Metrics(String),
-}
-
-fn format_join<T: std::fmt::Debug>(r: Result<T, tokio::task::JoinError>) -> String {
-    match r {
-        Ok(v) => format!("clean: {v:?}"),
-        Err(e) if e.is_cancelled() => "cancelled".into(),
-        Err(e) => format!("error: {e}"),
+    for file in config::dropin_files(&cfg_path, source.config_dir.as_deref())? {
+        info!("config drop-in applied: {}", file.display());
     }
-}
-
-async fn wait_or_signal(
-    dur: Duration,
-    sigterm: &mut tokio::signal::unix::Signal,
-    sigint: &mut tokio::signal::unix::Signal,
-) -> bool {
-    tokio::select! {
-        _ = tokio::time::sleep(dur) => false,
-        _ = sigterm.recv() => { info!("SIGTERM during backoff; shutting down"); true }
-        _ = sigint.recv()  => { info!("SIGINT during backoff; shutting down"); true }
+    for w in &cfg_warnings {
+        warn!("{cfg_path}: {w}");
     }
+
+    daemon::run_daemon(cfg, Vec::new(), Some(Box::new(move || source.load()))).await
 }
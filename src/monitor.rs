@@ -0,0 +1,209 @@
+//! Read-only UPS monitor for embedding in other programs.
+//!
+//! [`UpsMonitor`] opens the serial link and decodes telemetry the same way
+//! the daemon does, but runs none of the policy (shutdown SM, host.status,
+//! panel commands). One monitor = one connection: when the link drops, the
+//! sample feed ends and the caller decides whether to reconnect.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use w3p_ups::config::SerialConfig;
+//! use w3p_ups::UpsMonitor;
+//!
+//! let serial = SerialConfig {
+//!     port: "auto".into(),
+//!     baud_rate: 115_200,
+//!     format: Default::default(),
+//!     match_serial: String::new(),
+//!     match_products: w3p_ups::config::default_match_products(),
+//!     expected_interval_ms: 0,
+//!     missed_warn_pct: 5,
+//!     port_file: String::new(),
+//! };
+//! let monitor = UpsMonitor::spawn(&serial).await?;
+//! monitor.on_power_event(|event| println!("power.event {event}"));
+//! tokio::task::spawn_blocking(move || {
+//!     for s in monitor.samples() {
+//!         println!("vbat {} mV, vbus_in {} mV", s.vbat_mv, s.vbus_in_mv);
+//!     }
+//! })
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::config::SerialConfig;
+use crate::dispatcher;
+use crate::proto::payloads::PowerStatusV1;
+use crate::state::{PowerUpdate, State};
+use crate::transport;
+
+/// Per-subscriber backlog before a slow consumer starts skipping samples.
+const FEED_CAPACITY: usize = 64;
+
+pub struct UpsMonitor {
+    state: Arc<State>,
+    port: String,
+    // Only the link task holds a strong sender, so every receiver sees
+    // `Closed` once the connection goes away.
+    feed: broadcast::WeakSender<PowerUpdate>,
+    tasks: [JoinHandle<()>; 3],
+}
+
+impl UpsMonitor {
+    /// Resolve `serial.port` ("auto" or a path), open it and start decoding.
+    pub async fn spawn(serial: &SerialConfig) -> Result<Self> {
+        let port =
+            transport::resolve_port(&serial.port, &serial.match_serial, &serial.match_products)?;
+        let state = State::new();
+        let handles = transport::spawn_serial_tasks(
+            port.clone(),
+            serial.baud_rate,
+            serial.format,
+            // Raw readings, as the firmware means them.
+            crate::soc::SocCurve::STOCK,
+            state.kv_rejects(),
+            state.read_stats(),
+        )
+        .await?;
+
+        let (tx, _) = broadcast::channel(FEED_CAPACITY);
+        let feed = tx.downgrade();
+        let link = tokio::spawn(link_loop(
+            state.clone(),
+            handles.inbound,
+            handles.outbound,
+            tx,
+        ));
+        info!(port = %port, "UPS monitor running");
+
+        Ok(Self {
+            state,
+            port,
+            feed,
+            tasks: [link, handles.reader, handles.writer],
+        })
+    }
+
+    /// Resolved device path (useful when configured as "auto").
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    /// Latest decoded telemetry, same shape the daemon serves over IPC.
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    /// Async feed of every `power.status` / `power.event`. Ends with
+    /// `RecvError::Closed` when the link drops. `None` if it already has.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<PowerUpdate>> {
+        self.feed.upgrade().map(|tx| tx.subscribe())
+    }
+
+    /// Blocking iterator over `power.status` samples; ends when the link
+    /// drops. Must not be driven from inside the async runtime — use a plain
+    /// thread or `spawn_blocking`. Samples missed by a slow consumer are
+    /// skipped, not buffered.
+    pub fn samples(&self) -> impl Iterator<Item = PowerStatusV1> {
+        let mut rx = self.subscribe();
+        std::iter::from_fn(move || loop {
+            match rx.as_mut()?.blocking_recv() {
+                Ok(PowerUpdate::Status(s)) => return Some(s),
+                Ok(PowerUpdate::Event(_)) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        })
+    }
+
+    /// Run `f` with every `power.status` sample. The callback runs on the
+    /// runtime and should return promptly.
+    pub fn on_sample<F>(&self, mut f: F) -> JoinHandle<()>
+    where
+        F: FnMut(PowerStatusV1) + Send + 'static,
+    {
+        self.on_update(move |u| {
+            if let PowerUpdate::Status(s) = u {
+                f(s)
+            }
+        })
+    }
+
+    /// Run `f` with every `power.event` code (see `proto::payloads::power_event`).
+    pub fn on_power_event<F>(&self, mut f: F) -> JoinHandle<()>
+    where
+        F: FnMut(u8) + Send + 'static,
+    {
+        self.on_update(move |u| {
+            if let PowerUpdate::Event(e) = u {
+                f(e)
+            }
+        })
+    }
+
+    fn on_update<F>(&self, mut f: F) -> JoinHandle<()>
+    where
+        F: FnMut(PowerUpdate) + Send + 'static,
+    {
+        let rx = self.subscribe();
+        tokio::spawn(async move {
+            let Some(mut rx) = rx else { return };
+            loop {
+                match rx.recv().await {
+                    Ok(u) => f(u),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+
+    /// Resolves once the serial link has gone away.
+    pub async fn closed(&self) {
+        let Some(mut rx) = self.subscribe() else {
+            return;
+        };
+        while !matches!(rx.recv().await, Err(RecvError::Closed)) {}
+    }
+}
+
+impl Drop for UpsMonitor {
+    fn drop(&mut self) {
+        for t in &self.tasks {
+            t.abort();
+        }
+    }
+}
+
+/// Dispatcher (read-only: no command handler) plus a relay from the state's
+/// power feed into the connection-scoped one; returns when the reader does.
+async fn link_loop(
+    state: Arc<State>,
+    inbound: tokio::sync::mpsc::Receiver<crate::proto::Frame>,
+    outbound: tokio::sync::mpsc::Sender<transport::OutboundFrame>,
+    tx: broadcast::Sender<PowerUpdate>,
+) {
+    let mut from_state = state.subscribe_power();
+    let dispatch = dispatcher::dispatch_loop(state.clone(), inbound, outbound, None);
+    tokio::pin!(dispatch);
+    loop {
+        tokio::select! {
+            _ = &mut dispatch => break,
+            u = from_state.recv() => match u {
+                Ok(u) => {
+                    let _ = tx.send(u);
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+    info!("UPS monitor link closed");
+}
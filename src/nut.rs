@@ -0,0 +1,147 @@
+//! Network UPS Tools view of the agent state: the read-only variable subset
+//! NUT clients (`upsc`, upsmon-style scripts, Home Assistant's NUT
+//! integration) know, under their standard names. Served by the IPC `nut`
+//! op and printed by `w3p-ups nut` in `upsc` format.
+
+use std::collections::BTreeMap;
+
+use crate::config::BatteryConfig;
+use crate::proto::payloads::charge_state;
+use crate::shutdown_sm::classify_input;
+use crate::state::AgentState;
+use crate::VERSION;
+
+/// NUT variables for `snap`, or `None` when there is no fresh reading (NUT
+/// calls that `DATA-STALE`).
+pub fn nut_vars(
+    snap: &AgentState,
+    battery: &BatteryConfig,
+) -> Option<BTreeMap<&'static str, String>> {
+    let snap = &*snap.client_view();
+    let p = snap
+        .last_power
+        .filter(|_| snap.serial_connected || snap.injected.is_some())?;
+    let soc = battery.soc_pct(p.vbat_mv);
+    let on_battery = classify_input(
+        &p,
+        snap.last_power_v2.as_ref(),
+        battery.input_min_valid_mv,
+        battery.input_max_valid_mv,
+        battery.input_zero_cross_check,
+    )
+    .on_battery();
+
+    let mut status = vec![if on_battery { "OB" } else { "OL" }];
+    if on_battery && soc < battery.shutdown_threshold_pct {
+        status.push("LB");
+    }
+    // A top-off pulse on a full pack isn't charging as far as upsmon cares.
+    if p.charge_state == charge_state::CHARGING && !snap.charge_maintaining {
+        status.push("CHRG");
+    } else if p.ibat_ma < 0 {
+        status.push("DISCHRG");
+    }
+    // upsmon's "forced shutdown" flag: our countdown is running.
+    if snap.shutdown_pending_since.is_some() {
+        status.push("FSD");
+    }
+
+    let volts = |mv: u16| format!("{:.2}", mv as f32 / 1000.0);
+    let amps = |ma: i16| format!("{:.2}", ma as f32 / 1000.0);
+    Some(BTreeMap::from([
+        ("device.type", "ups".into()),
+        ("device.mfr", "Web3 Pi".into()),
+        ("device.model", "Web3 Pi UPS".into()),
+        ("driver.name", "w3p-ups".into()),
+        ("driver.version", VERSION.into()),
+        ("ups.mfr", "Web3 Pi".into()),
+        ("ups.model", "Web3 Pi UPS".into()),
+        ("ups.status", status.join(" ")),
+        ("battery.charge", soc.to_string()),
+        (
+            "battery.charge.low",
+            battery.shutdown_threshold_pct.to_string(),
+        ),
+        ("battery.voltage", volts(p.vbat_mv)),
+        ("battery.current", amps(p.ibat_ma)),
+        (
+            "battery.temperature",
+            format!("{:.1}", p.temp_dc as f32 / 10.0),
+        ),
+        ("input.voltage", volts(p.vbus_in_mv)),
+        ("output.voltage", volts(p.vbus_out_mv)),
+        ("output.current", amps(p.ibus_out_ma)),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::proto::payloads::PowerStatusV1;
+
+    fn snap(p: PowerStatusV1) -> AgentState {
+        AgentState {
+            last_power: Some(p),
+            serial_connected: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn on_grid_charging() {
+        let battery = Config::default().battery;
+        let vars = nut_vars(
+            &snap(PowerStatusV1 {
+                charge_state: charge_state::CHARGING,
+                vbus_in_mv: 20_000,
+                vbus_out_mv: 5_100,
+                ibus_out_ma: 1_250,
+                vbat_mv: 8_000,
+                ibat_ma: 900,
+                temp_dc: 315,
+                ..Default::default()
+            }),
+            &battery,
+        )
+        .unwrap();
+        assert_eq!(vars["ups.status"], "OL CHRG");
+        assert_eq!(vars["battery.charge"], "100");
+        assert_eq!(vars["battery.charge.low"], "10");
+        assert_eq!(vars["battery.voltage"], "8.00");
+        assert_eq!(vars["battery.current"], "0.90");
+        assert_eq!(vars["battery.temperature"], "31.5");
+        assert_eq!(vars["input.voltage"], "20.00");
+        assert_eq!(vars["output.current"], "1.25");
+    }
+
+    #[test]
+    fn on_battery_low_and_shutting_down() {
+        let battery = Config::default().battery;
+        let mut s = snap(PowerStatusV1 {
+            vbat_mv: 6_500,
+            ibat_ma: -1_500,
+            ..Default::default()
+        });
+        assert_eq!(
+            nut_vars(&s, &battery).unwrap()["ups.status"],
+            "OB LB DISCHRG"
+        );
+        s.shutdown_pending_since = Some(Instant::now());
+        assert_eq!(
+            nut_vars(&s, &battery).unwrap()["ups.status"],
+            "OB LB DISCHRG FSD"
+        );
+    }
+
+    #[test]
+    fn stale_without_a_live_link() {
+        let battery = Config::default().battery;
+        assert!(nut_vars(&AgentState::default(), &battery).is_none());
+        let mut s = snap(PowerStatusV1::default());
+        s.serial_connected = false;
+        assert!(nut_vars(&s, &battery).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Little-endian, fixed-order encoding behind the binary IPC snapshot
+//! (`[ipc].encoding = "binary"`). No field names or tags: writer and reader
+//! must agree on the order, which `ipc` documents. An `Option` is a 0/1
+//! byte, then the value if 1; a string is a `u16` byte length, then UTF-8.
+
+use anyhow::{Context, Result};
+
+pub(crate) trait Pack {
+    fn pack(&self, out: &mut Vec<u8>);
+}
+
+pub(crate) trait Unpack: Sized {
+    fn unpack(buf: &mut &[u8]) -> Result<Self>;
+}
+
+fn take_bytes<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    let (head, rest) = buf
+        .split_at_checked(n)
+        .context("binary snapshot is truncated")?;
+    *buf = rest;
+    Ok(head)
+}
+
+macro_rules! little_endian {
+    ($($t:ty),*) => {$(
+        impl Pack for $t {
+            fn pack(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Unpack for $t {
+            fn unpack(buf: &mut &[u8]) -> Result<Self> {
+                let bytes = take_bytes(buf, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().expect("sized above")))
+            }
+        }
+    )*};
+}
+
+little_endian!(u8, i8, u16, i16, u32, u64, f32);
+
+impl Pack for bool {
+    fn pack(&self, out: &mut Vec<u8>) {
+        (*self as u8).pack(out);
+    }
+}
+
+impl Unpack for bool {
+    fn unpack(buf: &mut &[u8]) -> Result<Self> {
+        match u8::unpack(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => anyhow::bail!("binary snapshot: bad bool byte {b}"),
+        }
+    }
+}
+
+impl<T: Pack> Pack for Option<T> {
+    fn pack(&self, out: &mut Vec<u8>) {
+        self.is_some().pack(out);
+        if let Some(v) = self {
+            v.pack(out);
+        }
+    }
+}
+
+impl<T: Unpack> Unpack for Option<T> {
+    fn unpack(buf: &mut &[u8]) -> Result<Self> {
+        match bool::unpack(buf)? {
+            true => T::unpack(buf).map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+impl Pack for String {
+    fn pack(&self, out: &mut Vec<u8>) {
+        // Every string in a snapshot is a short label; cut rather than wrap.
+        let mut end = self.len().min(u16::MAX as usize);
+        while !self.is_char_boundary(end) {
+            end -= 1;
+        }
+        (end as u16).pack(out);
+        out.extend_from_slice(&self.as_bytes()[..end]);
+    }
+}
+
+impl Unpack for String {
+    fn unpack(buf: &mut &[u8]) -> Result<Self> {
+        let n = u16::unpack(buf)? as usize;
+        let bytes = take_bytes(buf, n)?;
+        String::from_utf8(bytes.to_vec()).context("binary snapshot: string is not UTF-8")
+    }
+}
+
+/// Read the next value of type `T`.
+pub(crate) fn take<T: Unpack>(buf: &mut &[u8]) -> Result<T> {
+    T::unpack(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_come_back_in_order() {
+        let mut out = Vec::new();
+        0x1234u16.pack(&mut out);
+        (-55i16).pack(&mut out);
+        Some(7u8).pack(&mut out);
+        None::<u64>.pack(&mut out);
+        "15V @ 3A".to_string().pack(&mut out);
+        true.pack(&mut out);
+        assert_eq!(&out[..5], &[0x34, 0x12, 0xc9, 0xff, 1]);
+
+        let mut buf = out.as_slice();
+        assert_eq!(take::<u16>(&mut buf).unwrap(), 0x1234);
+        assert_eq!(take::<i16>(&mut buf).unwrap(), -55);
+        assert_eq!(take::<Option<u8>>(&mut buf).unwrap(), Some(7));
+        assert_eq!(take::<Option<u64>>(&mut buf).unwrap(), None);
+        assert_eq!(take::<String>(&mut buf).unwrap(), "15V @ 3A");
+        assert!(take::<bool>(&mut buf).unwrap());
+        assert!(buf.is_empty());
+        assert!(take::<u8>(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,765 @@
+//! Power-health watchers: slow-burning conditions that don't warrant a
+//! shutdown but that the operator should hear about — e.g. the UPS is on grid
+//! yet the battery isn't charging (blown fuse, dead cell), or the input sits
+//! well off the charger's nominal voltage (weak supply), or the load is
+//! eating the whole PD contract (undersized charger), or the output rail
+//! sags toward the Pi's brownout point. Runs at 1 Hz next to the shutdown
+//! SM; findings are logged and published to [`State`] so the IPC snapshot
+//! (and `w3p-ups status`) can surface them. The consolidated
+//! [`AgentState::fault_summary`](crate::state::AgentState::fault_summary)
+//! is logged once it has held for a few seconds, and again whenever the set
+//! of findings changes. A serial link losing samples at
+//! `[serial].missed_warn_pct` or more is warned about the same way.
+//!
+//! A full pack doesn't stay at "charged": as it self-discharges the charger
+//! tops it off, so the reports cycle through charging (and on some boards
+//! idle) near 100%. From the first "charged" on grid until the pack leaves
+//! `[battery].maintenance_soc_pct` or the grid goes, that counts as
+//! maintaining
+//! ([`AgentState::charge_maintaining`](crate::state::AgentState::charge_maintaining)):
+//! shown as such, and never a charging fault.
+//!
+//! After a deep discharge the pack should charge back up on grid. The
+//! recovery clock starts at the last sample below `shutdown_threshold_pct`
+//! (on battery; on grid only if it isn't running, so a pack that never
+//! charges still times out, and a host that shut down and came back up low
+//! is covered). If the SOC hasn't reached `recovery_target_soc` within
+//! `recovery_timeout_minutes`, the event handlers hear
+//! [`on_recovery_overdue`](crate::events::EventHandler::on_recovery_overdue)
+//! and it shows in the fault summary until the target is reached.
+//!
+//! Each finding of the fault summary is also reported on its own: event
+//! handlers hear [`on_fault`](crate::events::EventHandler::on_fault) once it
+//! has held for the same few seconds, and
+//! [`on_fault_cleared`](crate::events::EventHandler::on_fault_cleared) once
+//! it's gone. Which faults were reported is kept in [`State`] across
+//! reconnects, so a fault still present afterwards isn't reported again,
+//! and one that cleared meanwhile still gets its resolution.
+//!
+//! A supply below `input_min_valid_mv` reads as on battery for good, the
+//! usual cause being a 5 V USB-C charger against the 8 V default. So the
+//! first `[battery].input_range_check_samples` readings after connecting
+//! that carry power-good (v2) are compared with it, and if every one of
+//! them sits below, the threshold is warned about once as a likely
+//! misconfiguration.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::config::BatteryConfig;
+use crate::events::{EventHandlers, PowerContext};
+use crate::proto::payloads::{charge_state, power2_flag, PowerStatusV1};
+use crate::shutdown_sm::classify_input;
+use crate::state::{fault_name, State};
+
+/// An input deviation must persist this long before it's worth a warning;
+/// PD renegotiation and load steps cause brief excursions.
+const DEVIATION_WINDOW: Duration = Duration::from_secs(10);
+
+/// Load spikes (boot, CPU bursts) brush the contract limit routinely; only a
+/// sustained overload means the battery is actually being drained.
+const PD_LOAD_WINDOW: Duration = Duration::from_secs(30);
+
+/// 1 Hz tick: re-evaluate every watcher against the latest power sample.
+pub async fn power_watch_loop(
+    state: Arc<State>,
+    battery: BatteryConfig,
+    handlers: Arc<EventHandlers>,
+) {
+    info!(
+        not_charging_warn_s = battery.not_charging_warn_seconds,
+        "power watch running"
+    );
+    let mut watchers = Watchers::new(&battery);
+    watchers.resume_faults(&state.snapshot().await.notified_faults, state.now());
+    // Watchers start un-raised; drop anything left over from before a reconnect.
+    state.set_charging_fault(false).await;
+    state.set_charge_maintaining(false).await;
+    state.set_pd_overload(false).await;
+    state.set_recovery_overdue(false).await;
+    let mut tick = interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        watchers.step(&state, &battery, &handlers).await;
+    }
+}
+
+struct Watchers {
+    not_charging: Sustained,
+    off_nominal: Sustained,
+    pd_overload: Sustained,
+    unhealthy: Sustained,
+    lossy: Sustained,
+    /// The fault summary last logged while `unhealthy` is raised.
+    reported: Option<String>,
+    /// See [`maintaining_now`].
+    maintaining: bool,
+    /// Start of the recovery clock: the SOC was last low then.
+    low_at: Option<Instant>,
+    recovery_overdue: bool,
+    /// The output rail is below `rail_min_mv`; see [`BatteryConfig::rail_undervoltage`].
+    rail_low: bool,
+    range_check: InputRangeCheck,
+    /// Per fault name, whether handlers have heard of it (`raised`).
+    faults: BTreeMap<String, Sustained>,
+}
+
+impl Watchers {
+    fn new(battery: &BatteryConfig) -> Self {
+        Self {
+            not_charging: Sustained::new(Duration::from_secs(battery.not_charging_warn_seconds)),
+            off_nominal: Sustained::new(DEVIATION_WINDOW),
+            pd_overload: Sustained::new(PD_LOAD_WINDOW),
+            unhealthy: Sustained::new(DEVIATION_WINDOW),
+            lossy: Sustained::new(DEVIATION_WINDOW),
+            reported: None,
+            maintaining: false,
+            low_at: None,
+            recovery_overdue: false,
+            rail_low: false,
+            range_check: InputRangeCheck::new(battery.input_range_check_samples),
+            faults: BTreeMap::new(),
+        }
+    }
+
+    /// Faults reported before a reconnect count as reported.
+    fn resume_faults(&mut self, names: &[String], now: Instant) {
+        for name in names {
+            self.faults.insert(
+                name.clone(),
+                Sustained {
+                    window: DEVIATION_WINDOW,
+                    since: Some(now),
+                    raised: true,
+                },
+            );
+        }
+    }
+
+    async fn step(&mut self, state: &State, battery: &BatteryConfig, handlers: &EventHandlers) {
+        let snap = state.snapshot().await;
+        let Some(power) = snap.last_power else {
+            return;
+        };
+        let now = state.now();
+
+        let on_grid = !classify_input(
+            &power,
+            snap.last_power_v2.as_ref(),
+            battery.input_min_valid_mv,
+            battery.input_max_valid_mv,
+            battery.input_zero_cross_check,
+        )
+        .on_battery();
+        let power_good = snap
+            .last_power_v2
+            .is_some_and(|v2| v2.flags & power2_flag::POWER_GOOD != 0);
+        if let Some(seen_mv) =
+            self.range_check
+                .update(power.vbus_in_mv, power_good, battery.input_min_valid_mv)
+        {
+            warn!(
+                vbus_in_mv = seen_mv,
+                input_min_valid_mv = battery.input_min_valid_mv,
+                "the UPS reports power-good at {seen_mv} mV, below [battery].input_min_valid_mv \
+                 ({} mV), so the grid always reads as battery; set input_min_valid_mv \
+                 below the supply (e.g. {})",
+                battery.input_min_valid_mv,
+                suggested_min_valid_mv(seen_mv)
+            );
+        }
+        let soc = battery.soc_pct(power.vbat_mv);
+        let maintaining = maintaining_now(
+            self.maintaining,
+            &power,
+            on_grid,
+            soc,
+            battery.maintenance_soc_pct,
+        );
+        if maintaining != self.maintaining {
+            self.maintaining = maintaining;
+            if maintaining {
+                info!(soc, "battery full; charger maintaining it");
+            } else {
+                info!(
+                    soc,
+                    charge_state = power.charge_state,
+                    on_grid,
+                    "no longer maintaining a full battery"
+                );
+            }
+            state.set_charge_maintaining(maintaining).await;
+        }
+        let cond = battery.not_charging_warn_seconds > 0
+            && on_grid
+            && !maintaining
+            && not_charging_now(&power);
+        match self.not_charging.update(cond, now) {
+            Some(true) => {
+                // v2 firmware says whether it sees a battery at all.
+                let battery_present = snap
+                    .last_power_v2
+                    .map(|v2| v2.flags & power2_flag::BATT_PRESENT != 0);
+                warn!(
+                    charge_state = power.charge_state,
+                    ibat_ma = power.ibat_ma,
+                    vbat_mv = power.vbat_mv,
+                    battery_present,
+                    "charging fault: on grid but battery not charging for {} s",
+                    battery.not_charging_warn_seconds
+                );
+                state.set_charging_fault(true).await;
+            }
+            Some(false) => {
+                info!("charging fault cleared");
+                state.set_charging_fault(false).await;
+            }
+            None => {}
+        }
+
+        let deviation = battery.input_deviation_pct(power.vbus_in_mv);
+        let limit = battery.input_deviation_warn_pct as f32;
+        let cond = on_grid && limit > 0.0 && deviation.is_some_and(|d| d.abs() > limit);
+        match self.off_nominal.update(cond, now) {
+            Some(true) => warn!(
+                vbus_in_mv = power.vbus_in_mv,
+                nominal_mv = battery.nominal_input_mv,
+                "input voltage {:+.1}% off nominal (limit ±{}%); weak or mismatched supply?",
+                deviation.unwrap_or_default(),
+                battery.input_deviation_warn_pct
+            ),
+            Some(false) => info!(
+                vbus_in_mv = power.vbus_in_mv,
+                "input voltage back within limit of nominal"
+            ),
+            None => {}
+        }
+
+        let load_pct = snap.last_power_v2.and_then(|v2| v2.pd_load_pct());
+        let limit = battery.pd_load_warn_pct as u32;
+        let cond = on_grid && limit > 0 && load_pct.is_some_and(|l| l >= limit);
+        match self.pd_overload.update(cond, now) {
+            Some(true) => {
+                warn!(
+                    load_pct = load_pct.unwrap_or_default(),
+                    contract = snap
+                        .last_power_v2
+                        .and_then(|v2| v2.pd_contract_str())
+                        .unwrap_or_default(),
+                    "input power at {}% of the PD contract for {} s; charger undersized for this load",
+                    load_pct.unwrap_or_default(),
+                    PD_LOAD_WINDOW.as_secs()
+                );
+                state.set_pd_overload(true).await;
+            }
+            Some(false) => {
+                info!(load_pct = ?load_pct, "PD contract load back below limit");
+                state.set_pd_overload(false).await;
+            }
+            None => {}
+        }
+
+        // No debounce: a brownout resets the Pi within milliseconds, so
+        // the first low sample is already worth a line in the log.
+        let rail_low = battery.rail_undervoltage(power.vbus_out_mv);
+        if rail_low != self.rail_low {
+            self.rail_low = rail_low;
+            if rail_low {
+                warn!(
+                    vbus_out_mv = power.vbus_out_mv,
+                    ibus_out_ma = power.ibus_out_ma,
+                    rail_min_mv = battery.rail_min_mv,
+                    "output rail to the Pi at {:.2} V, below {:.2} V: the Pi may brown out",
+                    power.vbus_out_mv as f32 / 1000.0,
+                    battery.rail_min_mv as f32 / 1000.0
+                );
+            } else {
+                info!(
+                    vbus_out_mv = power.vbus_out_mv,
+                    "output rail voltage recovered"
+                );
+            }
+        }
+
+        if battery.recovery_timeout_minutes > 0 {
+            if soc < battery.shutdown_threshold_pct && (!on_grid || self.low_at.is_none()) {
+                self.low_at = Some(now);
+            } else if soc >= battery.recovery_target_soc {
+                self.low_at = None;
+            }
+            let timeout = Duration::from_secs(battery.recovery_timeout_minutes * 60);
+            let waited = self.low_at.map(|t| now.saturating_duration_since(t));
+            match (self.recovery_overdue, waited) {
+                (false, Some(w)) if on_grid && w >= timeout => {
+                    self.recovery_overdue = true;
+                    state.set_recovery_overdue(true).await;
+                    let ctx = PowerContext {
+                        power,
+                        soc_pct: soc,
+                    };
+                    handlers.recovery_overdue(&ctx, w);
+                }
+                (true, None) => {
+                    self.recovery_overdue = false;
+                    state.set_recovery_overdue(false).await;
+                    info!(soc, "battery recovered to {}%", battery.recovery_target_soc);
+                }
+                _ => {}
+            }
+        }
+
+        let summary = snap.fault_summary(battery);
+        match self.unhealthy.update(summary.is_some(), now) {
+            Some(false) => {
+                info!("UPS health back to normal");
+                self.reported = None;
+            }
+            _ if self.unhealthy.raised && summary != self.reported => {
+                warn!("UPS unhealthy: {}", summary.as_deref().unwrap_or_default());
+                self.reported = summary;
+            }
+            _ => {}
+        }
+
+        let ctx = PowerContext {
+            power,
+            soc_pct: soc,
+        };
+        let found = snap.faults(battery);
+        let mut changed = false;
+        for finding in &found {
+            let fault = self
+                .faults
+                .entry(fault_name(finding).to_string())
+                .or_insert_with(|| Sustained::new(DEVIATION_WINDOW));
+            if fault.update(true, now) == Some(true) {
+                handlers.fault(&ctx, finding);
+                changed = true;
+            }
+        }
+        self.faults.retain(|name, fault| {
+            if found.iter().any(|f| fault_name(f) == name) {
+                return true;
+            }
+            if fault.update(false, now) == Some(false) {
+                handlers.fault_cleared(&ctx, name);
+                changed = true;
+            }
+            false
+        });
+        if changed {
+            let reported = self
+                .faults
+                .iter()
+                .filter(|(_, f)| f.raised)
+                .map(|(name, _)| name.clone())
+                .collect();
+            state.set_notified_faults(reported).await;
+        }
+
+        let cadence = &snap.cadence;
+        match self.lossy.update(cadence.lossy(), now) {
+            Some(true) => warn!(
+                missed = cadence.missed,
+                max_gap_ms = ?cadence.max_recent_gap().map(|g| g.as_millis()),
+                "serial link losing {:.0}% of samples (limit {}%); check the cable and USB hub",
+                cadence.recent_miss_pct().unwrap_or_default(),
+                cadence.warn_pct()
+            ),
+            Some(false) => info!("serial sample rate back to normal"),
+            None => {}
+        }
+    }
+}
+
+/// On grid, the charger should either be charging or report the pack full.
+/// Anything else with no charge current flowing is suspicious.
+fn not_charging_now(p: &PowerStatusV1) -> bool {
+    p.charge_state != charge_state::CHARGING
+        && p.charge_state != charge_state::CHARGED
+        && p.ibat_ma <= 0
+}
+
+/// Maintaining a full pack: entered on a "charged" report on grid at
+/// `band` SOC or above, kept through top-off pulses (charging or idle
+/// reports) while that holds. A fault, the grid going or the SOC falling
+/// out of the band ends it. `band == 0` disables it.
+fn maintaining_now(was: bool, p: &PowerStatusV1, on_grid: bool, soc: u8, band: u8) -> bool {
+    if band == 0 || !on_grid || soc < band || p.charge_state == charge_state::FAULT {
+        return false;
+    }
+    was || p.charge_state == charge_state::CHARGED
+}
+
+/// The startup check on `input_min_valid_mv`: collects the input of the
+/// first `want` power-good readings.
+#[derive(Debug)]
+struct InputRangeCheck {
+    want: usize,
+    seen: Vec<u16>,
+}
+
+impl InputRangeCheck {
+    fn new(samples: u32) -> Self {
+        Self {
+            want: samples as usize,
+            seen: Vec::new(),
+        }
+    }
+
+    /// `Some(highest input seen)` once, when all `want` readings have come
+    /// in below `min_mv`. A 0 mV reading is a sensor glitch, not a supply.
+    fn update(&mut self, vbus_in_mv: u16, power_good: bool, min_mv: u16) -> Option<u16> {
+        if self.seen.len() >= self.want || !power_good || vbus_in_mv == 0 {
+            return None;
+        }
+        self.seen.push(vbus_in_mv);
+        if self.seen.len() < self.want {
+            return None;
+        }
+        let highest = self.seen.iter().copied().max()?;
+        (highest < min_mv).then_some(highest)
+    }
+}
+
+/// 90% of the supply, in whole 100 mV: below it with room for droop under
+/// load.
+fn suggested_min_valid_mv(supply_mv: u16) -> u16 {
+    (supply_mv as u32 * 9 / 10 / 100 * 100) as u16
+}
+
+/// Debounced boolean: raises once `cond` has held continuously for `window`,
+/// clears as soon as it stops holding. `update` returns `Some(new)` only on a
+/// transition.
+#[derive(Debug)]
+struct Sustained {
+    window: Duration,
+    since: Option<Instant>,
+    raised: bool,
+}
+
+impl Sustained {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            since: None,
+            raised: false,
+        }
+    }
+
+    fn update(&mut self, cond: bool, now: Instant) -> Option<bool> {
+        if !cond {
+            self.since = None;
+            if self.raised {
+                self.raised = false;
+                return Some(false);
+            }
+            return None;
+        }
+        let since = *self.since.get_or_insert(now);
+        if !self.raised && now.saturating_duration_since(since) >= self.window {
+            self.raised = true;
+            return Some(true);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(charge_state: u8, ibat_ma: i16) -> PowerStatusV1 {
+        PowerStatusV1 {
+            charge_state,
+            ibat_ma,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn idle_without_current_is_not_charging() {
+        assert!(not_charging_now(&sample(charge_state::IDLE, 0)));
+        assert!(not_charging_now(&sample(charge_state::FAULT, -10)));
+    }
+
+    #[test]
+    fn charging_or_full_is_fine() {
+        assert!(!not_charging_now(&sample(charge_state::CHARGING, 0)));
+        assert!(!not_charging_now(&sample(charge_state::CHARGED, 0)));
+        assert!(!not_charging_now(&sample(charge_state::IDLE, 250)));
+    }
+
+    #[test]
+    fn sustained_raises_after_window_and_clears() {
+        let t0 = Instant::now();
+        let mut s = Sustained::new(Duration::from_secs(10));
+        assert_eq!(s.update(true, t0), None);
+        assert_eq!(s.update(true, t0 + Duration::from_secs(9)), None);
+        assert_eq!(s.update(true, t0 + Duration::from_secs(10)), Some(true));
+        assert_eq!(s.update(true, t0 + Duration::from_secs(11)), None);
+        assert_eq!(s.update(false, t0 + Duration::from_secs(12)), Some(false));
+        assert_eq!(s.update(false, t0 + Duration::from_secs(13)), None);
+    }
+
+    #[tokio::test]
+    async fn charging_fault_raised_after_configured_window() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = crate::config::Config::default().battery;
+        let mut w = Watchers::new(&battery);
+        // On grid, idle, no current.
+        state
+            .update_power(PowerStatusV1 {
+                vbus_in_mv: 20_000,
+                ..sample(charge_state::IDLE, 0)
+            })
+            .await;
+
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        clock.advance(Duration::from_secs(battery.not_charging_warn_seconds - 1));
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        assert!(!state.snapshot().await.charging_fault);
+        clock.advance(Duration::from_secs(1));
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        assert!(state.snapshot().await.charging_fault);
+    }
+
+    #[tokio::test]
+    async fn top_off_cycling_on_a_full_pack_is_maintenance() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = crate::config::Config::default().battery;
+        let mut w = Watchers::new(&battery);
+        let curve = battery.soc_curve();
+        let at = |charge, ibat_ma, soc| PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: curve.pack_mv(soc),
+            ..sample(charge, ibat_ma)
+        };
+        let window = Duration::from_secs(battery.not_charging_warn_seconds);
+        let handlers = EventHandlers::default();
+        let maintaining = || async { state.snapshot().await.charge_maintaining };
+
+        // Near full but not reported charged yet: just charging.
+        state
+            .update_power(at(charge_state::CHARGING, 300, 97))
+            .await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(!maintaining().await);
+        state.update_power(at(charge_state::CHARGED, 0, 100)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(maintaining().await);
+
+        // A pulse, then the charger idles between pulses for longer than
+        // the charging-fault window: still maintaining, no fault.
+        state
+            .update_power(at(charge_state::CHARGING, 400, 98))
+            .await;
+        w.step(&state, &battery, &handlers).await;
+        state.update_power(at(charge_state::IDLE, 0, 99)).await;
+        w.step(&state, &battery, &handlers).await;
+        clock.advance(window);
+        w.step(&state, &battery, &handlers).await;
+        let snap = state.snapshot().await;
+        assert!(snap.charge_maintaining);
+        assert!(!snap.charging_fault);
+
+        // Out of the band, an idle charger is a fault again.
+        state.update_power(at(charge_state::IDLE, 0, 90)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(!maintaining().await);
+        clock.advance(window);
+        w.step(&state, &battery, &handlers).await;
+        assert!(state.snapshot().await.charging_fault);
+
+        // Charged again, then the grid goes.
+        state.update_power(at(charge_state::CHARGED, 0, 100)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(maintaining().await);
+        state
+            .update_power(PowerStatusV1 {
+                vbus_in_mv: 0,
+                ..at(charge_state::IDLE, -900, 100)
+            })
+            .await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(!maintaining().await);
+    }
+
+    #[tokio::test]
+    async fn fault_summary_is_logged_once_it_holds() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = crate::config::Config::default().battery;
+        let mut w = Watchers::new(&battery);
+        let faulty = PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            faults: crate::proto::payloads::power_fault::OTP,
+            ..sample(charge_state::CHARGING, 500)
+        };
+        state.update_power(faulty).await;
+
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        assert_eq!(w.reported, None);
+        clock.advance(DEVIATION_WINDOW);
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        assert_eq!(w.reported.as_deref(), Some("firmware-fault (otp)"));
+
+        state
+            .update_power(PowerStatusV1 {
+                faults: 0,
+                ..faulty
+            })
+            .await;
+        w.step(&state, &battery, &EventHandlers::default()).await;
+        assert_eq!(w.reported, None);
+    }
+
+    #[tokio::test]
+    async fn slow_recovery_after_a_deep_discharge_is_reported() {
+        use std::sync::Mutex;
+
+        struct Recorder(Arc<Mutex<Vec<(u8, u64)>>>);
+        impl crate::events::EventHandler for Recorder {
+            fn on_recovery_overdue(&self, ctx: &PowerContext, waited: Duration) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((ctx.soc_pct, waited.as_secs() / 60));
+            }
+        }
+
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = crate::config::Config::default().battery;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(seen.clone()))]);
+        let mut w = Watchers::new(&battery);
+        let curve = battery.soc_curve();
+        let at = |vbus_in_mv, soc| PowerStatusV1 {
+            vbus_in_mv,
+            vbat_mv: curve.pack_mv(soc),
+            ..sample(charge_state::CHARGING, 500)
+        };
+        let minutes = |m| Duration::from_secs(m * 60);
+
+        // Drained on battery, then back on grid charging only to 50 %.
+        state.update_power(at(0, 5)).await;
+        w.step(&state, &battery, &handlers).await;
+        state.update_power(at(20_000, 50)).await;
+        clock.advance(minutes(battery.recovery_timeout_minutes - 1));
+        w.step(&state, &battery, &handlers).await;
+        assert!(seen.lock().unwrap().is_empty());
+        clock.advance(minutes(1));
+        w.step(&state, &battery, &handlers).await;
+        w.step(&state, &battery, &handlers).await;
+        assert_eq!(*seen.lock().unwrap(), [(50, 360)]);
+        let snap = state.snapshot().await;
+        assert!(snap.recovery_overdue);
+        assert_eq!(
+            snap.fault_summary(&battery).as_deref(),
+            Some("recovery-overdue")
+        );
+
+        // Reaching the target clears it.
+        state.update_power(at(20_000, 80)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(!state.snapshot().await.recovery_overdue);
+
+        // A pack that stays low on grid times out too.
+        state.update_power(at(20_000, 5)).await;
+        w.step(&state, &battery, &handlers).await;
+        clock.advance(minutes(battery.recovery_timeout_minutes));
+        w.step(&state, &battery, &handlers).await;
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn sustained_resets_on_interruption() {
+        let t0 = Instant::now();
+        let mut s = Sustained::new(Duration::from_secs(10));
+        s.update(true, t0);
+        s.update(false, t0 + Duration::from_secs(5));
+        assert_eq!(s.update(true, t0 + Duration::from_secs(6)), None);
+        assert_eq!(s.update(true, t0 + Duration::from_secs(15)), None);
+        assert_eq!(s.update(true, t0 + Duration::from_secs(16)), Some(true));
+    }
+
+    #[test]
+    fn a_supply_below_the_valid_range_is_called_out_once() {
+        let mut check = InputRangeCheck::new(3);
+        // Readings without power-good, and 0 mV glitches, don't count.
+        assert_eq!(check.update(5_100, false, 8_000), None);
+        assert_eq!(check.update(0, true, 8_000), None);
+        assert_eq!(check.update(5_100, true, 8_000), None);
+        assert_eq!(check.update(5_050, true, 8_000), None);
+        assert_eq!(check.update(5_120, true, 8_000), Some(5_120));
+        assert_eq!(check.update(5_100, true, 8_000), None);
+        assert_eq!(suggested_min_valid_mv(5_120), 4_600);
+
+        // One reading in range means the threshold fits the supply.
+        let mut check = InputRangeCheck::new(2);
+        assert_eq!(check.update(7_900, true, 8_000), None);
+        assert_eq!(check.update(20_000, true, 8_000), None);
+        assert_eq!(InputRangeCheck::new(0).update(5_000, true, 8_000), None);
+    }
+
+    #[tokio::test]
+    async fn each_fault_is_reported_once_and_so_is_its_resolution() {
+        use std::sync::Mutex;
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+        impl crate::events::EventHandler for Recorder {
+            fn on_fault(&self, _: &PowerContext, fault: &str) {
+                self.0.lock().unwrap().push(format!("fault {fault}"));
+            }
+            fn on_fault_cleared(&self, _: &PowerContext, fault: &str) {
+                self.0.lock().unwrap().push(format!("cleared {fault}"));
+            }
+        }
+
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let battery = crate::config::Config::default().battery;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(seen.clone()))]);
+        let at = |temp_dc| PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: battery.soc_curve().pack_mv(60),
+            temp_dc,
+            ..sample(charge_state::CHARGING, 500)
+        };
+        let mut w = Watchers::new(&battery);
+
+        state.update_power(at(1_200)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert!(seen.lock().unwrap().is_empty());
+        clock.advance(DEVIATION_WINDOW);
+        w.step(&state, &battery, &handlers).await;
+        // The reading changes, the fault doesn't.
+        state.update_power(at(1_250)).await;
+        w.step(&state, &battery, &handlers).await;
+        assert_eq!(*seen.lock().unwrap(), ["fault implausible-temp (120.0 °C)"]);
+
+        // A reconnect starts a new watch; the fault is already known.
+        let mut w = Watchers::new(&battery);
+        w.resume_faults(&state.snapshot().await.notified_faults, state.now());
+        clock.advance(DEVIATION_WINDOW);
+        w.step(&state, &battery, &handlers).await;
+        state.update_power(at(300)).await;
+        w.step(&state, &battery, &handlers).await;
+        w.step(&state, &battery, &handlers).await;
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "fault implausible-temp (120.0 °C)",
+                "cleared implausible-temp"
+            ]
+        );
+        assert!(state.snapshot().await.notified_faults.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! `[privileges]`: run as an unprivileged user once the daemon is set up.
+//! The daemon starts as root, binds its IPC socket, then switches to
+//! `user` (and `group`, or the user's primary group) with its supplementary
+//! groups, so `dialout` membership still lets it reopen the serial port on
+//! a reconnect. From then on the shutdown needs `[shutdown].helper_command`
+//! (a sudo rule or a setuid helper) to get its privilege back.
+
+use std::ffi::CString;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+/// Who to become.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Identity {
+    pub(crate) name: CString,
+    pub(crate) uid: libc::uid_t,
+    pub(crate) gid: libc::gid_t,
+}
+
+/// Resolve `user` (and `group`; empty: the user's primary group).
+pub(crate) fn lookup(user: &str, group: &str) -> Result<Identity> {
+    let name = CString::new(user).context("user name contains NUL")?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: all-zero is a valid `passwd` (null pointers, zero ids).
+    let mut pw: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call and `buf` outlives the
+    // use of the strings it ends up holding.
+    let rc = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pw,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        bail!(
+            "look up user {user}: {}",
+            std::io::Error::from_raw_os_error(rc)
+        );
+    }
+    if found.is_null() {
+        bail!("no such user: {user}");
+    }
+    let gid = if group.is_empty() {
+        pw.pw_gid
+    } else {
+        lookup_group(group)?
+    };
+    Ok(Identity {
+        name,
+        uid: pw.pw_uid,
+        gid,
+    })
+}
+
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    let name = CString::new(group).context("group name contains NUL")?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: as in `lookup`.
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: as in `lookup`.
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut gr,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        bail!(
+            "look up group {group}: {}",
+            std::io::Error::from_raw_os_error(rc)
+        );
+    }
+    if found.is_null() {
+        bail!("no such group: {group}");
+    }
+    Ok(gr.gr_gid)
+}
+
+/// Switch the whole process to `user`/`group` for good. Groups first: once
+/// the uid is gone, so is the right to change them.
+pub(crate) fn drop_to(user: &str, group: &str) -> Result<()> {
+    let id = lookup(user, group)?;
+    // SAFETY: getuid/getgid have no preconditions and cannot fail.
+    if unsafe { (libc::getuid(), libc::getgid()) } == (id.uid, id.gid) {
+        info!("already running as {user}; nothing to drop");
+        return Ok(());
+    }
+    // SAFETY: plain syscalls on values we own; each result is checked.
+    unsafe {
+        if libc::initgroups(id.name.as_ptr(), id.gid as _) != 0 {
+            return Err(std::io::Error::last_os_error()).context("initgroups");
+        }
+        if libc::setgid(id.gid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setgid");
+        }
+        if libc::setuid(id.uid) != 0 {
+            return Err(std::io::Error::last_os_error()).context("setuid");
+        }
+        // A root that can come back would make all of this moot.
+        if id.uid != 0 && libc::setuid(0) == 0 {
+            bail!("could regain root after setuid({})", id.uid);
+        }
+    }
+    info!(
+        uid = id.uid,
+        gid = id.gid,
+        "dropped privileges; now running as {}",
+        id.name.to_string_lossy()
+    );
+    Ok(())
+}
+
+/// Give the daemon's runtime directories to `user`/`group` before
+/// [`drop_to`] them: after the drop, the IPC socket is rebound there on a
+/// reload, `[serial].port_file` rewritten on a reconnect, and both removed
+/// at exit. As in `ipc::prepare_socket_dir`, only a real directory named
+/// like the runtime directory is touched; anything else is warned about.
+pub(crate) fn hand_over(dirs: &[&Path], user: &str, group: &str) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let id = lookup(user, group)?;
+    for dir in dirs {
+        let meta = match std::fs::symlink_metadata(dir) {
+            Ok(m) => m,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("stat {}", dir.display())),
+        };
+        if (meta.uid(), meta.gid()) == (id.uid, id.gid) {
+            continue;
+        }
+        let ours = dir
+            .file_name()
+            .is_some_and(|n| n == crate::ipc::RUNTIME_DIR_NAME);
+        if !ours || !meta.is_dir() {
+            warn!(
+                "{} is not the daemon's runtime directory; not handing it to {user}, \
+                 so the socket and port file there may not be replaceable after the drop",
+                dir.display()
+            );
+            continue;
+        }
+        std::os::unix::fs::lchown(dir, Some(id.uid), Some(id.gid))
+            .with_context(|| format!("chown {} to {user}", dir.display()))?;
+        info!("handed {} to {}:{}", dir.display(), id.uid, id.gid);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_and_groups_are_resolved() {
+        let root = lookup("root", "").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(lookup("root", "root").unwrap().gid, 0);
+        let e = lookup("w3p-ups-no-such-user", "").unwrap_err();
+        assert_eq!(e.to_string(), "no such user: w3p-ups-no-such-user");
+        let e = lookup("root", "w3p-ups-no-such-group").unwrap_err();
+        assert_eq!(e.to_string(), "no such group: w3p-ups-no-such-group");
+    }
+}
@@ -0,0 +1,314 @@
+//! `w3p-ups probe` — talk to the UPS directly over serial, bypassing the
+//! daemon and IPC. Prints one decoded `power.status` (or, with `--follow`,
+//! every one until Ctrl-C) as a compact line or JSON. `--every N` downsamples
+//! the follow stream to one min/max/avg record per N seconds (see
+//! [`crate::aggregate`]). Every record is stamped with the host's wall clock
+//! when it was read: `unix_ts_ms`, or an RFC 3339 `ts` with `--rfc3339`.
+//!
+//! The serial port is not shared: if the daemon is running, stop it first or
+//! the two will split the frames between them.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::aggregate::{Aggregate, Aggregator};
+use crate::cli::{charge_state_name, fmt_mv, format_clock_utc, format_rfc3339_utc};
+use crate::config::{BatteryConfig, Config};
+use crate::monitor::UpsMonitor;
+use crate::proto::payloads::PowerStatusV1;
+use crate::shutdown_sm::classify_input;
+use crate::soc::SocCurve;
+use crate::state::PowerUpdate;
+
+/// The UPS emits power.status at ~1 Hz; this leaves room for a slow boot.
+const FIRST_SAMPLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wall-clock stamp of a record, in the format picked by `--rfc3339`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Stamp {
+    UnixTsMs(u64),
+    Ts(String),
+}
+
+impl Stamp {
+    fn now(rfc3339: bool) -> Self {
+        let ms = unix_now_ms();
+        if rfc3339 {
+            Self::Ts(format_rfc3339_utc(ms))
+        } else {
+            Self::UnixTsMs(ms)
+        }
+    }
+
+    /// Leading column of the compact text line.
+    fn text(&self) -> String {
+        match self {
+            Self::UnixTsMs(ms) => format_clock_utc(*ms),
+            Self::Ts(ts) => ts.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProbeSample {
+    #[serde(flatten)]
+    at: Stamp,
+    vbus_in_mv: u16,
+    vbus_out_mv: u16,
+    ibus_out_ma: i16,
+    vbat_mv: u16,
+    ibat_ma: i16,
+    soc_pct: u8,
+    on_battery: bool,
+    charge_state: u8,
+    temp_dc: i16,
+    faults: u16,
+}
+
+/// `every`: downsampling window; implies `follow`. `rfc3339`: stamp records
+/// with an RFC 3339 time instead of unix ms.
+pub async fn run_probe(
+    cfg: &Config,
+    follow: bool,
+    json: bool,
+    every: Option<Duration>,
+    rfc3339: bool,
+) -> Result<()> {
+    let out = Output { json, rfc3339 };
+    let monitor = UpsMonitor::spawn(&cfg.serial)
+        .await
+        .context("open UPS serial port (is the daemon holding it?)")?;
+    eprintln!("probing {}", monitor.port());
+    let Some(mut rx) = monitor.subscribe() else {
+        bail!("serial link closed before the first sample");
+    };
+
+    let first = tokio::time::timeout(FIRST_SAMPLE_TIMEOUT, next_status(&mut rx))
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "no power.status within {} s",
+                FIRST_SAMPLE_TIMEOUT.as_secs()
+            )
+        })?;
+    let Some(p) = first else {
+        bail!("serial link closed before the first sample");
+    };
+    if let Some(window) = every {
+        return follow_aggregated(&mut rx, p, window, cfg.battery.soc_curve(), out).await;
+    }
+    print_sample(cfg, &p, out)?;
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        tokio::select! {
+            s = next_status(&mut rx) => match s {
+                Some(p) => print_sample(cfg, &p, out)?,
+                None => bail!("serial link closed"),
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+async fn follow_aggregated(
+    rx: &mut tokio::sync::broadcast::Receiver<PowerUpdate>,
+    first: PowerStatusV1,
+    window: Duration,
+    curve: SocCurve,
+    out: Output,
+) -> Result<()> {
+    let mut agg = Aggregator::new(window, curve);
+    agg.push(&first, Instant::now());
+    loop {
+        tokio::select! {
+            s = next_status(rx) => match s {
+                Some(p) => {
+                    if let Some(a) = agg.push(&p, Instant::now()) {
+                        print_aggregate(&a, window, out)?;
+                    }
+                }
+                None => {
+                    if let Some(a) = agg.flush() {
+                        print_aggregate(&a, window, out)?;
+                    }
+                    bail!("serial link closed");
+                }
+            },
+            _ = tokio::signal::ctrl_c() => {
+                if let Some(a) = agg.flush() {
+                    print_aggregate(&a, window, out)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Output {
+    json: bool,
+    rfc3339: bool,
+}
+
+#[derive(Serialize)]
+struct AggregateLine<'a> {
+    #[serde(flatten)]
+    at: Stamp,
+    window_s: u64,
+    #[serde(flatten)]
+    agg: &'a Aggregate,
+}
+
+fn print_aggregate(a: &Aggregate, window: Duration, out: Output) -> Result<()> {
+    let at = Stamp::now(out.rfc3339);
+    if out.json {
+        let line = AggregateLine {
+            at,
+            window_s: window.as_secs(),
+            agg: a,
+        };
+        println!("{}", serde_json::to_string(&line)?);
+    } else {
+        let v = |s: &crate::aggregate::FieldStats| {
+            format!(
+                "{}/{}/{}V",
+                fmt_mv(s.min),
+                fmt_mv(s.avg.round() as i32),
+                fmt_mv(s.max)
+            )
+        };
+        println!(
+            "{}  n={:<3}  VI={}  VBAT={}  IBAT={}/{:.0}/{}mA  SOC={}-{}%  faults=0x{:04x}",
+            at.text(),
+            a.samples,
+            v(&a.vbus_in_mv),
+            v(&a.vbat_mv),
+            a.ibat_ma.min,
+            a.ibat_ma.avg,
+            a.ibat_ma.max,
+            a.soc_pct.min,
+            a.soc_pct.max,
+            a.faults,
+        );
+    }
+    Ok(())
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn next_status(
+    rx: &mut tokio::sync::broadcast::Receiver<PowerUpdate>,
+) -> Option<PowerStatusV1> {
+    loop {
+        match rx.recv().await {
+            Ok(PowerUpdate::Status(p)) => return Some(p),
+            Ok(PowerUpdate::Event(_)) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+impl ProbeSample {
+    /// `p` as read now, stamped with the wall clock.
+    pub(crate) fn new(b: &BatteryConfig, p: &PowerStatusV1, rfc3339: bool) -> Self {
+        // No v2 context here: the zero-input cross-check falls back to the
+        // battery-current test.
+        let on_battery = classify_input(
+            p,
+            None,
+            b.input_min_valid_mv,
+            b.input_max_valid_mv,
+            b.input_zero_cross_check,
+        )
+        .on_battery();
+        Self {
+            at: Stamp::now(rfc3339),
+            vbus_in_mv: p.vbus_in_mv,
+            vbus_out_mv: p.vbus_out_mv,
+            ibus_out_ma: p.ibus_out_ma,
+            vbat_mv: p.vbat_mv,
+            ibat_ma: p.ibat_ma,
+            soc_pct: b.soc_pct(p.vbat_mv),
+            on_battery,
+            charge_state: p.charge_state,
+            temp_dc: p.temp_dc,
+            faults: p.faults,
+        }
+    }
+}
+
+fn print_sample(cfg: &Config, p: &PowerStatusV1, out: Output) -> Result<()> {
+    let s = ProbeSample::new(&cfg.battery, p, out.rfc3339);
+    if out.json {
+        println!("{}", serde_json::to_string(&s)?);
+    } else {
+        println!("{}", compact_line(&s));
+    }
+    Ok(())
+}
+
+fn compact_line(s: &ProbeSample) -> String {
+    format!(
+        "{}  {:<7}  VI={}V  VOUT={}V  IOUT={}mA  VBAT={}V  IBAT={}mA  SOC={}%  chg={}  T={:.1}°C  faults=0x{:04x}",
+        s.at.text(),
+        if s.on_battery { "BATTERY" } else { "GRID" },
+        fmt_mv(s.vbus_in_mv as i32),
+        fmt_mv(s.vbus_out_mv as i32),
+        s.ibus_out_ma,
+        fmt_mv(s.vbat_mv as i32),
+        s.ibat_ma,
+        s.soc_pct,
+        charge_state_name(s.charge_state),
+        s.temp_dc as f32 / 10.0,
+        s.faults,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_line_has_every_field() {
+        let mut s = ProbeSample {
+            at: Stamp::UnixTsMs(0),
+            vbus_in_mv: 19_800,
+            vbus_out_mv: 5_100,
+            ibus_out_ma: 1_200,
+            vbat_mv: 7_400,
+            ibat_ma: -1_500,
+            soc_pct: 55,
+            on_battery: false,
+            charge_state: 1,
+            temp_dc: 315,
+            faults: 0x0008,
+        };
+        assert_eq!(
+            compact_line(&s),
+            "1970-01-01 00:00:00 UTC  GRID     VI=19.80V  VOUT=5.10V  IOUT=1200mA  VBAT=7.40V  \
+             IBAT=-1500mA  SOC=55%  chg=charging  T=31.5°C  faults=0x0008"
+        );
+        assert!(serde_json::to_string(&s)
+            .unwrap()
+            .starts_with(r#"{"unix_ts_ms":0,"vbus_in_mv""#));
+
+        // --rfc3339
+        s.at = Stamp::Ts(format_rfc3339_utc(1_791_964_800_123));
+        assert!(compact_line(&s).starts_with("2026-10-14T08:00:00.123Z  GRID"));
+        assert!(serde_json::to_string(&s)
+            .unwrap()
+            .starts_with(r#"{"ts":"2026-10-14T08:00:00.123Z","vbus_in_mv""#));
+    }
+}
@@ -221,12 +221,46 @@ impl PowerStatusV1 {
     }
 }
 
+/// `PowerStatusV1::charge_state` / `PowerStatusV2::charge_state` values.
+pub mod charge_state {
+    pub const IDLE: u8 = 0;
+    pub const CHARGING: u8 = 1;
+    pub const CHARGED: u8 = 2;
+    pub const FAULT: u8 = 3;
+}
+
 /// Bit positions for `PowerStatusV1::faults`.
 pub mod power_fault {
     pub const OVP: u16 = 1 << 0;
     pub const OCP: u16 = 1 << 1;
     pub const OTP: u16 = 1 << 2;
     pub const PD_NEG: u16 = 1 << 3;
+
+    const NAMES: [(u16, &str); 4] = [(OVP, "ovp"), (OCP, "ocp"), (OTP, "otp"), (PD_NEG, "pd-neg")];
+
+    /// The set bits as names, e.g. `"ovp otp"`; unknown bits as hex,
+    /// `"none"` if clear.
+    pub fn describe(faults: u16) -> String {
+        super::describe_bits(faults, &NAMES)
+    }
+}
+
+/// Names for the set bits of a flags field, in `names` order, with any
+/// bits not in `names` appended as hex; `"none"` when no bit is set.
+fn describe_bits(bits: u16, names: &[(u16, &str)]) -> String {
+    let mut parts: Vec<String> = names
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let unknown = names.iter().fold(bits, |b, (bit, _)| b & !bit);
+    if unknown != 0 {
+        parts.push(format!("0x{unknown:02x}"));
+    }
+    if parts.is_empty() {
+        return "none".into();
+    }
+    parts.join(" ")
 }
 
 /// `power.status` v2 — `wups_power_status_v2_t`. 40 bytes, version byte = 2.
@@ -267,6 +301,20 @@ pub mod power2_flag {
     pub const BATT_PRESENT: u8 = 1 << 2;
     pub const POWER_GOOD: u8 = 1 << 3;
     pub const USB_C_ATTACH: u8 = 1 << 4;
+
+    const NAMES: [(u16, &str); 5] = [
+        (DC_IN_EN as u16, "dc-in"),
+        (VBUS_OUT_EN as u16, "out-on"),
+        (BATT_PRESENT as u16, "battery"),
+        (POWER_GOOD as u16, "power-good"),
+        (USB_C_ATTACH as u16, "usb-c"),
+    ];
+
+    /// The set bits as names, e.g. `"dc-in battery power-good"`; unknown
+    /// bits as hex, `"none"` if clear.
+    pub fn describe(flags: u8) -> String {
+        super::describe_bits(flags.into(), &NAMES)
+    }
 }
 
 impl PowerStatusV2 {
@@ -330,6 +378,42 @@ impl PowerStatusV2 {
             faults: self.faults,
         }
     }
+
+    /// Negotiated input PD contract, e.g. `"15V @ 3A"`. `None` when the
+    /// HUSB238 reports no contract (legacy 5 V / non-PD charger, or N/A).
+    pub fn pd_contract_str(&self) -> Option<String> {
+        if self.pd_in_mv == 0 {
+            return None;
+        }
+        Some(format!(
+            "{}V @ {}A",
+            milli_str(self.pd_in_mv),
+            milli_str(self.pd_in_ma)
+        ))
+    }
+
+    /// Measured input power (`vbus_in × iin`) as a percentage of the
+    /// negotiated contract (`pd_in_mv × pd_in_ma`). `None` without a contract.
+    pub fn pd_load_pct(&self) -> Option<u32> {
+        let contract_uw = self.pd_in_mv as u64 * self.pd_in_ma as u64;
+        if contract_uw == 0 {
+            return None;
+        }
+        let input_uw = self.vbus_in_mv as u64 * self.iin_ma as u64;
+        Some((input_uw * 100 / contract_uw) as u32)
+    }
+}
+
+/// 15000 → "15", 1500 → "1.5", 3250 → "3.25": milli-units to a short decimal.
+fn milli_str(milli: u16) -> String {
+    let whole = milli / 1000;
+    let frac = milli % 1000;
+    if frac == 0 {
+        format!("{whole}")
+    } else {
+        let frac = format!("{frac:03}");
+        format!("{whole}.{}", frac.trim_end_matches('0'))
+    }
 }
 
 /// `power.cycle` REQ — `wups_power_cycle_v1_t`.
@@ -827,6 +911,25 @@ impl UiDisplayMsgV1 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn power2_flags_are_named() {
+        use power2_flag::*;
+        assert_eq!(describe(0), "none");
+        assert_eq!(
+            describe(DC_IN_EN | BATT_PRESENT | POWER_GOOD),
+            "dc-in battery power-good"
+        );
+        assert_eq!(describe(USB_C_ATTACH | 0x80), "usb-c 0x80");
+    }
+
+    #[test]
+    fn power_faults_are_named() {
+        use power_fault::*;
+        assert_eq!(describe(0), "none");
+        assert_eq!(describe(OVP | OTP), "ovp otp");
+        assert_eq!(describe(PD_NEG | 0x0700), "pd-neg 0x700");
+    }
+
     fn round_trip<T, F, G>(orig: T, encode: F, decode: G)
     where
         T: PartialEq + std::fmt::Debug,
@@ -1009,6 +1112,38 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn power_status_v2_pd_contract_str() {
+        let mut p = PowerStatusV2 {
+            pd_in_mv: 15_000,
+            pd_in_ma: 3_000,
+            ..Default::default()
+        };
+        assert_eq!(p.pd_contract_str().as_deref(), Some("15V @ 3A"));
+        (p.pd_in_mv, p.pd_in_ma) = (9_000, 1_500);
+        assert_eq!(p.pd_contract_str().as_deref(), Some("9V @ 1.5A"));
+        (p.pd_in_mv, p.pd_in_ma) = (20_000, 3_250);
+        assert_eq!(p.pd_contract_str().as_deref(), Some("20V @ 3.25A"));
+        p.pd_in_mv = 0;
+        assert_eq!(p.pd_contract_str(), None);
+    }
+
+    #[test]
+    fn power_status_v2_pd_load_pct() {
+        let mut p = PowerStatusV2 {
+            pd_in_mv: 15_000,
+            pd_in_ma: 3_000,
+            vbus_in_mv: 14_800,
+            iin_ma: 2_900,
+            ..Default::default()
+        };
+        assert_eq!(p.pd_load_pct(), Some(95));
+        p.iin_ma = 1_000;
+        assert_eq!(p.pd_load_pct(), Some(32));
+        p.pd_in_ma = 0;
+        assert_eq!(p.pd_load_pct(), None);
+    }
+
     #[test]
     fn power_cycle_round_trip() {
         round_trip(
@@ -0,0 +1,277 @@
+//! `w3p-ups replay FILE` — play a `probe --follow --json` recording back into
+//! the running daemon through the IPC `inject` op, so the status, web and
+//! shutdown paths can be exercised without pulling the plug. Needs
+//! `[debug].allow_inject`; every sample is marked synthetic like any other
+//! injected reading.
+//!
+//! Samples keep their recorded spacing (`unix_ts_ms` or `ts`), divided by
+//! `--speed`; unstamped ones are 1 s apart, the UPS's own rate. `--loop`
+//! starts over after the last sample until Ctrl-C. Lines that aren't single
+//! samples (e.g. `--every` aggregates) are skipped.
+
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{parse_rfc3339_utc, Endpoint, Injector};
+use crate::proto::payloads::PowerStatusV1;
+
+/// Spacing assumed between samples without a timestamp.
+const DEFAULT_GAP: Duration = Duration::from_secs(1);
+/// Each inject is held this much past the next one's due time, so the
+/// daemon never falls back to live data mid-replay.
+const HOLD_SLACK_S: u64 = 2;
+/// Accepted `--speed`: past these a replay is either frozen or a blur.
+const SPEED_RANGE: RangeInclusive<f64> = 0.001..=10_000.0;
+
+/// The `power.status` fields of a recorded sample, as `inject` takes them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct RecordedPower {
+    charge_state: u8,
+    vbus_in_mv: u16,
+    vbus_out_mv: u16,
+    ibus_out_ma: i16,
+    vbat_mv: u16,
+    ibat_ma: i16,
+    temp_dc: i16,
+    pd_contract_mv: u16,
+    pd_contract_ma: u16,
+    faults: u16,
+}
+
+impl RecordedPower {
+    pub(crate) fn to_status(&self) -> PowerStatusV1 {
+        PowerStatusV1 {
+            charge_state: self.charge_state,
+            vbus_in_mv: self.vbus_in_mv,
+            vbus_out_mv: self.vbus_out_mv,
+            ibus_out_ma: self.ibus_out_ma,
+            vbat_mv: self.vbat_mv,
+            ibat_ma: self.ibat_ma,
+            temp_dc: self.temp_dc,
+            pd_contract_mv: self.pd_contract_mv,
+            pd_contract_ma: self.pd_contract_ma,
+            faults: self.faults,
+        }
+    }
+}
+
+/// Body of an `{"op":"inject",…}` request.
+#[derive(Debug, Serialize)]
+pub(crate) struct InjectMsg {
+    pub(crate) data: RecordedPower,
+    pub(crate) hold_s: u64,
+    pub(crate) exercise_shutdown: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    pub(crate) at_ms: Option<u64>,
+    pub(crate) power: RecordedPower,
+    /// As `probe` classified it when recording.
+    pub(crate) on_battery: Option<bool>,
+}
+
+/// One recorded sample, or `None` for anything else (aggregates, blank
+/// lines, junk). A sample has at least `vbat_mv` as a number.
+fn parse_line(line: &str) -> Option<Sample> {
+    let v: serde_json::Value = serde_json::from_str(line).ok()?;
+    v.get("vbat_mv")?.as_u64()?;
+    let power = RecordedPower::deserialize(&v).ok()?;
+    let at_ms = v["unix_ts_ms"]
+        .as_u64()
+        .or_else(|| v["ts"].as_str().and_then(parse_rfc3339_utc));
+    Some(Sample {
+        at_ms,
+        power,
+        on_battery: v["on_battery"].as_bool(),
+    })
+}
+
+/// Every sample in the recording at `path`, and how many lines weren't one.
+pub(crate) fn read_recording(path: &Path) -> Result<(Vec<Sample>, usize)> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("read recording {}", path.display()))?;
+    let mut skipped = 0;
+    let samples: Vec<Sample> = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|l| {
+            let s = parse_line(l);
+            skipped += usize::from(s.is_none());
+            s
+        })
+        .collect();
+    if samples.is_empty() {
+        bail!(
+            "no samples in {} (record one with `w3p-ups probe --follow --json`)",
+            path.display()
+        );
+    }
+    Ok((samples, skipped))
+}
+
+/// Wait before sample `i`: the recorded gap to the previous one (1 s if
+/// either lacks a stamp or they're out of order), divided by `speed`.
+/// Sample 0 plays at once.
+pub(crate) fn gaps(samples: &[Sample], speed: f64) -> Vec<Duration> {
+    let mut out = Vec::with_capacity(samples.len());
+    out.push(Duration::ZERO);
+    for w in samples.windows(2) {
+        let gap = match (w[0].at_ms, w[1].at_ms) {
+            (Some(a), Some(b)) if b >= a => Duration::from_millis(b - a),
+            _ => DEFAULT_GAP,
+        };
+        out.push(scaled(gap, speed));
+    }
+    out
+}
+
+/// `gap / speed`, saturating instead of panicking on a huge recorded gap.
+fn scaled(gap: Duration, speed: f64) -> Duration {
+    Duration::try_from_secs_f64(gap.as_secs_f64() / speed).unwrap_or(Duration::MAX)
+}
+
+pub async fn run_replay(
+    ep: &Endpoint,
+    path: &Path,
+    speed: f64,
+    looping: bool,
+    exercise_shutdown: bool,
+) -> Result<()> {
+    if !SPEED_RANGE.contains(&speed) {
+        bail!(
+            "--speed must be {}–{}, got {speed}",
+            SPEED_RANGE.start(),
+            SPEED_RANGE.end()
+        );
+    }
+    let (samples, skipped) = read_recording(path)?;
+    let gaps = gaps(&samples, speed);
+    let total = gaps
+        .iter()
+        .fold(Duration::ZERO, |t, g| t.saturating_add(*g));
+    eprintln!(
+        "replaying {} samples from {} at {speed}x ({} s per pass){}",
+        samples.len(),
+        path.display(),
+        total.as_secs(),
+        if skipped > 0 {
+            format!(", {skipped} non-sample lines skipped")
+        } else {
+            String::new()
+        }
+    );
+
+    let mut injector = Injector::connect(ep).await?;
+    let mut pass = 1u64;
+    let run = async {
+        loop {
+            for (i, sample) in samples.iter().enumerate() {
+                // Back to the start of the recording after a pass: 1 s on.
+                let wait = match (i, pass) {
+                    (0, 1) => Duration::ZERO,
+                    (0, _) => scaled(DEFAULT_GAP, speed),
+                    _ => gaps[i],
+                };
+                tokio::time::sleep(wait).await;
+                let next = gaps.get(i + 1).copied().unwrap_or(DEFAULT_GAP);
+                let msg = InjectMsg {
+                    data: sample.power.clone(),
+                    // A gap over the daemon's cap lets live data show through.
+                    hold_s: next
+                        .as_secs()
+                        .saturating_add(HOLD_SLACK_S)
+                        .min(crate::ipc::MAX_INJECT_HOLD_S),
+                    exercise_shutdown,
+                };
+                injector.inject(msg).await?;
+            }
+            if !looping {
+                return Ok::<_, anyhow::Error>(());
+            }
+            pass += 1;
+            eprintln!("replay: pass {pass}");
+        }
+    };
+    tokio::select! {
+        res = run => res?,
+        _ = tokio::signal::ctrl_c() => eprintln!(),
+    }
+    eprintln!("replay done; the daemon returns to live data once the last sample's hold runs out");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_probe_json_lines() {
+        let s = parse_line(
+            r#"{"unix_ts_ms":1791964800123,"vbus_in_mv":0,"vbus_out_mv":5100,"ibus_out_ma":900,"vbat_mv":7100,"ibat_ma":-1200,"soc_pct":30,"on_battery":true,"charge_state":0,"temp_dc":312,"faults":0}"#,
+        )
+        .unwrap();
+        assert_eq!(s.at_ms, Some(1_791_964_800_123));
+        assert_eq!((s.power.vbat_mv, s.power.ibat_ma), (7_100, -1_200));
+
+        let s = parse_line(r#"{"ts":"2026-10-14T08:00:01.123Z","vbat_mv":7000}"#).unwrap();
+        assert_eq!(s.at_ms, Some(1_791_964_801_123));
+
+        // An `--every` aggregate, and lines without a pack voltage.
+        assert_eq!(
+            parse_line(r#"{"unix_ts_ms":1,"window_s":10,"vbat_mv":{"min":1,"max":2,"avg":1.5}}"#),
+            None
+        );
+        assert_eq!(parse_line(r#"{"vbus_in_mv":5000}"#), None);
+        assert_eq!(parse_line("probing /dev/ttyACM0"), None);
+    }
+
+    #[test]
+    fn gaps_follow_the_recording_scaled_by_speed() {
+        let at = |ms: Option<u64>| Sample {
+            at_ms: ms,
+            power: RecordedPower::default(),
+            on_battery: None,
+        };
+        let samples = [
+            at(Some(10_000)),
+            at(Some(12_000)),
+            at(None),
+            at(Some(20_000)),
+            at(Some(19_000)),
+        ];
+        let ms = Duration::from_millis;
+        assert_eq!(
+            gaps(&samples, 1.0),
+            [ms(0), ms(2_000), ms(1_000), ms(1_000), ms(1_000)]
+        );
+        assert_eq!(
+            gaps(&samples, 100.0),
+            [ms(0), ms(20), ms(10), ms(10), ms(10)]
+        );
+        assert_eq!(gaps(&samples[..2], 0.5)[1], ms(4_000));
+
+        // A recording spanning the whole clock, at the slowest speed.
+        let far = [at(Some(0)), at(Some(u64::MAX))];
+        assert_eq!(gaps(&far, *SPEED_RANGE.start())[1], Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn speed_out_of_range_is_refused() {
+        let ep = Endpoint::Unix("/nonexistent/w3p-ups.sock".into());
+        for speed in [0.0, 0.0001, 1e6, f64::NAN, f64::INFINITY] {
+            let err = run_replay(&ep, Path::new("/nonexistent"), speed, false, false)
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().starts_with("--speed must be 0.001–10000"),
+                "{err}"
+            );
+        }
+    }
+}
@@ -2,16 +2,19 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 use tokio::time::interval;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::config::{BatteryConfig, ShutdownConfig};
-use crate::proto::payloads::{host_event, host_shutdown_reason, HostEventV1, HostShutdownV1};
+use crate::config::{BatteryConfig, ShutdownConfig, SigtermPolicy};
+use crate::events::{EventHandlers, PowerContext};
+use crate::proto::payloads::{
+    host_event, host_shutdown_reason, power2_flag, HostEventV1, HostShutdownV1, PowerStatusV1,
+    PowerStatusV2,
+};
 use crate::proto::{addr, class, flag, op, Frame};
-use crate::soc::pack_mv_to_soc_pct;
-use crate::state::State;
+use crate::state::{AgentState, State};
 use crate::transport::OutboundFrame;
 
 /// Whether the input voltage indicates we are running on battery.
@@ -19,24 +22,80 @@ pub fn is_on_battery(vbus_in_mv: u16, min: u16, max: u16) -> bool {
     vbus_in_mv < min || vbus_in_mv > max
 }
 
+/// What the input reading means once cross-checked against other signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputReading {
+    Grid,
+    Battery,
+    /// `vbus_in_mv == 0` but power-good is asserted (v2) or the battery
+    /// isn't discharging (v1): most likely the sense line, not an outage.
+    /// Treated as grid.
+    Glitch,
+}
+
+impl InputReading {
+    pub fn on_battery(self) -> bool {
+        self == Self::Battery
+    }
+}
+
+/// `is_on_battery` plus, when `zero_cross_check` is on, the 0 mV sanity
+/// check. v2 `ichg_ma` is 0 on discharge, so only the power-good flag is
+/// trusted there; v1 uses the sign of `ibat_ma`.
+pub fn classify_input(
+    p: &PowerStatusV1,
+    v2: Option<&PowerStatusV2>,
+    min: u16,
+    max: u16,
+    zero_cross_check: bool,
+) -> InputReading {
+    if !is_on_battery(p.vbus_in_mv, min, max) {
+        return InputReading::Grid;
+    }
+    if zero_cross_check && p.vbus_in_mv == 0 {
+        let contradicted = match v2 {
+            Some(v2) => v2.flags & power2_flag::POWER_GOOD != 0,
+            None => p.ibat_ma >= 0,
+        };
+        if contradicted {
+            return InputReading::Glitch;
+        }
+    }
+    InputReading::Battery
+}
+
 /// 1 Hz tick: re-evaluate the low-battery shutdown decision.
 pub async fn shutdown_sm_loop(
     state: Arc<State>,
     battery: BatteryConfig,
     shutdown: ShutdownConfig,
     out_tx: mpsc::Sender<OutboundFrame>,
+    handlers: Arc<EventHandlers>,
 ) {
     info!(
         threshold_pct = battery.shutdown_threshold_pct,
         cancel_margin_pct = battery.shutdown_cancel_margin_pct,
         delay_s = shutdown.delay_seconds,
+        action = shutdown.action.systemctl_verb(),
         script = %shutdown.script_path,
         "shutdown SM running"
     );
+    let mut seen = Seen::default();
+    seen.flapping.unstable = state.snapshot().await.unstable_power;
+    // Resume a countdown armed before a reconnect / reload.
+    let mut ctl = ShutdownController::new(
+        Duration::from_secs(shutdown.delay_seconds),
+        state.snapshot().await.shutdown_pending_since,
+    )
+    .with_rearm_cooldown(Duration::from_secs(shutdown.rearm_cooldown_seconds));
     let mut tick = interval(Duration::from_secs(1));
     loop {
         tick.tick().await;
-        if step(&state, &battery, &shutdown, &out_tx).await {
+        if step(
+            &state, &battery, &shutdown, &out_tx, &handlers, &mut seen, &mut ctl,
+        )
+        .await
+        {
             // Shutdown initiated; block here so the supervisor doesn't
             // restart us before the system actually powers down.
             wait_forever().await;
@@ -44,79 +103,699 @@ pub async fn shutdown_sm_loop(
     }
 }
 
+/// Last observed conditions, so handlers hear about edges, not levels.
+#[derive(Debug, Default)]
+struct Seen {
+    on_batt: Option<bool>,
+    low: bool,
+    glitch: bool,
+    /// Held armed on grid by `[shutdown].require_recovery_soc`.
+    recovery_hold: bool,
+    /// Under `critical_threshold_pct` (see `step`).
+    below_floor: bool,
+    /// Output rail under `[shutdown].rail_critical_mv` for long enough.
+    rail_critical: bool,
+    rail: RailWatch,
+    /// Low again inside `[shutdown].rearm_cooldown_seconds`.
+    cooling_down: bool,
+    /// Low, but not armed inside `[shutdown].reload_grace_seconds`.
+    reload_grace: bool,
+    soc: SocFilter,
+    warmup: Warmup,
+    restore: GridRestore,
+    flapping: Flapping,
+}
+
+/// `[battery].grid_restore_seconds`: after an outage, input has to stay
+/// good that long before the SM believes it. Until then the sample counts
+/// as battery, so one good reading amid a flickering recovery neither
+/// cancels a countdown nor raises `on_grid`.
+#[derive(Debug, Default)]
+struct GridRestore {
+    /// When the input came back, while waiting it out.
+    since: Option<Instant>,
+}
+
+impl GridRestore {
+    /// Whether to treat this sample as on battery. `was_battery`: the last
+    /// verdict.
+    fn on_battery(
+        &mut self,
+        battery_now: bool,
+        was_battery: bool,
+        hold: Duration,
+        vbus_in_mv: u16,
+        now: Instant,
+    ) -> bool {
+        if battery_now {
+            if self.since.take().is_some() {
+                info!("input dropped again before it counted as restored");
+            }
+            return true;
+        }
+        if !was_battery {
+            return false;
+        }
+        let since = *self.since.get_or_insert_with(|| {
+            if !hold.is_zero() {
+                info!(
+                    vbus_in_mv,
+                    "input back; power counts as restored once it holds for {} s",
+                    hold.as_secs()
+                );
+            }
+            now
+        });
+        if now.saturating_duration_since(since) < hold {
+            return true;
+        }
+        self.since = None;
+        false
+    }
+}
+
+/// An unstable-power episode ([`Flapping`]), kept in the state so a
+/// reconnect neither ends it early nor leaves it open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnstablePower {
+    /// The first transition inside the window that made it unstable.
+    pub since: Instant,
+    /// Grid↔battery transitions since then, that one included.
+    pub transitions: u32,
+    /// The latest of them.
+    pub last: Instant,
+}
+
+/// `[battery].unstable_transitions`: flapping between grid and battery is
+/// reported to handlers as one episode rather than a pair of events per
+/// flap.
+#[derive(Debug, Default)]
+struct Flapping {
+    /// Recent transitions, within the window.
+    recent: std::collections::VecDeque<Instant>,
+    unstable: Option<UnstablePower>,
+}
+
+/// What handlers hear about one grid↔battery edge.
+#[derive(Debug, PartialEq)]
+enum Edge {
+    Report,
+    /// This edge made power unstable, after this many transitions.
+    Unstable(u32),
+    /// Already unstable; this is transition number `.0`.
+    Suppressed(u32),
+}
+
+impl Flapping {
+    /// `flip`: a real change of state, not the first verdict of a
+    /// connection (which a reconnect reports again and doesn't count).
+    fn edge(&mut self, flip: bool, max: u32, window: Duration, now: Instant) -> Edge {
+        if flip {
+            self.recent.push_back(now);
+            while self
+                .recent
+                .front()
+                .is_some_and(|&t| now.saturating_duration_since(t) > window)
+            {
+                self.recent.pop_front();
+            }
+        }
+        if let Some(u) = &mut self.unstable {
+            if flip {
+                u.transitions += 1;
+                u.last = now;
+            }
+            return Edge::Suppressed(u.transitions);
+        }
+        let n = self.recent.len() as u32;
+        if max == 0 || !flip || n < max {
+            return Edge::Report;
+        }
+        self.unstable = Some(UnstablePower {
+            since: self.recent[0],
+            transitions: n,
+            last: now,
+        });
+        Edge::Unstable(n)
+    }
+
+    /// The episode, once the input has gone a whole window without a
+    /// transition.
+    fn settled(&mut self, window: Duration, now: Instant) -> Option<UnstablePower> {
+        let u = self.unstable?;
+        if now.saturating_duration_since(u.last) < window {
+            return None;
+        }
+        self.recent.clear();
+        self.unstable.take()
+    }
+}
+
+/// Samples in a row (~3 s at 1 Hz) the output rail has to stay under
+/// `[shutdown].rail_critical_mv` before it shuts the host down: a one-sample
+/// dip from a load step or a noisy reading doesn't, a real brownout still
+/// does in time.
+const RAIL_CRITICAL_SAMPLES: u8 = 3;
+
+/// Counts consecutive samples under `[shutdown].rail_critical_mv`.
+#[derive(Debug, Default)]
+struct RailWatch {
+    low: u8,
+    /// `power_samples` of the last sample counted.
+    last: Option<u64>,
+}
+
+impl RailWatch {
+    /// Whether the rail has been low for [`RAIL_CRITICAL_SAMPLES`] samples,
+    /// sample number `sample` included. Re-reading a sample doesn't count.
+    fn sustained(&mut self, sample: u64, low: bool) -> bool {
+        if self.last != Some(sample) {
+            self.last = Some(sample);
+            self.low = if low { self.low.saturating_add(1) } else { 0 };
+        }
+        self.low >= RAIL_CRITICAL_SAMPLES
+    }
+}
+
+/// Pack voltages outside this can't come from a 2S Li-ion pack (2.5–4.5 V
+/// per cell): a garbled or placeholder reading, not a battery state.
+const PLAUSIBLE_VBAT_MV: std::ops::RangeInclusive<u16> = 5_000..=9_000;
+
+/// Connection warm-up: the decision logic stays off until
+/// `min_valid_samples` plausible samples in a row have come in, so noise
+/// right after the port opens can't arm a shutdown.
+#[derive(Debug, Default)]
+struct Warmup {
+    valid: u32,
+    last_sample: Option<u64>,
+    ready: bool,
+}
+
+impl Warmup {
+    /// Whether decisions may be made on sample number `sample`.
+    fn ready(&mut self, battery: &BatteryConfig, sample: u64, p: &PowerStatusV1) -> bool {
+        if battery.min_valid_samples == 0 {
+            return true;
+        }
+        if self.ready || self.last_sample == Some(sample) {
+            return self.ready;
+        }
+        self.last_sample = Some(sample);
+        if !PLAUSIBLE_VBAT_MV.contains(&p.vbat_mv) {
+            if self.valid > 0 {
+                debug!(
+                    vbat_mv = p.vbat_mv,
+                    "implausible sample during warm-up; restarting count"
+                );
+            }
+            self.valid = 0;
+            return false;
+        }
+        self.valid += 1;
+        if self.valid >= battery.min_valid_samples {
+            self.ready = true;
+            info!("decision logic armed after {} valid samples", self.valid);
+        }
+        self.ready
+    }
+}
+
+/// Holds back implausible single-sample SOC drops (the firmware briefly
+/// reports a near-empty pack during some mode transitions) until they either
+/// recover — rejected as a glitch — or persist for `soc_glitch_samples`.
+#[derive(Debug, Default)]
+struct SocFilter {
+    /// Last SOC passed on to the decision.
+    good: Option<u8>,
+    /// Lowest suspect SOC and how many samples it has lasted.
+    suspect: Option<(u8, u8)>,
+    /// `power_samples` of the last sample judged, and the verdict.
+    last: Option<(u64, Option<u8>)>,
+}
+
+impl SocFilter {
+    /// The SOC to decide on for sample number `sample`, or `None` while it
+    /// is held back. Re-reading the same sample returns the same verdict.
+    fn check(&mut self, battery: &BatteryConfig, sample: u64, soc: u8) -> Option<u8> {
+        match self.last {
+            Some((n, verdict)) if n == sample => return verdict,
+            _ => {}
+        }
+        let verdict = self.judge(battery, soc);
+        self.last = Some((sample, verdict));
+        verdict
+    }
+
+    fn judge(&mut self, battery: &BatteryConfig, soc: u8) -> Option<u8> {
+        let max_drop = battery.soc_glitch_drop_pct;
+        let plunged = |good: u8| max_drop > 0 && good.saturating_sub(soc) > max_drop;
+        match self.good {
+            Some(good) if plunged(good) => {
+                let (low, n) = self
+                    .suspect
+                    .map_or((soc, 1), |(low, n)| (low.min(soc), n + 1));
+                if n <= battery.soc_glitch_samples {
+                    self.suspect = Some((low, n));
+                    if n == 1 {
+                        warn!(
+                            from = good,
+                            to = soc,
+                            "implausible SOC drop; holding it back from the shutdown decision"
+                        );
+                    }
+                    return None;
+                }
+                warn!(
+                    from = good,
+                    to = soc,
+                    samples = n,
+                    "SOC drop persisted; accepting it"
+                );
+            }
+            _ => {
+                if let Some((low, n)) = self.suspect {
+                    warn!(
+                        low,
+                        recovered_to = soc,
+                        samples = n,
+                        "rejected SOC glitch: dropped to {low}% for {n} sample(s), then recovered"
+                    );
+                }
+            }
+        }
+        self.suspect = None;
+        self.good = Some(soc);
+        Some(soc)
+    }
+}
+
+/// What [`ShutdownController::on_sample`] decided for one sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShutdownDecision {
+    /// Nothing pending, nothing to do.
+    Idle,
+    /// Low on battery: the countdown starts now.
+    Arm,
+    /// Counting down; `remaining` until [`Self::Execute`].
+    Countdown { remaining: Duration },
+    /// Pending, no longer low, but not recovered past the hysteresis either.
+    Hold,
+    /// Low again, but a shutdown was cancelled less than the re-arm
+    /// cooldown ago; arming waits `remaining`.
+    Cooldown { remaining: Duration },
+    /// The full delay has elapsed while still low: shut down.
+    Execute,
+    /// Pending shutdown called off.
+    Cancel { restored: bool },
+}
+
+/// The delay / hysteresis timer, free of I/O so it can be driven with
+/// synthetic `Instant`s.
+#[derive(Debug)]
+pub(crate) struct ShutdownController {
+    delay: Duration,
+    armed_at: Option<Instant>,
+    rearm_cooldown: Duration,
+    cancelled_at: Option<Instant>,
+}
+
+impl ShutdownController {
+    pub(crate) fn new(delay: Duration, armed_at: Option<Instant>) -> Self {
+        Self {
+            delay,
+            armed_at,
+            rearm_cooldown: Duration::ZERO,
+            cancelled_at: None,
+        }
+    }
+
+    pub(crate) fn with_rearm_cooldown(mut self, cooldown: Duration) -> Self {
+        self.rearm_cooldown = cooldown;
+        self
+    }
+
+    pub(crate) fn armed_at(&self) -> Option<Instant> {
+        self.armed_at
+    }
+
+    /// `low`: critical SOC while on battery. `recovered`: the battery has
+    /// cleared the cancel hysteresis ([`BatteryConfig::battery_recovered`]).
+    pub(crate) fn on_sample(
+        &mut self,
+        on_battery: bool,
+        low: bool,
+        recovered: bool,
+        now: Instant,
+    ) -> ShutdownDecision {
+        match (self.armed_at, low) {
+            (None, true) => {
+                let since_cancel = self
+                    .cancelled_at
+                    .map(|at| now.saturating_duration_since(at));
+                match since_cancel {
+                    Some(since) if since < self.rearm_cooldown => ShutdownDecision::Cooldown {
+                        remaining: self.rearm_cooldown - since,
+                    },
+                    _ => {
+                        self.armed_at = Some(now);
+                        ShutdownDecision::Arm
+                    }
+                }
+            }
+            (Some(start), true) => {
+                let elapsed = now.saturating_duration_since(start);
+                if elapsed >= self.delay {
+                    ShutdownDecision::Execute
+                } else {
+                    ShutdownDecision::Countdown {
+                        remaining: self.delay - elapsed,
+                    }
+                }
+            }
+            (Some(_), false) if recovered || !on_battery => {
+                self.armed_at = None;
+                self.cancelled_at = Some(now);
+                ShutdownDecision::Cancel {
+                    restored: !on_battery,
+                }
+            }
+            (Some(_), false) => ShutdownDecision::Hold,
+            (None, false) => ShutdownDecision::Idle,
+        }
+    }
+
+    /// Skip the countdown: shut down on this sample.
+    pub(crate) fn execute_now(&mut self, now: Instant) -> ShutdownDecision {
+        self.armed_at.get_or_insert(now);
+        ShutdownDecision::Execute
+    }
+
+    /// Disarm without a cancel (after a stubbed execute).
+    pub(crate) fn reset(&mut self) {
+        self.armed_at = None;
+    }
+}
+
 /// One SM step. Returns `true` if shutdown was just initiated.
 async fn step(
     state: &State,
     battery: &BatteryConfig,
     shutdown: &ShutdownConfig,
     out_tx: &mpsc::Sender<OutboundFrame>,
+    handlers: &EventHandlers,
+    seen: &mut Seen,
+    ctl: &mut ShutdownController,
 ) -> bool {
-    let snap = state.snapshot().await;
+    let mut snap = state.snapshot().await;
+    // Injected data only reaches the SM when asked to, and then never powers
+    // the host down. Otherwise it keeps acting on the UPS's own readings.
+    let synthetic = snap.injected.is_some_and(|i| i.exercise_shutdown);
+    if synthetic {
+        snap = snap.client_view().into_owned();
+    }
     let Some(power) = snap.last_power else {
         return false;
     };
 
-    let soc = pack_mv_to_soc_pct(power.vbat_mv);
-    let on_batt = is_on_battery(
-        power.vbus_in_mv,
+    if !synthetic && !seen.warmup.ready(battery, snap.power_samples, &power) {
+        return false;
+    }
+    // A Pi browning out crashes whatever the SOC, so this outranks
+    // everything below, the SOC glitch filter included. Synthetic readings
+    // are deliberate and count at once; real ones have to last.
+    let rail_low = shutdown.rail_critical_mv > 0
+        && power.vbus_out_mv > 0
+        && power.vbus_out_mv < shutdown.rail_critical_mv;
+    let rail_critical = if synthetic {
+        rail_low
+    } else {
+        seen.rail.sustained(snap.power_samples, rail_low)
+    };
+    let raw_soc = battery.soc_pct(power.vbat_mv);
+    // Synthetic readings are deliberate; only real samples are de-glitched.
+    let soc = if synthetic {
+        raw_soc
+    } else {
+        match seen.soc.check(battery, snap.power_samples, raw_soc) {
+            Some(soc) => soc,
+            None if rail_critical => raw_soc,
+            None => return false,
+        }
+    };
+    let input = classify_input(
+        &power,
+        snap.last_power_v2.as_ref(),
         battery.input_min_valid_mv,
         battery.input_max_valid_mv,
+        battery.input_zero_cross_check,
+    );
+    let glitch = input == InputReading::Glitch;
+    if glitch && !seen.glitch {
+        warn!(
+            ibat_ma = power.ibat_ma,
+            power_good = snap.last_power_v2.map(|v2| v2.flags & power2_flag::POWER_GOOD != 0),
+            "input reads 0 mV but power looks present; likely a sense-line glitch, not arming shutdown"
+        );
+    } else if !glitch && seen.glitch {
+        info!(
+            vbus_in_mv = power.vbus_in_mv,
+            "input reading no longer contradicts itself"
+        );
+    }
+    seen.glitch = glitch;
+    let on_batt = seen.restore.on_battery(
+        input.on_battery(),
+        seen.on_batt == Some(true),
+        Duration::from_secs(battery.grid_restore_seconds),
+        power.vbus_in_mv,
+        state.now(),
     );
     let critical = soc < battery.shutdown_threshold_pct;
+    let ctx = PowerContext {
+        power,
+        soc_pct: soc,
+    };
 
-    match (snap.shutdown_pending_since, critical && on_batt) {
-        (None, true) => {
-            warn!(
+    // Real data only: an injected first sample says nothing about the boot.
+    if seen.on_batt.is_none() && !synthetic && state.claim_initial_state().await {
+        handlers.initial_state(&ctx, on_batt);
+    }
+    let unstable_window = Duration::from_secs(battery.unstable_window_seconds);
+    let before = seen.on_batt.replace(on_batt);
+    // A connection's first verdict is an edge only onto battery.
+    if before.map_or(on_batt, |was| was != on_batt) {
+        if !synthetic {
+            state.note_outage(on_batt).await;
+        }
+        let flip = before.is_some();
+        let now = state.now();
+        match seen
+            .flapping
+            .edge(flip, battery.unstable_transitions, unstable_window, now)
+        {
+            Edge::Report if on_batt => handlers.battery(&ctx),
+            Edge::Report => handlers.grid(&ctx),
+            Edge::Unstable(n) => handlers.unstable_power(&ctx, n),
+            Edge::Suppressed(n) if flip => info!(
+                transition = n,
                 soc,
-                vbat_mv = power.vbat_mv,
                 vbus_in_mv = power.vbus_in_mv,
-                "low battery on battery power; shutdown in {} s unless restored",
-                shutdown.delay_seconds
+                "unstable power: now on {}",
+                if on_batt { "battery" } else { "grid" }
+            ),
+            Edge::Suppressed(_) => {}
+        }
+    }
+    if let Some(u) = seen.flapping.settled(unstable_window, state.now()) {
+        handlers.power_stable(
+            &ctx,
+            on_batt,
+            u.transitions,
+            u.last.saturating_duration_since(u.since),
+        );
+    }
+    if seen.flapping.unstable != snap.unstable_power {
+        state.set_unstable_power(seen.flapping.unstable).await;
+    }
+    let low = critical && on_batt;
+    if low && !seen.low {
+        handlers.low_battery(&ctx);
+    }
+    seen.low = low;
+
+    // Safety floor: waiting any longer risks the pack cutting out. Applies on
+    // battery, and to a pending shutdown even if power has just returned.
+    // Fires on the edge; a stubbed (synthetic / dry-run) execute then falls
+    // back to the normal countdown.
+    let floor = battery.critical_threshold_pct;
+    let below_floor = floor > 0 && soc < floor && (on_batt || ctl.armed_at().is_some());
+    let critical_now = below_floor && !seen.below_floor;
+    seen.below_floor = below_floor;
+    // Same for the output rail, on grid too: a weak supply browns out the
+    // Pi just the same.
+    let rail_now = rail_critical && !seen.rail_critical;
+    seen.rail_critical = rail_critical;
+    if rail_now {
+        error!(
+            vbus_out_mv = power.vbus_out_mv,
+            rail_critical_mv = shutdown.rail_critical_mv,
+            soc,
+            on_batt,
+            synthetic,
+            "rail undervoltage — emergency shutdown"
+        );
+    } else if critical_now {
+        error!(
+            soc,
+            critical_threshold_pct = floor,
+            vbat_mv = power.vbat_mv,
+            on_batt,
+            synthetic,
+            "critical battery — immediate shutdown"
+        );
+    }
+
+    // Grid is back but the pack is nearly empty: stay armed until it has
+    // charged, so a second outage (or brownout under load) finds us ready.
+    let hold_for_recovery = !on_batt
+        && ctl.armed_at().is_some()
+        && shutdown.require_recovery_soc
+        && soc < shutdown.recovery_soc;
+    if hold_for_recovery && !seen.recovery_hold {
+        warn!(
+            soc,
+            recovery_soc = shutdown.recovery_soc,
+            "power restored but battery critically low; keeping shutdown armed until it recharges"
+        );
+    }
+    seen.recovery_hold = hold_for_recovery;
+    if hold_for_recovery && !critical_now && !rail_now {
+        return false;
+    }
+
+    // Just reloaded: a new threshold may have made the battery "low" at a
+    // stroke. Hold off arming until the operator has had a look.
+    let grace = Duration::from_secs(shutdown.reload_grace_seconds);
+    let since_reload = snap
+        .reloaded_at
+        .map(|at| state.now().saturating_duration_since(at));
+    let in_grace = since_reload.is_some_and(|since| since < grace);
+    if low && in_grace && ctl.armed_at().is_none() && !critical_now && !rail_now {
+        if !seen.reload_grace {
+            warn!(
+                soc,
+                threshold_pct = battery.shutdown_threshold_pct,
+                "battery low right after a config reload; not arming the shutdown for {} s \
+                 (reload_grace_seconds)",
+                (grace - since_reload.unwrap_or_default())
+                    .as_secs_f32()
+                    .ceil()
             );
-            state.set_shutdown_pending(Some(Instant::now())).await;
-            announce_shutdown_imminent(out_tx).await;
-            false
         }
-        (Some(start), true) => {
-            let elapsed = start.elapsed().as_secs();
-            if elapsed >= shutdown.delay_seconds {
-                warn!(
-                    soc,
-                    vbus_in_mv = power.vbus_in_mv,
-                    "delay elapsed; initiating shutdown"
-                );
-                trigger_shutdown(shutdown).await;
-                true
+        seen.reload_grace = true;
+        return false;
+    }
+    seen.reload_grace = false;
+
+    let recovered = battery.battery_recovered(soc, power.vbat_mv);
+    let decision = if critical_now || rail_now {
+        ctl.execute_now(state.now())
+    } else {
+        ctl.on_sample(on_batt, low, recovered, state.now())
+    };
+    let cooling_down = matches!(decision, ShutdownDecision::Cooldown { .. });
+    if let (ShutdownDecision::Cooldown { remaining }, false) = (decision, seen.cooling_down) {
+        warn!(
+            soc,
+            "battery low again right after a cancelled shutdown; not re-arming for {} s (rearm_cooldown_seconds)",
+            remaining.as_secs_f32().ceil()
+        );
+    } else if !cooling_down && seen.cooling_down && decision != ShutdownDecision::Arm {
+        info!(
+            soc,
+            "low-battery condition cleared during the re-arm cooldown"
+        );
+    }
+    seen.cooling_down = cooling_down;
+    match decision {
+        ShutdownDecision::Arm => {
+            handlers.shutdown_armed(&ctx, Duration::from_secs(shutdown.delay_seconds));
+            // Checked again now, while the delay still leaves time to act.
+            if !synthetic && !snap.dry_run {
+                for p in prerequisite_problems(shutdown) {
+                    error!("shutdown armed, but it may not work: {p}");
+                }
+            }
+            state.set_shutdown_pending(ctl.armed_at()).await;
+            if synthetic {
+                warn!("shutdown armed on SYNTHETIC data; not announcing to peers");
+            } else if snap.dry_run {
+                warn!("[dry-run] would announce the shutdown to the UPS and peers");
             } else {
-                let remaining = shutdown.delay_seconds - elapsed;
-                warn!(soc, "shutdown countdown: {remaining} s remaining");
-                false
+                announce_shutdown_imminent(out_tx).await;
             }
+            false
         }
-        (Some(_), false) => {
-            // Cancellation hysteresis: only cancel if power is truly back OR the
-            // battery has cleared the cancel margin above the threshold.
-            let cancel_threshold = battery
-                .shutdown_threshold_pct
-                .saturating_add(battery.shutdown_cancel_margin_pct);
-            let recovered = soc >= cancel_threshold;
-            let restored = !on_batt;
-            if recovered || restored {
-                info!(
-                    soc,
-                    on_batt,
-                    "shutdown cancelled ({})",
-                    if restored {
-                        "power restored"
-                    } else {
-                        "battery recovered"
-                    }
-                );
-                state.set_shutdown_pending(None).await;
+        ShutdownDecision::Execute if synthetic => {
+            warn!(
+                script = %shutdown.script_path,
+                action = shutdown.action.systemctl_verb(),
+                "SYNTHETIC data: shutdown stubbed — would shut down now"
+            );
+            ctl.reset();
+            state.set_shutdown_pending(None).await;
+            false
+        }
+        ShutdownDecision::Execute if snap.dry_run => {
+            warn!(
+                soc,
+                "[dry-run] would shut down now: run {} (action: {})",
+                shutdown.script_path,
+                shutdown.action.systemctl_verb()
+            );
+            // Start over, so a battery that stays low shows up once per delay.
+            ctl.reset();
+            state.set_shutdown_pending(None).await;
+            false
+        }
+        ShutdownDecision::Execute => {
+            handlers.shutdown(&ctx);
+            if execute_shutdown(state, shutdown).await {
+                return true;
             }
+            // Back from a sleep: watch the battery from scratch.
+            ctl.reset();
+            false
+        }
+        ShutdownDecision::Countdown { remaining } => {
+            // Round up so the last tick before execution reads "1 s".
+            let remaining = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            warn!(
+                soc,
+                synthetic, "shutdown countdown: {remaining} s remaining"
+            );
+            false
+        }
+        ShutdownDecision::Cancel { restored } => {
+            // Cancellation hysteresis: only cancel if power is truly back OR the
+            // battery has cleared the cancel margin (SOC and/or voltage).
+            info!(
+                soc,
+                vbat_mv = power.vbat_mv,
+                on_batt,
+                "shutdown cancelled ({})",
+                if restored {
+                    "power restored"
+                } else {
+                    "battery recovered"
+                }
+            );
+            state.set_shutdown_pending(None).await;
+            false
+        }
+        ShutdownDecision::Hold | ShutdownDecision::Idle | ShutdownDecision::Cooldown { .. } => {
             false
         }
-        (None, false) => false,
     }
 }
 
@@ -157,29 +836,428 @@ async fn announce_shutdown_imminent(out_tx: &mpsc::Sender<OutboundFrame>) {
     let _ = out_tx.send(OutboundFrame { frame }).await;
 }
 
-async fn trigger_shutdown(shutdown: &ShutdownConfig) {
-    let path = &shutdown.script_path;
-    if Path::new(path).exists() {
-        info!("executing shutdown script: {path}");
-        match Command::new("sh").arg(path).spawn() {
-            Ok(_child) => {}
-            Err(e) => {
-                error!("failed to spawn shutdown script: {e}");
-                fallback_shutdown().await;
+/// How the configured shutdown script gets started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ScriptLaunch {
+    /// Executable: run as is, so a shebang or a binary works.
+    Direct,
+    /// Not executable but a shell script: `sh <path>`.
+    Shell,
+    /// Neither; `systemctl <action>` runs instead. The reason, for the log.
+    Unusable(String),
+    Missing,
+}
+
+pub(crate) fn script_launch(path: &Path) -> ScriptLaunch {
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = match std::fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return ScriptLaunch::Missing,
+        Err(e) => return ScriptLaunch::Unusable(format!("can't stat it: {e}")),
+    };
+    if !meta.is_file() {
+        return ScriptLaunch::Unusable("not a regular file".into());
+    }
+    if meta.permissions().mode() & 0o111 != 0 {
+        return ScriptLaunch::Direct;
+    }
+    let mut head = Vec::with_capacity(128);
+    let read = std::fs::File::open(path).and_then(|f| f.take(128).read_to_end(&mut head));
+    if let Err(e) = read {
+        return ScriptLaunch::Unusable(format!("can't read it: {e}"));
+    }
+    if head.contains(&0) {
+        return ScriptLaunch::Unusable("a binary without the execute bit".into());
+    }
+    // `#!/bin/bash`, `#!/usr/bin/env sh`, … — or no shebang at all, which
+    // `sh` reads as a shell script.
+    let line = String::from_utf8_lossy(&head);
+    let line = line.lines().next().unwrap_or_default();
+    match line.strip_prefix("#!") {
+        None => ScriptLaunch::Shell,
+        Some(interp) => {
+            let prog = interp.split_whitespace().last().unwrap_or_default();
+            let prog = prog.rsplit('/').next().unwrap_or_default();
+            if prog.ends_with("sh") {
+                ScriptLaunch::Shell
+            } else {
+                ScriptLaunch::Unusable(format!("its `#!{}` needs the execute bit", interp.trim()))
             }
         }
+    }
+}
+
+/// Startup / reload check, so a script that can't run shows up now rather
+/// than at the moment power runs out. Returns the
+/// [`prerequisite_problems`], each already logged.
+pub(crate) fn check_script(shutdown: &ShutdownConfig) -> Vec<String> {
+    let path = &shutdown.script_path;
+    let verb = shutdown.action.systemctl_verb();
+    match script_launch(Path::new(path)) {
+        ScriptLaunch::Direct => debug!("shutdown script {path} is executable; will run it directly"),
+        ScriptLaunch::Shell => debug!("shutdown script {path} will run via `sh`"),
+        ScriptLaunch::Unusable(why) => warn!(
+            "shutdown script {path} can't be run ({why}); `systemctl {verb}` would be used instead. Fix with `chmod +x {path}`"
+        ),
+        ScriptLaunch::Missing => {
+            warn!("shutdown script {path} not found; `systemctl {verb}` would be used instead")
+        }
+    }
+    let problems = prerequisite_problems(shutdown);
+    for p in &problems {
+        warn!("SHUTDOWN MAY NOT WORK: {p}");
+    }
+    problems
+}
+
+/// What would stop the shutdown once it runs, beyond the script's own
+/// mode (which falls back to `systemctl`): a missing interpreter, no
+/// `systemctl`, or no privilege to power the host off (nor a helper to
+/// get it).
+pub(crate) fn prerequisite_problems(shutdown: &ShutdownConfig) -> Vec<String> {
+    let path = Path::new(&shutdown.script_path);
+    let verb = shutdown.action.systemctl_verb();
+    let mut problems = Vec::new();
+    if let Err(e) = shutdown.action.check_supported() {
+        problems.push(format!("[shutdown].action = \"{verb}\": {e:#}"));
+    }
+    match script_launch(path) {
+        ScriptLaunch::Direct => problems.extend(interpreter_problem(path)),
+        ScriptLaunch::Shell if !on_path("sh") => {
+            problems.push(format!("`sh` for {} is not on PATH", path.display()))
+        }
+        _ => {}
+    }
+    if !on_path("systemctl") {
+        problems.push(format!(
+            "`systemctl` is not on PATH, so `systemctl {verb}` (the fallback, and the stock script's last step) can't run"
+        ));
+    }
+    if let Some(helper) = shutdown.helper_command.first() {
+        // The helper brings the privilege; whether its sudo rule or setuid
+        // bit is right only shows when it runs.
+        let found = if helper.contains('/') {
+            Path::new(helper).is_file()
+        } else {
+            on_path(helper)
+        };
+        if !found {
+            problems.push(format!("[shutdown].helper_command {helper} is not there"));
+        }
+        return problems;
+    }
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    let euid = unsafe { libc::geteuid() };
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    if euid != 0 && !has_cap_sys_boot(&status) {
+        problems.push(format!(
+            "running as uid {euid} without CAP_SYS_BOOT: `systemctl {verb}` is likely to be refused"
+        ));
+    }
+    problems
+}
+
+/// A `#!` interpreter that isn't there: `#!/bin/bash` on a system without
+/// bash, or a name `#!/usr/bin/env` can't find.
+fn interpreter_problem(script: &Path) -> Option<String> {
+    let text = std::fs::read(script).ok()?;
+    let head = String::from_utf8_lossy(&text[..text.len().min(128)]);
+    let mut words = head.lines().next()?.strip_prefix("#!")?.split_whitespace();
+    let interp = words.next()?;
+    let missing = if interp.rsplit('/').next() == Some("env") {
+        let prog = words.find(|w| !w.starts_with('-'))?;
+        (!on_path(prog)).then(|| format!("`{prog}` (via {interp}) is not on PATH"))
     } else {
-        warn!("shutdown script not found at {path}; falling back to `shutdown -h now`");
-        fallback_shutdown().await;
+        (!Path::new(interp).is_file()).then(|| format!("{interp} does not exist"))
+    };
+    missing.map(|m| format!("the interpreter of {}: {m}", script.display()))
+}
+
+fn on_path(prog: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(prog).is_file()))
+}
+
+/// `CapEff` in `/proc/self/status` includes `CAP_SYS_BOOT` (bit 22).
+fn has_cap_sys_boot(status: &str) -> bool {
+    const CAP_SYS_BOOT: u32 = 22;
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("CapEff:"))
+        .and_then(|hex| u64::from_str_radix(hex.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_SYS_BOOT) != 0)
+}
+
+/// Run `[shutdown].script_path` with the configured action, or `systemctl
+/// <action>` if it's missing or won't start. Shared by the low-battery path,
+/// the `host.shutdown` REQ handler and a stop during the countdown. Returns
+/// the started process, if any, for a caller that has to wait for it.
+pub(crate) async fn trigger_shutdown(shutdown: &ShutdownConfig) -> Option<Child> {
+    let path = &shutdown.script_path;
+    let verb = shutdown.action.systemctl_verb();
+    let mut cmd = match script_launch(Path::new(path)) {
+        ScriptLaunch::Direct => {
+            info!("executing shutdown script: {path} (action: {verb})");
+            privileged(shutdown, path, &[])
+        }
+        ScriptLaunch::Shell => {
+            info!("executing shutdown script: sh {path} (action: {verb})");
+            privileged(shutdown, "sh", &[path])
+        }
+        ScriptLaunch::Unusable(why) => {
+            error!(
+                "shutdown script {path} can't be run ({why}); falling back to `systemctl {verb}`"
+            );
+            return fallback_shutdown(shutdown, verb).await;
+        }
+        ScriptLaunch::Missing => {
+            warn!("shutdown script not found at {path}; falling back to `systemctl {verb}`");
+            return fallback_shutdown(shutdown, verb).await;
+        }
+    };
+    match cmd.env("W3P_UPS_SHUTDOWN_ACTION", verb).spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            error!("failed to spawn shutdown script: {e}");
+            fallback_shutdown(shutdown, verb).await
+        }
+    }
+}
+
+async fn fallback_shutdown(shutdown: &ShutdownConfig, verb: &str) -> Option<Child> {
+    privileged(shutdown, "systemctl", &[verb])
+        .spawn()
+        .map_err(|e| error!("fallback `systemctl {verb}` failed: {e}"))
+        .ok()
+}
+
+/// `program args…`, behind `[shutdown].helper_command` if one is set.
+/// Everything that needs root to act on the host goes through here.
+pub(crate) fn privileged(shutdown: &ShutdownConfig, program: &str, args: &[&str]) -> Command {
+    match shutdown.helper_command.split_first() {
+        Some((helper, helper_args)) => {
+            let mut cmd = Command::new(helper);
+            cmd.args(helper_args).arg(program).args(args);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
     }
 }
 
-async fn fallback_shutdown() {
-    if let Err(e) = Command::new("shutdown").args(["-h", "now"]).spawn() {
-        error!("fallback shutdown failed: {e}");
+/// `[shutdown].on_serial_loss_when_low`: how long the UPS has been silent
+/// and the SOC of its last sample, if that sample was on battery under
+/// `serial_loss_soc_pct` and the silence has lasted the timeout. Not while
+/// an injection exercises the SM, nor with a shutdown already under way.
+fn blind_and_low(
+    snap: &AgentState,
+    battery: &BatteryConfig,
+    shutdown: &ShutdownConfig,
+    now: Instant,
+) -> Option<(Duration, u8)> {
+    if snap.injected.is_some_and(|i| i.exercise_shutdown) || snap.shutdown_triggered {
+        return None;
+    }
+    let (p, at) = (snap.last_power?, snap.last_power_at?);
+    let silent = now.saturating_duration_since(at);
+    if silent < Duration::from_secs(shutdown.serial_loss_timeout_seconds) {
+        return None;
+    }
+    let soc = battery.soc_pct(p.vbat_mv);
+    let on_batt = classify_input(
+        &p,
+        snap.last_power_v2.as_ref(),
+        battery.input_min_valid_mv,
+        battery.input_max_valid_mv,
+        battery.input_zero_cross_check,
+    )
+    .on_battery();
+    (on_batt && soc < shutdown.serial_loss_soc_pct).then_some((silent, soc))
+}
+
+/// Watches for telemetry dying mid-outage. Runs for the daemon's lifetime,
+/// across reconnects: the SM stops with the serial link, which is exactly
+/// when this has to act. Idle unless `on_serial_loss_when_low` is set.
+pub(crate) async fn serial_loss_loop(
+    state: Arc<State>,
+    battery: BatteryConfig,
+    shutdown: ShutdownConfig,
+) {
+    if !shutdown.on_serial_loss_when_low {
+        return std::future::pending().await;
+    }
+    // `power_samples` of the sample a dry run last reported, so it's
+    // logged once per loss rather than every second.
+    let mut reported = None;
+    let mut tick = interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        let snap = state.snapshot().await;
+        let Some((silent, soc)) = blind_and_low(&snap, &battery, &shutdown, state.now()) else {
+            continue;
+        };
+        if reported == Some(snap.power_samples) {
+            continue;
+        }
+        reported = Some(snap.power_samples);
+        error!(
+            soc,
+            vbat_mv = snap.last_power.map(|p| p.vbat_mv),
+            serial_connected = snap.serial_connected,
+            "no UPS data for {} s, last seen on battery at {soc}%: protective shutdown \
+             (on_serial_loss_when_low)",
+            silent.as_secs()
+        );
+        if snap.dry_run {
+            warn!(
+                "[dry-run] would shut down now: run {} (action: {})",
+                shutdown.script_path,
+                shutdown.action.systemctl_verb()
+            );
+            continue;
+        }
+        if execute_shutdown(&state, &shutdown).await {
+            return std::future::pending().await;
+        }
+    }
+}
+
+/// A countdown armed before the serial link went down, once its delay is
+/// up: how long ago it was armed. The SM stops with the link, so nothing
+/// else would act on it until the UPS is back. Injected data and a
+/// shutdown already under way don't count.
+fn detached_countdown_due(snap: &AgentState, delay: Duration, now: Instant) -> Option<Duration> {
+    if snap.serial_connected || snap.injected.is_some() || snap.shutdown_triggered {
+        return None;
+    }
+    let armed = now.saturating_duration_since(snap.shutdown_pending_since?);
+    (armed >= delay).then_some(armed)
+}
+
+/// Keeps a pending countdown's deadline while the serial link is down.
+/// Runs for the daemon's lifetime, across reconnects: the decision was
+/// taken on real data, and losing the UPS can't have made the battery any
+/// better, so the shutdown runs on time rather than after the reconnect.
+/// Within a connection the SM's own 1 Hz tick does this, samples or not.
+pub(crate) async fn detached_countdown_loop(state: Arc<State>, shutdown: ShutdownConfig) {
+    let delay = Duration::from_secs(shutdown.delay_seconds);
+    let mut waiting = false;
+    let mut tick = interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        let snap = state.snapshot().await;
+        let detached = !snap.serial_connected && snap.shutdown_pending_since.is_some();
+        if detached && !waiting {
+            warn!("serial link lost during the shutdown countdown; it keeps running");
+        }
+        waiting = detached;
+        let Some(armed) = detached_countdown_due(&snap, delay, state.now()) else {
+            continue;
+        };
+        error!(
+            armed_s = armed.as_secs(),
+            "shutdown countdown ran out with no UPS data; shutting down"
+        );
+        if snap.dry_run {
+            warn!(
+                "[dry-run] would shut down now: run {} (action: {})",
+                shutdown.script_path,
+                shutdown.action.systemctl_verb()
+            );
+            // As the SM does: start over, so this shows once per delay.
+            state.set_shutdown_pending(None).await;
+            continue;
+        }
+        if execute_shutdown(&state, &shutdown).await {
+            return std::future::pending().await;
+        }
+    }
+}
+
+/// How long a stop that proceeds with the shutdown waits for the script:
+/// it runs in the service's cgroup, which systemd tears down once the
+/// daemon has exited. Under systemd's default 90 s stop timeout.
+const STOP_SCRIPT_WAIT: Duration = Duration::from_secs(60);
+
+/// The daemon is stopping on SIGTERM. If a shutdown countdown is armed,
+/// apply `[shutdown].on_sigterm_during_countdown`; either way it's logged
+/// loudly, since the host is otherwise left running on a low battery.
+pub(crate) async fn on_sigterm(state: &State, shutdown: &ShutdownConfig) {
+    let snap = state.snapshot().await;
+    let Some(since) = snap.shutdown_pending_since else {
+        return;
+    };
+    let armed_s = state.now().saturating_duration_since(since).as_secs();
+    match shutdown.on_sigterm_during_countdown {
+        SigtermPolicy::Abort => warn!(
+            armed_s,
+            "SIGTERM during the shutdown countdown: exiting WITHOUT shutting down; \
+             the host is unprotected until the daemon runs again \
+             (on_sigterm_during_countdown = \"proceed\" shuts down instead)"
+        ),
+        SigtermPolicy::Proceed if snap.injected.is_some() => warn!(
+            armed_s,
+            "SIGTERM during the shutdown countdown: SYNTHETIC data: shutdown stubbed — would shut down now"
+        ),
+        SigtermPolicy::Proceed if snap.dry_run => warn!(
+            armed_s,
+            "SIGTERM during the shutdown countdown: [dry-run] would shut down now: run {} (action: {})",
+            shutdown.script_path,
+            shutdown.action.systemctl_verb()
+        ),
+        SigtermPolicy::Proceed => {
+            warn!(
+                armed_s,
+                "SIGTERM during the shutdown countdown: shutting down now, before exiting"
+            );
+            state.set_shutdown_triggered().await;
+            let Some(mut child) = trigger_shutdown(shutdown).await else {
+                return;
+            };
+            match tokio::time::timeout(STOP_SCRIPT_WAIT, child.wait()).await {
+                Ok(Ok(status)) if !status.success() => warn!("shutdown script {status}"),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => warn!("waiting for the shutdown script: {e}"),
+                Err(_) => warn!(
+                    "shutdown script still running after {} s; exiting anyway",
+                    STOP_SCRIPT_WAIT.as_secs()
+                ),
+            }
+        }
     }
 }
 
+/// Run the shutdown for real. `true` once the host is powering off, with
+/// nothing left to do but wait. A sleep action (`suspend`, `hibernate`)
+/// instead returns `false` after the script or `systemctl` has exited,
+/// which is after the host has slept and woken up again. The shutdown is
+/// then cleared so that protection carries on, rather than being spent on
+/// the first suspend.
+pub(crate) async fn execute_shutdown(state: &State, shutdown: &ShutdownConfig) -> bool {
+    state.set_shutdown_triggered().await;
+    let child = trigger_shutdown(shutdown).await;
+    if !shutdown.action.is_sleep() {
+        return true;
+    }
+    let verb = shutdown.action.systemctl_verb();
+    match child.map(|mut c| async move { c.wait().await }) {
+        Some(wait) => match wait.await {
+            Ok(status) if !status.success() => warn!("{verb}: shutdown script {status}"),
+            Ok(_) => {}
+            Err(e) => warn!("{verb}: waiting for the shutdown script: {e}"),
+        },
+        None => warn!("{verb} could not be started"),
+    }
+    info!("back from {verb}; low-battery protection active again");
+    state.clear_shutdown().await;
+    false
+}
+
 async fn wait_forever() -> ! {
     loop {
         tokio::time::sleep(Duration::from_secs(60)).await;
@@ -188,7 +1266,945 @@ async fn wait_forever() -> ! {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
+
     use super::*;
+    use crate::config::{Config, ShutdownAction};
+    use crate::events::EventHandler;
+    use crate::proto::payloads::PowerStatusV1;
+    use crate::test_util::Scratch;
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<&'static str>>>);
+
+    impl EventHandler for Recorder {
+        fn on_initial_state(&self, _: &PowerContext, on_battery: bool) {
+            let state = if on_battery {
+                "boot-battery"
+            } else {
+                "boot-grid"
+            };
+            self.0.lock().unwrap().push(state);
+        }
+        fn on_battery(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("battery");
+        }
+        fn on_grid(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("grid");
+        }
+        fn on_low_battery(&self, _: &PowerContext) {
+            self.0.lock().unwrap().push("low");
+        }
+        fn on_shutdown_armed(&self, _: &PowerContext, _: Duration) {
+            self.0.lock().unwrap().push("armed");
+        }
+        fn on_unstable_power(&self, _: &PowerContext, _: u32) {
+            self.0.lock().unwrap().push("unstable");
+        }
+        fn on_power_stable(&self, _: &PowerContext, _: bool, _: u32, _: Duration) {
+            self.0.lock().unwrap().push("stable");
+        }
+    }
+
+    #[tokio::test]
+    async fn handlers_see_edges_only() {
+        let mut cfg = Config::default();
+        // Full to empty in one sample would be held back as a SOC glitch.
+        cfg.battery.soc_glitch_drop_pct = 0;
+        // This clock doesn't move: take grid at once.
+        cfg.battery.grid_restore_seconds = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 3600,
+            ..cfg.shutdown.clone()
+        };
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(log.clone()))]);
+        let state = State::new();
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::from_secs(shutdown.delay_seconds), None);
+
+        let samples = [
+            (12_000, 8_000), // grid, full: nothing
+            (0, 8_000),      // battery
+            (0, 8_000),      // still battery: nothing
+            (0, 6_000),      // low + armed
+            (0, 6_000),      // countdown: nothing new
+            (12_000, 6_000), // grid (cancels)
+        ];
+        for (vbus_in_mv, vbat_mv) in samples {
+            let p = PowerStatusV1 {
+                vbus_in_mv,
+                vbat_mv,
+                ibat_ma: if vbus_in_mv == 0 { -800 } else { 0 },
+                ..Default::default()
+            };
+            state.update_power(p).await;
+            let fired = step(
+                &state,
+                &cfg.battery,
+                &shutdown,
+                &out_tx,
+                &handlers,
+                &mut seen,
+                &mut ctl,
+            )
+            .await;
+            assert!(!fired);
+        }
+        // The warm-up holds back the first samples, so the SM starts out on
+        // battery.
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["boot-battery", "battery", "low", "armed", "grid"]
+        );
+        let snap = state.snapshot().await;
+        assert!(snap.shutdown_pending_since.is_none());
+        assert_eq!((snap.outages, snap.shutdown_triggered), (1, false));
+    }
+
+    #[tokio::test]
+    async fn boot_during_an_outage_is_reported_and_acted_on() {
+        let cfg = Config::default();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(log.clone()))]);
+        let state = State::new();
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let low = PowerStatusV1 {
+            vbus_in_mv: 0,
+            vbat_mv: cfg
+                .battery
+                .soc_curve()
+                .pack_mv(cfg.battery.shutdown_threshold_pct - 2),
+            ibat_ma: -800,
+            ..Default::default()
+        };
+        // Two connections (a reconnect in between): the second hears no
+        // initial state, only the battery edge its fresh view starts with.
+        for connection in 1..=2 {
+            let mut seen = Seen::default();
+            let mut ctl = ShutdownController::new(
+                Duration::from_secs(cfg.shutdown.delay_seconds),
+                state.snapshot().await.shutdown_pending_since,
+            );
+            for n in 1..=cfg.battery.min_valid_samples {
+                state.update_power(low).await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &cfg.shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+                // The very first sample past the warm-up arms the countdown,
+                // which then carries over the reconnect.
+                let armed = state.snapshot().await.shutdown_pending_since.is_some();
+                let want = connection == 2 || n == cfg.battery.min_valid_samples;
+                assert_eq!(armed, want, "connection {connection}, sample {n}");
+            }
+            state.set_serial_connected(false).await;
+        }
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["boot-battery", "battery", "low", "armed", "battery", "low"]
+        );
+        assert_eq!(state.snapshot().await.outages, 1);
+    }
+
+    #[tokio::test]
+    async fn grid_return_can_wait_for_recovery_soc() {
+        let mut cfg = Config::default();
+        cfg.battery.soc_glitch_drop_pct = 0;
+        cfg.battery.min_valid_samples = 0;
+        cfg.battery.grid_restore_seconds = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 3600,
+            require_recovery_soc: true,
+            recovery_soc: 30,
+            ..cfg.shutdown.clone()
+        };
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let state = State::new();
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::from_secs(shutdown.delay_seconds), None);
+
+        let samples = [
+            (0, 6_000, true),       // low on battery: armed
+            (12_000, 6_000, true),  // grid back, pack empty: still armed
+            (12_000, 6_900, true),  // charging, 16%: still armed
+            (12_000, 7_400, false), // 60%: cancelled
+        ];
+        for (vbus_in_mv, vbat_mv, pending) in samples {
+            let p = PowerStatusV1 {
+                vbus_in_mv,
+                vbat_mv,
+                ibat_ma: if vbus_in_mv == 0 { -800 } else { 500 },
+                ..Default::default()
+            };
+            state.update_power(p).await;
+            step(
+                &state,
+                &cfg.battery,
+                &shutdown,
+                &out_tx,
+                &handlers,
+                &mut seen,
+                &mut ctl,
+            )
+            .await;
+            let snap = state.snapshot().await;
+            assert_eq!(snap.shutdown_pending_since.is_some(), pending, "{vbat_mv}");
+        }
+    }
+
+    #[tokio::test]
+    async fn injected_low_battery_never_shuts_down() {
+        let cfg = Config::default();
+        let shutdown = ShutdownConfig {
+            delay_seconds: 0,
+            ..cfg.shutdown.clone()
+        };
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(log.clone()))]);
+        let state = State::new();
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::ZERO, None);
+        let low = PowerStatusV1 {
+            vbat_mv: 6_500,
+            ibat_ma: -800,
+            ..Default::default()
+        };
+
+        // Not opted in: the SM ignores it.
+        state
+            .inject_power(low, Duration::from_secs(60), false)
+            .await;
+        assert!(
+            !step(
+                &state,
+                &cfg.battery,
+                &shutdown,
+                &out_tx,
+                &handlers,
+                &mut seen,
+                &mut ctl
+            )
+            .await
+        );
+        assert!(log.lock().unwrap().is_empty());
+
+        // Opted in: arms and "fires", but stubbed and silent on the wire.
+        state.inject_power(low, Duration::from_secs(60), true).await;
+        for _ in 0..2 {
+            assert!(
+                !step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl
+                )
+                .await
+            );
+        }
+        assert_eq!(*log.lock().unwrap(), ["battery", "low", "armed"]);
+        assert!(out_rx.try_recv().is_err());
+        assert!(state.snapshot().await.shutdown_pending_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn real_samples_still_drive_the_sm_during_an_injection() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let state = State::new();
+        state.set_dry_run(true).await;
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl =
+            ShutdownController::new(Duration::from_secs(cfg.shutdown.delay_seconds), None);
+        // Clients are shown a full battery on grid…
+        let healthy = PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: 8_300,
+            ..Default::default()
+        };
+        state
+            .inject_power(healthy, Duration::from_secs(3600), false)
+            .await;
+        // …while the UPS reports a low one on battery.
+        state
+            .update_power(PowerStatusV1 {
+                vbat_mv: 6_500,
+                ibat_ma: -800,
+                ..Default::default()
+            })
+            .await;
+        step(
+            &state,
+            &cfg.battery,
+            &cfg.shutdown,
+            &out_tx,
+            &handlers,
+            &mut seen,
+            &mut ctl,
+        )
+        .await;
+        let snap = state.snapshot().await;
+        assert!(snap.shutdown_pending_since.is_some());
+        assert_eq!(snap.client_view().last_power, Some(healthy));
+    }
+
+    #[tokio::test]
+    async fn dry_run_logs_instead_of_shutting_down() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 0,
+            ..cfg.shutdown.clone()
+        };
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let state = State::new();
+        state.set_dry_run(true).await;
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::ZERO, None);
+        state
+            .update_power(PowerStatusV1 {
+                vbat_mv: 6_500,
+                ibat_ma: -800,
+                ..Default::default()
+            })
+            .await;
+        // Arm, then the elapsed delay: real data, but nothing leaves.
+        for _ in 0..2 {
+            assert!(
+                !step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl
+                )
+                .await
+            );
+        }
+        assert!(out_rx.try_recv().is_err());
+        assert!(state.snapshot().await.shutdown_pending_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn critical_floor_skips_the_countdown() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        cfg.battery.critical_threshold_pct = 3;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 3600,
+            ..cfg.shutdown.clone()
+        };
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        // Dry run, so "executed" shows as the pending shutdown being cleared.
+        let state = State::new();
+        state.set_dry_run(true).await;
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::from_secs(shutdown.delay_seconds), None);
+        macro_rules! feed {
+            ($vbat_mv:expr) => {{
+                state
+                    .update_power(PowerStatusV1 {
+                        vbat_mv: $vbat_mv,
+                        ibat_ma: -800,
+                        ..Default::default()
+                    })
+                    .await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+                state.snapshot().await.shutdown_pending_since.is_some()
+            }};
+        }
+
+        // 4%: below the shutdown threshold only, so a normal countdown.
+        assert!(feed!(6_600));
+        // 0%: straight to execute, with an hour of delay left.
+        assert!(!feed!(6_400));
+        // In a dry run the next sample falls back to the countdown.
+        assert!(feed!(6_400));
+    }
+
+    #[tokio::test]
+    async fn rail_undervoltage_skips_the_countdown() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 3600,
+            rail_critical_mv: 4_500,
+            ..cfg.shutdown.clone()
+        };
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let state = State::new();
+        state.set_dry_run(true).await;
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::from_secs(shutdown.delay_seconds), None);
+        macro_rules! feed {
+            ($vout_mv:expr) => {{
+                state
+                    .update_power(PowerStatusV1 {
+                        vbat_mv: 6_600,
+                        ibat_ma: -800,
+                        vbus_out_mv: $vout_mv,
+                        ..Default::default()
+                    })
+                    .await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+                state.snapshot().await.shutdown_pending_since.is_some()
+            }};
+        }
+
+        // Low SOC, healthy rail: the normal countdown. An unmeasured rail
+        // (0 mV) doesn't change that.
+        assert!(feed!(5_100));
+        assert!(feed!(0));
+        // A single dip, or two, is a load step, not a brownout.
+        assert!(feed!(4_400));
+        assert!(feed!(5_100));
+        assert!(feed!(4_400));
+        assert!(feed!(4_400));
+        assert!(feed!(5_100));
+        // Browning out: executed on the third low sample in a row (cleared,
+        // in a dry run)…
+        assert!(feed!(4_400));
+        assert!(feed!(4_400));
+        assert!(!feed!(4_400));
+        // …then back to the countdown while it stays low.
+        assert!(feed!(4_400));
+    }
+
+    #[tokio::test]
+    async fn protection_carries_on_after_a_suspend() {
+        let dir = Scratch::new("suspend");
+        let marker = dir.join("ran");
+        let script = dir.join("shutdown.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "echo \"$W3P_UPS_SHUTDOWN_ACTION\" >> {}\n",
+                marker.display()
+            ),
+        )
+        .unwrap();
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 0,
+            action: ShutdownAction::Suspend,
+            script_path: script.to_string_lossy().into_owned(),
+            ..cfg.shutdown.clone()
+        };
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let state = State::new();
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::ZERO, None);
+        let low = PowerStatusV1 {
+            vbat_mv: 6_600,
+            ibat_ma: -800,
+            ..Default::default()
+        };
+
+        macro_rules! feed {
+            () => {{
+                state.update_power(low).await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await
+            }};
+        }
+
+        for round in 1..=2 {
+            assert!(!feed!());
+            assert!(state.snapshot().await.shutdown_pending_since.is_some());
+            // Woken up again: not parked, nothing left triggered or armed,
+            // so the battery is still watched and suspends again.
+            assert!(!feed!());
+            let snap = state.snapshot().await;
+            assert!(!snap.shutdown_triggered);
+            assert_eq!(snap.shutdown_pending_since, None);
+            let ran = std::fs::read_to_string(&marker).unwrap();
+            assert_eq!(ran, "suspend\n".repeat(round));
+        }
+    }
+
+    #[tokio::test]
+    async fn step_counts_down_on_the_state_clock() {
+        let cfg = Config::default();
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl =
+            ShutdownController::new(Duration::from_secs(cfg.shutdown.delay_seconds), None);
+        let low = PowerStatusV1 {
+            vbat_mv: 6_500,
+            ibat_ma: -800,
+            ..Default::default()
+        };
+        // Synthetic so that reaching the deadline is stubbed, not a poweroff.
+        state
+            .inject_power(low, Duration::from_secs(3600), true)
+            .await;
+        macro_rules! step_now {
+            () => {
+                step(
+                    &state,
+                    &cfg.battery,
+                    &cfg.shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await
+            };
+        }
+
+        assert!(!step_now!());
+        let armed = state.snapshot().await.shutdown_pending_since;
+        assert_eq!(armed, Some(state.now()));
+        clock.advance(Duration::from_secs(cfg.shutdown.delay_seconds - 1));
+        assert!(!step_now!());
+        assert_eq!(state.snapshot().await.shutdown_pending_since, armed);
+        clock.advance(Duration::from_secs(1));
+        assert!(!step_now!());
+        // Deadline hit: the stub disarmed it.
+        assert_eq!(state.snapshot().await.shutdown_pending_since, None);
+    }
+
+    #[tokio::test]
+    async fn a_reload_holds_off_arming_for_the_grace_period() {
+        let mut cfg = Config::default();
+        cfg.shutdown.reload_grace_seconds = 30;
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl =
+            ShutdownController::new(Duration::from_secs(cfg.shutdown.delay_seconds), None);
+        let low = PowerStatusV1 {
+            vbat_mv: 6_500,
+            ibat_ma: -800,
+            ..Default::default()
+        };
+        state
+            .inject_power(low, Duration::from_secs(3600), true)
+            .await;
+        state.note_reload().await;
+        macro_rules! armed {
+            () => {{
+                step(
+                    &state,
+                    &cfg.battery,
+                    &cfg.shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+                state.snapshot().await.shutdown_pending_since.is_some()
+            }};
+        }
+
+        assert!(!armed!());
+        assert!(seen.reload_grace);
+        clock.advance(Duration::from_secs(29));
+        assert!(!armed!());
+        clock.advance(Duration::from_secs(1));
+        assert!(armed!());
+        // A countdown that is already running isn't held by a new reload.
+        state.note_reload().await;
+        assert!(armed!());
+    }
+
+    #[tokio::test]
+    async fn flapping_power_is_one_episode_for_handlers() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        cfg.battery.grid_restore_seconds = 0;
+        cfg.battery.unstable_transitions = 4;
+        cfg.battery.unstable_window_seconds = 60;
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(log.clone()))]);
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl =
+            ShutdownController::new(Duration::from_secs(cfg.shutdown.delay_seconds), None);
+        let sample = |on_battery: bool| PowerStatusV1 {
+            vbus_in_mv: if on_battery { 0 } else { 12_000 },
+            vbat_mv: 8_000,
+            ibat_ma: if on_battery { -800 } else { 0 },
+            ..Default::default()
+        };
+        macro_rules! step_on {
+            ($on_battery:expr) => {
+                clock.advance(Duration::from_secs(1));
+                state.update_power(sample($on_battery)).await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &cfg.shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+            };
+        }
+
+        for on_battery in [false, true, false, true, false, true] {
+            step_on!(on_battery);
+        }
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["boot-grid", "battery", "grid", "battery", "unstable"]
+        );
+        // A reconnect carries the episode on; its first verdict isn't a
+        // transition.
+        seen = Seen::default();
+        seen.flapping.unstable = state.snapshot().await.unstable_power;
+        step_on!(true);
+        step_on!(false);
+        assert_eq!(log.lock().unwrap().len(), 5);
+        assert_eq!(
+            state.snapshot().await.unstable_power.unwrap().transitions,
+            6
+        );
+
+        // Shutdown decisions never stopped following the input.
+        assert!(!state.snapshot().await.in_outage);
+        clock.advance(Duration::from_secs(59));
+        step_on!(false);
+        step_on!(true);
+        assert_eq!(log.lock().unwrap()[5..], ["stable", "battery"]);
+        assert_eq!(state.snapshot().await.unstable_power, None);
+    }
+
+    #[tokio::test]
+    async fn grid_must_hold_before_it_cancels() {
+        let mut cfg = Config::default();
+        cfg.battery.min_valid_samples = 0;
+        let shutdown = ShutdownConfig {
+            delay_seconds: 3600,
+            ..cfg.shutdown.clone()
+        };
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handlers = EventHandlers::with_builtin(vec![Box::new(Recorder(log.clone()))]);
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl = ShutdownController::new(Duration::from_secs(shutdown.delay_seconds), None);
+        macro_rules! feed {
+            ($vbus_in_mv:expr) => {{
+                state
+                    .update_power(PowerStatusV1 {
+                        vbus_in_mv: $vbus_in_mv,
+                        vbat_mv: 6_500,
+                        ibat_ma: if $vbus_in_mv == 0 { -800 } else { 300 },
+                        ..Default::default()
+                    })
+                    .await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await;
+                state.snapshot().await.shutdown_pending_since.is_some()
+            }};
+        }
+        let hold = cfg.battery.grid_restore_seconds;
+
+        assert!(feed!(0));
+        // One good sample amid the outage: still armed.
+        clock.advance(Duration::from_secs(1));
+        assert!(feed!(12_000));
+        clock.advance(Duration::from_secs(1));
+        assert!(feed!(0));
+        // Back for good: armed until it has held for the full time.
+        for _ in 0..hold {
+            clock.advance(Duration::from_secs(1));
+            assert!(feed!(12_000));
+        }
+        clock.advance(Duration::from_secs(1));
+        assert!(!feed!(12_000));
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["boot-battery", "battery", "low", "armed", "grid"]
+        );
+        assert_eq!(state.snapshot().await.outages, 1);
+    }
+
+    #[test]
+    fn soc_filter_rejects_blips_and_believes_persistent_drops() {
+        let battery = Config::default().battery;
+        let mut f = SocFilter::default();
+        assert_eq!(f.check(&battery, 1, 80), Some(80));
+        // 80 → 0 is held back, also when the same sample is re-read.
+        assert_eq!(f.check(&battery, 2, 0), None);
+        assert_eq!(f.check(&battery, 2, 0), None);
+        assert_eq!(f.check(&battery, 3, 79), Some(79));
+        assert_eq!(f.check(&battery, 3, 79), Some(79));
+        // A normal decline passes straight through.
+        assert_eq!(f.check(&battery, 4, 60), Some(60));
+
+        // Still down after `soc_glitch_samples`: genuine.
+        for n in 0..battery.soc_glitch_samples {
+            assert_eq!(f.check(&battery, 10 + n as u64, 5), None);
+        }
+        assert_eq!(f.check(&battery, 20, 5), Some(5));
+        assert_eq!(f.check(&battery, 21, 4), Some(4));
+
+        let mut off = battery.clone();
+        off.soc_glitch_drop_pct = 0;
+        let mut f = SocFilter::default();
+        assert_eq!(f.check(&off, 1, 80), Some(80));
+        assert_eq!(f.check(&off, 2, 0), Some(0));
+    }
+
+    #[test]
+    fn warmup_needs_consecutive_plausible_samples() {
+        let battery = Config::default().battery;
+        let mut w = Warmup::default();
+        let sample = |vbat_mv| PowerStatusV1 {
+            vbat_mv,
+            ..Default::default()
+        };
+        assert!(!w.ready(&battery, 1, &sample(7_400)));
+        assert!(!w.ready(&battery, 2, &sample(7_400)));
+        // Re-reading sample 2 doesn't count twice.
+        assert!(!w.ready(&battery, 2, &sample(7_400)));
+        // Garbage restarts the count.
+        assert!(!w.ready(&battery, 3, &sample(0)));
+        assert!(!w.ready(&battery, 4, &sample(7_400)));
+        assert!(!w.ready(&battery, 5, &sample(7_400)));
+        assert!(w.ready(&battery, 6, &sample(7_400)));
+        // Once armed it stays armed for the connection.
+        assert!(w.ready(&battery, 7, &sample(0)));
+
+        let mut eager = battery.clone();
+        eager.min_valid_samples = 0;
+        assert!(Warmup::default().ready(&eager, 1, &sample(7_400)));
+    }
+
+    #[tokio::test]
+    async fn soc_glitch_on_battery_does_not_arm() {
+        let cfg = Config::default();
+        let state = State::new();
+        let handlers = EventHandlers::with_builtin(Vec::new());
+        let (out_tx, _out_rx) = mpsc::channel(8);
+        let mut seen = Seen::default();
+        let mut ctl =
+            ShutdownController::new(Duration::from_secs(cfg.shutdown.delay_seconds), None);
+        let on_battery = |soc| PowerStatusV1 {
+            vbat_mv: crate::soc::soc_pct_to_pack_mv(soc),
+            ibat_ma: -800,
+            ..Default::default()
+        };
+        macro_rules! feed {
+            ($soc:expr) => {{
+                state.update_power(on_battery($soc)).await;
+                step(
+                    &state,
+                    &cfg.battery,
+                    &cfg.shutdown,
+                    &out_tx,
+                    &handlers,
+                    &mut seen,
+                    &mut ctl,
+                )
+                .await
+            }};
+        }
+
+        for _ in 0..cfg.battery.min_valid_samples {
+            assert!(!feed!(60));
+        }
+        assert!(!feed!(0));
+        assert!(state.snapshot().await.shutdown_pending_since.is_none());
+        assert!(!feed!(60));
+        assert!(state.snapshot().await.shutdown_pending_since.is_none());
+
+        for _ in 0..cfg.battery.soc_glitch_samples {
+            assert!(!feed!(5));
+        }
+        assert!(state.snapshot().await.shutdown_pending_since.is_none());
+        assert!(!feed!(5));
+        assert!(state.snapshot().await.shutdown_pending_since.is_some());
+    }
+
+    #[test]
+    fn executes_exactly_at_the_delay_boundary() {
+        let t0 = Instant::now();
+        let delay = Duration::from_secs(30);
+        let ms = Duration::from_millis;
+        let mut ctl = ShutdownController::new(delay, None);
+
+        assert_eq!(ctl.on_sample(true, true, false, t0), ShutdownDecision::Arm);
+        assert_eq!(ctl.armed_at(), Some(t0));
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + ms(1)),
+            ShutdownDecision::Countdown {
+                remaining: delay - ms(1)
+            }
+        );
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + delay - ms(1)),
+            ShutdownDecision::Countdown { remaining: ms(1) }
+        );
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + delay),
+            ShutdownDecision::Execute
+        );
+        // Re-arming later doesn't restart the clock.
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + delay + ms(500)),
+            ShutdownDecision::Execute
+        );
+    }
+
+    #[test]
+    fn cancel_and_hold_follow_hysteresis() {
+        let t0 = Instant::now();
+        let s = Duration::from_secs;
+        let mut ctl = ShutdownController::new(s(30), None);
+        assert_eq!(
+            ctl.on_sample(false, false, false, t0),
+            ShutdownDecision::Idle
+        );
+        assert_eq!(ctl.on_sample(true, true, false, t0), ShutdownDecision::Arm);
+
+        // Back above the threshold but inside the margin: keep the timer.
+        assert_eq!(
+            ctl.on_sample(true, false, false, t0 + s(5)),
+            ShutdownDecision::Hold
+        );
+        assert_eq!(ctl.armed_at(), Some(t0));
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(30)),
+            ShutdownDecision::Execute
+        );
+
+        assert_eq!(
+            ctl.on_sample(true, false, true, t0 + s(31)),
+            ShutdownDecision::Cancel { restored: false }
+        );
+        assert_eq!(ctl.armed_at(), None);
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(40)),
+            ShutdownDecision::Arm
+        );
+        assert_eq!(
+            ctl.on_sample(false, false, false, t0 + s(41)),
+            ShutdownDecision::Cancel { restored: true }
+        );
+    }
+
+    #[test]
+    fn rearm_waits_out_the_cooldown_after_a_cancel() {
+        let t0 = Instant::now();
+        let s = Duration::from_secs;
+        let mut ctl = ShutdownController::new(s(30), None).with_rearm_cooldown(s(60));
+        assert_eq!(ctl.on_sample(true, true, false, t0), ShutdownDecision::Arm);
+        assert_eq!(
+            ctl.on_sample(false, false, false, t0 + s(2)),
+            ShutdownDecision::Cancel { restored: true }
+        );
+
+        // Power flaps: out, back, out again, all inside the cooldown.
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(3)),
+            ShutdownDecision::Cooldown { remaining: s(59) }
+        );
+        assert_eq!(
+            ctl.on_sample(false, false, false, t0 + s(4)),
+            ShutdownDecision::Idle
+        );
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(50)),
+            ShutdownDecision::Cooldown { remaining: s(12) }
+        );
+        assert_eq!(ctl.armed_at(), None);
+
+        // Still low once it has passed: a fresh countdown, not a late execute.
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(62)),
+            ShutdownDecision::Arm
+        );
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0 + s(63)),
+            ShutdownDecision::Countdown { remaining: s(29) }
+        );
+        // The critical floor isn't held back.
+        let mut ctl = ShutdownController::new(s(30), None).with_rearm_cooldown(s(60));
+        ctl.on_sample(true, true, false, t0);
+        ctl.on_sample(false, false, false, t0 + s(1));
+        assert_eq!(ctl.execute_now(t0 + s(2)), ShutdownDecision::Execute);
+    }
+
+    #[test]
+    fn zero_delay_executes_on_the_next_sample() {
+        let t0 = Instant::now();
+        let mut ctl = ShutdownController::new(Duration::ZERO, None);
+        assert_eq!(ctl.on_sample(true, true, false, t0), ShutdownDecision::Arm);
+        assert_eq!(
+            ctl.on_sample(true, true, false, t0),
+            ShutdownDecision::Execute
+        );
+    }
 
     #[test]
     fn on_battery_below_min() {
@@ -204,4 +2220,260 @@ mod tests {
     fn on_grid_in_range() {
         assert!(!is_on_battery(12000, 8000, 26000));
     }
+
+    fn classify(vbus_in_mv: u16, ibat_ma: i16, v2_flags: Option<u8>, check: bool) -> InputReading {
+        let p = PowerStatusV1 {
+            vbus_in_mv,
+            ibat_ma,
+            ..Default::default()
+        };
+        let v2 = v2_flags.map(|flags| PowerStatusV2 {
+            flags,
+            ..Default::default()
+        });
+        classify_input(&p, v2.as_ref(), 8000, 26000, check)
+    }
+
+    #[test]
+    fn zero_input_while_discharging_is_an_outage() {
+        assert_eq!(classify(0, -900, None, true), InputReading::Battery);
+        assert_eq!(classify(0, 0, Some(0), true), InputReading::Battery);
+    }
+
+    #[test]
+    fn zero_input_contradicted_is_a_glitch() {
+        assert_eq!(classify(0, 0, None, true), InputReading::Glitch);
+        assert_eq!(classify(0, 120, None, true), InputReading::Glitch);
+        // v2: ichg is 0 on discharge, so only power-good counts.
+        assert_eq!(
+            classify(0, 0, Some(power2_flag::POWER_GOOD), true),
+            InputReading::Glitch
+        );
+        assert!(!InputReading::Glitch.on_battery());
+    }
+
+    #[test]
+    fn zero_cross_check_can_be_disabled_and_ignores_nonzero() {
+        assert_eq!(classify(0, 0, None, false), InputReading::Battery);
+        // Low but non-zero input is a real brown-out, not a glitch.
+        assert_eq!(classify(4000, 0, None, true), InputReading::Battery);
+        assert_eq!(classify(12000, -900, None, true), InputReading::Grid);
+    }
+
+    #[tokio::test]
+    async fn sigterm_mid_countdown_follows_the_policy() {
+        let dir = Scratch::new("sigterm");
+        let marker = dir.join("ran");
+        let script = dir.join("shutdown.sh");
+        std::fs::write(
+            &script,
+            format!("echo \"$W3P_UPS_SHUTDOWN_ACTION\" > {}\n", marker.display()),
+        )
+        .unwrap();
+        let mut shutdown = ShutdownConfig {
+            script_path: script.to_string_lossy().into_owned(),
+            ..Config::default().shutdown
+        };
+        let state = State::new();
+
+        // Nothing armed: nothing to do, whatever the policy.
+        shutdown.on_sigterm_during_countdown = SigtermPolicy::Proceed;
+        on_sigterm(&state, &shutdown).await;
+        assert!(!marker.exists());
+
+        state.set_shutdown_pending(Some(state.now())).await;
+        shutdown.on_sigterm_during_countdown = SigtermPolicy::Abort;
+        on_sigterm(&state, &shutdown).await;
+        assert!(!marker.exists());
+        assert!(!state.snapshot().await.shutdown_triggered);
+
+        // Proceed waits for the script before the daemon exits.
+        shutdown.on_sigterm_during_countdown = SigtermPolicy::Proceed;
+        on_sigterm(&state, &shutdown).await;
+        assert_eq!(std::fs::read_to_string(&marker).unwrap(), "poweroff\n");
+        assert!(state.snapshot().await.shutdown_triggered);
+    }
+
+    #[tokio::test]
+    async fn serial_loss_while_low_is_caught() {
+        let battery = Config::default().battery;
+        let shutdown = ShutdownConfig {
+            on_serial_loss_when_low: true,
+            ..Config::default().shutdown
+        };
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let sample = |vbus_in_mv, soc| PowerStatusV1 {
+            vbus_in_mv,
+            vbat_mv: battery.soc_curve().pack_mv(soc),
+            ibat_ma: if vbus_in_mv == 0 { -800 } else { 300 },
+            ..Default::default()
+        };
+        let timeout = Duration::from_secs(shutdown.serial_loss_timeout_seconds);
+        let check = |snap: &AgentState| blind_and_low(snap, &battery, &shutdown, state.now());
+
+        state.update_power(sample(0, 15)).await;
+        state.set_serial_connected(false).await;
+        clock.advance(timeout - Duration::from_secs(1));
+        assert_eq!(check(&state.snapshot().await), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(check(&state.snapshot().await), Some((timeout, 15)));
+
+        // Not low enough, or on grid: keep waiting for the link.
+        for (vbus_in_mv, soc) in [(0, 25), (12_000, 15)] {
+            state.update_power(sample(vbus_in_mv, soc)).await;
+            clock.advance(timeout * 10);
+            assert_eq!(check(&state.snapshot().await), None, "{vbus_in_mv} {soc}");
+        }
+        // Already shutting down: nothing more to do.
+        state.update_power(sample(0, 15)).await;
+        clock.advance(timeout);
+        state.set_shutdown_triggered().await;
+        assert_eq!(check(&state.snapshot().await), None);
+    }
+
+    #[tokio::test]
+    async fn a_countdown_runs_out_while_the_link_is_down() {
+        let clock = Arc::new(crate::clock::ManualClock::new());
+        let state = State::with_clock(clock.clone());
+        let delay = Duration::from_secs(60);
+        let due = |snap: &AgentState| detached_countdown_due(snap, delay, state.now());
+
+        // Armed on a real sample, then the samples stop with the link.
+        state.set_serial_connected(true).await;
+        state.set_shutdown_pending(Some(state.now())).await;
+        clock.advance(Duration::from_secs(20));
+        state.set_serial_connected(false).await;
+        assert_eq!(due(&state.snapshot().await), None);
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(due(&state.snapshot().await), Some(delay));
+
+        // Reconnected, the SM picks the same deadline up itself.
+        state.set_serial_connected(true).await;
+        assert_eq!(due(&state.snapshot().await), None);
+        // No countdown, or one already carried out: nothing to do.
+        state.set_serial_connected(false).await;
+        state.set_shutdown_triggered().await;
+        assert_eq!(due(&state.snapshot().await), None);
+        state.set_shutdown_pending(None).await;
+        assert_eq!(due(&AgentState::default()), None);
+    }
+
+    #[test]
+    fn missing_interpreters_and_privileges_are_found() {
+        let dir = Scratch::new("interp");
+        let script = |name: &str, body: &str| {
+            let p = dir.join(name);
+            std::fs::write(&p, body).unwrap();
+            p
+        };
+        assert_eq!(interpreter_problem(&script("sh", "#!/bin/sh\n")), None);
+        assert_eq!(
+            interpreter_problem(&script("none", "systemctl poweroff\n")),
+            None
+        );
+        let gone = interpreter_problem(&script("gone", "#!/opt/nowhere/bash -e\n")).unwrap();
+        assert!(gone.ends_with("/opt/nowhere/bash does not exist"), "{gone}");
+        let env = interpreter_problem(&script("env", "#!/usr/bin/env -S w3p-no-such-shell\n"));
+        assert!(env
+            .unwrap()
+            .contains("`w3p-no-such-shell` (via /usr/bin/env) is not on PATH"));
+
+        assert!(has_cap_sys_boot(
+            "Name:\tw3p-ups\nCapEff:\t0000000000400000\n"
+        ));
+        assert!(has_cap_sys_boot("CapEff:\t000001ffffffffff\n"));
+        assert!(!has_cap_sys_boot("CapEff:\t0000000000000000\n"));
+        assert!(!has_cap_sys_boot(""));
+    }
+
+    #[test]
+    fn helper_command_wraps_privileged_commands() {
+        let argv = |cmd: &Command| {
+            let cmd = cmd.as_std();
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let mut shutdown = Config::default().shutdown;
+        let direct = privileged(&shutdown, "systemctl", &["poweroff"]);
+        assert_eq!(argv(&direct), ["systemctl", "poweroff"]);
+
+        shutdown.helper_command = vec!["sudo".into(), "-n".into()];
+        let wrapped = privileged(&shutdown, "sh", &["/etc/w3p-ups/shutdown.sh"]);
+        assert_eq!(
+            argv(&wrapped),
+            ["sudo", "-n", "sh", "/etc/w3p-ups/shutdown.sh"]
+        );
+
+        // The helper stands in for root, but it has to exist.
+        shutdown.helper_command = vec!["/opt/nowhere/w3p-poweroff".into()];
+        let problems = prerequisite_problems(&shutdown);
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains("helper_command /opt/nowhere/w3p-poweroff is not there")),
+            "{problems:?}"
+        );
+        assert!(!problems.iter().any(|p| p.contains("CAP_SYS_BOOT")));
+    }
+
+    #[test]
+    fn an_unsupported_sleep_action_is_a_prerequisite_problem() {
+        for action in [ShutdownAction::Suspend, ShutdownAction::Hibernate] {
+            let shutdown = ShutdownConfig {
+                action,
+                ..Config::default().shutdown
+            };
+            let flagged = prerequisite_problems(&shutdown)
+                .iter()
+                .any(|p| p.starts_with("[shutdown].action"));
+            // Whatever this host's kernel supports, the two agree.
+            assert_eq!(flagged, action.check_supported().is_err(), "{action:?}");
+        }
+        let poweroff = prerequisite_problems(&Config::default().shutdown);
+        assert!(!poweroff.iter().any(|p| p.starts_with("[shutdown].action")));
+    }
+
+    #[test]
+    fn script_launch_follows_mode_and_shebang() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Scratch::new("script");
+        let file = |name: &str, body: &[u8], mode: u32| {
+            let p = dir.join(name);
+            std::fs::write(&p, body).unwrap();
+            std::fs::set_permissions(&p, std::fs::Permissions::from_mode(mode)).unwrap();
+            p
+        };
+
+        let exec = file("exec.py", b"#!/usr/bin/python3\n", 0o755);
+        assert_eq!(script_launch(&exec), ScriptLaunch::Direct);
+        for (name, body) in [
+            ("bash.sh", &b"#!/bin/bash\nsystemctl poweroff\n"[..]),
+            ("env.sh", b"#!/usr/bin/env sh\n"),
+            ("bare.sh", b"systemctl poweroff\n"),
+        ] {
+            assert_eq!(
+                script_launch(&file(name, body, 0o644)),
+                ScriptLaunch::Shell,
+                "{name}"
+            );
+        }
+        for (name, body) in [
+            ("py", &b"#!/usr/bin/python3\n"[..]),
+            ("elf", b"\x7fELF\x02\x01\x00"),
+        ] {
+            assert!(
+                matches!(
+                    script_launch(&file(name, body, 0o644)),
+                    ScriptLaunch::Unusable(_)
+                ),
+                "{name}"
+            );
+        }
+        assert_eq!(script_launch(&dir.join("nope")), ScriptLaunch::Missing);
+        assert!(matches!(script_launch(&dir), ScriptLaunch::Unusable(_)));
+    }
 }
@@ -9,6 +9,11 @@
 //!
 //! Keep this LUT in lockstep with the RP2040 firmware so the OLED and the
 //! agent agree on SOC.
+//!
+//! Other packs pick a curve with `[battery].chemistry` and `cell_count`
+//! ([`SocCurve`]); the free functions are the stock 2S curve.
+
+use crate::config::Chemistry;
 
 const LUT: &[(u16, u8)] = &[
     (4000, 100),
@@ -22,17 +27,106 @@ const LUT: &[(u16, u8)] = &[
     (3200, 0),
 ];
 
-/// Compute SOC% from 2S pack voltage (mV).
+/// Generic Li-ion (NMC/LCO) resting voltage, 4.20 V full, per cell.
+const LIION_LUT: &[(u16, u8)] = &[
+    (4200, 100),
+    (4150, 95),
+    (4110, 90),
+    (4080, 85),
+    (4020, 80),
+    (3980, 75),
+    (3950, 70),
+    (3910, 65),
+    (3870, 60),
+    (3850, 55),
+    (3840, 50),
+    (3820, 45),
+    (3800, 40),
+    (3790, 35),
+    (3770, 30),
+    (3750, 25),
+    (3730, 20),
+    (3710, 15),
+    (3690, 10),
+    (3610, 5),
+    (3270, 0),
+];
+
+/// LiFePO4 resting voltage per cell. Between ~20 % and ~90 % the curve is
+/// nearly flat (3.20–3.32 V), so there a few mV of sag or noise move the
+/// reading by several percent; only the two knees are sharp.
+const LIFEPO4_LUT: &[(u16, u8)] = &[
+    (3400, 100),
+    (3350, 99),
+    (3320, 90),
+    (3300, 80),
+    (3270, 70),
+    (3260, 60),
+    (3250, 50),
+    (3230, 40),
+    (3210, 30),
+    (3200, 20),
+    (3000, 10),
+    (2500, 0),
+];
+
+/// Pack voltage ↔ SOC for one chemistry and cell count (cells in series).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocCurve {
+    /// Per-cell (mV, SOC%), highest voltage first.
+    lut: &'static [(u16, u8)],
+    cells: u8,
+}
+
+impl SocCurve {
+    /// The stock Web3 Pi UPS pack (matches the firmware).
+    pub const STOCK: Self = Self { lut: LUT, cells: 2 };
+
+    /// `cells` is clamped to at least 1.
+    pub fn new(chemistry: Chemistry, cells: u8) -> Self {
+        let lut = match chemistry {
+            Chemistry::Stock => LUT,
+            Chemistry::Liion => LIION_LUT,
+            Chemistry::Lifepo4 => LIFEPO4_LUT,
+        };
+        Self {
+            lut,
+            cells: cells.max(1),
+        }
+    }
+
+    /// SOC% for a pack voltage (mV).
+    pub fn soc_pct(&self, vbat_mv: u16) -> u8 {
+        lookup(self.lut, vbat_mv / self.cells as u16)
+    }
+
+    /// Lowest pack voltage (mV) that [`Self::soc_pct`] reads as at least
+    /// `soc_pct`.
+    pub fn pack_mv(&self, soc_pct: u8) -> u16 {
+        let cells = self.cells as u16;
+        let top = self.lut[0].0 * cells;
+        let bottom = self.lut[self.lut.len() - 1].0 * cells;
+        (bottom..=top)
+            .step_by(cells as usize)
+            .find(|&mv| self.soc_pct(mv) >= soc_pct)
+            .unwrap_or(top)
+    }
+}
+
+/// Compute SOC% from 2S pack voltage (mV) on the stock curve.
 pub fn pack_mv_to_soc_pct(vbat_mv: u16) -> u8 {
-    let cell_mv = vbat_mv / 2;
-    if cell_mv >= LUT[0].0 {
-        return LUT[0].1;
+    SocCurve::STOCK.soc_pct(vbat_mv)
+}
+
+fn lookup(lut: &[(u16, u8)], cell_mv: u16) -> u8 {
+    if cell_mv >= lut[0].0 {
+        return lut[0].1;
     }
-    let last = LUT[LUT.len() - 1];
+    let last = lut[lut.len() - 1];
     if cell_mv <= last.0 {
         return last.1;
     }
-    for w in LUT.windows(2) {
+    for w in lut.windows(2) {
         let (v_hi, s_hi) = w[0]; // higher voltage, higher SOC
         let (v_lo, s_lo) = w[1];
         if cell_mv <= v_hi && cell_mv > v_lo {
@@ -46,6 +140,12 @@ pub fn pack_mv_to_soc_pct(vbat_mv: u16) -> u8 {
     0
 }
 
+/// Lowest 2S pack voltage (mV) that [`pack_mv_to_soc_pct`] reads as at least
+/// `soc_pct` — for synthesising a sample at a given SOC.
+pub fn soc_pct_to_pack_mv(soc_pct: u8) -> u16 {
+    SocCurve::STOCK.pack_mv(soc_pct)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +186,15 @@ mod tests {
         assert!((pct as i32 - 31).abs() <= 1, "expected ~31, got {pct}");
     }
 
+    #[test]
+    fn inverse_round_trips() {
+        for pct in 0..=100 {
+            let mv = soc_pct_to_pack_mv(pct);
+            assert_eq!(pack_mv_to_soc_pct(mv), pct, "{pct}% -> {mv} mV");
+        }
+        assert_eq!(soc_pct_to_pack_mv(150), 8000);
+    }
+
     #[test]
     fn monotonic_non_decreasing() {
         // Sanity: SOC never decreases as voltage increases through the table.
@@ -96,4 +205,27 @@ mod tests {
             last = s;
         }
     }
+
+    #[test]
+    fn chemistry_curves_scale_by_cell_count() {
+        let liion = SocCurve::new(Chemistry::Liion, 4);
+        assert_eq!(liion.soc_pct(16_800), 100); // 4.20 V/cell
+        assert_eq!(liion.soc_pct(4 * 3840), 50);
+        assert_eq!(liion.soc_pct(4 * 3270), 0);
+
+        let lfp = SocCurve::new(Chemistry::Lifepo4, 4);
+        assert_eq!(lfp.soc_pct(4 * 3400), 100);
+        assert_eq!(lfp.soc_pct(4 * 3250), 50);
+        // The knee below the plateau: 3.10 V/cell → 15 %.
+        assert_eq!(lfp.soc_pct(4 * 3100), 15);
+
+        // Unset chemistry is the stock curve.
+        assert_eq!(SocCurve::new(Chemistry::Stock, 2), SocCurve::STOCK);
+        for curve in [liion, lfp, SocCurve::new(Chemistry::Liion, 1)] {
+            for pct in 0..=100 {
+                let mv = curve.pack_mv(pct);
+                assert!(curve.soc_pct(mv) >= pct, "{curve:?} {pct}% -> {mv} mV");
+            }
+        }
+    }
 }
@@ -1,23 +1,101 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use tokio::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch, RwLock};
+use tracing::debug;
 
+use crate::cadence::SampleCadence;
+use crate::capacity::CapacityLog;
+use crate::clock::{Clock, SystemClock};
+use crate::config::BatteryConfig;
+use crate::health::HealthReport;
+use crate::histogram::InputHistogram;
 use crate::host_metrics::{HostMetricsSample, NetTotals};
-use crate::proto::payloads::{HostStatusV1, NetStatusV1, PowerStatusV1, SysHelloV1};
+use crate::proto::payloads::{
+    charge_state, power2_flag, power_fault, HostStatusV1, NetStatusV1, PowerStatusV1,
+    PowerStatusV2, SysHelloV1,
+};
+use crate::shutdown_sm::UnstablePower;
+use crate::transport::kv::{KvRejectCounts, KvRejects};
+use crate::transport::ReadStats;
 
 /// Snapshot of the most recent telemetry observed from each peer.
 #[derive(Debug, Default, Clone)]
 pub struct AgentState {
     pub last_power: Option<PowerStatusV1>,
     pub last_power_at: Option<Instant>,
+    /// Real power frames stored so far, so once-a-second readers can tell a
+    /// fresh sample from one they've already seen.
+    pub power_samples: u64,
+    /// The undown-converted frame when `last_power` came from a v2 status
+    /// (flags etc. that v1 can't carry); `None` after a v1 frame. Its
+    /// `POWER_GOOD` bit is the debounced one (see [`PowerGoodFilter`]), so
+    /// displays and fault detection don't flicker with it.
+    pub last_power_v2: Option<PowerStatusV2>,
+    /// `flags` of that frame as the firmware sent them, for debugging.
+    pub last_power_flags_raw: Option<u8>,
+    pub power_good: PowerGoodFilter,
     pub last_power_event: Option<u8>,
     pub last_power_event_at: Option<Instant>,
     pub last_net: Option<NetStatusV1>,
     pub last_net_at: Option<Instant>,
     pub peers: HashMap<u8, SysHelloV1>,
     pub shutdown_pending_since: Option<Instant>,
+    /// On grid but not charging for longer than the configured window
+    /// (set by `power_watch_loop`).
+    pub charging_fault: bool,
+    /// Full pack on grid with the charger topping it off, per
+    /// `[battery].maintenance_soc_pct` (set by `power_watch_loop`).
+    pub charge_maintaining: bool,
+    /// Input power near/over the negotiated PD contract for a while (set by
+    /// `power_watch_loop`).
+    pub pd_overload: bool,
+    /// The SOC hasn't recovered to `[battery].recovery_target_soc` in time
+    /// after being low (set by `power_watch_loop`).
+    pub recovery_overdue: bool,
+    /// Names of the faults event handlers have been told about and not yet
+    /// told are resolved (kept by `power_watch_loop` across reconnects).
+    pub notified_faults: Vec<String>,
+    /// Grid↔battery flapping is being reported as one episode (kept by
+    /// `shutdown_sm_loop` across reconnects).
+    pub unstable_power: Option<UnstablePower>,
+    /// When the config was last reloaded (`[shutdown].reload_grace_seconds`).
+    pub reloaded_at: Option<Instant>,
+    /// Serial transport is up. While false the `last_*` fields are whatever
+    /// was seen before the link dropped.
+    pub serial_connected: bool,
+    /// The port the serial link last opened, e.g. what `"auto"` found.
+    pub serial_port: Option<String>,
+    /// A synthetic reading (IPC `inject`) shown to clients in place of
+    /// `last_power`, which keeps tracking the UPS. Cleared by the first real
+    /// frame after the hold expires.
+    pub injected: Option<Injection>,
+    /// `[debug].dry_run`: side effects are logged, not performed.
+    pub dry_run: bool,
+    /// Outages seen on real data since the daemon started, counting one
+    /// already under way at startup.
+    pub outages: u64,
+    /// The last real transition was to battery: a reconnect mid-outage
+    /// doesn't count it twice.
+    pub in_outage: bool,
+    /// The shutdown SM ran the shutdown (script or `systemctl`).
+    pub shutdown_triggered: bool,
+    /// The SM has reported the power state it started in
+    /// ([`crate::events::EventHandler::on_initial_state`]).
+    pub initial_state_reported: bool,
+    /// `[ipc].max_sample_age_seconds`: older power samples are stale.
+    pub max_sample_age: Option<Duration>,
+    /// Inter-sample gaps and missed samples of real power frames.
+    pub cadence: SampleCadence,
+    /// Measured full↔empty spans (set by `capacity_loop`).
+    pub capacity: CapacityLog,
+    /// Set by `histogram_loop` when `[power_quality]` has buckets.
+    pub input_histogram: Option<InputHistogram>,
+    /// The latest power health score (set by `health_loop`).
+    pub health: Option<HealthReport>,
 
     // Host metrics — populated by `host_metrics_loop`. Only `last_host` is
     // emitted on the wire as `host.status`; the rest is local-only (IPC).
@@ -29,6 +107,151 @@ pub struct AgentState {
     pub net_tx_bytes_per_s: Option<u64>,
 }
 
+/// Outside this band (0.1 °C) the board sensor reading can't be real:
+/// a disconnected or failing sensor, not a hot or frozen UPS.
+pub(crate) const PLAUSIBLE_TEMP_DC: std::ops::RangeInclusive<i16> = -400..=1000;
+
+impl AgentState {
+    /// What clients are shown: an active injection stands in for the UPS's
+    /// reading, as if it were the latest frame. Everything that acts on the
+    /// battery reads `self` instead.
+    pub fn client_view(&self) -> std::borrow::Cow<'_, AgentState> {
+        let Some(i) = self.injected else {
+            return std::borrow::Cow::Borrowed(self);
+        };
+        let mut view = self.clone();
+        view.last_power = Some(i.status);
+        view.last_power_at = Some(i.at);
+        view.last_power_v2 = None;
+        view.last_power_flags_raw = None;
+        std::borrow::Cow::Owned(view)
+    }
+
+    /// One line naming everything that looks wrong with the UPS right now,
+    /// or `None` if it looks healthy:
+    ///
+    /// - `power-not-good-but-grid-ok`: v2 firmware clears power-good while
+    ///   the input sits inside `[battery].input_{min,max}_valid_mv`.
+    /// - `charging-fault`: the charger reports a fault, or
+    ///   [`AgentState::charging_fault`] is raised.
+    /// - `recovery-overdue`: [`AgentState::recovery_overdue`] is raised.
+    /// - `implausible-temp`: the board temperature is outside -40..100 °C.
+    /// - `rail-undervoltage (…)`: the output to the Pi is below
+    ///   `[battery].rail_min_mv`.
+    /// - `battery-absent`: v2 firmware sees no pack.
+    /// - `firmware-fault (…)`: any `faults` bit, by name.
+    pub fn fault_summary(&self, battery: &BatteryConfig) -> Option<String> {
+        let found = self.faults(battery);
+        (!found.is_empty()).then(|| found.join(", "))
+    }
+
+    /// The findings of [`Self::fault_summary`], one per entry.
+    pub fn faults(&self, battery: &BatteryConfig) -> Vec<String> {
+        let Some(p) = self.last_power else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        if let Some(v2) = self.last_power_v2 {
+            let grid_ok =
+                (battery.input_min_valid_mv..=battery.input_max_valid_mv).contains(&p.vbus_in_mv);
+            if grid_ok && v2.flags & power2_flag::POWER_GOOD == 0 {
+                found.push("power-not-good-but-grid-ok".to_string());
+            }
+        }
+        if self.charging_fault || p.charge_state == charge_state::FAULT {
+            found.push("charging-fault".into());
+        }
+        if self.recovery_overdue {
+            found.push("recovery-overdue".into());
+        }
+        if !PLAUSIBLE_TEMP_DC.contains(&p.temp_dc) {
+            found.push(format!(
+                "implausible-temp ({:.1} °C)",
+                p.temp_dc as f32 / 10.0
+            ));
+        }
+        if battery.rail_undervoltage(p.vbus_out_mv) {
+            found.push(format!(
+                "rail-undervoltage ({:.2} V)",
+                p.vbus_out_mv as f32 / 1000.0
+            ));
+        }
+        if let Some(v2) = self.last_power_v2 {
+            if v2.flags & power2_flag::BATT_PRESENT == 0 {
+                found.push("battery-absent".into());
+            }
+        }
+        if p.faults != 0 {
+            found.push(format!(
+                "firmware-fault ({})",
+                power_fault::describe(p.faults)
+            ));
+        }
+        found
+    }
+}
+
+/// A finding's name without its details: `implausible-temp (104.0 °C)` is
+/// `implausible-temp` whatever the reading.
+pub fn fault_name(finding: &str) -> &str {
+    finding.split(" (").next().unwrap_or(finding)
+}
+
+/// Debounce for the v2 power-good flag, which can drop for a sample or two
+/// during load transients: a new value is only reported once the firmware
+/// has sent it `[battery].power_good_debounce_samples` times in a row. The
+/// first frame after a (re)connect is taken as is.
+#[derive(Debug, Default, Clone)]
+pub struct PowerGoodFilter {
+    samples: u32,
+    reported: Option<bool>,
+    /// A differing raw value and how many frames in a row carried it.
+    candidate: Option<(bool, u32)>,
+}
+
+impl PowerGoodFilter {
+    pub fn configure(&mut self, samples: u32) {
+        self.samples = samples;
+    }
+
+    pub fn reset(&mut self) {
+        self.reported = None;
+        self.candidate = None;
+    }
+
+    /// Feed one frame's raw flag; returns the value to report.
+    pub fn update(&mut self, raw: bool) -> bool {
+        let reported = *self.reported.get_or_insert(raw);
+        if raw == reported {
+            self.candidate = None;
+            return reported;
+        }
+        let n = match self.candidate {
+            Some((v, n)) if v == raw => n + 1,
+            _ => 1,
+        };
+        if n >= self.samples.max(1) {
+            self.reported = Some(raw);
+            self.candidate = None;
+            raw
+        } else {
+            self.candidate = Some((raw, n));
+            reported
+        }
+    }
+}
+
+/// An operator-injected power reading (see [`State::inject_power`]).
+#[derive(Debug, Clone, Copy)]
+pub struct Injection {
+    pub status: PowerStatusV1,
+    pub at: Instant,
+    /// Clients are shown `status` until then, so the reading sticks.
+    pub hold_until: Instant,
+    /// Let the shutdown SM act on it (with the actual shutdown stubbed).
+    pub exercise_shutdown: bool,
+}
+
 /// Per-destination outbound sequence counter (matches "scoped per (SRC, DST)"
 /// in the wire protocol spec).
 #[derive(Debug, Default)]
@@ -45,11 +268,71 @@ impl TxSeq {
     }
 }
 
+/// A power-class frame as it arrives, fanned out to [`State::subscribe_power`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerUpdate {
+    Status(PowerStatusV1),
+    Event(u8),
+}
+
+/// Real `power.status` samples for the loops that run for the daemon's
+/// lifetime: they subscribe once, so the feed carries on across reconnects,
+/// and they idle on it when their feature is unconfigured. Injected readings
+/// are an overlay and never appear here.
+pub struct SampleFeed {
+    rx: broadcast::Receiver<PowerUpdate>,
+    who: &'static str,
+}
+
+impl SampleFeed {
+    /// The next sample, skipping events; `None` once the feed has closed.
+    /// Cancel-safe, so it can sit in a `select!`.
+    pub async fn next_real_sample(&mut self) -> Option<PowerStatusV1> {
+        loop {
+            match self.rx.recv().await {
+                Ok(PowerUpdate::Status(p)) => return Some(p),
+                Ok(PowerUpdate::Event(_)) => {}
+                Err(RecvError::Lagged(n)) => debug!("{} lagged {n} samples", self.who),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Slow subscribers lag (and skip) rather than back-pressure the dispatcher.
+const POWER_FEED_CAPACITY: usize = 64;
+
 /// Shared, mutable agent state. Wrap in `Arc<...>` for tasks.
-#[derive(Default)]
 pub struct State {
     inner: RwLock<AgentState>,
     tx_seq: RwLock<TxSeq>,
+    power_tx: broadcast::Sender<PowerUpdate>,
+    /// Flipped once when the daemon starts shutting down.
+    stopping_tx: watch::Sender<bool>,
+    /// Rejected key-value telemetry lines, counted by the serial reader.
+    kv_rejects: Arc<KvRejects>,
+    /// Bytes and frames read, counted by the serial reader.
+    read_stats: Arc<ReadStats>,
+    /// Connected IPC clients, and how many of them are subscribed.
+    ipc_clients: AtomicUsize,
+    ipc_subscribers: AtomicUsize,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+}
+
+/// One count in [`State::ipc_clients`], given back on drop.
+pub(crate) struct IpcCount<'a>(&'a AtomicUsize);
+
+impl Drop for IpcCount<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::with_clock_inner(Arc::new(SystemClock))
+    }
 }
 
 impl State {
@@ -57,22 +340,172 @@ impl State {
         Arc::new(Self::default())
     }
 
+    /// State whose timestamps (and every task reading [`State::now`]) follow
+    /// `clock`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Arc<Self> {
+        Arc::new(Self::with_clock_inner(clock))
+    }
+
+    fn with_clock_inner(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: RwLock::default(),
+            tx_seq: RwLock::default(),
+            power_tx: broadcast::channel(POWER_FEED_CAPACITY).0,
+            stopping_tx: watch::channel(false).0,
+            kv_rejects: Arc::default(),
+            read_stats: Arc::default(),
+            ipc_clients: AtomicUsize::new(0),
+            ipc_subscribers: AtomicUsize::new(0),
+            started: clock.now(),
+            clock,
+        }
+    }
+
+    /// Tell long-lived clients (IPC subscribers) the daemon is going away.
+    /// Returns how many are listening.
+    pub fn announce_stopping(&self) -> usize {
+        self.stopping_tx.send_replace(true);
+        self.stopping_tx.receiver_count()
+    }
+
+    /// Changes once, to `true`, on [`Self::announce_stopping`].
+    pub fn subscribe_stopping(&self) -> watch::Receiver<bool> {
+        self.stopping_tx.subscribe()
+    }
+
+    /// Handed to the serial reader, which counts into it.
+    pub fn kv_rejects(&self) -> Arc<KvRejects> {
+        self.kv_rejects.clone()
+    }
+
+    pub fn kv_reject_counts(&self) -> KvRejectCounts {
+        self.kv_rejects.counts()
+    }
+
+    /// Handed to the serial reader, which counts into it.
+    pub fn read_stats(&self) -> Arc<ReadStats> {
+        self.read_stats.clone()
+    }
+
+    /// The agent's notion of "now"; all `*_at` fields are on this clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Time since the state was created, i.e. since the daemon started.
+    pub fn uptime(&self) -> Duration {
+        self.now().saturating_duration_since(self.started)
+    }
+
+    /// Counts an IPC client for as long as the returned guard lives.
+    pub(crate) fn count_ipc_client(&self) -> IpcCount<'_> {
+        self.ipc_clients.fetch_add(1, Ordering::Relaxed);
+        IpcCount(&self.ipc_clients)
+    }
+
+    /// Same, for a client's subscription.
+    pub(crate) fn count_ipc_subscriber(&self) -> IpcCount<'_> {
+        self.ipc_subscribers.fetch_add(1, Ordering::Relaxed);
+        IpcCount(&self.ipc_subscribers)
+    }
+
+    /// Connected IPC clients, and how many of them are subscribed.
+    pub fn ipc_clients(&self) -> (usize, usize) {
+        (
+            self.ipc_clients.load(Ordering::Relaxed),
+            self.ipc_subscribers.load(Ordering::Relaxed),
+        )
+    }
+
     pub async fn update_power(&self, status: PowerStatusV1) {
-        let mut s = self.inner.write().await;
-        s.last_power = Some(status);
-        s.last_power_at = Some(Instant::now());
+        self.store_power(status, None).await;
+    }
+
+    /// v2 frame: stored down-converted in `last_power`, raw in `last_power_v2`.
+    pub async fn update_power_v2(&self, status: PowerStatusV2) {
+        self.store_power(status.to_v1(), Some(status)).await;
+    }
+
+    /// Show clients a synthetic reading for `hold` (see
+    /// [`AgentState::client_view`]). Real frames keep being stored and fed
+    /// to everything else, the shutdown SM included unless
+    /// `exercise_shutdown` hands it the synthetic one. `false` if `hold`
+    /// is too long to represent.
+    pub async fn inject_power(
+        &self,
+        status: PowerStatusV1,
+        hold: Duration,
+        exercise_shutdown: bool,
+    ) -> bool {
+        let now = self.now();
+        let Some(hold_until) = now.checked_add(hold) else {
+            return false;
+        };
+        self.inner.write().await.injected = Some(Injection {
+            status,
+            at: now,
+            hold_until,
+            exercise_shutdown,
+        });
+        true
+    }
+
+    async fn store_power(&self, v1: PowerStatusV1, v2: Option<PowerStatusV2>) {
+        {
+            let mut s = self.inner.write().await;
+            if s.injected.is_some_and(|i| self.now() >= i.hold_until) {
+                s.injected = None;
+            }
+            s.last_power = Some(v1);
+            s.last_power_flags_raw = v2.map(|v2| v2.flags);
+            s.last_power_v2 = match v2 {
+                Some(mut v2) => {
+                    let pg = s.power_good.update(v2.flags & power2_flag::POWER_GOOD != 0);
+                    v2.flags = v2.flags & !power2_flag::POWER_GOOD
+                        | if pg { power2_flag::POWER_GOOD } else { 0 };
+                    Some(v2)
+                }
+                None => {
+                    s.power_good.reset();
+                    None
+                }
+            };
+            s.last_power_at = Some(self.now());
+            s.power_samples += 1;
+            s.cadence.record(self.now());
+        }
+        // Err only means nobody is subscribed.
+        let _ = self.power_tx.send(PowerUpdate::Status(v1));
     }
 
     pub async fn update_power_event(&self, event: u8) {
-        let mut s = self.inner.write().await;
-        s.last_power_event = Some(event);
-        s.last_power_event_at = Some(Instant::now());
+        {
+            let mut s = self.inner.write().await;
+            s.last_power_event = Some(event);
+            s.last_power_event_at = Some(self.now());
+        }
+        let _ = self.power_tx.send(PowerUpdate::Event(event));
+    }
+
+    /// Live feed of `power.status` / `power.event` frames, after they have
+    /// been applied to the snapshot.
+    pub fn subscribe_power(&self) -> broadcast::Receiver<PowerUpdate> {
+        self.power_tx.subscribe()
+    }
+
+    /// [`SampleFeed`] for a daemon-lifetime loop; `who` names it in the
+    /// lag log.
+    pub fn sample_feed(&self, who: &'static str) -> SampleFeed {
+        SampleFeed {
+            rx: self.power_tx.subscribe(),
+            who,
+        }
     }
 
     pub async fn update_net(&self, status: NetStatusV1) {
         let mut s = self.inner.write().await;
         s.last_net = Some(status);
-        s.last_net_at = Some(Instant::now());
+        s.last_net_at = Some(self.now());
     }
 
     pub async fn record_hello(&self, src: u8, hello: SysHelloV1) {
@@ -83,7 +516,7 @@ impl State {
     pub async fn update_host_sample(&self, sample: HostMetricsSample) {
         let mut s = self.inner.write().await;
         s.last_host = Some(sample.status);
-        s.last_host_at = Some(Instant::now());
+        s.last_host_at = Some(self.now());
         s.cpu_usage_pct = sample.cpu_usage_pct;
         s.net_totals = sample.net;
         s.net_rx_bytes_per_s = sample.net_rx_bytes_per_s;
@@ -98,7 +531,249 @@ impl State {
         self.inner.write().await.shutdown_pending_since = since;
     }
 
+    pub async fn set_charge_maintaining(&self, maintaining: bool) {
+        self.inner.write().await.charge_maintaining = maintaining;
+    }
+
+    pub async fn set_charging_fault(&self, fault: bool) {
+        self.inner.write().await.charging_fault = fault;
+    }
+
+    pub async fn set_recovery_overdue(&self, overdue: bool) {
+        self.inner.write().await.recovery_overdue = overdue;
+    }
+
+    pub async fn set_unstable_power(&self, unstable: Option<UnstablePower>) {
+        self.inner.write().await.unstable_power = unstable;
+    }
+
+    /// A config reload was applied just now.
+    pub async fn note_reload(&self) {
+        let now = self.now();
+        self.inner.write().await.reloaded_at = Some(now);
+    }
+
+    pub async fn set_notified_faults(&self, names: Vec<String>) {
+        self.inner.write().await.notified_faults = names;
+    }
+
+    pub async fn set_pd_overload(&self, overload: bool) {
+        self.inner.write().await.pd_overload = overload;
+    }
+
+    pub async fn set_capacity(&self, log: CapacityLog) {
+        self.inner.write().await.capacity = log;
+    }
+
+    pub async fn set_health(&self, report: HealthReport) {
+        self.inner.write().await.health = Some(report);
+    }
+
+    pub async fn set_input_histogram(&self, hist: Option<InputHistogram>) {
+        self.inner.write().await.input_histogram = hist;
+    }
+
+    pub async fn set_dry_run(&self, dry_run: bool) {
+        self.inner.write().await.dry_run = dry_run;
+    }
+
+    /// The shutdown SM saw the input go to battery (`true`) or back.
+    pub async fn note_outage(&self, on_battery: bool) {
+        let mut s = self.inner.write().await;
+        if on_battery && !s.in_outage {
+            s.outages += 1;
+        }
+        s.in_outage = on_battery;
+    }
+
+    /// `true` on the first call only: the initial state is reported once
+    /// per daemon run, not again after a reconnect or reload.
+    pub async fn claim_initial_state(&self) -> bool {
+        !std::mem::replace(&mut self.inner.write().await.initial_state_reported, true)
+    }
+
+    pub async fn set_shutdown_triggered(&self) {
+        self.inner.write().await.shutdown_triggered = true;
+    }
+
+    /// A sleep action has returned: nothing is triggered or pending.
+    pub async fn clear_shutdown(&self) {
+        let mut s = self.inner.write().await;
+        s.shutdown_triggered = false;
+        s.shutdown_pending_since = None;
+    }
+
+    pub async fn set_max_sample_age(&self, age: Option<Duration>) {
+        self.inner.write().await.max_sample_age = age;
+    }
+
+    /// `[serial].expected_interval_ms` / `missed_warn_pct`.
+    pub async fn set_sample_cadence(&self, expected: Option<Duration>, warn_pct: u8) {
+        self.inner
+            .write()
+            .await
+            .cadence
+            .configure(expected, warn_pct);
+    }
+
+    pub async fn set_serial_port(&self, port: String) {
+        self.inner.write().await.serial_port = Some(port);
+    }
+
+    pub async fn set_serial_connected(&self, connected: bool) {
+        let mut s = self.inner.write().await;
+        s.serial_connected = connected;
+        s.cadence.restart();
+        s.power_good.reset();
+    }
+
+    pub async fn set_power_good_debounce(&self, samples: u32) {
+        self.inner.write().await.power_good.configure(samples);
+    }
+
     pub async fn next_seq(&self, dst: u8) -> u8 {
         self.tx_seq.write().await.next_for(dst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn power_feed_follows_updates() {
+        let state = State::new();
+        let mut rx = state.subscribe_power();
+        let p = PowerStatusV1 {
+            vbat_mv: 7400,
+            ..Default::default()
+        };
+        state.update_power(p).await;
+        state.update_power_event(3).await;
+        assert_eq!(rx.recv().await.unwrap(), PowerUpdate::Status(p));
+        assert_eq!(rx.recv().await.unwrap(), PowerUpdate::Event(3));
+        assert_eq!(state.snapshot().await.last_power, Some(p));
+    }
+
+    #[test]
+    fn fault_summary_names_each_contradiction() {
+        let battery = crate::config::Config::default().battery;
+        let healthy = PowerStatusV1 {
+            vbus_in_mv: 20_000,
+            vbat_mv: 7_800,
+            charge_state: charge_state::CHARGING,
+            temp_dc: 310,
+            ..Default::default()
+        };
+        let v2 = PowerStatusV2 {
+            flags: power2_flag::POWER_GOOD | power2_flag::BATT_PRESENT,
+            ..Default::default()
+        };
+        let mut s = AgentState {
+            last_power: Some(healthy),
+            last_power_v2: Some(v2),
+            ..Default::default()
+        };
+        assert_eq!(s.fault_summary(&battery), None);
+        assert_eq!(AgentState::default().fault_summary(&battery), None);
+
+        s.last_power_v2 = Some(PowerStatusV2 { flags: 0, ..v2 });
+        s.last_power = Some(PowerStatusV1 {
+            temp_dc: i16::MIN,
+            faults: power_fault::OTP,
+            vbus_out_mv: 4_550,
+            ..healthy
+        });
+        s.charging_fault = true;
+        assert_eq!(
+            s.fault_summary(&battery).unwrap(),
+            "power-not-good-but-grid-ok, charging-fault, implausible-temp (-3276.8 °C), \
+             rail-undervoltage (4.55 V), battery-absent, firmware-fault (otp)"
+        );
+
+        // On battery, power-good being clear is expected.
+        s.last_power = Some(PowerStatusV1 {
+            vbus_in_mv: 0,
+            ..healthy
+        });
+        s.charging_fault = false;
+        assert_eq!(s.fault_summary(&battery).unwrap(), "battery-absent");
+    }
+
+    #[tokio::test]
+    async fn power_good_flicker_is_held_back() {
+        let state = State::new();
+        state.set_power_good_debounce(3).await;
+        let frame = |pg: bool| PowerStatusV2 {
+            flags: power2_flag::BATT_PRESENT | if pg { power2_flag::POWER_GOOD } else { 0 },
+            ..Default::default()
+        };
+        let reported = || async {
+            let s = state.snapshot().await;
+            let flags = s.last_power_v2.unwrap().flags;
+            (
+                flags & power2_flag::POWER_GOOD != 0,
+                s.last_power_flags_raw.unwrap() & power2_flag::POWER_GOOD != 0,
+            )
+        };
+
+        state.update_power_v2(frame(true)).await;
+        assert_eq!(reported().await, (true, true));
+        // Two frames of a transient dip: reported good, raw shows the dip.
+        for _ in 0..2 {
+            state.update_power_v2(frame(false)).await;
+            assert_eq!(reported().await, (true, false));
+        }
+        state.update_power_v2(frame(true)).await;
+        state.update_power_v2(frame(false)).await;
+        state.update_power_v2(frame(false)).await;
+        assert_eq!(reported().await, (true, false));
+        // The third frame in a row is believed; other flags pass untouched.
+        state.update_power_v2(frame(false)).await;
+        assert_eq!(reported().await, (false, false));
+        let flags = state.snapshot().await.last_power_v2.unwrap().flags;
+        assert_eq!(flags, power2_flag::BATT_PRESENT);
+
+        // After a reconnect the first frame is taken as is.
+        state.set_serial_connected(true).await;
+        state.update_power_v2(frame(true)).await;
+        assert_eq!(reported().await, (true, true));
+    }
+
+    #[tokio::test]
+    async fn injection_holds_then_yields_to_real_frames() {
+        let state = State::new();
+        let fake = PowerStatusV1 {
+            vbat_mv: 6600,
+            ..Default::default()
+        };
+        let real = PowerStatusV1 {
+            vbat_mv: 8000,
+            ..Default::default()
+        };
+        let mut feed = state.subscribe_power();
+        assert!(
+            state
+                .inject_power(fake, Duration::from_secs(60), false)
+                .await
+        );
+        state.update_power(real).await;
+        let s = state.snapshot().await;
+        // Clients see the injection; the real frame is still stored and
+        // is all that reaches the feed.
+        assert_eq!(s.client_view().last_power, Some(fake));
+        assert_eq!(s.last_power, Some(real));
+        assert_eq!(s.power_samples, 1);
+        assert_eq!(feed.try_recv().unwrap(), PowerUpdate::Status(real));
+        assert!(feed.try_recv().is_err());
+
+        state.inject_power(fake, Duration::ZERO, false).await;
+        state.update_power(real).await;
+        let s = state.snapshot().await;
+        assert_eq!(s.client_view().last_power, Some(real));
+        assert!(s.injected.is_none());
+
+        assert!(!state.inject_power(fake, Duration::MAX, false).await);
+        assert!(state.snapshot().await.injected.is_none());
+    }
+}
@@ -0,0 +1,310 @@
+//! `w3p-ups stats FILE` — an offline summary of a `probe --follow --json`
+//! recording: how long it covers, every stretch on battery, the SOC and
+//! input-voltage extremes, the temperature, and the pack capacity the
+//! discharges point to. A quick postmortem of a captured outage without a
+//! spreadsheet.
+//!
+//! Samples are timed like `replay` times them (`unix_ts_ms` or `ts`, else
+//! 1 s apart). The whole-recording min / max / average come from the same
+//! [`Aggregator`] `probe --every` uses, over one window spanning the file.
+//! Discharge is integrated like the daemon's capacity tracking, trapezoid by
+//! trapezoid, skipping gaps too long to trust.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::aggregate::{Aggregate, Aggregator};
+use crate::budget::fmt_hm;
+use crate::capacity::MAX_SAMPLE_GAP;
+use crate::cli::fmt_mv;
+use crate::config::{BatteryConfig, Config};
+use crate::replay::{gaps, read_recording, Sample};
+use crate::shutdown_sm::classify_input;
+
+/// A capacity guess from a shallower discharge than this (SOC points) is
+/// mostly noise in the voltage-based SOC.
+const MIN_SOC_DROP_FOR_CAPACITY: u8 = 10;
+
+/// One uninterrupted stretch on battery.
+#[derive(Debug, Clone, PartialEq)]
+struct Episode {
+    /// From the start of the recording.
+    starts_after: Duration,
+    duration: Duration,
+    soc_from: u8,
+    soc_to: u8,
+    /// Charge the pack delivered.
+    mah: f64,
+    /// The recording ends during this episode.
+    open: bool,
+}
+
+#[derive(Debug)]
+struct RecordingStats {
+    samples: usize,
+    duration: Duration,
+    all: Aggregate,
+    /// Input while on grid; `None` if the whole recording is on battery.
+    on_grid: Option<Aggregate>,
+    episodes: Vec<Episode>,
+}
+
+fn summarize(samples: &[Sample], battery: &BatteryConfig) -> RecordingStats {
+    let curve = battery.soc_curve();
+    let t0 = Instant::now();
+    let mut offset = Duration::ZERO;
+    let mut all = Aggregator::new(Duration::MAX, curve);
+    let mut on_grid = Aggregator::new(Duration::MAX, curve);
+    let mut episodes: Vec<Episode> = Vec::new();
+    let mut prev: Option<(bool, i16)> = None;
+
+    for (s, gap) in samples.iter().zip(gaps(samples, 1.0)) {
+        offset += gap;
+        let p = s.power.to_status();
+        let on_battery = s.on_battery.unwrap_or_else(|| {
+            classify_input(
+                &p,
+                None,
+                battery.input_min_valid_mv,
+                battery.input_max_valid_mv,
+                battery.input_zero_cross_check,
+            )
+            .on_battery()
+        });
+        let soc = battery.soc_pct(p.vbat_mv);
+        all.push(&p, t0 + offset);
+        if !on_battery {
+            on_grid.push(&p, t0 + offset);
+        }
+
+        match (prev, episodes.last_mut()) {
+            (Some((true, prev_ma)), Some(e)) => {
+                e.duration += gap;
+                if gap <= MAX_SAMPLE_GAP {
+                    // Discharge current is negative; a charging blip counts against it.
+                    let avg_ma = (prev_ma as f64 + p.ibat_ma as f64) / 2.0;
+                    e.mah -= avg_ma * gap.as_secs_f64() / 3600.0;
+                }
+                e.soc_to = soc;
+                e.open = on_battery;
+            }
+            _ if on_battery => episodes.push(Episode {
+                starts_after: offset,
+                duration: Duration::ZERO,
+                soc_from: soc,
+                soc_to: soc,
+                mah: 0.0,
+                open: true,
+            }),
+            _ => {}
+        }
+        prev = Some((on_battery, p.ibat_ma));
+    }
+
+    RecordingStats {
+        samples: samples.len(),
+        duration: offset,
+        all: all
+            .flush()
+            .expect("read_recording yields at least one sample"),
+        on_grid: on_grid.flush(),
+        episodes,
+    }
+}
+
+impl RecordingStats {
+    /// Full-pack capacity the discharges extrapolate to, with the charge and
+    /// SOC drop it rests on.
+    fn capacity(&self) -> Option<(f64, f64, u32)> {
+        let mah: f64 = self.episodes.iter().map(|e| e.mah).sum();
+        let drop: u32 = self
+            .episodes
+            .iter()
+            .map(|e| e.soc_from.saturating_sub(e.soc_to) as u32)
+            .sum();
+        (drop >= MIN_SOC_DROP_FOR_CAPACITY as u32 && mah > 0.0)
+            .then(|| (mah * 100.0 / drop as f64, mah, drop))
+    }
+
+    fn report(&self) -> Vec<String> {
+        let mut out = vec![format!(
+            "{:<12} {} samples over {}",
+            "recording",
+            self.samples,
+            fmt_hm(self.duration)
+        )];
+        let on_battery: Duration = self.episodes.iter().map(|e| e.duration).sum();
+        out.push(match self.episodes.len() {
+            0 => format!("{:<12} never", "on battery"),
+            n => format!(
+                "{:<12} {n} episode{}, {} in total",
+                "on battery",
+                if n == 1 { "" } else { "s" },
+                fmt_hm(on_battery)
+            ),
+        });
+        for (i, e) in self.episodes.iter().enumerate() {
+            out.push(format!(
+                "  {}. after {}: {}, SOC {}% → {}%, {:.0} mAh delivered{}",
+                i + 1,
+                fmt_hm(e.starts_after),
+                fmt_hm(e.duration),
+                e.soc_from,
+                e.soc_to,
+                e.mah.max(0.0),
+                if e.open {
+                    " (still on battery at the end)"
+                } else {
+                    ""
+                }
+            ));
+        }
+        out.push(format!(
+            "{:<12} min {}%, max {}%",
+            "SOC", self.all.soc_pct.min, self.all.soc_pct.max
+        ));
+        out.push(match &self.on_grid {
+            Some(g) => format!(
+                "{:<12} lowest {} V on grid (highest {} V)",
+                "input",
+                fmt_mv(g.vbus_in_mv.min),
+                fmt_mv(g.vbus_in_mv.max)
+            ),
+            None => format!("{:<12} never on grid", "input"),
+        });
+        let t = &self.all.temp_dc;
+        out.push(format!(
+            "{:<12} avg {:.1} °C (min {:.1}, max {:.1})",
+            "temperature",
+            t.avg / 10.0,
+            t.min as f64 / 10.0,
+            t.max as f64 / 10.0
+        ));
+        out.push(match self.capacity() {
+            Some((full, mah, drop)) => format!(
+                "{:<12} ~{full:.0} mAh full pack ({mah:.0} mAh over {drop} SOC points)",
+                "capacity"
+            ),
+            None => format!(
+                "{:<12} n/a (needs a discharge of at least {MIN_SOC_DROP_FOR_CAPACITY} SOC points)",
+                "capacity"
+            ),
+        });
+        out
+    }
+}
+
+pub fn run_stats(cfg: &Config, path: &Path) -> Result<()> {
+    let (samples, skipped) = read_recording(path)?;
+    if skipped > 0 {
+        eprintln!("{skipped} non-sample lines skipped");
+    }
+    for line in summarize(&samples, &cfg.battery).report() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    fn recording(name: &str, lines: &[(u64, u16, u16, i16, i16)]) -> Vec<Sample> {
+        let text: String = lines
+            .iter()
+            .map(|&(s, vin, vbat, ibat, t)| {
+                format!(
+                    r#"{{"unix_ts_ms":{},"vbus_in_mv":{vin},"vbat_mv":{vbat},"ibat_ma":{ibat},"temp_dc":{t}}}"#,
+                    s * 1000
+                ) + "\n"
+            })
+            .collect();
+        let dir = Scratch::new(&format!("stats-{name}"));
+        let path = dir.join("recording.jsonl");
+        std::fs::write(&path, text).unwrap();
+        read_recording(&path).unwrap().0
+    }
+
+    #[test]
+    fn outage_is_summed_up() {
+        let battery = Config::default().battery;
+        let full = battery.soc_curve().pack_mv(100);
+        let half = battery.soc_curve().pack_mv(50);
+        // Grid, a one-hour outage at a steady 1 A, grid again.
+        let samples = recording(
+            "outage",
+            &[
+                (0, 19_600, full, 0, 300),
+                (5, 20_000, full, 0, 310),
+                (10, 0, full, -1_000, 320),
+                (3_610, 0, half, -1_000, 340),
+                (3_615, 19_800, half, 800, 330),
+            ],
+        );
+        let st = summarize(&samples, &battery);
+        assert_eq!(st.duration, Duration::from_secs(3_615));
+        assert_eq!(st.episodes.len(), 1);
+        let e = &st.episodes[0];
+        assert_eq!(
+            (e.starts_after, e.duration),
+            (Duration::from_secs(10), Duration::from_secs(3_605))
+        );
+        assert_eq!((e.soc_from, e.soc_to, e.open), (100, 50, false));
+        // The hour-long gap can't be trusted; only the last 5 s integrate
+        // (-1000 → +800 mA averages to a 100 mA discharge).
+        assert!((e.mah - 100.0 * 5.0 / 3600.0).abs() < 1e-9);
+
+        let report = st.report();
+        assert_eq!(report[0], "recording    5 samples over 1h 00m");
+        assert_eq!(report[1], "on battery   1 episode, 1h 00m in total");
+        assert_eq!(
+            report[2],
+            "  1. after 0m: 1h 00m, SOC 100% → 50%, 0 mAh delivered"
+        );
+        assert_eq!(report[3], "SOC          min 50%, max 100%");
+        assert_eq!(
+            report[4],
+            "input        lowest 19.60 V on grid (highest 20.00 V)"
+        );
+        assert_eq!(report[5], "temperature  avg 32.0 °C (min 30.0, max 34.0)");
+    }
+
+    #[test]
+    fn capacity_extrapolates_from_the_soc_drop() {
+        let battery = Config::default().battery;
+        let curve = battery.soc_curve();
+        // On battery throughout at 2 A, one sample a second for 30 min.
+        let lines: Vec<_> = (0..=1_800u64)
+            .map(|s| {
+                (
+                    s,
+                    0,
+                    curve.pack_mv(80 - (s * 20 / 1_800) as u8),
+                    -2_000,
+                    250,
+                )
+            })
+            .collect();
+        let st = summarize(&recording("drain", &lines), &battery);
+        assert_eq!(st.episodes.len(), 1);
+        assert!(st.episodes[0].open);
+        let (full, mah, drop) = st.capacity().unwrap();
+        assert!((mah - 1_000.0).abs() < 1e-6);
+        assert_eq!(drop, 20);
+        assert!((full - 5_000.0).abs() < 1e-6);
+        let report = st.report();
+        assert!(
+            report[2].ends_with("1000 mAh delivered (still on battery at the end)"),
+            "{}",
+            report[2]
+        );
+        assert_eq!(report[4], "input        never on grid");
+        assert_eq!(
+            report[6],
+            "capacity     ~5000 mAh full pack (1000 mAh over 20 SOC points)"
+        );
+    }
+}
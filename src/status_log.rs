@@ -0,0 +1,127 @@
+//! Periodic one-line power status in the journal, with the fields chosen by
+//! `[logging].status_fields`, e.g.
+//! `status: GRID VI=19.80V VBAT=7.40V IBAT=850mA SOC=60% chg=charging T=31.5°C`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::interval;
+use tracing::info;
+
+use crate::cli::{charge_label, fmt_mv};
+use crate::config::{BatteryConfig, LoggingConfig, StatusField};
+use crate::proto::payloads::{PowerStatusV1, PowerStatusV2};
+use crate::shutdown_sm::classify_input;
+use crate::state::State;
+
+pub async fn status_log_loop(state: Arc<State>, battery: BatteryConfig, logging: LoggingConfig) {
+    if logging.status_interval_seconds == 0 || logging.status_fields.is_empty() {
+        return std::future::pending().await;
+    }
+    let mut tick = interval(Duration::from_secs(logging.status_interval_seconds));
+    // The first tick fires at once, before any sample has arrived.
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let snap = state.snapshot().await;
+        let Some(p) = snap.last_power else {
+            info!("status: no power reading yet");
+            continue;
+        };
+        let line = status_line(
+            &logging.status_fields,
+            &p,
+            snap.last_power_v2.as_ref(),
+            snap.charge_maintaining,
+            &battery,
+        );
+        match snap.last_power_at {
+            Some(at) if state.now().saturating_duration_since(at) > STALE_AFTER => {
+                let age = state.now().saturating_duration_since(at).as_secs();
+                info!("status: {line} (stale, {age} s old)");
+            }
+            _ => info!("status: {line}"),
+        }
+    }
+}
+
+/// power.status arrives at ~1 Hz; flag the line when it's this far behind.
+const STALE_AFTER: Duration = Duration::from_secs(10);
+
+fn status_line(
+    fields: &[StatusField],
+    p: &PowerStatusV1,
+    v2: Option<&PowerStatusV2>,
+    maintaining: bool,
+    battery: &BatteryConfig,
+) -> String {
+    let field = |f: &StatusField| match f {
+        StatusField::Source => {
+            let on_battery = classify_input(
+                p,
+                v2,
+                battery.input_min_valid_mv,
+                battery.input_max_valid_mv,
+                battery.input_zero_cross_check,
+            )
+            .on_battery();
+            if on_battery { "BATTERY" } else { "GRID" }.to_string()
+        }
+        StatusField::Vin => format!("VI={}V", fmt_mv(p.vbus_in_mv as i32)),
+        StatusField::Vout => format!("VOUT={}V", fmt_mv(p.vbus_out_mv as i32)),
+        StatusField::Iout => format!("IOUT={}mA", p.ibus_out_ma),
+        StatusField::Vbat => format!("VBAT={}V", fmt_mv(p.vbat_mv as i32)),
+        StatusField::Ibat => format!("IBAT={}mA", p.ibat_ma),
+        StatusField::Soc => format!("SOC={}%", battery.soc_pct(p.vbat_mv)),
+        StatusField::Charge => {
+            format!("chg={}", charge_label(p.charge_state, maintaining))
+        }
+        StatusField::Temp => format!("T={:.1}°C", p.temp_dc as f32 / 10.0),
+        StatusField::Faults => format!("faults=0x{:04x}", p.faults),
+    };
+    fields.iter().map(field).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn line_follows_the_configured_fields_and_order() {
+        let battery = Config::default().battery;
+        let p = PowerStatusV1 {
+            charge_state: 1,
+            vbus_in_mv: 19_800,
+            vbat_mv: 7_400,
+            ibat_ma: 850,
+            temp_dc: 315,
+            faults: 0x0008,
+            ..Default::default()
+        };
+        let fields = Config::default().logging.status_fields;
+        assert_eq!(
+            status_line(&fields, &p, None, false, &battery),
+            "GRID VI=19.80V VBAT=7.40V IBAT=850mA SOC=60% chg=charging T=31.5°C"
+        );
+        assert_eq!(
+            status_line(&[StatusField::Charge], &p, None, true, &battery),
+            "chg=maintaining"
+        );
+        assert_eq!(
+            status_line(
+                &[StatusField::Temp, StatusField::Faults, StatusField::Source],
+                &p,
+                None,
+                false,
+                &battery
+            ),
+            "T=31.5°C faults=0x0008 GRID"
+        );
+        let cold = PowerStatusV1 { temp_dc: -55, ..p };
+        assert_eq!(
+            status_line(&[StatusField::Temp], &cold, None, false, &battery),
+            "T=-5.5°C"
+        );
+    }
+}
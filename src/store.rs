@@ -0,0 +1,225 @@
+//! Small JSON state files on the SD card, written sparingly.
+//!
+//! Every persisted feature goes through a [`StateStore`]: it skips writes
+//! when the value hasn't changed, coalesces changes to at most one write per
+//! `[persist].min_write_interval_seconds`, and replaces the file atomically
+//! (temp file, fsync, rename) so a power cut mid-write leaves the old copy.
+//!
+//! A file that still fails to parse (truncated or zeroed by the filesystem
+//! after a power cut, or edited by hand) doesn't stop the daemon: it is
+//! moved aside to `<file>.corrupt` with a warning, and the feature starts
+//! from its defaults and writes a fresh file.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+pub struct StateStore<T> {
+    /// `None`: memory only (the feature's state file is unset).
+    path: Option<PathBuf>,
+    min_interval: Duration,
+    /// What's on disk, as far as we know.
+    written: Option<T>,
+    written_at: Option<Instant>,
+    /// Newer than `written`, waiting for the interval to pass.
+    pending: Option<T>,
+}
+
+impl<T: Serialize + DeserializeOwned + PartialEq + Clone> StateStore<T> {
+    /// Open `path` (empty = memory only) and return the stored value, if the
+    /// file exists and parses. Only an unreadable file is an error.
+    pub fn open(path: &str, min_interval: Duration) -> Result<(Self, Option<T>)> {
+        let path = (!path.is_empty()).then(|| PathBuf::from(path));
+        let loaded = match &path {
+            Some(p) => match read(p)? {
+                Some(bytes) => match serde_json::from_slice(&bytes) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        set_aside(p, &e);
+                        None
+                    }
+                },
+                None => None,
+            },
+            None => None,
+        };
+        let store = Self {
+            path,
+            min_interval,
+            written: loaded.clone(),
+            written_at: None,
+            pending: None,
+        };
+        Ok((store, loaded))
+    }
+
+    /// Record the current value. Writes now if it differs from what's on
+    /// disk and the last write is at least `min_interval` old; otherwise it
+    /// is kept for [`Self::tick`] / [`Self::flush`]. Returns whether the file
+    /// was written.
+    pub fn update(&mut self, value: &T, now: Instant) -> Result<bool> {
+        if self.written.as_ref() == Some(value) {
+            self.pending = None;
+            return Ok(false);
+        }
+        self.pending = Some(value.clone());
+        self.tick(now)
+    }
+
+    /// Write a deferred value once its interval has passed.
+    pub fn tick(&mut self, now: Instant) -> Result<bool> {
+        let due = match self.written_at {
+            Some(at) => now.saturating_duration_since(at) >= self.min_interval,
+            None => true,
+        };
+        if !due || self.pending.is_none() {
+            return Ok(false);
+        }
+        self.written_at = Some(now);
+        self.flush()
+    }
+
+    /// Write a deferred value now, interval or not.
+    pub fn flush(&mut self) -> Result<bool> {
+        let Some(value) = self.pending.take() else {
+            return Ok(false);
+        };
+        if let Some(path) = &self.path {
+            write_atomic(path, &serde_json::to_vec_pretty(&value)?)?;
+            debug!("state written to {}", path.display());
+        }
+        self.written = Some(value);
+        Ok(self.path.is_some())
+    }
+}
+
+/// The raw bytes: garbage that isn't even UTF-8 is a parse failure, set
+/// aside like any other, not a read error.
+fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    read(path)?
+        .map(|b| serde_json::from_slice(&b).with_context(|| format!("parse {}", path.display())))
+        .transpose()
+}
+
+/// Keep a corrupt file for inspection rather than overwriting it.
+fn set_aside(path: &Path, why: &serde_json::Error) {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".corrupt");
+    let backup = PathBuf::from(backup);
+    match std::fs::rename(path, &backup) {
+        Ok(()) => warn!(
+            "{} is corrupt ({why}); moved to {}, starting from defaults",
+            path.display(),
+            backup.display()
+        ),
+        Err(e) => warn!(
+            "{} is corrupt ({why}), starting from defaults; it will be overwritten \
+             (moving it to {} failed: {e})",
+            path.display(),
+            backup.display()
+        ),
+    }
+}
+
+/// Temp file + fsync + rename + fsync of the directory: after a power cut
+/// the file is either the old or the new version, never torn.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut f = std::fs::File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+    f.write_all(data)
+        .and_then(|()| f.sync_all())
+        .with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("rename to {}", path.display()))?;
+    if let Some(dir) = dir {
+        // Best effort: persists the rename itself.
+        if let Ok(d) = std::fs::File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Scratch;
+
+    #[test]
+    fn writes_only_changes_and_at_most_once_per_interval() {
+        let dir = Scratch::new("store");
+        let path = dir.join("s.json");
+        let path_str = path.to_string_lossy();
+        let min = Duration::from_secs(60);
+        let t0 = Instant::now();
+
+        let (mut store, loaded) = StateStore::<Vec<u32>>::open(&path_str, min).unwrap();
+        assert_eq!(loaded, None);
+        assert!(store.update(&vec![1], t0).unwrap());
+        // Unchanged: no write.
+        assert!(!store.update(&vec![1], t0 + min).unwrap());
+        // Changed, but too soon: deferred until the interval passes.
+        assert!(!store
+            .update(&vec![1, 2], t0 + Duration::from_secs(10))
+            .unwrap());
+        assert!(!store.tick(t0 + Duration::from_secs(59)).unwrap());
+        assert_eq!(load::<Vec<u32>>(&path).unwrap(), Some(vec![1]));
+        assert!(store.tick(t0 + min).unwrap());
+        assert_eq!(load::<Vec<u32>>(&path).unwrap(), Some(vec![1, 2]));
+
+        // flush ignores the interval; a reopened store sees the value.
+        assert!(!store.update(&vec![3], t0 + min).unwrap());
+        assert!(store.flush().unwrap());
+        let (_, loaded) = StateStore::<Vec<u32>>::open(&path_str, min).unwrap();
+        assert_eq!(loaded, Some(vec![3]));
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn corrupt_file_is_set_aside_and_replaced() {
+        let dir = Scratch::new("store-bad");
+        let path = dir.join("s.json");
+        let backup = dir.join("s.json.corrupt");
+        let path_str = path.to_string_lossy();
+
+        // Cut off mid-write, zeroed, or not even UTF-8: none stops the open.
+        let torn: [&[u8]; 3] = [b"[1, 2", b"\0\0\0\0", b"\xff\xfe\x00"];
+        for torn in torn {
+            std::fs::write(&path, torn).unwrap();
+            let (mut store, loaded) =
+                StateStore::<Vec<u32>>::open(&path_str, Duration::ZERO).unwrap();
+            assert_eq!(loaded, None);
+            assert!(!path.exists());
+            assert_eq!(std::fs::read(&backup).unwrap(), torn);
+            // Persisting carries on with a fresh file.
+            assert!(store.update(&vec![7], Instant::now()).unwrap());
+            assert_eq!(load::<Vec<u32>>(&path).unwrap(), Some(vec![7]));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_path_keeps_state_in_memory() {
+        let (mut store, loaded) = StateStore::<u8>::open("", Duration::ZERO).unwrap();
+        assert_eq!(loaded, None);
+        assert!(!store.update(&1, Instant::now()).unwrap());
+    }
+}
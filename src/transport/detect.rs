@@ -2,33 +2,153 @@ use std::fs;
 use std::path::Path;
 
 use anyhow::{anyhow, Result};
+use tokio_serial::SerialPortType;
 use tracing::{debug, info, warn};
 
+/// Raspberry Pi Ltd USB vendor ID — what the RP2040 enumerates as unless the
+/// firmware overrides it.
+const RPI_USB_VID: u16 = 0x2E8A;
+
 /// Resolve a configured serial port: either an explicit path or "auto".
-pub fn resolve_port(configured: &str) -> Result<String> {
-    if configured == "auto" {
-        info!("auto-detecting Web3_Pi_UPS device...");
-        detect_ups_port()
-            .ok_or_else(|| anyhow!("Web3_Pi_UPS device not found. Check USB connection."))
+/// With `match_serial` non-empty, "auto" only accepts the USB device with
+/// that serial number, whatever its product name; otherwise it looks for
+/// the `products` (`[serial].match_products`).
+pub fn resolve_port(configured: &str, match_serial: &str, products: &[String]) -> Result<String> {
+    if configured == "auto" && !match_serial.is_empty() {
+        info!("auto-detecting UPS with USB serial {match_serial:?}...");
+        find_by_serial(match_serial).ok_or_else(|| {
+            anyhow!(
+                "no USB serial device with serial number {match_serial:?}. Check USB connection."
+            )
+        })
+    } else if configured == "auto" {
+        info!("auto-detecting UPS device ({})...", products.join(", "));
+        detect_ups_port(products)
+            .ok_or_else(|| anyhow!("UPS device not found. Check USB connection."))
     } else {
+        if !match_serial.is_empty() {
+            warn!("[serial].match_serial is ignored with an explicit port ({configured})");
+        }
         Ok(configured.to_string())
     }
 }
 
-/// Find the UPS serial port via sysfs.
+/// How confident we are that a port is the UPS. Lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Match {
+    /// USB product contains `products[n]`; 0 is the one we hope for.
+    Product(usize),
+    /// No listed product, but a Raspberry Pi VID (legacy bring-up firmware).
+    Vid,
+}
+
+fn classify(products: &[String], product: Option<&str>, vid: Option<u16>) -> Option<Match> {
+    let listed = product
+        .map(str::trim)
+        .and_then(|found| products.iter().position(|want| found.contains(want.trim())));
+    match listed {
+        Some(n) => Some(Match::Product(n)),
+        None if vid == Some(RPI_USB_VID) => Some(Match::Vid),
+        None => None,
+    }
+}
+
+/// Find the UPS serial port.
 ///
 /// Priority:
-///   1. USB product == "Web3_Pi_UPS" (production firmware)
-///   2. USB product contains "Pico" (legacy bring-up firmware)
+///   1. USB product contains an entry of `products`, earlier entries first
+///      (by default "Web3_Pi_UPS", production firmware, then "Pico",
+///      legacy bring-up firmware)
+///   2. A Raspberry Pi USB VID (legacy bring-up firmware)
 ///   3. First available `/dev/ttyACM*` (last-ditch fallback)
-pub fn detect_ups_port() -> Option<String> {
-    let tty_class = Path::new("/sys/class/tty");
+///
+/// Tiers 1–2 are tried via sysfs first (Linux, firmware-product-aware), then
+/// via `serialport` enumeration, which also works where the sysfs layout
+/// differs and on macOS for local development.
+pub fn detect_ups_port(products: &[String]) -> Option<String> {
+    let sysfs = scan_sysfs(products);
+    let preferred = products.first().map_or("", String::as_str);
+    match sysfs.best.or_else(|| scan_serialport(products)) {
+        Some((Match::Product(0), port)) => {
+            info!("auto-detected {preferred} at {port}");
+            return Some(port);
+        }
+        Some((Match::Product(n), port)) => {
+            warn!("{preferred} not found, using {} at {port}", products[n]);
+            return Some(port);
+        }
+        Some((Match::Vid, port)) => {
+            warn!("no listed UPS product found, using Raspberry Pi device at {port} (legacy firmware)");
+            return Some(port);
+        }
+        None => {}
+    }
+    if let Some(port) = sysfs.first_ttyacm {
+        warn!("no known UPS device found, falling back to first ttyACM: {port}");
+        return Some(port);
+    }
+    None
+}
+
+fn serial_matches(found: &str, want: &str) -> bool {
+    found.trim() == want.trim()
+}
+
+/// The port of the USB device whose serial number is `want`: sysfs first,
+/// then `serialport` enumeration, like [`detect_ups_port`]; no fallback.
+fn find_by_serial(want: &str) -> Option<String> {
+    if let Ok(entries) = fs::read_dir(Path::new("/sys/class/tty")) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name_str = name.to_string_lossy();
+            if !name_str.starts_with("ttyACM") {
+                continue;
+            }
+            let serial_path = entry.path().join("device/../serial");
+            let Ok(serial) = fs::read_to_string(&serial_path) else {
+                continue;
+            };
+            if serial_matches(&serial, want) {
+                let port = format!("/dev/{name_str}");
+                info!("found USB serial {want:?} at {port}");
+                return Some(port);
+            }
+            debug!(
+                "/dev/{name_str} has USB serial {:?}, not {want:?}",
+                serial.trim()
+            );
+        }
+    }
+    let ports = tokio_serial::available_ports().ok()?;
+    ports.into_iter().find_map(|port| {
+        let SerialPortType::UsbPort(usb) = &port.port_type else {
+            return None;
+        };
+        if port.port_name.starts_with("/dev/tty.") {
+            return None;
+        }
+        let serial = usb.serial_number.as_deref()?;
+        serial_matches(serial, want).then(|| {
+            info!("found USB serial {want:?} at {}", port.port_name);
+            port.port_name.clone()
+        })
+    })
+}
 
-    let mut web3_pi_ups: Option<String> = None;
-    let mut raspberry_pi_pico: Option<String> = None;
-    let mut first_ttyacm: Option<String> = None;
+struct SysfsScan {
+    best: Option<(Match, String)>,
+    first_ttyacm: Option<String>,
+}
 
-    let entries = fs::read_dir(tty_class).ok()?;
+fn scan_sysfs(products: &[String]) -> SysfsScan {
+    let mut scan = SysfsScan {
+        best: None,
+        first_ttyacm: None,
+    };
+    let Ok(entries) = fs::read_dir(Path::new("/sys/class/tty")) else {
+        debug!("/sys/class/tty not readable; skipping sysfs detection");
+        return scan;
+    };
     for entry in entries.flatten() {
         let name = entry.file_name();
         let name_str = name.to_string_lossy();
@@ -39,34 +159,121 @@ pub fn detect_ups_port() -> Option<String> {
 
         let device_path = format!("/dev/{name_str}");
 
-        if first_ttyacm.is_none() {
-            first_ttyacm = Some(device_path.clone());
+        if scan.first_ttyacm.is_none() {
+            scan.first_ttyacm = Some(device_path.clone());
         }
 
         let product_path = entry.path().join("device/../product");
-        if let Ok(product) = fs::read_to_string(&product_path) {
-            let product = product.trim();
-            if product == "Web3_Pi_UPS" {
-                web3_pi_ups = Some(device_path.clone());
-                debug!("found Web3_Pi_UPS at {device_path}");
-            } else if product.contains("Pico") && raspberry_pi_pico.is_none() {
-                raspberry_pi_pico = Some(device_path.clone());
-                debug!("found Raspberry Pi Pico at {device_path} (legacy firmware candidate)");
-            }
+        let Ok(product) = fs::read_to_string(&product_path) else {
+            continue;
+        };
+        let Some(m) = classify(products, Some(&product), None) else {
+            continue;
+        };
+        debug!("found {} at {device_path} ({m:?})", product.trim());
+        if scan.best.as_ref().is_none_or(|(b, _)| m < *b) {
+            scan.best = Some((m, device_path));
         }
     }
+    scan
+}
 
-    if let Some(port) = web3_pi_ups {
-        info!("auto-detected Web3_Pi_UPS at {port}");
-        return Some(port);
+fn scan_serialport(products: &[String]) -> Option<(Match, String)> {
+    let ports = match tokio_serial::available_ports() {
+        Ok(p) => p,
+        Err(e) => {
+            debug!("serialport enumeration failed: {e}");
+            return None;
+        }
+    };
+    let mut best: Option<(Match, String)> = None;
+    for port in ports {
+        let SerialPortType::UsbPort(usb) = &port.port_type else {
+            continue;
+        };
+        // macOS lists every device twice; the call-out (`cu.*`) node is the
+        // one that doesn't block waiting for carrier detect.
+        if port.port_name.starts_with("/dev/tty.") {
+            continue;
+        }
+        let Some(m) = classify(products, usb.product.as_deref(), Some(usb.vid)) else {
+            continue;
+        };
+        debug!(
+            port = %port.port_name,
+            vid = format!("{:#06x}", usb.vid),
+            pid = format!("{:#06x}", usb.pid),
+            product = ?usb.product,
+            manufacturer = ?usb.manufacturer,
+            "serialport candidate"
+        );
+        if best.as_ref().is_none_or(|(b, _)| m < *b) {
+            best = Some((m, port.port_name));
+        }
     }
-    if let Some(port) = raspberry_pi_pico {
-        warn!("Web3_Pi_UPS not found, using Raspberry Pi Pico at {port} (legacy firmware)");
-        return Some(port);
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<String> {
+        crate::config::default_match_products()
     }
-    if let Some(port) = first_ttyacm {
-        warn!("no known UPS device found, falling back to first ttyACM: {port}");
-        return Some(port);
+
+    #[test]
+    fn classify_by_product() {
+        let p = defaults();
+        assert_eq!(
+            classify(&p, Some("Web3_Pi_UPS\n"), None),
+            Some(Match::Product(0))
+        );
+        assert_eq!(classify(&p, Some("Pico"), None), Some(Match::Product(1)));
+        assert_eq!(classify(&p, Some("FT232R USB UART"), None), None);
+    }
+
+    #[test]
+    fn configured_products_are_ranked_in_order() {
+        let p = vec!["Acme_UPS".to_string(), "Web3_Pi_UPS".to_string()];
+        assert_eq!(
+            classify(&p, Some("Acme_UPS v2"), None),
+            Some(Match::Product(0))
+        );
+        assert_eq!(
+            classify(&p, Some("Web3_Pi_UPS"), None),
+            Some(Match::Product(1))
+        );
+        assert!(Match::Product(0) < Match::Product(1));
+        assert!(Match::Product(1) < Match::Vid);
+        // "Pico" isn't listed any more; only the VID still points at it.
+        assert_eq!(classify(&p, Some("Pico"), None), None);
+        assert_eq!(
+            classify(&p, Some("Pico"), Some(RPI_USB_VID)),
+            Some(Match::Vid)
+        );
+    }
+
+    #[test]
+    fn serial_number_match_ignores_surrounding_whitespace() {
+        assert!(serial_matches("E6614C311B4A8F2D\n", "E6614C311B4A8F2D"));
+        assert!(!serial_matches("E6614C311B4A8F2D", "E6614C311B4A8F2E"));
+        assert!(!serial_matches("E6614C311B4A8F2D", "E6614C"));
+    }
+
+    #[test]
+    fn classify_falls_back_to_vid() {
+        let p = defaults();
+        assert_eq!(classify(&p, None, Some(RPI_USB_VID)), Some(Match::Vid));
+        assert_eq!(
+            classify(&p, Some("Board CDC"), Some(RPI_USB_VID)),
+            Some(Match::Vid)
+        );
+        assert_eq!(classify(&p, None, Some(0x0403)), None);
+        // Product string wins over VID.
+        assert_eq!(
+            classify(&p, Some("Web3_Pi_UPS"), Some(RPI_USB_VID)),
+            Some(Match::Product(0))
+        );
     }
-    None
 }
@@ -0,0 +1,267 @@
+//! Legacy text telemetry: older firmware prints one line per sample, e.g.
+//! `SOC=42 VI=19800 BV=7400`, instead of WUPS frames. Each such line becomes
+//! a `power.status` frame, so everything past the transport sees the same
+//! data either way. Lines that look like telemetry but lack a required key
+//! or carry a value that doesn't parse are counted by reason
+//! ([`KvRejects`]) so a firmware format change shows up in `info`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::proto::payloads::PowerStatusV1;
+use crate::proto::{addr, class, flag, op, Frame};
+use crate::soc::SocCurve;
+
+/// Why a text line didn't become a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvReject {
+    /// Not key-value telemetry at all (boot banner, binary noise, blank);
+    /// not worth counting.
+    NotKv,
+    /// A telemetry line without a required key: `VI`, or `SOC`/`BV`.
+    Missing(&'static str),
+    /// A known key whose value doesn't parse (`SOC=null`, `VI=-5`).
+    Malformed { key: String, value: String },
+}
+
+/// Parse one line of space-separated `KEY=VALUE` pairs (keys are
+/// case-insensitive):
+///
+/// | key   | field          | unit       |
+/// |-------|----------------|------------|
+/// | `VI`  | `vbus_in_mv`   | mV         |
+/// | `VO`  | `vbus_out_mv`  | mV         |
+/// | `IO`  | `ibus_out_ma`  | mA         |
+/// | `BV`  | `vbat_mv`      | mV (pack)  |
+/// | `BA`  | `ibat_ma`      | mA, + = charging |
+/// | `T`   | `temp_dc`      | 0.1 °C, signed (see [`parse_temp_dc`]) |
+/// | `CS`  | `charge_state` | 0–3        |
+/// | `F`   | `faults`       | bitmask    |
+/// | `VS`  | `pd_contract_mv` | mV, as the source reports it |
+/// | `IS`  | `pd_contract_ma` | mA, as the source reports it |
+/// | `SOC` | `vbat_mv`, if no `BV` (the pack voltage `curve` gives it) | % |
+///
+/// Unknown keys are skipped. A line is telemetry only if every token is
+/// `KEY=VALUE` and at least one key is known — so stray binary or a boot
+/// banner isn't mistaken for a sample. Telemetry must then carry `VI` and
+/// one of `SOC` / `BV`, and every known value must parse.
+pub fn parse_kv_line(line: &str, curve: SocCurve) -> Result<PowerStatusV1, KvReject> {
+    let mut p = PowerStatusV1::default();
+    let mut known = 0;
+    let mut soc = None;
+    let mut have_bv = false;
+    let mut have_vi = false;
+    let mut malformed = None;
+    for token in line.split_whitespace() {
+        let (key, value) = token.split_once('=').ok_or(KvReject::NotKv)?;
+        let key = key.to_ascii_uppercase();
+        let parsed = match key.as_str() {
+            "VI" => value.parse().map(|v| {
+                p.vbus_in_mv = v;
+                have_vi = true;
+            }),
+            "VO" => value.parse().map(|v| p.vbus_out_mv = v),
+            "IO" => value.parse().map(|v| p.ibus_out_ma = v),
+            "BV" => value.parse().map(|v| {
+                p.vbat_mv = v;
+                have_bv = true;
+            }),
+            "BA" => value.parse().map(|v| p.ibat_ma = v),
+            "T" => parse_temp_dc(value).map(|v| p.temp_dc = v),
+            "CS" => value.parse().map(|v| p.charge_state = v),
+            "F" => value.parse().map(|v| p.faults = v),
+            "VS" => value.parse().map(|v| p.pd_contract_mv = v),
+            "IS" => value.parse().map(|v| p.pd_contract_ma = v),
+            "SOC" => value.parse::<u8>().map(|v| soc = Some(v.min(100))),
+            _ => continue,
+        };
+        known += 1;
+        if parsed.is_err() && malformed.is_none() {
+            malformed = Some(KvReject::Malformed {
+                key,
+                value: value.to_string(),
+            });
+        }
+    }
+    if known == 0 {
+        return Err(KvReject::NotKv);
+    }
+    if let Some(reject) = malformed {
+        return Err(reject);
+    }
+    if !have_vi {
+        return Err(KvReject::Missing("VI"));
+    }
+    match (soc, have_bv) {
+        (_, true) => {}
+        (Some(soc), false) => p.vbat_mv = curve.pack_mv(soc),
+        (None, false) => return Err(KvReject::Missing("SOC")),
+    }
+    Ok(p)
+}
+
+/// A `T` value: signed, or a below-zero reading printed as its unsigned
+/// 16- or 32-bit wrap (`65481` / `4294967241` for -5.5 °C), as firmware
+/// that formats the raw register with `%u` does. No real reading is
+/// anywhere near those magnitudes, so the wrap can't be mistaken for heat.
+fn parse_temp_dc(value: &str) -> Result<i16, std::num::ParseIntError> {
+    let v: i64 = value.parse()?;
+    let wrapped = match v {
+        0x8000..=0xFFFF => v - 0x1_0000,
+        0xFFFF_8000..=0xFFFF_FFFF => v - 0x1_0000_0000,
+        _ => v,
+    };
+    // Out of i16 range after unwrapping: let `parse` report the overflow.
+    i16::try_from(wrapped).or_else(|_| value.parse::<i16>())
+}
+
+/// Telemetry lines rejected so far, by reason. Shared between the serial
+/// reader (across reconnects) and [`State`](crate::state::State), which
+/// reports them in `info`.
+#[derive(Debug, Default)]
+pub struct KvRejects {
+    missing: AtomicU64,
+    malformed: AtomicU64,
+}
+
+/// A point-in-time copy of [`KvRejects`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvRejectCounts {
+    pub missing: u64,
+    pub malformed: u64,
+}
+
+impl KvRejects {
+    /// Count `reject` and return the new total for its reason; 0 for
+    /// [`KvReject::NotKv`], which isn't counted.
+    pub fn record(&self, reject: &KvReject) -> u64 {
+        let counter = match reject {
+            KvReject::NotKv => return 0,
+            KvReject::Missing(_) => &self.missing,
+            KvReject::Malformed { .. } => &self.malformed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn counts(&self) -> KvRejectCounts {
+        KvRejectCounts {
+            missing: self.missing.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// `p` as the `power.status` EVENT the firmware would have sent.
+pub fn power_status_frame(p: &PowerStatusV1) -> Frame {
+    Frame {
+        dst: addr::RPI,
+        src: addr::CH32X,
+        class: class::POWER,
+        op: op::power::STATUS,
+        flags: flag::EVENT,
+        seq: 0,
+        payload: p.encode().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Chemistry;
+
+    fn parse(line: &str) -> Result<PowerStatusV1, KvReject> {
+        parse_kv_line(line, SocCurve::STOCK)
+    }
+
+    #[test]
+    fn parses_key_value_lines() {
+        let p = parse("VI=19800 BV=7400 BA=-850 t=315 CS=1 X=?\r").unwrap();
+        assert_eq!(
+            (
+                p.vbus_in_mv,
+                p.vbat_mv,
+                p.ibat_ma,
+                p.temp_dc,
+                p.charge_state
+            ),
+            (19_800, 7_400, -850, 315, 1)
+        );
+        let p = parse("VI=19800 BV=7400 VS=20000 IS=1500").unwrap();
+        assert_eq!((p.pd_contract_mv, p.pd_contract_ma), (20_000, 1_500));
+        // SOC alone picks the matching pack voltage; BV wins if both are sent.
+        let p = parse("SOC=42 VI=19800").unwrap();
+        assert_eq!(p.vbat_mv, SocCurve::STOCK.pack_mv(42));
+        assert_eq!(parse("SOC=42 BV=7000 VI=0").unwrap().vbat_mv, 7_000);
+
+        for junk in ["", "UPS firmware v1.2 booting", "X=1 Y=2"] {
+            assert_eq!(parse(junk), Err(KvReject::NotKv), "{junk:?}");
+        }
+    }
+
+    #[test]
+    fn soc_without_bv_follows_the_configured_curve() {
+        let lifepo4 = SocCurve::new(Chemistry::Lifepo4, 4);
+        let p = parse_kv_line("SOC=42 VI=19800", lifepo4).unwrap();
+        assert_eq!(p.vbat_mv, lifepo4.pack_mv(42));
+        assert_ne!(p.vbat_mv, SocCurve::STOCK.pack_mv(42));
+        // Read back on the same curve, it is the SOC the firmware sent.
+        assert_eq!(lifepo4.soc_pct(p.vbat_mv), 42);
+    }
+
+    #[test]
+    fn below_zero_temperatures_parse_signed_or_wrapped() {
+        for t in ["-55", "65481", "4294967241"] {
+            let p = parse(&format!("VI=0 BV=7400 T={t}")).unwrap();
+            assert_eq!(p.temp_dc, -55, "T={t}");
+        }
+        assert_eq!(parse("VI=0 BV=7400 T=-400").unwrap().temp_dc, -400);
+        // Neither a plausible signed value nor a 16/32-bit wrap.
+        for t in ["70000", "-40000", "4294901759"] {
+            assert!(
+                matches!(
+                    parse(&format!("VI=0 BV=7400 T={t}")),
+                    Err(KvReject::Malformed { .. })
+                ),
+                "T={t}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_and_malformed_fields_are_told_apart() {
+        assert_eq!(parse("SOC=42 BA=-800"), Err(KvReject::Missing("VI")));
+        assert_eq!(parse("VI=19800 BA=-800"), Err(KvReject::Missing("SOC")));
+        let malformed = |key: &str, value: &str| {
+            Err(KvReject::Malformed {
+                key: key.into(),
+                value: value.into(),
+            })
+        };
+        assert_eq!(parse("VI=abc BV=7400"), malformed("VI", "abc"));
+        assert_eq!(parse("VI=-5 BV=7400"), malformed("VI", "-5"));
+        assert_eq!(parse("soc=null VI=0"), malformed("SOC", "null"));
+        // A bad value wins over a missing key: it's the likelier firmware change.
+        assert_eq!(parse("SOC=\"42\""), malformed("SOC", "\"42\""));
+
+        let rejects = KvRejects::default();
+        assert_eq!(rejects.record(&KvReject::Missing("VI")), 1);
+        assert_eq!(rejects.record(&KvReject::Missing("SOC")), 2);
+        assert_eq!(rejects.record(&KvReject::NotKv), 0);
+        rejects.record(&parse("VI=x").unwrap_err());
+        assert_eq!(
+            rejects.counts(),
+            KvRejectCounts {
+                missing: 2,
+                malformed: 1
+            }
+        );
+    }
+
+    #[test]
+    fn frame_round_trips_through_the_decoder() {
+        let p = parse("VI=5000 BV=7100 BA=-1200").unwrap();
+        let frame = power_status_frame(&p);
+        assert_eq!(PowerStatusV1::decode(&frame.payload).unwrap(), p);
+    }
+}
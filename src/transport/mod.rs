@@ -1,5 +1,6 @@
 pub mod detect;
+pub mod kv;
 pub mod serial;
 
 pub use detect::resolve_port;
-pub use serial::{spawn_serial_tasks, OutboundFrame};
+pub use serial::{spawn_serial_tasks, OutboundFrame, ReadCounts, ReadStats};
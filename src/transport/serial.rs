@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -6,7 +9,63 @@ use tokio::sync::mpsc;
 use tokio_serial::SerialPortBuilderExt;
 use tracing::{debug, error, info, warn};
 
-use crate::proto::{Deframer, Frame};
+use super::kv::{parse_kv_line, power_status_frame, KvReject, KvRejects};
+use crate::config::SerialFormat;
+use crate::proto::{Deframer, Frame, FRAMING_BYTES, MAX_PAYLOAD};
+use crate::soc::SocCurve;
+
+/// Bytes pulled per `read`: several max-size frames, so a burst from the
+/// firmware is drained in one syscall. Every complete frame in a chunk is
+/// decoded before the next read; a trailing partial frame stays in the
+/// deframer until the rest arrives.
+const READ_CHUNK: usize = 4 * (FRAMING_BYTES + MAX_PAYLOAD);
+
+/// Longer text "lines" are binary noise, not key-value telemetry.
+const MAX_KV_LINE: usize = 256;
+
+/// After the first, only every this-many-th rejected telemetry line of a
+/// kind is logged above debug.
+const KV_REJECT_LOG_EVERY: u64 = 100;
+
+/// What the reader has pulled off the link, for `benchmark`.
+#[derive(Debug, Default)]
+pub struct ReadStats {
+    bytes: AtomicU64,
+    frames: AtomicU64,
+    frame_errors: AtomicU64,
+    decode_ns: AtomicU64,
+}
+
+/// A point-in-time copy of [`ReadStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadCounts {
+    pub bytes: u64,
+    /// Frames decoded, WUPS or key-value.
+    pub frames: u64,
+    /// WUPS frames that failed to decode (bad CRC, bad length, …).
+    pub frame_errors: u64,
+    /// Time spent decoding, all chunks together.
+    pub decode_ns: u64,
+}
+
+impl ReadStats {
+    fn record(&self, bytes: usize, frames: usize, frame_errors: u64, took: Duration) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frames.fetch_add(frames as u64, Ordering::Relaxed);
+        self.frame_errors.fetch_add(frame_errors, Ordering::Relaxed);
+        self.decode_ns
+            .fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> ReadCounts {
+        ReadCounts {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            frames: self.frames.load(Ordering::Relaxed),
+            frame_errors: self.frame_errors.load(Ordering::Relaxed),
+            decode_ns: self.decode_ns.load(Ordering::Relaxed),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct OutboundFrame {
@@ -20,8 +79,15 @@ pub struct SerialHandles {
     pub writer: tokio::task::JoinHandle<()>,
 }
 
-pub async fn spawn_serial_tasks(port_path: String, baud: u32) -> Result<SerialHandles> {
-    info!("opening serial port: {port_path} at {baud} baud");
+pub async fn spawn_serial_tasks(
+    port_path: String,
+    baud: u32,
+    format: SerialFormat,
+    curve: SocCurve,
+    kv_rejects: Arc<KvRejects>,
+    read_stats: Arc<ReadStats>,
+) -> Result<SerialHandles> {
+    info!("opening serial port: {port_path} at {baud} baud ({format:?})");
     let port = tokio_serial::new(&port_path, baud)
         .timeout(Duration::from_millis(100))
         .open_native_async()
@@ -31,7 +97,9 @@ pub async fn spawn_serial_tasks(port_path: String, baud: u32) -> Result<SerialHa
     let (in_tx, in_rx) = mpsc::channel::<Frame>(64);
     let (out_tx, out_rx) = mpsc::channel::<OutboundFrame>(64);
 
-    let reader = tokio::spawn(reader_loop(rd, in_tx));
+    let reader = tokio::spawn(reader_loop(
+        rd, in_tx, format, curve, kv_rejects, read_stats,
+    ));
     let writer = tokio::spawn(writer_loop(wr, out_rx));
 
     Ok(SerialHandles {
@@ -42,53 +110,133 @@ pub async fn spawn_serial_tasks(port_path: String, baud: u32) -> Result<SerialHa
     })
 }
 
-async fn reader_loop<R: tokio::io::AsyncRead + Unpin>(mut rd: R, sink: mpsc::Sender<Frame>) {
+/// `Auto` settles on whichever of WUPS frames / key-value lines decodes
+/// first and sticks with it for the connection. A chunk is decoded in full
+/// before its frames are handed on, so `read_stats` times the decoding
+/// alone.
+async fn reader_loop<R: tokio::io::AsyncRead + Unpin>(
+    mut rd: R,
+    sink: mpsc::Sender<Frame>,
+    mut format: SerialFormat,
+    curve: SocCurve,
+    kv_rejects: Arc<KvRejects>,
+    read_stats: Arc<ReadStats>,
+) {
     let mut deframer = Deframer::new();
-    let mut buf = [0u8; 256];
+    let mut line = Vec::new();
+    let mut buf = [0u8; READ_CHUNK];
     loop {
-        match rd.read(&mut buf).await {
+        let n = match rd.read(&mut buf).await {
             Ok(0) => {
                 warn!("serial read EOF; reader exiting");
                 return;
             }
-            Ok(n) => {
-                for &b in &buf[..n] {
-                    if let Some(result) = deframer.feed(b) {
-                        match result {
-                            Ok(frame) => {
-                                debug!(
-                                    src = frame.src,
-                                    dst = frame.dst,
-                                    class = frame.class,
-                                    op = frame.op,
-                                    flags = frame.flags,
-                                    seq = frame.seq,
-                                    payload_len = frame.payload.len(),
-                                    "rx"
-                                );
-                                if sink.send(frame).await.is_err() {
-                                    warn!("inbound channel closed; reader exiting");
-                                    return;
-                                }
+            Ok(n) => n,
+            Err(e) => {
+                error!("serial read error: {e}");
+                return;
+            }
+        };
+        let started = Instant::now();
+        let mut decoded = Vec::new();
+        let mut frame_errors = 0;
+        for &b in &buf[..n] {
+            let mut frame = None;
+            if format != SerialFormat::Kv {
+                match deframer.feed(b) {
+                    Some(Ok(f)) => {
+                        if format == SerialFormat::Auto {
+                            info!("serial: WUPS frames detected");
+                            format = SerialFormat::Wups;
+                        }
+                        frame = Some(f);
+                    }
+                    Some(Err(e)) if format == SerialFormat::Wups => {
+                        warn!("frame parse error: {e}");
+                        frame_errors += 1;
+                    }
+                    _ => {}
+                }
+            }
+            if format != SerialFormat::Wups {
+                if b != b'\n' {
+                    if line.len() < MAX_KV_LINE {
+                        line.push(b);
+                    }
+                } else {
+                    let text = String::from_utf8_lossy(&line);
+                    match parse_kv_line(&text, curve) {
+                        Ok(p) => {
+                            if format == SerialFormat::Auto {
+                                info!("serial: key-value text telemetry detected");
+                                format = SerialFormat::Kv;
+                            }
+                            frame = Some(power_status_frame(&p));
+                        }
+                        Err(KvReject::NotKv) => {
+                            if format == SerialFormat::Kv && !text.trim().is_empty() {
+                                debug!(line = %text.trim(), "kv: line skipped")
                             }
-                            Err(e) => warn!("frame parse error: {e}"),
                         }
+                        Err(reject) => log_kv_reject(&reject, kv_rejects.record(&reject), &text),
                     }
+                    line.clear();
                 }
             }
-            Err(e) => {
-                error!("serial read error: {e}");
+            decoded.extend(frame);
+        }
+        read_stats.record(n, decoded.len(), frame_errors, started.elapsed());
+        for frame in decoded {
+            debug!(
+                src = frame.src,
+                dst = frame.dst,
+                class = frame.class,
+                op = frame.op,
+                flags = frame.flags,
+                seq = frame.seq,
+                payload_len = frame.payload.len(),
+                "rx"
+            );
+            if sink.send(frame).await.is_err() {
+                warn!("inbound channel closed; reader exiting");
                 return;
             }
         }
     }
 }
 
+fn log_kv_reject(reject: &KvReject, total: u64, line: &str) {
+    let what = match reject {
+        KvReject::Missing(key) => format!("missing required key {key}"),
+        KvReject::Malformed { key, value } => format!("malformed {key}={value}"),
+        KvReject::NotKv => return,
+    };
+    if total == 1 || total.is_multiple_of(KV_REJECT_LOG_EVERY) {
+        warn!(line = %line.trim(), "kv: telemetry line dropped, {what} ({total} so far); firmware format changed?");
+    } else {
+        debug!(line = %line.trim(), "kv: telemetry line dropped, {what}");
+    }
+}
+
+/// A port that opens but refuses writes (a read-only device node, a USB
+/// quirk) still delivers telemetry: after the first failed write the link
+/// carries on read-only for the rest of the connection. Outbound frames are
+/// then dropped, so senders neither block nor see the link closed, and the
+/// failure is logged once rather than per frame.
 async fn writer_loop<W: tokio::io::AsyncWrite + Unpin>(
     mut wr: W,
     mut src: mpsc::Receiver<OutboundFrame>,
 ) {
+    let mut read_only = false;
     while let Some(out) = src.recv().await {
+        if read_only {
+            debug!(
+                class = out.frame.class,
+                op = out.frame.op,
+                "tx dropped: serial link is read-only"
+            );
+            continue;
+        }
         let bytes = match out.frame.encode() {
             Ok(b) => b,
             Err(e) => {
@@ -104,8 +252,143 @@ async fn writer_loop<W: tokio::io::AsyncWrite + Unpin>(
             "tx"
         );
         if let Err(e) = wr.write_all(&bytes).await {
-            error!("serial write error: {e}");
-            return;
+            warn!(
+                "serial write error: {e}; continuing read-only until the next reconnect \
+                 (frames to the UPS are dropped, telemetry still read)"
+            );
+            read_only = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{addr, class, flag, op};
+
+    fn frame(seq: u8) -> Frame {
+        Frame {
+            dst: addr::RPI,
+            src: addr::CH32X,
+            class: class::SYSTEM,
+            op: op::system::PING,
+            flags: flag::EVENT,
+            seq,
+            payload: vec![seq; 20],
         }
     }
+
+    #[tokio::test]
+    async fn burst_in_one_read_is_fully_drained() {
+        let (mut tx, rx) = tokio::io::duplex(4096);
+        let (sink, mut frames) = mpsc::channel(16);
+        let stats = Arc::new(ReadStats::default());
+        let reader = tokio::spawn(reader_loop(
+            rx,
+            sink,
+            SerialFormat::Wups,
+            SocCurve::STOCK,
+            Arc::default(),
+            stats.clone(),
+        ));
+
+        let mut burst = Vec::new();
+        for seq in 0..4 {
+            frame(seq).encode_into(&mut burst).unwrap();
+        }
+        let last = frame(4).encode().unwrap();
+        let (head, tail) = last.split_at(last.len() / 2);
+        burst.extend_from_slice(head);
+        tx.write_all(&burst).await.unwrap();
+        for seq in 0..4 {
+            assert_eq!(frames.recv().await.unwrap().seq, seq);
+        }
+        // The partial frame waited for the rest.
+        assert!(frames.try_recv().is_err());
+        tx.write_all(tail).await.unwrap();
+        assert_eq!(frames.recv().await.unwrap(), frame(4));
+        let counts = stats.counts();
+        assert_eq!(
+            (counts.bytes, counts.frames, counts.frame_errors),
+            ((burst.len() + tail.len()) as u64, 5, 0)
+        );
+
+        drop(tx);
+        reader.await.unwrap();
+    }
+
+    /// Accepts nothing.
+    struct WriteProtected;
+
+    impl tokio::io::AsyncWrite for WriteProtected {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            _: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            std::task::Poll::Ready(Err(std::io::ErrorKind::PermissionDenied.into()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_leaves_the_link_read_only() {
+        let (out_tx, out_rx) = mpsc::channel(1);
+        let writer = tokio::spawn(writer_loop(WriteProtected, out_rx));
+        // Had the writer given up, the channel would close under the sender.
+        for seq in 0..5 {
+            let out = OutboundFrame { frame: frame(seq) };
+            out_tx.send(out).await.unwrap();
+        }
+        assert!(!writer.is_finished());
+        drop(out_tx);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn auto_detects_key_value_text() {
+        use crate::proto::payloads::PowerStatusV1;
+
+        let (mut tx, rx) = tokio::io::duplex(4096);
+        let (sink, mut frames) = mpsc::channel(16);
+        let rejects = Arc::new(KvRejects::default());
+        let reader = tokio::spawn(reader_loop(
+            rx,
+            sink,
+            SerialFormat::Auto,
+            SocCurve::STOCK,
+            rejects.clone(),
+            Arc::default(),
+        ));
+
+        tx.write_all(b"booting...\r\nVI=19800 BV=74").await.unwrap();
+        tx.write_all(b"00 BA=500\r\nSOC=10\nSOC=x VI=0\nVI=0 SOC=10\n")
+            .await
+            .unwrap();
+        let f = frames.recv().await.unwrap();
+        assert_eq!((f.class, f.op), (class::POWER, op::power::STATUS));
+        let p = PowerStatusV1::decode(&f.payload).unwrap();
+        assert_eq!((p.vbus_in_mv, p.vbat_mv, p.ibat_ma), (19_800, 7_400, 500));
+        assert!(frames.recv().await.is_some());
+
+        drop(tx);
+        reader.await.unwrap();
+        assert!(frames.try_recv().is_err());
+        // The banner isn't counted; the SOC-only and `SOC=x` lines are.
+        let counts = rejects.counts();
+        assert_eq!((counts.missing, counts.malformed), (1, 1));
+    }
 }
@@ -0,0 +1,348 @@
+//! Optional browser dashboard (`[web]`): one self-contained HTML page plus
+//! the live snapshot over HTTP, for a quick look without the CLI.
+//!
+//!   - `GET /`              → the dashboard ([`DASHBOARD`])
+//!   - `GET /api/snapshot`  → one snapshot, same JSON as the IPC `snapshot` reply
+//!   - `GET /api/stream`    → Server-Sent Events: a snapshot every second
+//!   - `GET /api/poll`      → long-poll: waits for the next snapshot, returns it
+//!
+//! One producer task builds the snapshot JSON once per second, only
+//! while someone is listening, and broadcasts it to every stream and poll
+//! client; a client that can't be written to is dropped.
+//!
+//! Read-only and unauthenticated, like the IPC TCP listener without a token;
+//! it binds localhost unless told otherwise. A deliberately small HTTP/1.1
+//! subset: one request per connection, `GET` only.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+use crate::config::{BatteryConfig, WebConfig};
+use crate::ipc::snapshot_json;
+use crate::state::State;
+
+/// The dashboard page; no external assets.
+pub const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Request line + headers beyond this are refused.
+const MAX_HEAD: usize = 8 * 1024;
+/// A client that doesn't finish its request in this long is dropped.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// Snapshots a slow client may fall behind before it skips ahead.
+const FEED_DEPTH: usize = 4;
+
+/// What every connection shares.
+struct Hub {
+    state: Arc<State>,
+    battery: BatteryConfig,
+    /// Snapshot JSON, once per second while it has receivers.
+    feed: broadcast::Sender<Arc<str>>,
+}
+
+impl Hub {
+    fn new(state: Arc<State>, battery: BatteryConfig) -> Arc<Self> {
+        let (feed, _) = broadcast::channel(FEED_DEPTH);
+        Arc::new(Self {
+            state,
+            battery,
+            feed,
+        })
+    }
+}
+
+/// Feeds [`Hub::feed`]. Idle ticks (no stream or poll client) cost nothing.
+async fn produce(hub: Arc<Hub>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        if hub.feed.receiver_count() == 0 {
+            continue;
+        }
+        let json = snapshot_json(&hub.state, &hub.battery).await;
+        let _ = hub.feed.send(json.into());
+    }
+}
+
+/// Serves `[web]` for the daemon's lifetime; idle when disabled or when the
+/// address can't be bound (logged).
+pub async fn web_loop(state: Arc<State>, web: WebConfig, battery: BatteryConfig) {
+    if !web.enabled {
+        return std::future::pending().await;
+    }
+    let listener = match bind(&web.listen_addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("web dashboard disabled: {e:#}");
+            return std::future::pending().await;
+        }
+    };
+    let hub = Hub::new(state, battery);
+    let producer = tokio::spawn(produce(hub.clone()));
+    let _producer = AbortOnDrop(producer);
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(serve(stream, peer, hub.clone()));
+            }
+            Err(e) => {
+                warn!("web accept: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// The daemon aborts `web_loop`; take the producer down with it.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn bind(addr: &str) -> Result<TcpListener> {
+    let addr: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("[web].listen_addr {addr:?} is not an ip:port"))?;
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("bind web {addr}"))?;
+    if addr.ip().is_loopback() {
+        info!("web dashboard on http://{addr}/");
+    } else {
+        warn!("web dashboard on http://{addr}/ without authentication; anyone who can reach it can read UPS telemetry");
+    }
+    Ok(listener)
+}
+
+async fn serve(mut stream: TcpStream, peer: SocketAddr, hub: Arc<Hub>) {
+    let path = match tokio::time::timeout(HEAD_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(path)) => path,
+        Ok(Err(status)) => {
+            let _ = respond(&mut stream, status, "text/plain", status.as_bytes()).await;
+            return;
+        }
+        Err(_) => return,
+    };
+    debug!(%peer, path, "web request");
+    let res = match path.as_str() {
+        "/" | "/index.html" => {
+            respond(
+                &mut stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                DASHBOARD.as_bytes(),
+            )
+            .await
+        }
+        "/api/snapshot" => {
+            let json = snapshot_json(&hub.state, &hub.battery).await;
+            respond(&mut stream, "200 OK", "application/json", json.as_bytes()).await
+        }
+        "/api/stream" => stream_events(&mut stream, hub.feed.subscribe()).await,
+        "/api/poll" => {
+            let mut feed = hub.feed.subscribe();
+            match next(&mut feed).await {
+                Some(json) => {
+                    respond(&mut stream, "200 OK", "application/json", json.as_bytes()).await
+                }
+                None => Ok(()),
+            }
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"404 Not Found").await,
+    };
+    if let Err(e) = res {
+        debug!(%peer, "web client gone: {e}");
+    }
+}
+
+/// The path of a `GET`, query string dropped; `Err` is the status to answer.
+async fn read_request(stream: &mut TcpStream) -> Result<String, &'static str> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.map_err(|_| "400 Bad Request")?;
+        if n == 0 {
+            return Err("400 Bad Request");
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_HEAD {
+            return Err("431 Request Header Fields Too Large");
+        }
+    }
+    parse_request_line(&String::from_utf8_lossy(&head))
+}
+
+fn parse_request_line(head: &str) -> Result<String, &'static str> {
+    let line = head.lines().next().unwrap_or_default();
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err("400 Bad Request");
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err("400 Bad Request");
+    }
+    if method != "GET" {
+        return Err("405 Method Not Allowed");
+    }
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(path.to_string())
+}
+
+async fn respond(
+    wr: &mut (impl AsyncWrite + Unpin),
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    wr.write_all(head.as_bytes()).await?;
+    wr.write_all(body).await?;
+    wr.flush().await
+}
+
+/// One `data:` event per feed snapshot until a write fails, which drops
+/// this receiver.
+async fn stream_events(
+    wr: &mut (impl AsyncWrite + Unpin),
+    mut feed: broadcast::Receiver<Arc<str>>,
+) -> std::io::Result<()> {
+    wr.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+          Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+    )
+    .await?;
+    wr.flush().await?;
+    while let Some(json) = next(&mut feed).await {
+        wr.write_all(format!("data: {json}\n\n").as_bytes()).await?;
+        wr.flush().await?;
+    }
+    Ok(())
+}
+
+/// The next snapshot; a client that fell behind skips to the newest.
+async fn next(feed: &mut broadcast::Receiver<Arc<str>>) -> Option<Arc<str>> {
+    loop {
+        match feed.recv().await {
+            Ok(json) => return Some(json),
+            Err(RecvError::Lagged(n)) => debug!("web client skipped {n} snapshots"),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_line_is_checked() {
+        assert_eq!(
+            parse_request_line("GET /api/stream?x=1 HTTP/1.1\r\nHost: pi\r\n\r\n"),
+            Ok("/api/stream".to_string())
+        );
+        assert_eq!(
+            parse_request_line("POST / HTTP/1.1\r\n\r\n"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(parse_request_line("hello\r\n\r\n"), Err("400 Bad Request"));
+    }
+
+    #[tokio::test]
+    async fn serves_page_and_snapshot() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hub = Hub::new(State::new(), crate::config::Config::default().battery);
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let (s, peer) = listener.accept().await.unwrap();
+                tokio::spawn(serve(s, peer, hub.clone()));
+            }
+        });
+
+        let get = |path: &'static str| async move {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(format!("GET {path} HTTP/1.1\r\nHost: x\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut out = String::new();
+            c.read_to_string(&mut out).await.unwrap();
+            out
+        };
+        let page = get("/").await;
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n"), "{page}");
+        assert!(page.ends_with(DASHBOARD));
+        let snap = get("/api/snapshot").await;
+        let body = snap.split("\r\n\r\n").nth(1).unwrap();
+        let v: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert!(v["power"].is_null() && v["unix_ts_ms"].is_u64(), "{v}");
+    }
+
+    #[tokio::test]
+    async fn stream_clients_share_the_feed_and_are_pruned() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hub = Hub::new(State::new(), crate::config::Config::default().battery);
+        let server = hub.clone();
+        tokio::spawn(async move {
+            loop {
+                let (s, peer) = listener.accept().await.unwrap();
+                tokio::spawn(serve(s, peer, server.clone()));
+            }
+        });
+
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(b"GET /api/stream HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+            clients.push(tokio::io::BufReader::new(c));
+        }
+        while hub.feed.receiver_count() < 2 {
+            tokio::task::yield_now().await;
+        }
+        hub.feed.send(Arc::from(r#"{"n":1}"#)).unwrap();
+        for c in &mut clients {
+            let mut text = String::new();
+            while !text.ends_with("\n\n") || !text.contains("data:") {
+                let mut line = String::new();
+                assert!(
+                    tokio::io::AsyncBufReadExt::read_line(c, &mut line)
+                        .await
+                        .unwrap()
+                        > 0
+                );
+                text.push_str(&line);
+            }
+            assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{text}");
+            assert!(text.contains("text/event-stream"), "{text}");
+            assert!(text.ends_with("data: {\"n\":1}\n\n"), "{text}");
+        }
+
+        // A closed client is noticed on a later write and its receiver dropped.
+        drop(clients.pop());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while hub.feed.receiver_count() > 1 {
+                let _ = hub.feed.send(Arc::from("{}"));
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("dead client pruned");
+    }
+}